@@ -0,0 +1,48 @@
+use std::process::Command;
+
+// Embed the git commit and build time so a running server can be matched to the
+// build it came from. Both can be overridden from the environment for builds
+// outside of a git checkout (e.g. from a source archive).
+fn main() {
+    let commit = std::env::var("GODATA_BUILD_COMMIT")
+        .ok()
+        .or_else(|| git(&["rev-parse", "--short", "HEAD"]));
+    let build_date = std::env::var("GODATA_BUILD_DATE")
+        .unwrap_or_else(|_| chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string());
+
+    println!(
+        "cargo:rustc-env=GODATA_BUILD_COMMIT={}",
+        commit.unwrap_or_else(|| "unknown".to_string())
+    );
+    println!("cargo:rustc-env=GODATA_BUILD_DATE={}", build_date);
+    println!("cargo:rerun-if-env-changed=GODATA_BUILD_COMMIT");
+    println!("cargo:rerun-if-env-changed=GODATA_BUILD_DATE");
+    for path in git_ref_files() {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_string())
+}
+
+fn git_ref_files() -> Vec<String> {
+    // HEAD only changes on checkout. A commit moves the branch HEAD points at, which
+    // lives in its own file or, once git has packed it, in packed-refs. Paths come
+    // from git so they're right inside a worktree too. Files that don't exist are left
+    // out, since cargo would rebuild on every run for them.
+    let mut names = vec!["HEAD".to_string(), "packed-refs".to_string()];
+    if let Some(branch) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        names.push(branch);
+    }
+    names
+        .iter()
+        .filter_map(|name| git(&["rev-parse", "--git-path", name]))
+        .filter(|path| std::path::Path::new(path).exists())
+        .collect()
+}
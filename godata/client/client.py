@@ -67,7 +67,7 @@ def get_client():
 
 def get_version(client, url):
     resp = client.get(f"{url}/version")
-    return resp.json()
+    return resp.json()["version"]
 
 
 def list_collections(show_hidden=False):
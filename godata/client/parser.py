@@ -38,7 +38,12 @@ def parse_response(
         case _:
             error = match_other_error(response.status_code)
     if not err_ok:
-        raise error(response.json())
+        raise error(error_message(response))
+
+
+def error_message(response: Response) -> str:
+    # Errors from the server come back as {"error_type": ..., "message": ...}
+    return response.json()["message"]
 
 
 def match_file_error(status_code: int):
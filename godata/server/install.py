@@ -73,7 +73,7 @@ def install(upgrade=False, version=None):
 
 def upgrade():
     """Upgrade the godata server binary to the latest version."""
-    current_version = get_version().strip("\n")
+    current_version = get_version().split()[0]
     install(upgrade=True, version=current_version)
 
 
@@ -0,0 +1,142 @@
+// Helpers for compressing exported project trees, and for reading them back in.
+// Archives are gzipped tarballs, so they can also be unpacked with standard tools.
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Compression {
+    None,
+    Gzip,
+}
+
+impl std::str::FromStr for Compression {
+    type Err = GodataError;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            _ => Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Unknown compression `{}`, expected `none` or `gzip`", s),
+            )),
+        }
+    }
+}
+
+pub(crate) struct ScratchDir {
+    // A directory under the system temp dir that is removed again when dropped, so
+    // operations that fail part way through don't leave unpacked files behind
+    path: PathBuf,
+}
+
+impl ScratchDir {
+    pub(crate) fn new(prefix: &str) -> ScratchDir {
+        let path = std::env::temp_dir().join(format!("{}-{}", prefix, uuid::Uuid::new_v4()));
+        ScratchDir { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        if self.path.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&self.path) {
+                tracing::warn!("Could not remove `{}`: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+pub(crate) fn compress_dir(dir: &Path, name: &str, output: &Path) -> Result<()> {
+    // Write the contents of `dir` into a gzipped tarball, stored under `name`
+    let file = File::create(output)?;
+    let encoder = GzEncoder::new(file, flate2::Compression::default());
+    let mut archive = tar::Builder::new(encoder);
+    archive.append_dir_all(name, dir)?;
+    archive.into_inner()?.finish()?;
+    Ok(())
+}
+
+pub(crate) fn extract(archive: &Path, dest: &Path) -> Result<()> {
+    let file = File::open(archive)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    archive.unpack(dest)?;
+    Ok(())
+}
+
+pub(crate) fn is_gzip(path: &Path) -> Result<bool> {
+    // Check for the gzip magic number rather than trusting the file extension
+    let mut magic = [0u8; 2];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut magic) {
+        Ok(_) => Ok(magic == [0x1f, 0x8b]),
+        Err(_) => Ok(false),
+    }
+}
+
+// Layout of a project bundle. The tree database lives under BUNDLE_TREE_DIR, internal
+// files under BUNDLE_DATA_DIR at their path relative to the storage root, and a
+// manifest describing the contents sits at the top level.
+pub(crate) const BUNDLE_TREE_DIR: &str = "tree";
+pub(crate) const BUNDLE_DATA_DIR: &str = "data";
+pub(crate) const BUNDLE_MANIFEST: &str = "manifest.json";
+
+pub(crate) fn write_bundle(
+    output: &Path,
+    tree_dir: &Path,
+    files: &[(PathBuf, PathBuf)],
+    manifest: &[u8],
+) -> Result<()> {
+    // files are (path on disk, path relative to the storage root)
+    let mut archive = tar::Builder::new(File::create(output)?);
+    archive.append_dir_all(BUNDLE_TREE_DIR, tree_dir)?;
+    for (path, relpath) in files {
+        archive.append_path_with_name(path, Path::new(BUNDLE_DATA_DIR).join(relpath))?;
+    }
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    archive.append_data(&mut header, BUNDLE_MANIFEST, manifest)?;
+    archive.finish()?;
+    Ok(())
+}
+
+pub(crate) fn read_bundle(bundle: &Path, tree_dest: &Path, data_dest: &Path) -> Result<()> {
+    // Unpack the tree and the data into separate locations. The manifest is only there
+    // for people looking at the bundle, so it isn't needed here.
+    let mut archive = tar::Archive::new(File::open(bundle)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        if path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Bundle contains invalid path `{}`", path.display()),
+            ));
+        }
+        let dest = if let Ok(relpath) = path.strip_prefix(BUNDLE_TREE_DIR) {
+            tree_dest.join(relpath)
+        } else if let Ok(relpath) = path.strip_prefix(BUNDLE_DATA_DIR) {
+            data_dest.join(relpath)
+        } else {
+            continue;
+        };
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        entry.unpack(dest)?;
+    }
+    Ok(())
+}
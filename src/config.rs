@@ -0,0 +1,163 @@
+// Server defaults read from a json file at startup. The file lives at
+// `~/.config/godata/config.json` unless GODATA_CONFIG points somewhere else. Anything
+// set on the command line takes precedence over the file.
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use crate::locations::validate_name;
+use directories::BaseDirs;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+// The collection clients should use when they aren't given one
+pub(crate) const DEFAULT_COLLECTION: &str = "default";
+
+static CONFIGURED_COLLECTION: OnceCell<String> = OnceCell::new();
+
+pub(crate) fn set_default_collection(name: String) {
+    // Only the first call has any effect
+    let _ = CONFIGURED_COLLECTION.set(name);
+}
+
+pub(crate) fn get_default_collection() -> &'static str {
+    CONFIGURED_COLLECTION
+        .get()
+        .map_or(DEFAULT_COLLECTION, |name| name.as_str())
+}
+
+// Folder layouts that new projects can be created with when there's no template file
+// of the same name
+const BUILTIN_TEMPLATES: [(&str, &[&str]); 2] = [
+    ("observation", &["raw", "calib", "processed"]),
+    ("analysis", &["data", "notebooks", "figures", "results"]),
+];
+
+fn config_dir() -> PathBuf {
+    BaseDirs::new().unwrap().home_dir().join(".config/godata")
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path, what: &str) -> Result<T> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        GodataError::new(
+            GodataErrorType::IOError,
+            format!("Failed to read {} `{}`: {}", what, path.display(), e),
+        )
+    })?;
+    serde_json::from_str(&contents).map_err(|e| {
+        GodataError::new(
+            GodataErrorType::InvalidPath,
+            format!("Invalid {} `{}`: {}", what, path.display(), e),
+        )
+    })
+}
+
+// Misspelled settings are refused rather than quietly ignored
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    pub(crate) port: Option<u16>,
+    pub(crate) socket_path: Option<PathBuf>,
+    pub(crate) flush_interval: Option<u64>,
+    // Where project data and logs are stored, in place of `~/godata`
+    pub(crate) storage_root: Option<PathBuf>,
+    // Days to keep old log files around for
+    pub(crate) log_retention_days: Option<i64>,
+    // Reported to clients as the collection to use when they aren't given one
+    pub(crate) default_collection: Option<String>,
+}
+
+impl Config {
+    pub(crate) fn load() -> Result<Config> {
+        // A missing file at the default location just means nothing is configured, but
+        // a file named in GODATA_CONFIG has to exist
+        let path = match std::env::var("GODATA_CONFIG") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => {
+                let path = config_dir().join("config.json");
+                if !path.exists() {
+                    return Ok(Config::default());
+                }
+                path
+            }
+        };
+        read_json(&path, "config file")
+    }
+
+    pub(crate) fn overridden_by(self, overrides: Config) -> Config {
+        // Settings given in `overrides` win, the rest come from this config
+        Config {
+            port: overrides.port.or(self.port),
+            socket_path: overrides.socket_path.or(self.socket_path),
+            flush_interval: overrides.flush_interval.or(self.flush_interval),
+            storage_root: overrides.storage_root.or(self.storage_root),
+            log_retention_days: overrides.log_retention_days.or(self.log_retention_days),
+            default_collection: overrides.default_collection.or(self.default_collection),
+        }
+    }
+}
+
+// The folders a new project starts out with. Templates are json files like
+// `{"folders": ["raw", "calib/darks"]}`, named `<template>.json` in
+// `~/.config/godata/templates` or the directory GODATA_TEMPLATE_DIR points to.
+#[derive(Deserialize, Debug)]
+pub(crate) struct ProjectTemplate {
+    pub(crate) folders: Vec<String>,
+}
+
+impl ProjectTemplate {
+    pub(crate) fn load(name: &str) -> Result<ProjectTemplate> {
+        // The name ends up in a file path, so it has to be a plain name
+        validate_name(name)?;
+        let dir = match std::env::var("GODATA_TEMPLATE_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => config_dir().join("templates"),
+        };
+        let path = dir.join(format!("{}.json", name));
+        if path.exists() {
+            return read_json(&path, "project template");
+        }
+        match BUILTIN_TEMPLATES.iter().find(|(n, _)| *n == name) {
+            Some((_, folders)) => Ok(ProjectTemplate {
+                folders: folders.iter().map(|f| f.to_string()).collect(),
+            }),
+            None => Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!("No project template named `{}`", name),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{scratch_dir, write_file};
+
+    #[test]
+    fn command_line_beats_config_file_beats_defaults() {
+        let path = write_file(
+            &scratch_dir().join("config.json"),
+            r#"{"port": 8000, "socket_path": "/tmp/godata.sock", "default_collection": "survey"}"#,
+        );
+        let file: Config = read_json(&path, "config file").unwrap();
+        let config = file.overridden_by(Config {
+            port: Some(9000),
+            ..Default::default()
+        });
+        assert_eq!(config.port, Some(9000));
+        assert_eq!(config.socket_path, Some(PathBuf::from("/tmp/godata.sock")));
+        assert_eq!(config.default_collection.as_deref(), Some("survey"));
+        // Left to the built-in defaults
+        assert_eq!(config.flush_interval, None);
+        assert_eq!(config.log_retention_days, None);
+        assert_eq!(get_default_collection(), DEFAULT_COLLECTION);
+    }
+
+    #[test]
+    fn unknown_config_keys_are_refused() {
+        let path = write_file(&scratch_dir().join("config.json"), r#"{"prot": 8000}"#);
+        let error = read_json::<Config>(&path, "config file").err().unwrap();
+        assert_eq!(error.error_type, GodataErrorType::InvalidPath);
+        assert!(error.message.contains("prot"));
+    }
+}
@@ -1,11 +1,20 @@
+use serde::Serialize;
 use std::error::Error;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+// Sent to clients alongside the message, so they can tell errors apart without
+// relying on the status code or the wording
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub(crate) enum GodataErrorType {
     NotFound,
     AlreadyExists,
     InvalidPath,
     NotPermitted,
+    // A project database that something else has open. Sent with the same status as
+    // NotPermitted, but kept apart so loads know it's worth waiting and retrying.
+    Locked,
+    TooLarge,
+    #[serde(rename = "io_error")]
     IOError,
     InternalError,
 }
@@ -16,13 +25,16 @@ impl Into<warp::http::StatusCode> for GodataErrorType {
             GodataErrorType::NotFound => warp::http::StatusCode::NOT_FOUND,
             GodataErrorType::AlreadyExists => warp::http::StatusCode::CONFLICT,
             GodataErrorType::InvalidPath => warp::http::StatusCode::BAD_REQUEST,
-            GodataErrorType::NotPermitted => warp::http::StatusCode::FORBIDDEN,
+            GodataErrorType::NotPermitted | GodataErrorType::Locked => {
+                warp::http::StatusCode::FORBIDDEN
+            }
+            GodataErrorType::TooLarge => warp::http::StatusCode::PAYLOAD_TOO_LARGE,
             _ => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub(crate) struct GodataError {
     pub(crate) error_type: GodataErrorType,
     pub(crate) message: String,
@@ -30,8 +42,8 @@ pub(crate) struct GodataError {
 
 impl warp::Reply for GodataError {
     fn into_response(self) -> warp::reply::Response {
-        warp::reply::with_status(warp::reply::json(&self.message), self.error_type.into())
-            .into_response()
+        // Every error goes out as the same JSON object, with the status to match
+        warp::reply::with_status(warp::reply::json(&self), self.error_type.into()).into_response()
     }
 }
 
@@ -42,6 +54,16 @@ impl GodataError {
             message,
         }
     }
+
+    pub(crate) fn db_locked(what: &str) -> Self {
+        Self::new(
+            GodataErrorType::Locked,
+            format!(
+                "{} is open in another process, connect to the running server instead",
+                what
+            ),
+        )
+    }
 }
 
 impl std::fmt::Display for GodataError {
@@ -59,8 +81,18 @@ impl From<std::io::Error> for GodataError {
     }
 }
 
+pub(crate) fn is_lock_error(error: &sled::Error) -> bool {
+    // Sled keeps an exclusive lock on an open database, and fails to open it again
+    // while anything else holds that lock
+    matches!(error, sled::Error::Io(e) if e.to_string().starts_with("could not acquire lock"))
+}
+
 impl From<sled::Error> for GodataError {
     fn from(error: sled::Error) -> Self {
+        // A project that another process has open can't be opened here
+        if is_lock_error(&error) {
+            return Self::db_locked("Database");
+        }
         Self {
             error_type: GodataErrorType::IOError,
             message: error.to_string(),
@@ -86,6 +118,15 @@ impl From<fnmatch_regex::error::Error> for GodataError {
     }
 }
 
+impl From<notify::Error> for GodataError {
+    fn from(error: notify::Error) -> Self {
+        Self {
+            error_type: GodataErrorType::IOError,
+            message: error.to_string(),
+        }
+    }
+}
+
 impl Error for GodataError {}
 
 pub(crate) type Result<T> = std::result::Result<T, GodataError>;
@@ -4,17 +4,70 @@
 
 // As far as the rest of the library is concrened,
 
+use chrono::Utc;
 use regex::Regex;
 use sled::{Batch, Db};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
 use ciborium::{from_reader, into_writer};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::instrument;
 
-use crate::errors::{GodataError, GodataErrorType, Result};
+use crate::errors::{is_lock_error, GodataError, GodataErrorType, Result};
+
+// Settings and bookkeeping managed by the server are kept in each folder's system
+// metadata, apart from the metadata users set. Their keys are wrapped in double
+// underscores, which is also how older trees mixed them into the user metadata.
+
+// Root system metadata keys recording when the project was created and last changed
+pub(crate) const CREATED_KEY: &str = "__created__";
+pub(crate) const UPDATED_KEY: &str = "__updated__";
+
+// Root system metadata key that moves file metadata out of the folder records and into
+// a compressed blob per file, which is only read when the metadata is asked for
+pub(crate) const EXTERNAL_METADATA_KEY: &str = "__external_metadata__";
+
+// Root system metadata key caching the hash of every file in the tree. Any change that
+// gets saved drops it.
+pub(crate) const MANIFEST_HASH_KEY: &str = "__manifest_hash__";
+
+// How many more times to try loading a project while its database is locked, how long
+// to wait before the first retry, and the longest wait between tries. The wait doubles
+// after each try. A database that was only just closed can keep its lock for a moment,
+// so an immediate reload would otherwise fail. The first two can be overridden with
+// GODATA_DB_OPEN_RETRIES and GODATA_DB_OPEN_BACKOFF_MS.
+const DB_OPEN_RETRIES: usize = 5;
+const DB_OPEN_BACKOFF_MS: u64 = 20;
+const DB_OPEN_MAX_BACKOFF_MS: u64 = 1000;
+
+pub(crate) fn is_reserved_key(key: &str) -> bool {
+    key.len() > 4 && key.starts_with("__") && key.ends_with("__")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ObjectKind {
+    File,
+    Folder,
+}
+
+impl std::str::FromStr for ObjectKind {
+    type Err = GodataError;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "file" => Ok(ObjectKind::File),
+            "folder" => Ok(ObjectKind::Folder),
+            _ => Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Unknown object type `{}`, expected `file` or `folder`", s),
+            )),
+        }
+    }
+}
 
 #[derive(Clone)]
 enum FSObject {
@@ -36,11 +89,41 @@ impl FSObject {
         }
     }
 }
-#[derive(Clone)]
+// Where a file that stands in for a file in another project points
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct FileRef {
+    pub(crate) collection: String,
+    pub(crate) project: String,
+    pub(crate) path: String,
+}
+
+impl std::fmt::Display for FileRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.collection, self.project, self.path)
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub(crate) struct File {
     pub(crate) real_path: PathBuf,
     pub(crate) name: String,
     pub(crate) metadata: HashMap<String, String>,
+    // The path the file was originally linked from, kept for provenance
+    pub(crate) origin: Option<String>,
+    // Unix timestamp of the last time the file was fetched, if access is tracked
+    pub(crate) accessed_at: Option<i64>,
+    // Unix timestamp of the last time the file was linked, relinked or had its metadata
+    // changed. Files from trees saved before this was tracked don't have one.
+    pub(crate) modified_at: Option<i64>,
+    // SHA-256 of the file contents, if the project computes checksums
+    pub(crate) checksum: Option<String>,
+    // A preview image for the file, stored like real_path
+    pub(crate) preview: Option<String>,
+    // Set on files that refer to a file in another project, which have no real path of
+    // their own
+    pub(crate) reference: Option<FileRef>,
+    // When set, `metadata` is empty and the real metadata lives in its own key
+    _external_metadata: bool,
     _uuid: String,
 }
 #[derive(Clone)]
@@ -48,6 +131,7 @@ struct Folder {
     pub(self) name: String,
     children: HashMap<String, FSObject>,
     metadata: HashMap<String, String>,
+    system_metadata: HashMap<String, String>,
     _uuid: String,
     _modified: bool,
 }
@@ -59,6 +143,8 @@ struct DbFolder {
     files: Vec<DbFile>,
     #[serde(default)]
     metadata: HashMap<String, String>,
+    #[serde(default)]
+    system_metadata: HashMap<String, String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -68,6 +154,206 @@ struct DbFile {
     uuid: String,
     #[serde(default)]
     metadata: HashMap<String, String>,
+    #[serde(default)]
+    origin: Option<String>,
+    #[serde(default)]
+    accessed_at: Option<i64>,
+    #[serde(default)]
+    modified_at: Option<i64>,
+    #[serde(default)]
+    checksum: Option<String>,
+    #[serde(default)]
+    preview: Option<String>,
+    #[serde(default)]
+    reference: Option<FileRef>,
+    #[serde(default)]
+    external_metadata: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct TreeSummary<'a> {
+    // Everything in the tree, gathered in a single walk
+    pub(crate) files: Vec<&'a File>,
+    pub(crate) folders: usize,
+    pub(crate) max_depth: usize,
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct FolderWidth {
+    pub(crate) path: String,
+    pub(crate) children: usize,
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct TreeShape {
+    pub(crate) max_depth: usize,
+    pub(crate) widest_folder: FolderWidth,
+}
+
+#[derive(Serialize)]
+pub(crate) struct DetailedListing {
+    // File names mapped to their metadata
+    pub(crate) files: HashMap<String, HashMap<String, String>>,
+    pub(crate) folders: Vec<String>,
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct TreeDiff {
+    // Whether anything in memory is still waiting to be saved
+    pub(crate) pending: bool,
+    // File paths that exist in memory but not in the database, and the reverse
+    pub(crate) added: Vec<String>,
+    pub(crate) removed: Vec<String>,
+    // Files at the same path whose entries differ
+    pub(crate) changed: Vec<String>,
+}
+
+// A serializable snapshot of the tree. Which fields are filled in depends on the
+// TreeOptions it was built with; anything left out is skipped when serializing.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub(crate) enum TreeNode {
+    File {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        real_path: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        origin: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        metadata: Option<HashMap<String, String>>,
+    },
+    Folder {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        metadata: Option<HashMap<String, String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        children: Option<Vec<TreeNode>>,
+    },
+}
+
+#[derive(Default)]
+pub(crate) struct TreeOptions<'a> {
+    // Real paths are only included when there is something to resolve them with
+    pub(crate) resolve: Option<&'a dyn Fn(&Path) -> PathBuf>,
+    pub(crate) with_metadata: bool,
+    // Folders deeper than this are listed without their children
+    pub(crate) max_depth: Option<usize>,
+}
+
+impl TreeNode {
+    fn name(&self) -> &str {
+        match self {
+            TreeNode::File { name, .. } => name,
+            TreeNode::Folder { name, .. } => name,
+        }
+    }
+}
+
+// Changes to files, folders and their metadata are written to the project's history as
+// they happen. Bookkeeping like access times, checksums and settings isn't recorded.
+//
+// Links, new folders, moves, renames and swaps can be undone, unless they replaced a
+// file that was already there. Removals and changes to metadata, real paths and
+// previews can't, since the history doesn't keep what was there before.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Operation {
+    Link,
+    LinkFolder,
+    CreateFolder,
+    Reference,
+    Remove,
+    Move,
+    Rename,
+    Swap,
+    SetMetadata,
+    SetFolderMetadata,
+    UpdateMetadata,
+    Relink,
+    RewritePaths,
+    SetPreview,
+    Undo,
+}
+
+impl Operation {
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::Link => "link",
+            Operation::LinkFolder => "link_folder",
+            Operation::CreateFolder => "create_folder",
+            Operation::Reference => "reference",
+            Operation::Remove => "remove",
+            Operation::Move => "move",
+            Operation::Rename => "rename",
+            Operation::Swap => "swap",
+            Operation::SetMetadata => "set_metadata",
+            Operation::SetFolderMetadata => "set_folder_metadata",
+            Operation::UpdateMetadata => "update_metadata",
+            Operation::Relink => "relink",
+            Operation::RewritePaths => "rewrite_paths",
+            Operation::SetPreview => "set_preview",
+            Operation::Undo => "undo",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct LogEntry {
+    pub(crate) timestamp: i64,
+    pub(crate) op: Operation,
+    // The paths the operation touched, empty for changes made across the whole project
+    pub(crate) paths: Vec<String>,
+    // Set when the operation put something in place of a file that was already there
+    #[serde(default)]
+    pub(crate) replaced: bool,
+    // Ids of the entries an undo reversed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) undone: Vec<u64>,
+}
+
+impl LogEntry {
+    fn new(op: Operation, paths: Vec<String>) -> LogEntry {
+        LogEntry {
+            timestamp: Utc::now().timestamp(),
+            op,
+            paths,
+            replaced: false,
+            undone: Vec::new(),
+        }
+    }
+
+    fn describe(&self) -> String {
+        let separator = match self.op {
+            Operation::Move | Operation::Rename => " -> ",
+            _ => ", ",
+        };
+        format!("{} {}", self.op.name(), self.paths.join(separator))
+    }
+
+    fn irreversible(&self) -> Option<&'static str> {
+        // Why the operation can't be undone, if it can't
+        if self.replaced {
+            return Some("it replaced a file that was already there");
+        }
+        match self.op {
+            Operation::Remove => Some("removed files aren't kept"),
+            Operation::SetMetadata | Operation::SetFolderMetadata | Operation::UpdateMetadata => {
+                Some("the previous metadata isn't kept")
+            }
+            Operation::Relink | Operation::RewritePaths => {
+                Some("the previous real paths aren't kept")
+            }
+            Operation::SetPreview => Some("the previous preview isn't kept"),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct FileContext<'a> {
+    pub(crate) file: &'a File,
+    // Metadata of every folder above the file, deeper folders winning
+    pub(crate) inherited: HashMap<String, String>,
+    pub(crate) siblings: Vec<String>,
 }
 
 pub(crate) struct FileSystem {
@@ -75,25 +361,99 @@ pub(crate) struct FileSystem {
     _name: String,
     _modified: bool,
     db: Db,
+    // Collects every change while operations are being undone, so the reversals are
+    // written out together and recorded as a single entry
+    _staged: Option<Batch>,
+    // Goes up with every change to the tree, so anything worked out from the tree can
+    // tell whether it's still current
+    _generation: u64,
 }
 
 enum RemoveResult {
-    Item(FSObject),
+    Item(Box<FSObject>),
     IsEmpty,
 }
 
-pub(crate) fn is_empty(path: &PathBuf) -> bool {
-    let db = sled::open(path).unwrap();
+pub(crate) fn is_empty(path: &PathBuf) -> Result<bool> {
+    // A database that is still locked is reported rather than waited on, since the
+    // caller holds the project manager
+    let db = sled::open(path)?;
     // Count the entries in the database
-    let root_folder = db.get("root".as_bytes()).unwrap();
+    let root_folder = match db.get("root".as_bytes())? {
+        Some(root_folder) => root_folder,
+        None => return Ok(true),
+    };
     // Deserialize the root folder
-    let db_folder = from_reader(root_folder.unwrap().as_ref());
-    let db_folder: DbFolder = db_folder.unwrap();
+    let db_folder: DbFolder = from_reader(root_folder.as_ref()).map_err(|e| {
+        GodataError::new(
+            GodataErrorType::IOError,
+            format!("Failed to read the root folder: {}", e),
+        )
+    })?;
     // If there are any files or folders in the root folder, return false
     if db_folder.folders_uuids.len() > 0 || db_folder.files.len() > 0 {
-        return false;
+        return Ok(false);
     }
-    true
+    Ok(true)
+}
+
+pub(crate) fn stored_system_value(db: &Db, key: &str) -> Result<Option<String>> {
+    // Read a value from the root folder of a database that isn't loaded as a tree
+    let root_folder = match db.get("root".as_bytes())? {
+        Some(root_folder) => root_folder,
+        None => return Ok(None),
+    };
+    let mut db_folder: DbFolder = from_reader(root_folder.as_ref()).map_err(|e| {
+        GodataError::new(
+            GodataErrorType::IOError,
+            format!("Failed to read the root folder: {}", e),
+        )
+    })?;
+    Ok(db_folder.system_metadata.remove(key))
+}
+
+pub(crate) fn db_open_delays() -> impl Iterator<Item = Duration> {
+    // How long to wait before each retry of opening a locked database. The wait doubles
+    // each time, up to a limit.
+    let retries = std::env::var("GODATA_DB_OPEN_RETRIES")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DB_OPEN_RETRIES);
+    let first = std::env::var("GODATA_DB_OPEN_BACKOFF_MS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map_or(
+            Duration::from_millis(DB_OPEN_BACKOFF_MS),
+            Duration::from_millis,
+        );
+    backoff_delays(retries, first)
+}
+
+fn backoff_delays(retries: usize, first: Duration) -> impl Iterator<Item = Duration> {
+    std::iter::successors(Some(first), |delay| Some(delay.saturating_mul(2)))
+        .map(|delay| delay.min(Duration::from_millis(DB_OPEN_MAX_BACKOFF_MS)))
+        .take(retries)
+}
+
+fn open_db(name: &str, path: &Path) -> Result<Db> {
+    // A project that is open somewhere else gets its own error, anything else is
+    // reported as a generic failure. There's no waiting here, since callers usually
+    // hold the project manager. ProjectManager::load_retrying waits without it.
+    sled::open(path).map_err(|e| {
+        tracing::error!(
+            "Sled failed to open database for project `{}` at path `{}`: {}",
+            name,
+            path.display(),
+            e
+        );
+        match is_lock_error(&e) {
+            true => GodataError::db_locked(&format!("Project `{}`", name)),
+            false => GodataError::new(
+                GodataErrorType::IOError,
+                "Failed to open database".to_string(),
+            ),
+        }
+    })
 }
 
 fn drain(mut folder: Folder) -> Vec<File> {
@@ -113,32 +473,78 @@ fn drain(mut folder: Folder) -> Vec<File> {
     files
 }
 
+fn join_path(prefix: &str, name: &str) -> String {
+    match prefix {
+        "" => name.to_string(),
+        _ => format!("{}/{}", prefix, name),
+    }
+}
+
+fn metadata_key(uuid: &str) -> String {
+    format!("metadata/{}", uuid)
+}
+
+fn path_key(uuid: &str) -> String {
+    // Index entry holding the current virtual path of a file, rewritten whenever the
+    // folder holding the file is saved
+    format!("path/{}", uuid)
+}
+
+fn history_key(id: u64) -> String {
+    // Ids are padded so the entries sort in the order they were written
+    format!("history/{:020}", id)
+}
+
+fn encode_metadata(metadata: &HashMap<String, String>) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    into_writer(metadata, &mut encoder).map_err(|e| {
+        GodataError::new(
+            GodataErrorType::IOError,
+            format!("Failed to serialize metadata: {}", e),
+        )
+    })?;
+    Ok(encoder.finish()?)
+}
+
+fn decode_metadata(bytes: &[u8]) -> Result<HashMap<String, String>> {
+    from_reader(ZlibDecoder::new(bytes)).map_err(|e| {
+        GodataError::new(
+            GodataErrorType::IOError,
+            format!("Failed to read stored metadata: {}", e),
+        )
+    })
+}
+
+fn load_file_metadata(db: &Db, file: &File) -> Result<HashMap<String, String>> {
+    // Files with external metadata have it read from the database on demand
+    if !file._external_metadata {
+        return Ok(file.metadata.clone());
+    }
+    match db.get(metadata_key(&file._uuid).as_bytes())? {
+        Some(bytes) => decode_metadata(&bytes),
+        None => Ok(HashMap::new()),
+    }
+}
+
+fn drop_file_records(files: &[File], batch: &mut Batch) {
+    // Remove the database entries kept alongside files that have left the tree
+    for file in files {
+        batch.remove(path_key(&file._uuid).as_bytes());
+        if file._external_metadata {
+            batch.remove(metadata_key(&file._uuid).as_bytes());
+        }
+    }
+}
+
 impl FileSystem {
     #[instrument]
     pub(crate) fn new(name: String, root_path: PathBuf) -> Result<FileSystem> {
-        let db = sled::open(&root_path); // If we can't open the database, we just fail
-
-        let db = match db {
-            Ok(db) => db,
-            Err(e) => {
-                tracing::error!("Sled failed to open database: {}", e);
-                return Err(GodataError::new(
-                    GodataErrorType::IOError,
-                    format!("Failed to open database"),
-                ));
-            }
-        };
+        let db = open_db(&name, &root_path)?; // If we can't open the database, we just fail
 
         let root_folder = db.get("root".as_bytes())?;
         // If there is already a root folder, fail
         let root = match root_folder {
-            None => Folder {
-                name: "root".to_string(),
-                children: HashMap::new(),
-                metadata: HashMap::new(),
-                _uuid: "root".to_string(),
-                _modified: true,
-            },
+            None => Folder::new_root(),
             Some(_) => {
                 tracing::error!(
                     "Was able to create a new filesystem for project `{}` at path `{}`, but somehow the root folder already exists!",
@@ -157,6 +563,34 @@ impl FileSystem {
             _name: name,
             _modified: true,
             db,
+            _staged: None,
+            _generation: 0,
+        })
+    }
+
+    pub(crate) fn temporary(name: String) -> Result<FileSystem> {
+        // A tree backed by a throwaway database that sled deletes once it's closed
+        let db = sled::Config::new().temporary(true).open()?;
+        Ok(FileSystem {
+            root: Folder::new_root(),
+            _name: name,
+            _modified: true,
+            db,
+            _staged: None,
+            _generation: 0,
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn second_handle(&self) -> Result<FileSystem> {
+        // Another tree reading from the same database, standing in for a second writer
+        Ok(FileSystem {
+            root: Folder::from_tree(&self.db, "root".to_string())?,
+            _name: self._name.clone(),
+            _modified: false,
+            db: self.db.clone(),
+            _staged: None,
+            _generation: 0,
         })
     }
 
@@ -172,23 +606,60 @@ impl FileSystem {
         Ok(res)
     }
 
-    pub(crate) fn load(name: &str, root_dir: PathBuf) -> Result<FileSystem> {
-        let db = sled::open(&root_dir);
-        let db = match db {
-            Ok(db) => db,
-            Err(e) => {
-                tracing::error!(
-                    "Sled failed to open database for project `{}` at path `{}`: {}",
-                    name,
-                    root_dir.display(),
-                    e
-                );
-                return Err(GodataError::new(
-                    GodataErrorType::IOError,
-                    format!("Failed to open database"),
-                ));
-            }
+    #[instrument(skip(self))]
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        // Save any pending changes and make sure they have reached the disk
+        self.save()?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn reload(&mut self) -> Result<()> {
+        // Changes that haven't been saved yet are lost
+        let root = Folder::from_tree(&self.db, "root".to_string())?;
+        self._modified = root._modified;
+        self.root = root;
+        self._generation += 1;
+        tracing::info!("Reloaded filesystem for project `{}`", self._name);
+        Ok(())
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self._generation
+    }
+
+    pub(crate) fn has_pending_changes(&self) -> bool {
+        self._modified || self.root.has_modified()
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn disk_diff(&self) -> Result<TreeDiff> {
+        // Compare the tree in memory with a fresh copy read back from the database
+        let stored = Folder::from_tree(&self.db, "root".to_string())?;
+        let mut stored_files = Vec::new();
+        stored.walk_files("", &mut stored_files);
+        let mut stored_files: HashMap<String, &File> = stored_files.into_iter().collect();
+        let mut diff = TreeDiff {
+            pending: self.has_pending_changes(),
+            ..Default::default()
         };
+        for (path, file) in self.files() {
+            match stored_files.remove(&path) {
+                None => diff.added.push(path),
+                Some(stored) if stored != file => diff.changed.push(path),
+                Some(_) => (),
+            }
+        }
+        diff.removed = stored_files.into_keys().collect();
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort();
+        Ok(diff)
+    }
+
+    pub(crate) fn load(name: &str, root_dir: PathBuf) -> Result<FileSystem> {
+        let db = open_db(name, &root_dir)?;
         let root_folder = db.get("root".as_bytes())?;
         // If there is no root folder, fail
 
@@ -213,6 +684,8 @@ impl FileSystem {
             _modified: false,
             _name: name.to_string(),
             db,
+            _staged: None,
+            _generation: 0,
         })
     }
 
@@ -220,6 +693,7 @@ impl FileSystem {
     pub(crate) fn list(
         &self,
         virtual_path: Option<String>,
+        kind: Option<ObjectKind>,
     ) -> Result<HashMap<String, Vec<String>>> {
         let folder = match virtual_path {
             Some(path) => {
@@ -241,9 +715,12 @@ impl FileSystem {
         let mut folders = Vec::new();
 
         for (name, child) in folder.children.iter() {
-            match child {
-                FSObject::File(_) => files.push(name.clone()),
-                FSObject::Folder(_) => folders.push(name.clone()),
+            match (child, kind) {
+                (FSObject::File(_), None | Some(ObjectKind::File)) => files.push(name.clone()),
+                (FSObject::Folder(_), None | Some(ObjectKind::Folder)) => {
+                    folders.push(name.clone())
+                }
+                _ => (),
             }
         }
         let mut children = HashMap::new();
@@ -252,6 +729,38 @@ impl FileSystem {
         Ok(children)
     }
 
+    #[instrument(skip(self))]
+    pub(crate) fn list_detailed(
+        &self,
+        virtual_path: Option<&str>,
+        kind: Option<ObjectKind>,
+        fields: Option<&[String]>,
+    ) -> Result<DetailedListing> {
+        // Like list, but with each file's metadata. If fields are given, only those
+        // keys are included.
+        let folder = self.folder(virtual_path)?;
+        let mut listing = DetailedListing {
+            files: HashMap::new(),
+            folders: Vec::new(),
+        };
+        for (name, child) in folder.children.iter() {
+            match (child, kind) {
+                (FSObject::File(f), None | Some(ObjectKind::File)) => {
+                    let mut metadata = self.file_metadata(f)?;
+                    if let Some(fields) = fields {
+                        metadata.retain(|key, _| fields.contains(key));
+                    }
+                    listing.files.insert(name.clone(), metadata);
+                }
+                (FSObject::Folder(_), None | Some(ObjectKind::Folder)) => {
+                    listing.folders.push(name.clone())
+                }
+                _ => (),
+            }
+        }
+        Ok(listing)
+    }
+
     #[instrument(skip(self))]
     pub(crate) fn get(&self, virtual_path: &str) -> Result<&File> {
         let file = self.root.get(virtual_path)?;
@@ -267,6 +776,24 @@ impl FileSystem {
         }
     }
 
+    pub(crate) fn path_of(&self, uuid: &str) -> Result<String> {
+        // The current virtual path of the file with this uuid. The index is checked
+        // against the tree, and trees saved before it existed are searched instead.
+        if let Some(path) = self.db.get(path_key(uuid).as_bytes())? {
+            let path = String::from_utf8_lossy(&path).to_string();
+            if matches!(self.root.get(&path), Ok(FSObject::File(f)) if f._uuid == uuid) {
+                return Ok(path);
+            }
+        }
+        match self.files().into_iter().find(|(_, f)| f._uuid == uuid) {
+            Some((path, _)) => Ok(path),
+            None => Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!("No file with uuid `{}`", uuid),
+            )),
+        }
+    }
+
     pub(crate) fn get_many(
         &self,
         virtual_path: Option<&str>,
@@ -308,51 +835,226 @@ impl FileSystem {
         project_path: &str,
         real_path: PathBuf,
         metadata: HashMap<String, String>,
+        origin: Option<String>,
         overwrite: bool,
     ) -> Result<Option<Vec<File>>> {
+        let mut batch = self.new_batch();
+        let result = self.stage_insert(
+            project_path,
+            real_path,
+            metadata,
+            origin,
+            overwrite,
+            &mut batch,
+        )?;
+        self._modified = true;
+        let mut entry = LogEntry::new(Operation::Link, vec![project_path.to_string()]);
+        entry.replaced = matches!(&result, Some(previous) if !previous.is_empty());
+        self.commit(batch, Some(entry))?;
+        Ok(result)
+    }
+
+    #[instrument(skip(self, entries))]
+    pub(crate) fn insert_each<I>(&mut self, entries: I) -> Result<Vec<(usize, GodataError)>>
+    where
+        I: Iterator<Item = (String, PathBuf, HashMap<String, String>, Option<String>)>,
+    {
+        // Insert files one by one without overwriting, then save them all at once.
+        // Entries that can't be inserted are handed back by position along with the
+        // reason, and don't stop the others from going in.
+        let mut batch = self.new_batch();
+        let mut failed = Vec::new();
+        let mut inserted = Vec::new();
+        for (index, (project_path, real_path, metadata, origin)) in entries.enumerate() {
+            let result = self.stage_insert(
+                &project_path,
+                real_path,
+                metadata,
+                origin,
+                false,
+                &mut batch,
+            );
+            match result {
+                Ok(_) => inserted.push(project_path),
+                Err(e) => failed.push((index, e)),
+            }
+        }
+        self._modified = true;
+        let entry = (!inserted.is_empty()).then(|| LogEntry::new(Operation::Link, inserted));
+        self.commit(batch, entry)?;
+        Ok(failed)
+    }
+
+    fn stage_insert(
+        &mut self,
+        project_path: &str,
+        real_path: PathBuf,
+        metadata: HashMap<String, String>,
+        origin: Option<String>,
+        overwrite: bool,
+        batch: &mut Batch,
+    ) -> Result<Option<Vec<File>>> {
+        // Put a file in the tree, leaving any database changes in the batch
         let name = project_path.split('/').last().unwrap().to_string();
-        let result = if name == project_path {
-            let mut file = File::new(real_path, name);
+        let mut file = File::new(real_path, name);
+        file.origin = origin;
+        self.stage_insert_file(project_path, file, metadata, overwrite, batch)
+    }
+
+    fn stage_insert_file(
+        &mut self,
+        project_path: &str,
+        mut file: File,
+        metadata: HashMap<String, String>,
+        overwrite: bool,
+        batch: &mut Batch,
+    ) -> Result<Option<Vec<File>>> {
+        let name = file.name.clone();
+        let uuid = file._uuid.clone();
+        let mut external = None;
+        if self.uses_external_metadata() && !metadata.is_empty() {
+            external = Some(encode_metadata(&metadata)?);
+            file._external_metadata = true;
+        } else {
             file.metadata = metadata;
+        }
+        let result = if name == project_path {
             self.root.insert(FSObject::File(file), "", overwrite)?
         } else {
             let ppath = project_path
                 .strip_suffix(format!("/{}", name).as_str())
                 .unwrap();
-            let mut file = File::new(real_path, name);
-            file.metadata = metadata;
             self.root.insert(FSObject::File(file), ppath, overwrite)?
         };
+        if let Some(external) = external {
+            batch.insert(metadata_key(&uuid).as_bytes(), external);
+        }
+        if let Some(previous) = &result {
+            drop_file_records(previous, batch);
+        }
+        Ok(result)
+    }
+
+    pub(crate) fn insert_reference(
+        &mut self,
+        project_path: &str,
+        reference: FileRef,
+        overwrite: bool,
+    ) -> Result<Option<Vec<File>>> {
+        // References have an empty real path, which is never treated as internal
+        let name = project_path.rsplit('/').next().unwrap().to_string();
+        let mut file = File::new(PathBuf::new(), name);
+        file.reference = Some(reference);
+        let mut batch = self.new_batch();
+        let result =
+            self.stage_insert_file(project_path, file, HashMap::new(), overwrite, &mut batch)?;
         self._modified = true;
-        self.save()?;
+        let mut entry = LogEntry::new(Operation::Reference, vec![project_path.to_string()]);
+        entry.replaced = matches!(&result, Some(previous) if !previous.is_empty());
+        self.commit(batch, Some(entry))?;
         Ok(result)
     }
 
     pub(crate) fn insert_many<I>(&mut self, files: I, virtual_path: &str) -> Result<()>
     where
-        I: Iterator<Item = PathBuf>,
+        I: Iterator<Item = (PathBuf, PathBuf)>,
     {
-        let file_objects = files.map(|path| {
-            let name = path.file_name().unwrap().to_str().unwrap().to_string();
-            File::new(path, name)
-        });
-        self.root.insert_many(file_objects, virtual_path)?;
+        // Files come in as (path to store, path they were linked from). Everything is
+        // checked before the tree is touched, so a bad batch doesn't leave half of
+        // itself behind.
+        let mut file_objects = Vec::new();
+        for (real_path, origin) in files {
+            let name = match origin.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => {
+                    return Err(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        format!(
+                            "Cannot link `{}`, it has no valid file name",
+                            origin.display()
+                        ),
+                    ))
+                }
+            };
+            let mut file = File::new(real_path, name);
+            file.origin = origin.to_str().map(|p| p.to_string());
+            file_objects.push(file);
+        }
+        let path_parts: Vec<&str> = virtual_path.split('/').collect();
+        if path_parts.iter().any(|part| part.is_empty()) {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Invalid path `{}`", virtual_path),
+            ));
+        }
+        let names: Vec<&str> = file_objects.iter().map(|f| f.get_name()).collect();
+        self.root.check_insert_many(&path_parts, &names)?;
+        let (op, paths) = match names.is_empty() {
+            true => (Operation::CreateFolder, vec![virtual_path.to_string()]),
+            false => (
+                Operation::LinkFolder,
+                names.iter().map(|n| join_path(virtual_path, n)).collect(),
+            ),
+        };
+
+        self.root
+            .insert_many(file_objects.into_iter(), virtual_path)?;
         self._modified = true;
-        self.save()?;
-        Ok(())
+        self.save_recorded(op, paths)
+    }
+
+    pub(crate) fn create_folder(&mut self, virtual_path: &str) -> Result<()> {
+        // Make an empty folder, along with any of its parents that are missing
+        self.insert_many(std::iter::empty(), virtual_path)
     }
 
     #[instrument(skip(self))]
     pub(crate) fn remove(&mut self, virtual_path: &str) -> Result<Vec<File>> {
+        let mut batch = self.new_batch();
+        let output = self.remove_into(virtual_path, &mut batch)?;
+        drop_file_records(&output, &mut batch);
+        self._modified = true;
+        // The folders that pointed at the dropped records are written out with them
+        let entry = LogEntry::new(Operation::Remove, vec![virtual_path.to_string()]);
+        self.commit(batch, Some(entry))?;
+        Ok(output)
+    }
+
+    #[instrument(skip(self, virtual_paths))]
+    pub(crate) fn remove_many(
+        &mut self,
+        virtual_paths: &[String],
+    ) -> Result<Vec<(String, Result<Vec<File>>)>> {
+        // Remove several paths, writing all of the removals to the database at once.
+        // A path that fails to remove is reported rather than aborting the rest.
+        let mut batch = self.new_batch();
+        let results = virtual_paths
+            .iter()
+            .map(|path| (path.clone(), self.remove_into(path, &mut batch)))
+            .collect::<Vec<_>>();
+        let mut removed_paths = Vec::new();
+        for (path, removed) in results.iter() {
+            if let Ok(files) = removed {
+                drop_file_records(files, &mut batch);
+                removed_paths.push(path.clone());
+            }
+        }
+        self._modified = true;
+        let entry =
+            (!removed_paths.is_empty()).then(|| LogEntry::new(Operation::Remove, removed_paths));
+        self.commit(batch, entry)?;
+        Ok(results)
+    }
+
+    fn remove_into(&mut self, virtual_path: &str, batch: &mut Batch) -> Result<Vec<File>> {
         let result = self.root.delete(virtual_path)?;
         tracing::info!(
             "Removed item at path `{}`, dropping from tree",
             virtual_path
         );
-        let mut batch = Batch::default();
         let output = match result {
             RemoveResult::IsEmpty => {
-                self.root.drop_from_tree(&mut batch)?;
+                self.root.drop_from_tree(batch)?;
                 let mut files: Vec<File> = Vec::new();
                 for (_, child) in self.root.children.drain() {
                     match child {
@@ -365,72 +1067,823 @@ impl FileSystem {
                         }
                     }
                 }
-                self.root.children.clear();
-                files
+                self.root.children.clear();
+                files
+            }
+            RemoveResult::Item(f) => match *f {
+                FSObject::File(f) => {
+                    vec![f]
+                }
+                FSObject::Folder(mut f) => {
+                    f.drop_from_tree(batch)?;
+                    drain(f)
+                }
+            },
+        };
+        Ok(output)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn move_(
+        &mut self,
+        source_path: &str,
+        dest_path: &str,
+        overwrite: bool,
+    ) -> Result<Option<Vec<File>>> {
+        if !self.root.exists(source_path) {
+            tracing::info!("Source path does not exist");
+            return Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!("Source path `{}` does not exist", source_path),
+            ));
+        }
+        if self.root.exists(dest_path) && !overwrite {
+            tracing::info!("Destination path already exists");
+            return Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("Destination path `{}` already exists", dest_path),
+            ));
+        }
+        let item = self.root.get(source_path)?;
+        // HANDLE RENAME SEMANTICS
+        // make a copy of the item
+        let (fpath, fname) = dest_path.rsplit_once('/').unwrap_or(("", dest_path));
+        let mut item = (*item).clone();
+        item.rename(fname.to_string());
+        // The copy shares its folders' uuids with the original, whose records are about
+        // to be dropped, so the whole copy has to be written out again
+        if let FSObject::Folder(f) = &mut item {
+            f.mark_modified();
+        }
+        // Split the destination path into path and name
+
+        let result = self.root.insert(item, fpath, overwrite)?;
+        // The moved item keeps its uuid, so only displaced files lose their stored records
+        let mut batch = self.new_batch();
+        self.remove_into(source_path, &mut batch)?;
+        if let Some(displaced) = &result {
+            drop_file_records(displaced, &mut batch);
+        }
+        self._modified = true;
+        let mut entry = LogEntry::new(
+            Operation::Move,
+            vec![source_path.to_string(), dest_path.to_string()],
+        );
+        entry.replaced = matches!(&result, Some(previous) if !previous.is_empty());
+        self.commit(batch, Some(entry))?;
+        Ok(result)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn rename(&mut self, virtual_path: &str, new_name: &str) -> Result<()> {
+        // Change the name of a file or folder without moving it to a different folder
+        if new_name.is_empty() || new_name.contains('/') {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("`{}` is not a valid name", new_name),
+            ));
+        }
+        let (parent, name) = match virtual_path.rsplit_once('/') {
+            Some((parent, name)) => (Some(parent), name),
+            None => (None, virtual_path),
+        };
+        let folder = self.folder_mut(parent)?;
+        if !folder.children.contains_key(name) {
+            let msg = format!("Path `{}` does not exist", virtual_path);
+            tracing::info!(msg);
+            return Err(GodataError::new(GodataErrorType::NotFound, msg));
+        }
+        if folder.children.contains_key(new_name) {
+            let msg = format!(
+                "Folder `{}` already contains `{}`",
+                parent.unwrap_or("root"),
+                new_name
+            );
+            tracing::info!(msg);
+            return Err(GodataError::new(GodataErrorType::AlreadyExists, msg));
+        }
+        let mut child = folder.children.remove(name).unwrap();
+        child.rename(new_name.to_string());
+        // Folders store their own name, so a renamed folder needs to be written out too,
+        // along with everything under it so the path index follows the new name
+        if let FSObject::Folder(f) = &mut child {
+            f.mark_modified();
+        }
+        folder.children.insert(new_name.to_string(), child);
+        folder._modified = true;
+        self._modified = true;
+        let new_path = join_path(parent.unwrap_or(""), new_name);
+        self.save_recorded(Operation::Rename, vec![virtual_path.to_string(), new_path])
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn swap(&mut self, path_a: &str, path_b: &str) -> Result<()> {
+        // Exchange the files at two paths. Each file keeps its uuid, metadata and real
+        // path, it just takes the other's place, and both changes are saved together.
+        if path_a == path_b {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Can't swap `{}` with itself", path_a),
+            ));
+        }
+        let mut file_a = FSObject::File(self.get(path_a)?.clone());
+        let mut file_b = FSObject::File(self.get(path_b)?.clone());
+        let (parent_a, name_a) = match path_a.rsplit_once('/') {
+            Some((parent, name)) => (Some(parent), name),
+            None => (None, path_a),
+        };
+        let (parent_b, name_b) = match path_b.rsplit_once('/') {
+            Some((parent, name)) => (Some(parent), name),
+            None => (None, path_b),
+        };
+        file_a.rename(name_b.to_string());
+        file_b.rename(name_a.to_string());
+        let folder = self.folder_mut(parent_b)?;
+        folder.children.insert(name_b.to_string(), file_a);
+        folder._modified = true;
+        let folder = self.folder_mut(parent_a)?;
+        folder.children.insert(name_a.to_string(), file_b);
+        folder._modified = true;
+        self._modified = true;
+        self.save_recorded(
+            Operation::Swap,
+            vec![path_a.to_string(), path_b.to_string()],
+        )
+    }
+
+    pub(crate) fn exists(&self, virtual_path: &str) -> bool {
+        self.root.exists(virtual_path)
+    }
+
+    pub(crate) fn kind(&self, virtual_path: &str) -> Result<&'static str> {
+        match self.root.get(virtual_path)? {
+            FSObject::File(_) => Ok("file"),
+            FSObject::Folder(_) => Ok("folder"),
+        }
+    }
+
+    pub(crate) fn folder_contains(&self, folder_path: Option<&str>, name: &str) -> bool {
+        match self.get_folder(folder_path) {
+            Some(folder) => folder.children.contains_key(name),
+            None => false,
+        }
+    }
+
+    fn get_folder(&self, virtual_path: Option<&str>) -> Option<&Folder> {
+        match virtual_path {
+            Some(path) => match self.root.get(path) {
+                Ok(FSObject::Folder(f)) => Some(f),
+                _ => None,
+            },
+            None => Some(&self.root),
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn ancestors(&self, virtual_path: &str) -> Result<Vec<String>> {
+        // Every prefix of the path, from the top level folder down to the path itself
+        let parts: Vec<&str> = virtual_path.split('/').collect();
+        let mut chain = Vec::new();
+        let mut folder = &self.root;
+        for (i, part) in parts.iter().enumerate() {
+            let path = parts[..=i].join("/");
+            match folder.children.get(*part) {
+                None => {
+                    let msg = format!(
+                        "Path `{}` does not exist, first missing segment is `{}`",
+                        virtual_path, path
+                    );
+                    tracing::info!(msg);
+                    return Err(GodataError::new(GodataErrorType::NotFound, msg));
+                }
+                Some(FSObject::File(_)) if i + 1 < parts.len() => {
+                    let msg = format!("Path `{}` is blocked by file `{}`", virtual_path, path);
+                    tracing::info!(msg);
+                    return Err(GodataError::new(GodataErrorType::InvalidPath, msg));
+                }
+                Some(FSObject::File(_)) => (),
+                Some(FSObject::Folder(f)) => folder = f,
+            }
+            chain.push(path);
+        }
+        Ok(chain)
+    }
+
+    pub(crate) fn file_context(&self, virtual_path: &str) -> Result<FileContext<'_>> {
+        let not_found = || {
+            GodataError::new(
+                GodataErrorType::NotFound,
+                format!("No file found at `{}`", virtual_path),
+            )
+        };
+        let (parent_parts, name) = match virtual_path.rsplit_once('/') {
+            Some((parent, name)) => (parent.split('/').collect(), name),
+            None => (Vec::new(), virtual_path),
+        };
+        let mut folder = &self.root;
+        let mut inherited = folder.metadata.clone();
+        for part in parent_parts {
+            match folder.children.get(part) {
+                Some(FSObject::Folder(f)) => {
+                    inherited.extend(f.metadata.clone());
+                    folder = f;
+                }
+                _ => return Err(not_found()),
+            }
+        }
+        let file = match folder.children.get(name) {
+            Some(FSObject::File(f)) => f,
+            _ => return Err(not_found()),
+        };
+        let mut siblings: Vec<String> = folder
+            .children
+            .keys()
+            .filter(|k| k.as_str() != name)
+            .cloned()
+            .collect();
+        siblings.sort();
+        Ok(FileContext {
+            file,
+            inherited,
+            siblings,
+        })
+    }
+
+    pub(crate) fn summarize(&self) -> TreeSummary<'_> {
+        let mut summary = TreeSummary::default();
+        self.root.summarize(1, &mut summary);
+        summary
+    }
+
+    pub(crate) fn shape(&self) -> TreeShape {
+        // How deep the tree goes and which folder has the most direct children.
+        // The root is reported with an empty path.
+        let mut shape = TreeShape::default();
+        self.root.measure("", 1, &mut shape);
+        shape
+    }
+
+    pub(crate) fn to_tree_node(
+        &self,
+        virtual_path: Option<&str>,
+        options: &TreeOptions,
+    ) -> Result<TreeNode> {
+        match virtual_path {
+            Some(path) => match self.root.get(path)? {
+                FSObject::File(f) => f.to_tree_node(&self.db, options),
+                FSObject::Folder(f) => f.to_tree_node(&self.db, 0, options),
+            },
+            None => self.root.to_tree_node(&self.db, 0, options),
+        }
+    }
+
+    pub(crate) fn files(&self) -> Vec<(String, &File)> {
+        // Every file in the tree, along with its virtual path
+        let mut files = Vec::new();
+        self.root.walk_files("", &mut files);
+        files
+    }
+
+    pub(crate) fn child_counts(
+        &self,
+        virtual_path: Option<&str>,
+    ) -> Result<HashMap<String, usize>> {
+        // How many direct children each immediate subfolder has
+        let folder = self.folder(virtual_path)?;
+        let counts = folder
+            .children
+            .iter()
+            .filter_map(|(name, child)| match child {
+                FSObject::Folder(f) => Some((name.clone(), f.children.len())),
+                FSObject::File(_) => None,
+            })
+            .collect();
+        Ok(counts)
+    }
+
+    pub(crate) fn files_by_child(
+        &self,
+        virtual_path: Option<&str>,
+    ) -> Result<HashMap<String, Vec<&File>>> {
+        // Every file below a folder, grouped by the immediate subfolder it is under.
+        // Files sitting directly in the folder are grouped under `.`
+        let folder = self.folder(virtual_path)?;
+        let mut groups: HashMap<String, Vec<&File>> = HashMap::new();
+        for (name, child) in folder.children.iter() {
+            match child {
+                FSObject::File(f) => groups.entry(".".to_string()).or_default().push(f),
+                FSObject::Folder(f) => {
+                    let mut files = Vec::new();
+                    f.walk_files(name, &mut files);
+                    groups.insert(name.clone(), files.into_iter().map(|(_, f)| f).collect());
+                }
+            }
+        }
+        Ok(groups)
+    }
+
+    pub(crate) fn files_under(&self, virtual_path: &str) -> Result<Vec<(String, &File)>> {
+        // Every file below a folder, along with its virtual path
+        match self.root.get(virtual_path)? {
+            FSObject::File(_) => Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Path `{}` is a file", virtual_path),
+            )),
+            FSObject::Folder(f) => {
+                let mut files = Vec::new();
+                f.walk_files(virtual_path, &mut files);
+                Ok(files)
+            }
+        }
+    }
+
+    pub(crate) fn get_folder_metadata(
+        &self,
+        virtual_path: Option<&str>,
+    ) -> Result<&HashMap<String, String>> {
+        let folder = match virtual_path {
+            Some(path) => match self.root.get(path)? {
+                FSObject::File(_) => {
+                    tracing::info!("Path is a file!");
+                    return Err(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        format!("Path `{}` is a file", path),
+                    ));
+                }
+                FSObject::Folder(f) => f,
+            },
+            None => &self.root,
+        };
+        Ok(&folder.metadata)
+    }
+
+    pub(crate) fn set_folder_metadata(
+        &mut self,
+        virtual_path: Option<&str>,
+        metadata: HashMap<String, String>,
+    ) -> Result<()> {
+        let folder = self.folder_mut(virtual_path)?;
+        folder.metadata = metadata;
+        folder._modified = true;
+        self._modified = true;
+        self.save_recorded(
+            Operation::SetFolderMetadata,
+            vec![virtual_path.unwrap_or("").to_string()],
+        )
+    }
+
+    pub(crate) fn system_value(&self, key: &str) -> Option<&str> {
+        self.root.system_metadata.get(key).map(|v| v.as_str())
+    }
+
+    pub(crate) fn set_system_value(&mut self, key: &str, value: Option<String>) -> Result<()> {
+        match value {
+            Some(value) => self.root.system_metadata.insert(key.to_string(), value),
+            None => self.root.system_metadata.remove(key),
+        };
+        self.root._modified = true;
+        self._modified = true;
+        self.save()
+    }
+
+    pub(crate) fn cache_manifest_hash(&mut self, hash: String) -> Result<()> {
+        // Storing the hash isn't a change to the tree, so unlike set_system_value this
+        // doesn't count as a modification (which would throw the hash away again)
+        self.root
+            .system_metadata
+            .insert(MANIFEST_HASH_KEY.to_string(), hash);
+        self.root._modified = true;
+        self.save()
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn relink(&mut self, virtual_path: &str, new_real_path: PathBuf) -> Result<()> {
+        // Point an existing file at a new location, leaving everything else untouched
+        let file = self.file_mut(virtual_path)?;
+        file.real_path = new_real_path;
+        file.modified_at = Some(Utc::now().timestamp());
+        self._modified = true;
+        self.save_recorded(Operation::Relink, vec![virtual_path.to_string()])
+    }
+
+    #[instrument(skip(self, rewrite))]
+    pub(crate) fn rewrite_paths<F>(&mut self, rewrite: F) -> Result<usize>
+    where
+        F: Fn(&Path) -> PathBuf,
+    {
+        // Apply a transformation to every file's real path, returning how many changed
+        let count = self.root.rewrite_paths(&rewrite);
+        if count > 0 {
+            self._modified = true;
+            self.save_recorded(Operation::RewritePaths, Vec::new())?;
+        }
+        Ok(count)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn touch(&mut self, virtual_path: &str, timestamp: i64) -> Result<()> {
+        // Reading a file isn't a change to the project, so this doesn't count towards
+        // its updated time. The parent folder is left marked and written out with the
+        // next save or flush, rather than on every read.
+        let file = self.file_mut(virtual_path)?;
+        file.accessed_at = Some(timestamp);
+        Ok(())
+    }
+
+    pub(crate) fn uses_external_metadata(&self) -> bool {
+        self.system_value(EXTERNAL_METADATA_KEY) == Some("true")
+    }
+
+    pub(crate) fn file_metadata(&self, file: &File) -> Result<HashMap<String, String>> {
+        load_file_metadata(&self.db, file)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn set_external_metadata(&mut self, enabled: bool) -> Result<usize> {
+        // Move every file's metadata in or out of the folder records, returning how
+        // many files were migrated.
+        let db = &self.db;
+        let mut batch = Batch::default();
+        let mut count = 0;
+        self.root.for_each_file_mut(&mut |file: &mut File| {
+            if enabled && !file._external_metadata && !file.metadata.is_empty() {
+                let key = metadata_key(&file._uuid);
+                batch.insert(key.as_bytes(), encode_metadata(&file.metadata)?);
+                file.metadata.clear();
+                file._external_metadata = true;
+                count += 1;
+            } else if !enabled && file._external_metadata {
+                let key = metadata_key(&file._uuid);
+                if let Some(bytes) = db.get(key.as_bytes())? {
+                    file.metadata = decode_metadata(&bytes)?;
+                }
+                batch.remove(key.as_bytes());
+                file._external_metadata = false;
+                count += 1;
+            }
+            Ok(())
+        })?;
+        let value = enabled.then(|| "true".to_string());
+        match value {
+            Some(value) => self
+                .root
+                .system_metadata
+                .insert(EXTERNAL_METADATA_KEY.to_string(), value),
+            None => self.root.system_metadata.remove(EXTERNAL_METADATA_KEY),
+        };
+        self.root._modified = true;
+        self._modified = true;
+        if enabled {
+            // Blobs go in before the folders stop carrying the metadata
+            self.db.apply_batch(batch)?;
+            self.save()?;
+        } else {
+            self.save()?;
+            self.db.apply_batch(batch)?;
+        }
+        Ok(count)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn set_checksums(&mut self, checksums: Vec<(String, String)>) -> Result<()> {
+        // Everything is saved in one go, so backfilling a large project stays cheap
+        for (virtual_path, checksum) in checksums {
+            let file = self.file_mut(&virtual_path)?;
+            file.checksum = Some(checksum);
+        }
+        self._modified = true;
+        self.save()
+    }
+
+    #[instrument(skip(self, metadata))]
+    pub(crate) fn set_file_metadata(
+        &mut self,
+        virtual_path: &str,
+        metadata: HashMap<String, String>,
+    ) -> Result<()> {
+        self.set_files_metadata(vec![(virtual_path.to_string(), metadata)])
+    }
+
+    #[instrument(skip(self, updates))]
+    pub(crate) fn set_files_metadata(
+        &mut self,
+        updates: Vec<(String, HashMap<String, String>)>,
+    ) -> Result<()> {
+        // Replace the metadata on each file, saving everything in one go
+        let external = self.uses_external_metadata();
+        let now = Utc::now().timestamp();
+        let mut batch = self.new_batch();
+        let mut paths = Vec::new();
+        for (virtual_path, metadata) in updates {
+            let file = self.file_mut(&virtual_path)?;
+            if file._external_metadata || (external && !metadata.is_empty()) {
+                batch.insert(
+                    metadata_key(&file._uuid).as_bytes(),
+                    encode_metadata(&metadata)?,
+                );
+                file._external_metadata = true;
+            } else {
+                file.metadata = metadata;
+            }
+            file.modified_at = Some(now);
+            paths.push(virtual_path);
+        }
+        self._modified = true;
+        self.commit(batch, Some(LogEntry::new(Operation::SetMetadata, paths)))
+    }
+
+    #[instrument(skip(self, update))]
+    pub(crate) fn update_file_metadata<F>(&mut self, mut update: F) -> Result<usize>
+    where
+        F: FnMut(&mut HashMap<String, String>) -> bool,
+    {
+        // Run `update` over every file's metadata, where it returns whether it changed
+        // anything. Changed files are all written out together, and the number of them
+        // is returned.
+        let mut batch = self.new_batch();
+        let db = &self.db;
+        let mut count = 0;
+        let now = Utc::now().timestamp();
+        self.root.for_each_file_mut(&mut |file: &mut File| {
+            let changed = if file._external_metadata {
+                let mut metadata = load_file_metadata(db, file)?;
+                let changed = update(&mut metadata);
+                if changed {
+                    let key = metadata_key(&file._uuid);
+                    batch.insert(key.as_bytes(), encode_metadata(&metadata)?);
+                }
+                changed
+            } else {
+                update(&mut file.metadata)
+            };
+            if changed {
+                file.modified_at = Some(now);
+                count += 1;
+            }
+            Ok(())
+        })?;
+        self._modified |= count > 0;
+        let entry = (count > 0).then(|| LogEntry::new(Operation::UpdateMetadata, Vec::new()));
+        self.commit(batch, entry)?;
+        Ok(count)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn set_preview(
+        &mut self,
+        virtual_path: &str,
+        preview: Option<String>,
+    ) -> Result<()> {
+        let file = self.file_mut(virtual_path)?;
+        file.preview = preview;
+        self._modified = true;
+        self.save_recorded(Operation::SetPreview, vec![virtual_path.to_string()])
+    }
+
+    fn history_rev(&self) -> impl Iterator<Item = Result<(u64, LogEntry)>> {
+        // Every entry in the history with its id, newest first
+        self.db
+            .scan_prefix("history/".as_bytes())
+            .rev()
+            .map(|item| {
+                let (key, bytes) = item?;
+                let id = String::from_utf8_lossy(&key)
+                    .trim_start_matches("history/")
+                    .parse::<u64>();
+                let entry = from_reader(bytes.as_ref());
+                match (id, entry) {
+                    (Ok(id), Ok(entry)) => Ok((id, entry)),
+                    _ => Err(GodataError::new(
+                        GodataErrorType::IOError,
+                        format!(
+                            "Failed to read history entry `{}`",
+                            String::from_utf8_lossy(&key)
+                        ),
+                    )),
+                }
+            })
+    }
+
+    pub(crate) fn history(&self, limit: usize) -> Result<Vec<LogEntry>> {
+        // The most recent entries, oldest first
+        let mut entries = self
+            .history_rev()
+            .take(limit)
+            .map(|item| item.map(|(_, entry)| entry))
+            .collect::<Result<Vec<LogEntry>>>()?;
+        entries.reverse();
+        Ok(entries)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn undo(&mut self, count: usize) -> Result<Vec<String>> {
+        // Reverse the last `count` operations that haven't been undone already, newest
+        // first. The reversals are staged in one batch and written together with a
+        // single undo entry, so undoing again keeps going further back. If any of them
+        // fails the tree is reloaded and neither the database nor the history changes.
+        let mut undone = HashSet::new();
+        let mut targets = Vec::new();
+        for item in self.history_rev() {
+            if targets.len() == count {
+                break;
+            }
+            let (id, entry) = item?;
+            if entry.op == Operation::Undo {
+                undone.extend(entry.undone.iter().copied());
+                continue;
+            }
+            if undone.contains(&id) {
+                continue;
+            }
+            if let Some(reason) = entry.irreversible() {
+                return Err(GodataError::new(
+                    GodataErrorType::NotPermitted,
+                    format!("Can't undo `{}`, {}", entry.describe(), reason),
+                ));
+            }
+            targets.push((id, entry));
+        }
+        if targets.len() < count {
+            return Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!(
+                    "Only {} operations in the history can be undone",
+                    targets.len()
+                ),
+            ));
+        }
+
+        // Anything pending goes out first, so it isn't caught up in the undo
+        self.save()?;
+        self._staged = Some(Batch::default());
+        let mut result = targets
+            .iter()
+            .try_for_each(|(_, entry)| self.reverse(entry));
+        let batch = self._staged.take().unwrap_or_default();
+        if result.is_ok() {
+            let mut entry = LogEntry::new(
+                Operation::Undo,
+                targets
+                    .iter()
+                    .flat_map(|(_, e)| e.paths.iter().cloned())
+                    .collect(),
+            );
+            entry.undone = targets.iter().map(|(id, _)| *id).collect();
+            result = self.commit(batch, Some(entry));
+        }
+        if let Err(e) = result {
+            self.reload()?;
+            return Err(e);
+        }
+        Ok(targets.iter().map(|(_, e)| e.describe()).collect())
+    }
+
+    fn reverse(&mut self, entry: &LogEntry) -> Result<()> {
+        match entry.op {
+            // Linked files are only unlinked, anything in the project's storage is left
+            // for garbage collection
+            Operation::Link | Operation::LinkFolder | Operation::Reference => {
+                for (_, removed) in self.remove_many(&entry.paths)? {
+                    removed?;
+                }
+                Ok(())
             }
-            RemoveResult::Item(f) => match f {
-                FSObject::File(f) => {
-                    vec![f]
+            Operation::CreateFolder => {
+                let path = &entry.paths[0];
+                if !self.folder(Some(path))?.children.is_empty() {
+                    return Err(GodataError::new(
+                        GodataErrorType::NotPermitted,
+                        format!("Can't undo `{}`, it's no longer empty", entry.describe()),
+                    ));
                 }
-                FSObject::Folder(mut f) => {
-                    f.drop_from_tree(&mut batch)?;
-                    drain(f)
+                self.remove(path).map(|_| ())
+            }
+            Operation::Move => self
+                .move_(&entry.paths[1], &entry.paths[0], false)
+                .map(|_| ()),
+            Operation::Rename => {
+                let old_name = entry.paths[0].rsplit('/').next().unwrap();
+                self.rename(&entry.paths[1], old_name)
+            }
+            Operation::Swap => self.swap(&entry.paths[0], &entry.paths[1]),
+            _ => Err(GodataError::new(
+                GodataErrorType::InternalError,
+                format!("Can't undo `{}`", entry.describe()),
+            )),
+        }
+    }
+
+    fn folder(&self, virtual_path: Option<&str>) -> Result<&Folder> {
+        match virtual_path {
+            Some(path) => match self.root.get(path)? {
+                FSObject::File(_) => {
+                    tracing::info!("Path is a file!");
+                    Err(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        format!("Path `{}` is a file", path),
+                    ))
                 }
+                FSObject::Folder(f) => Ok(f),
             },
-        };
-        self.db.apply_batch(batch)?;
-        self._modified = true;
-
-        Ok(output)
+            None => Ok(&self.root),
+        }
     }
 
-    #[instrument(skip(self))]
-    pub(crate) fn move_(
-        &mut self,
-        source_path: &str,
-        dest_path: &str,
-        overwrite: bool,
-    ) -> Result<Option<Vec<File>>> {
-        if !self.root.exists(source_path) {
-            tracing::info!("Source path does not exist");
-            return Err(GodataError::new(
-                GodataErrorType::NotFound,
-                format!("Source path `{}` does not exist", source_path),
-            ));
-        }
-        if self.root.exists(dest_path) && !overwrite {
-            tracing::info!("Destination path already exists");
-            return Err(GodataError::new(
-                GodataErrorType::AlreadyExists,
-                format!("Destination path `{}` already exists", dest_path),
-            ));
+    fn folder_mut(&mut self, virtual_path: Option<&str>) -> Result<&mut Folder> {
+        match virtual_path {
+            Some(path) => match self.root.get_mut(path)? {
+                FSObject::File(_) => {
+                    tracing::info!("Path is a file!");
+                    Err(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        format!("Path `{}` is a file", path),
+                    ))
+                }
+                FSObject::Folder(f) => Ok(f),
+            },
+            None => Ok(&mut self.root),
         }
-        let item = self.root.get(source_path)?;
-        // HANDLE RENAME SEMANTICS
-        // make a copy of the item
-        let (fpath, fname) = dest_path.rsplit_once('/').unwrap_or(("", dest_path));
-        let mut item = (*item).clone();
-        item.rename(fname.to_string());
-        // Split the destination path into path and name
-
-        let result = self.root.insert(item, fpath, overwrite)?;
-        self.remove(source_path)?;
-        self._modified = true;
-        self.save()?;
-        Ok(result)
     }
 
-    pub(crate) fn exists(&self, virtual_path: &str) -> bool {
-        self.root.exists(virtual_path)
+    fn file_mut(&mut self, virtual_path: &str) -> Result<&mut File> {
+        // Files are stored as part of their parent folder, so the parent is marked as
+        // modified to make sure the change gets written out.
+        let (parent, name) = match virtual_path.rsplit_once('/') {
+            Some((parent, name)) => (Some(parent), name),
+            None => (None, virtual_path),
+        };
+        let folder = self.folder_mut(parent)?;
+        match folder.children.get_mut(name) {
+            Some(FSObject::File(f)) => {
+                folder._modified = true;
+                Ok(f)
+            }
+            Some(FSObject::Folder(_)) => {
+                tracing::info!("Path is a folder!");
+                Err(GodataError::new(
+                    GodataErrorType::InvalidPath,
+                    "Path is a folder".into(),
+                ))
+            }
+            None => {
+                let msg = format!(
+                    "Child `{}` does not exist in folder `{}`",
+                    name, folder.name
+                );
+                tracing::info!(msg);
+                Err(GodataError::new(GodataErrorType::NotFound, msg))
+            }
+        }
     }
 
     #[instrument(skip(self))]
     fn save(&mut self) -> Result<()> {
-        // Write the root folder to the database
+        let batch = self.new_batch();
+        self.commit(batch, None)
+    }
+
+    fn save_recorded(&mut self, op: Operation, paths: Vec<String>) -> Result<()> {
+        let batch = self.new_batch();
+        self.commit(batch, Some(LogEntry::new(op, paths)))
+    }
+
+    fn new_batch(&mut self) -> Batch {
+        // Changes made during an undo carry on from the ones staged before them
+        match &mut self._staged {
+            Some(staged) => std::mem::take(staged),
+            None => Batch::default(),
+        }
+    }
+
+    fn commit(&mut self, mut batch: Batch, entry: Option<LogEntry>) -> Result<()> {
+        // Write the root folder to the database along with the batch of changes that
+        // went with it, and the history entry describing them. Sled's ids only ever go
+        // up, even across restarts, so the entries stay in the order they were written.
+        // During an undo the batch is staged instead, and nothing is recorded.
         tracing::info!("Saving filesystem for project `{}`", self._name);
-        let mut batch = Batch::default();
-        self.root.write_to_tree(&mut batch)?;
-        self.db.apply_batch(batch)?;
+        if self._modified {
+            self._generation += 1;
+            self.root.system_metadata.remove(MANIFEST_HASH_KEY);
+            self.root
+                .system_metadata
+                .insert(UPDATED_KEY.to_string(), Utc::now().timestamp().to_string());
+            self.root._modified = true;
+        }
+        if let Some(entry) = entry.filter(|_| self._staged.is_none()) {
+            let mut bytes = Vec::new();
+            into_writer(&entry, &mut bytes).map_err(|e| {
+                GodataError::new(
+                    GodataErrorType::IOError,
+                    format!("Failed to serialize history entry: {}", e),
+                )
+            })?;
+            batch.insert(history_key(self.db.generate_id()?).as_bytes(), bytes);
+        }
+        self.root.write_to_tree(&mut batch, "")?;
+        match &mut self._staged {
+            Some(staged) => *staged = batch,
+            None => self.db.apply_batch(batch)?,
+        }
         self.root.reset();
         self._modified = false;
         Ok(())
@@ -451,16 +1904,48 @@ impl Drop for FileSystem {
 }
 
 impl Folder {
+    fn new_root() -> Folder {
+        Folder {
+            name: "root".to_string(),
+            children: HashMap::new(),
+            metadata: HashMap::new(),
+            system_metadata: HashMap::from([(
+                CREATED_KEY.to_string(),
+                Utc::now().timestamp().to_string(),
+            )]),
+            _uuid: "root".to_string(),
+            _modified: true,
+        }
+    }
+
     fn new(name: String) -> Folder {
         Folder {
             name,
             children: HashMap::new(),
             metadata: HashMap::new(),
+            system_metadata: HashMap::new(),
             _uuid: Uuid::new_v4().to_string(),
             _modified: true,
         }
     }
 
+    fn mark_modified(&mut self) {
+        self._modified = true;
+        for (_, child) in self.children.iter_mut() {
+            if let FSObject::Folder(f) = child {
+                f.mark_modified();
+            }
+        }
+    }
+
+    fn has_modified(&self) -> bool {
+        self._modified
+            || self.children.values().any(|child| match child {
+                FSObject::Folder(f) => f.has_modified(),
+                FSObject::File(_) => false,
+            })
+    }
+
     fn reset(&mut self) {
         self._modified = false;
         for (_, child) in self.children.iter_mut() {
@@ -503,24 +1988,38 @@ impl Folder {
             children.insert(file.name.clone(), FSObject::File(File::from_db_file(file)));
         }
 
+        // Trees written before system metadata had its own place kept it in the user
+        // metadata, so it gets moved over (and written back on the next save)
+        let (legacy, metadata): (HashMap<_, _>, HashMap<_, _>) = db_folder
+            .metadata
+            .into_iter()
+            .partition(|(k, _)| is_reserved_key(k));
+        let migrated = !legacy.is_empty();
+        let mut system_metadata = db_folder.system_metadata;
+        for (key, value) in legacy {
+            system_metadata.entry(key).or_insert(value);
+        }
+
         Ok(Folder {
             name: db_folder.name,
             children,
-            metadata: db_folder.metadata,
+            metadata,
+            system_metadata,
             _uuid: uuid,
-            _modified: false,
+            _modified: migrated,
         })
     }
 
-    fn write_to_tree(&mut self, batch: &mut Batch) -> Result<()> {
-        // Write the folder and all of its children to the database
+    fn write_to_tree(&mut self, batch: &mut Batch, path: &str) -> Result<()> {
+        // Write the folder and all of its children to the database. `path` is where
+        // the folder sits in the tree, which is empty for the root.
         if self._modified {
-            self.write_to_db(batch)?;
+            self.write_to_db(batch, path)?;
         }
-        for (_, child) in self.children.iter_mut() {
+        for (name, child) in self.children.iter_mut() {
             match child {
                 FSObject::File(_) => (),
-                FSObject::Folder(f) => f.write_to_tree(batch)?,
+                FSObject::Folder(f) => f.write_to_tree(batch, &join_path(path, name))?,
             }
         }
         Ok(())
@@ -546,6 +2045,31 @@ impl Folder {
         self._insert_many(files, path_parts)
     }
 
+    fn check_insert_many(&self, path_parts: &[&str], names: &[&str]) -> Result<()> {
+        // Make sure insert_many would go through without changing anything
+        match path_parts.split_first() {
+            None => match names
+                .iter()
+                .find(|name| matches!(self.children.get(**name), Some(FSObject::Folder(_))))
+            {
+                Some(name) => Err(GodataError::new(
+                    GodataErrorType::InvalidPath,
+                    format!("Cannot replace folder `{}` with a file", name),
+                )),
+                None => Ok(()),
+            },
+            Some((part, rest)) => match self.children.get(*part) {
+                Some(FSObject::File(_)) => Err(GodataError::new(
+                    GodataErrorType::InvalidPath,
+                    format!("`{}` is a file", part),
+                )),
+                Some(FSObject::Folder(f)) => f.check_insert_many(rest, names),
+                // Folders that don't exist yet will be created empty
+                None => Ok(()),
+            },
+        }
+    }
+
     fn _insert_many<I>(&mut self, files: I, mut path_parts: std::str::Split<char>) -> Result<()>
     where
         I: Iterator<Item = File>,
@@ -608,10 +2132,11 @@ impl Folder {
             folders_uuids,
             files,
             metadata: self.metadata.clone(),
+            system_metadata: self.system_metadata.clone(),
         }
     }
 
-    fn write_to_db(&mut self, batch: &mut Batch) -> Result<()> {
+    fn write_to_db(&mut self, batch: &mut Batch, path: &str) -> Result<()> {
         let db_folder = self.to_db_folder();
         let mut bytes = Vec::new();
         let write_result = into_writer(&db_folder, &mut bytes);
@@ -627,6 +2152,14 @@ impl Folder {
             ));
         }
         batch.insert(self._uuid.as_bytes(), bytes);
+        for (name, child) in self.children.iter() {
+            if let FSObject::File(f) = child {
+                batch.insert(
+                    path_key(&f._uuid).as_bytes(),
+                    join_path(path, name).as_bytes(),
+                );
+            }
+        }
         Ok(())
     }
 
@@ -715,7 +2248,8 @@ impl Folder {
                         self.name
                     );
                     tracing::info!(msg);
-                    return Err(GodataError::new(GodataErrorType::NotFound, msg));
+                    // The path is malformed rather than missing, since a file can't have children
+                    return Err(GodataError::new(GodataErrorType::InvalidPath, msg));
                 }
                 FSObject::Folder(f) => {
                     return f._get(&path_parts[1..]);
@@ -724,6 +2258,149 @@ impl Folder {
         }
     }
 
+    fn get_mut(&mut self, virtual_path: &str) -> Result<&mut FSObject> {
+        let path: Vec<&str> = virtual_path.split('/').collect();
+        let result = self._get_mut(&path);
+        if result.is_err() {
+            let mut err = result.err().unwrap();
+            err.message = format!("Failed to get path `{}`: {}", virtual_path, err.message);
+            return Err(err);
+        }
+        result
+    }
+
+    fn _get_mut(&mut self, path_parts: &[&str]) -> Result<&mut FSObject> {
+        let part = match path_parts.first() {
+            None => {
+                tracing::error!("Path part is none!");
+                return Err(GodataError::new(
+                    GodataErrorType::InternalError,
+                    "Invalid path part".to_string(),
+                ));
+            }
+            Some(&part) => part,
+        };
+        let folder_name = self.name.clone();
+        let child = match self.children.get_mut(part) {
+            None => {
+                let msg = format!(
+                    "Child `{}` does not exist in folder `{}`",
+                    part, folder_name
+                );
+                tracing::info!(msg);
+                return Err(GodataError::new(GodataErrorType::NotFound, msg));
+            }
+            Some(child) => child,
+        };
+        if path_parts.len() == 1 {
+            return Ok(child);
+        }
+        match child {
+            FSObject::File(_) => {
+                let msg = format!("Child `{}` of folder `{}` is a file", part, folder_name);
+                tracing::info!(msg);
+                Err(GodataError::new(GodataErrorType::InvalidPath, msg))
+            }
+            FSObject::Folder(f) => f._get_mut(&path_parts[1..]),
+        }
+    }
+
+    fn rewrite_paths(&mut self, rewrite: &dyn Fn(&Path) -> PathBuf) -> usize {
+        let mut count = 0;
+        for child in self.children.values_mut() {
+            match child {
+                FSObject::File(f) => {
+                    let new_path = rewrite(&f.real_path);
+                    if new_path != f.real_path {
+                        f.real_path = new_path;
+                        self._modified = true;
+                        count += 1;
+                    }
+                }
+                FSObject::Folder(f) => count += f.rewrite_paths(rewrite),
+            }
+        }
+        count
+    }
+
+    fn for_each_file_mut(&mut self, f: &mut dyn FnMut(&mut File) -> Result<()>) -> Result<()> {
+        for child in self.children.values_mut() {
+            match child {
+                FSObject::File(file) => {
+                    f(file)?;
+                    self._modified = true;
+                }
+                FSObject::Folder(folder) => folder.for_each_file_mut(f)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn summarize<'a>(&'a self, depth: usize, summary: &mut TreeSummary<'a>) {
+        for child in self.children.values() {
+            summary.max_depth = summary.max_depth.max(depth);
+            match child {
+                FSObject::File(f) => summary.files.push(f),
+                FSObject::Folder(f) => {
+                    summary.folders += 1;
+                    f.summarize(depth + 1, summary);
+                }
+            }
+        }
+    }
+
+    fn measure(&self, path: &str, depth: usize, shape: &mut TreeShape) {
+        if self.children.len() > shape.widest_folder.children {
+            shape.widest_folder = FolderWidth {
+                path: path.to_string(),
+                children: self.children.len(),
+            };
+        }
+        for (name, child) in self.children.iter() {
+            shape.max_depth = shape.max_depth.max(depth);
+            if let FSObject::Folder(f) = child {
+                let child_path = match path {
+                    "" => name.clone(),
+                    _ => format!("{}/{}", path, name),
+                };
+                f.measure(&child_path, depth + 1, shape);
+            }
+        }
+    }
+
+    fn to_tree_node(&self, db: &Db, depth: usize, options: &TreeOptions) -> Result<TreeNode> {
+        let children = match options.max_depth {
+            Some(max_depth) if depth >= max_depth => None,
+            _ => {
+                let mut children = self
+                    .children
+                    .values()
+                    .map(|child| match child {
+                        FSObject::File(f) => f.to_tree_node(db, options),
+                        FSObject::Folder(f) => f.to_tree_node(db, depth + 1, options),
+                    })
+                    .collect::<Result<Vec<TreeNode>>>()?;
+                children.sort_by(|a, b| a.name().cmp(b.name()));
+                Some(children)
+            }
+        };
+        Ok(TreeNode::Folder {
+            name: self.name.clone(),
+            metadata: options.with_metadata.then(|| self.metadata.clone()),
+            children,
+        })
+    }
+
+    fn walk_files<'a>(&'a self, prefix: &str, files: &mut Vec<(String, &'a File)>) {
+        for (name, child) in self.children.iter() {
+            let path = join_path(prefix, name);
+            match child {
+                FSObject::File(f) => files.push((path, f)),
+                FSObject::Folder(f) => f.walk_files(&path, files),
+            }
+        }
+    }
+
     fn search_files(&self, pattern: &regex::Regex) -> Option<Vec<&File>> {
         let file_matches = self.children.values().filter_map(|child| {
             match child {
@@ -898,9 +2575,9 @@ impl Folder {
             if self.children.len() == 1 {
                 return Ok(RemoveResult::IsEmpty);
             }
-            return Ok(RemoveResult::Item(
+            return Ok(RemoveResult::Item(Box::new(
                 self.children.remove(*path_part).unwrap(),
-            ));
+            )));
         }
         match self.children.get_mut(*path_part).unwrap() {
             FSObject::File(_) => {
@@ -915,9 +2592,10 @@ impl Folder {
                         if self.children.len() == 1 {
                             return Ok(RemoveResult::IsEmpty);
                         }
-                        Ok(RemoveResult::Item(
+                        self._modified = true;
+                        Ok(RemoveResult::Item(Box::new(
                             self.children.remove(*path_part).unwrap(),
-                        ))
+                        )))
                     }
                     RemoveResult::Item(_) => Ok(rm_result),
                 }
@@ -936,6 +2614,13 @@ impl File {
             real_path,
             name,
             metadata: HashMap::new(),
+            origin: None,
+            accessed_at: None,
+            modified_at: Some(Utc::now().timestamp()),
+            checksum: None,
+            preview: None,
+            reference: None,
+            _external_metadata: false,
             _uuid: Uuid::new_v4().to_string(),
         }
     }
@@ -943,11 +2628,37 @@ impl File {
         &self.name
     }
 
+    pub(crate) fn uuid(&self) -> &str {
+        &self._uuid
+    }
+
+    fn to_tree_node(&self, db: &Db, options: &TreeOptions) -> Result<TreeNode> {
+        let metadata = match options.with_metadata {
+            true => Some(load_file_metadata(db, self)?),
+            false => None,
+        };
+        Ok(TreeNode::File {
+            name: self.name.clone(),
+            real_path: options
+                .resolve
+                .map(|resolve| resolve(&self.real_path).to_string_lossy().to_string()),
+            origin: options.resolve.and(self.origin.clone()),
+            metadata,
+        })
+    }
+
     fn to_db_file(&self) -> DbFile {
         DbFile {
             name: self.name.clone(),
             real_path: self.real_path.to_str().unwrap().to_string(),
             metadata: self.metadata.clone(),
+            origin: self.origin.clone(),
+            accessed_at: self.accessed_at,
+            modified_at: self.modified_at,
+            checksum: self.checksum.clone(),
+            preview: self.preview.clone(),
+            reference: self.reference.clone(),
+            external_metadata: self._external_metadata,
             uuid: self._uuid.clone(),
         }
     }
@@ -957,7 +2668,410 @@ impl File {
             name: db_file.name,
             real_path: PathBuf::from(db_file.real_path),
             metadata: db_file.metadata,
+            origin: db_file.origin,
+            accessed_at: db_file.accessed_at,
+            modified_at: db_file.modified_at,
+            checksum: db_file.checksum,
+            preview: db_file.preview,
+            reference: db_file.reference,
+            _external_metadata: db_file.external_metadata,
             _uuid: db_file.uuid,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_type<T>(result: Result<T>) -> GodataErrorType {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.error_type,
+        }
+    }
+
+    fn tree_with_file() -> FileSystem {
+        let mut tree = FileSystem::temporary("test".to_string()).unwrap();
+        tree.insert(
+            "data/a.txt",
+            PathBuf::from("/data/a.txt"),
+            HashMap::new(),
+            None,
+            false,
+        )
+        .unwrap();
+        tree
+    }
+
+    #[test]
+    fn missing_and_blocked_paths_are_different_errors() {
+        let mut tree = tree_with_file();
+        // Nothing at the path
+        assert_eq!(
+            error_type(tree.get("data/b.txt")),
+            GodataErrorType::NotFound
+        );
+        assert_eq!(
+            error_type(tree.get("other/b.txt")),
+            GodataErrorType::NotFound
+        );
+        assert_eq!(
+            error_type(tree.remove("data/b.txt")),
+            GodataErrorType::NotFound
+        );
+        // A file where the path needs a folder
+        assert_eq!(
+            error_type(tree.get("data/a.txt/b.txt")),
+            GodataErrorType::InvalidPath
+        );
+        assert_eq!(
+            error_type(tree.remove("data/a.txt/b.txt")),
+            GodataErrorType::InvalidPath
+        );
+        // A folder where get needs a file
+        assert_eq!(error_type(tree.get("data")), GodataErrorType::InvalidPath);
+        assert!(!tree.exists("data/b.txt"));
+        assert!(!tree.exists("data/a.txt/b.txt"));
+        assert!(tree.exists("data/a.txt"));
+        assert!(tree.exists("data"));
+    }
+
+    #[test]
+    fn shape_of_a_lopsided_tree() {
+        let mut tree = tree_with_file();
+        let link = |tree: &mut FileSystem, path: &str| {
+            tree.insert(path, PathBuf::from("/x"), HashMap::new(), None, false)
+                .unwrap();
+        };
+        for i in 0..50 {
+            link(&mut tree, &format!("wide/file{}.txt", i));
+        }
+        link(&mut tree, "a/b/c/d/deep.txt");
+        let shape = tree.shape();
+        assert_eq!(shape.max_depth, 5);
+        assert_eq!(shape.widest_folder.path, "wide");
+        assert_eq!(shape.widest_folder.children, 50);
+    }
+
+    #[test]
+    fn child_counts_per_subfolder() {
+        let mut tree = tree_with_file();
+        for (folder, count) in [("big", 5), ("small", 1), ("mixed", 2)] {
+            for i in 0..count {
+                let path = format!("data/{}/file{}.txt", folder, i);
+                tree.insert(&path, PathBuf::from("/x"), HashMap::new(), None, false)
+                    .unwrap();
+            }
+        }
+        tree.insert(
+            "data/mixed/nested/deep.txt",
+            PathBuf::from("/x"),
+            HashMap::new(),
+            None,
+            false,
+        )
+        .unwrap();
+        let counts = tree.child_counts(Some("data")).unwrap();
+        // Files directly in the folder aren't counted, and nested files only count
+        // towards their own folder
+        assert_eq!(
+            counts,
+            HashMap::from([
+                ("big".to_string(), 5),
+                ("small".to_string(), 1),
+                ("mixed".to_string(), 3),
+            ])
+        );
+        assert_eq!(
+            tree.child_counts(None).unwrap(),
+            HashMap::from([("data".to_string(), 4)])
+        );
+    }
+
+    #[test]
+    fn external_metadata_round_trip() {
+        let mut tree = tree_with_file();
+        let header: HashMap<String, String> = (0..200)
+            .map(|i| (format!("KEY{}", i), "x".repeat(80)))
+            .collect();
+        tree.insert(
+            "data/image.fits",
+            PathBuf::from("/data/image.fits"),
+            header.clone(),
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(tree.set_external_metadata(true).unwrap(), 1);
+        assert!(tree.uses_external_metadata());
+        // Folders no longer carry the header, but it is still there when asked for
+        let file = tree.get("data/image.fits").unwrap();
+        assert!(file.metadata.is_empty());
+        assert_eq!(tree.file_metadata(file).unwrap(), header);
+        tree.reload().unwrap();
+        let file = tree.get("data/image.fits").unwrap();
+        assert_eq!(tree.file_metadata(file).unwrap(), header);
+
+        // Turning it off moves the header back inline
+        assert_eq!(tree.set_external_metadata(false).unwrap(), 1);
+        tree.reload().unwrap();
+        let file = tree.get("data/image.fits").unwrap();
+        assert_eq!(file.metadata, header);
+        assert_eq!(tree.file_metadata(file).unwrap(), header);
+    }
+
+    #[test]
+    fn failed_insert_many_changes_nothing() {
+        let mut tree = tree_with_file();
+        tree.insert(
+            "data/sub/c.txt",
+            PathBuf::from("/data/sub/c.txt"),
+            HashMap::new(),
+            None,
+            false,
+        )
+        .unwrap();
+        let batch = |names: &[&str]| {
+            names
+                .iter()
+                .map(|n| (PathBuf::from(n), PathBuf::from(format!("/new/{}", n))))
+                .collect::<Vec<_>>()
+                .into_iter()
+        };
+        // The target path runs through a file
+        assert_eq!(
+            error_type(tree.insert_many(batch(&["b.txt"]), "data/a.txt/deeper")),
+            GodataErrorType::InvalidPath
+        );
+        // One of the files would replace a folder
+        assert_eq!(
+            error_type(tree.insert_many(batch(&["b.txt", "sub"]), "data")),
+            GodataErrorType::InvalidPath
+        );
+        let paths = |tree: &FileSystem| {
+            let mut paths: Vec<String> = tree.files().into_iter().map(|(p, _)| p).collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(paths(&tree), vec!["data/a.txt", "data/sub/c.txt"]);
+        // Nor was anything half-done written out
+        tree.reload().unwrap();
+        assert_eq!(paths(&tree), vec!["data/a.txt", "data/sub/c.txt"]);
+    }
+
+    #[test]
+    fn detailed_listing_keeps_only_requested_fields() {
+        let mut tree = tree_with_file();
+        let metadata = HashMap::from([
+            ("band".to_string(), "r".to_string()),
+            ("exposure".to_string(), "30".to_string()),
+        ]);
+        tree.insert(
+            "data/b.txt",
+            PathBuf::from("/data/b.txt"),
+            metadata.clone(),
+            None,
+            false,
+        )
+        .unwrap();
+        tree.insert(
+            "data/sub/c.txt",
+            PathBuf::from("/data/sub/c.txt"),
+            HashMap::new(),
+            None,
+            false,
+        )
+        .unwrap();
+
+        let listing = tree.list_detailed(Some("data"), None, None).unwrap();
+        assert_eq!(listing.folders, vec!["sub"]);
+        assert_eq!(listing.files.len(), 2);
+        assert_eq!(listing.files["b.txt"], metadata);
+
+        let fields = vec!["band".to_string(), "absent".to_string()];
+        let listing = tree
+            .list_detailed(Some("data"), Some(ObjectKind::File), Some(&fields))
+            .unwrap();
+        assert!(listing.folders.is_empty());
+        assert_eq!(
+            listing.files["b.txt"],
+            HashMap::from([("band".to_string(), "r".to_string())])
+        );
+        assert!(listing.files["a.txt"].is_empty());
+    }
+
+    #[test]
+    fn failed_undo_changes_nothing() {
+        let mut tree = tree_with_file();
+        // An entry for a file that was never linked can't be reversed
+        let bogus = LogEntry::new(Operation::Link, vec!["data/gone.txt".to_string()]);
+        tree.commit(Batch::default(), Some(bogus)).unwrap();
+        tree.insert(
+            "data/b.txt",
+            PathBuf::from("/data/b.txt"),
+            HashMap::new(),
+            None,
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(error_type(tree.undo(2)), GodataErrorType::NotFound);
+        assert!(tree.exists("data/b.txt"));
+        tree.reload().unwrap();
+        assert!(tree.exists("data/b.txt"));
+        let history = tree.history(10).unwrap();
+        assert_eq!(history.len(), 3);
+        assert!(history.iter().all(|e| e.op == Operation::Link));
+
+        // The reversals that can go through still do
+        tree.undo(1).unwrap();
+        tree.reload().unwrap();
+        assert!(!tree.exists("data/b.txt"));
+        assert!(tree.exists("data/a.txt"));
+    }
+
+    #[test]
+    fn database_open_twice_is_locked() {
+        let path = crate::testing::scratch_dir().join("tree");
+        let tree = FileSystem::new("first".to_string(), path.clone()).unwrap();
+        let error = FileSystem::load("second", path).err().unwrap();
+        assert_eq!(error.error_type, GodataErrorType::Locked);
+        assert!(error.message.contains("open in another process"));
+        drop(tree);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_a_limit() {
+        let delays: Vec<u128> = backoff_delays(5, Duration::from_millis(20))
+            .map(|d| d.as_millis())
+            .collect();
+        assert_eq!(delays, vec![20, 40, 80, 160, 320]);
+        let delays: Vec<Duration> = backoff_delays(100, Duration::from_millis(300)).collect();
+        assert_eq!(delays.len(), 100);
+        assert!(delays[2..]
+            .iter()
+            .all(|d| *d == Duration::from_millis(DB_OPEN_MAX_BACKOFF_MS)));
+        assert!(backoff_delays(3, Duration::MAX)
+            .all(|d| d == Duration::from_millis(DB_OPEN_MAX_BACKOFF_MS)));
+    }
+
+    #[test]
+    fn legacy_description_moves_to_system_metadata() {
+        let mut tree = tree_with_file();
+        // Write the root back the way older versions kept it, with the description
+        // mixed in with the user's metadata
+        let stored = tree.db.get("root").unwrap().unwrap();
+        let mut db_folder: DbFolder = from_reader(stored.as_ref()).unwrap();
+        db_folder
+            .metadata
+            .insert("__description__".to_string(), "Old notes".to_string());
+        db_folder
+            .metadata
+            .insert("owner".to_string(), "me".to_string());
+        let mut bytes = Vec::new();
+        into_writer(&db_folder, &mut bytes).unwrap();
+        tree.db.insert("root", bytes).unwrap();
+
+        tree.reload().unwrap();
+        assert_eq!(tree.system_value("__description__"), Some("Old notes"));
+        let metadata = tree.get_folder_metadata(None).unwrap();
+        assert!(!metadata.contains_key("__description__"));
+        assert_eq!(metadata.get("owner").map(|v| v.as_str()), Some("me"));
+
+        // The move is written back, so the stored root no longer has the old key
+        assert!(tree.has_pending_changes());
+        tree.save().unwrap();
+        let stored = tree.db.get("root").unwrap().unwrap();
+        let db_folder: DbFolder = from_reader(stored.as_ref()).unwrap();
+        assert!(!db_folder.metadata.contains_key("__description__"));
+        assert_eq!(
+            db_folder
+                .system_metadata
+                .get("__description__")
+                .map(|v| v.as_str()),
+            Some("Old notes")
+        );
+        tree.reload().unwrap();
+        assert!(!tree.has_pending_changes());
+        assert_eq!(tree.system_value("__description__"), Some("Old notes"));
+    }
+
+    // A benchmark rather than a check, so it only runs when asked for:
+    // cargo test list_speed_with_inline_and_external_metadata -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn list_speed_with_inline_and_external_metadata() {
+        const FILES: usize = 500;
+        const ROUNDS: u32 = 20;
+        let mut tree = FileSystem::temporary("bench".to_string()).unwrap();
+        // Each file gets something the size of a full FITS header. Storing externally
+        // first keeps the setup from rewriting every header on each insert.
+        tree.set_external_metadata(true).unwrap();
+        let header: HashMap<String, String> = (0..300)
+            .map(|i| (format!("KEY{}", i), "x".repeat(60)))
+            .collect();
+        for i in 0..FILES {
+            tree.insert(
+                &format!("images/{}.fits", i),
+                PathBuf::from(format!("/data/{}.fits", i)),
+                header.clone(),
+                None,
+                false,
+            )
+            .unwrap();
+        }
+
+        let time_listing = |tree: &mut FileSystem| {
+            let start = std::time::Instant::now();
+            for _ in 0..ROUNDS {
+                tree.reload().unwrap();
+                let listing = tree.list(Some("images".to_string()), None).unwrap();
+                assert_eq!(listing["files"].len(), FILES);
+            }
+            start.elapsed() / ROUNDS
+        };
+        let external = time_listing(&mut tree);
+        assert_eq!(tree.set_external_metadata(false).unwrap(), FILES);
+        let inline = time_listing(&mut tree);
+        println!(
+            "Loading and listing {} files: {:?} inline, {:?} external",
+            FILES, inline, external
+        );
+    }
+
+    #[test]
+    fn disk_diff_reports_unsaved_changes() {
+        let mut tree = tree_with_file();
+        tree.insert(
+            "data/b.txt",
+            PathBuf::from("/data/b.txt"),
+            HashMap::new(),
+            None,
+            false,
+        )
+        .unwrap();
+        let diff = tree.disk_diff().unwrap();
+        assert!(!diff.pending);
+        assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.changed.is_empty());
+
+        // Change the tree in memory without saving it
+        tree.file_mut("data/a.txt").unwrap().real_path = PathBuf::from("/elsewhere/a.txt");
+        let data = tree.folder_mut(Some("data")).unwrap();
+        data.children.remove("b.txt");
+        data._modified = true;
+        data.children.insert(
+            "c.txt".to_string(),
+            FSObject::File(File::new(PathBuf::from("/data/c.txt"), "c.txt".to_string())),
+        );
+        let diff = tree.disk_diff().unwrap();
+        assert!(diff.pending);
+        assert_eq!(diff.added, vec!["data/c.txt"]);
+        assert_eq!(diff.removed, vec!["data/b.txt"]);
+        assert_eq!(diff.changed, vec!["data/a.txt"]);
+
+        // Reloading throws the changes away
+        tree.reload().unwrap();
+        assert!(!tree.disk_diff().unwrap().pending);
+    }
+}
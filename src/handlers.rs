@@ -1,6 +1,13 @@
+use crate::archive::Compression;
+use crate::config::get_default_collection;
 use crate::errors::{GodataError, GodataErrorType};
-use crate::project::get_collection_names;
-use crate::project::ProjectManager;
+use crate::fsystem::{FileRef, ObjectKind};
+use crate::locations::{get_default_storage_dir, get_main_dir};
+use crate::log::get_log_dir;
+use crate::manifest::ManifestFormat;
+use crate::project::{get_collection_names, retry_while_locked};
+use crate::project::{CollisionStrategy, MoveReport, ProjectManager, ProjectSort, ZipSelection};
+use fnmatch_regex::glob_to_regex;
 use warp::reply::Reply;
 use warp::{http::Response, hyper::Body};
 
@@ -11,15 +18,53 @@ use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use tracing::instrument;
 use warp::http::StatusCode;
-use warp::reply::WithStatus;
+
+#[derive(Serialize)]
+struct VersionResponse {
+    version: &'static str,
+    commit: &'static str,
+    build_date: &'static str,
+}
 
 #[instrument(name = "handlers.get_version", level = "info")]
 pub(crate) fn get_version() -> Result<impl warp::Reply, Infallible> {
+    let response = VersionResponse {
+        version: crate::VERSION,
+        commit: crate::BUILD_COMMIT,
+        build_date: crate::BUILD_DATE,
+    };
     Ok(warp::reply::with_status(
-        warp::reply::json(&env!("CARGO_PKG_VERSION").to_string()),
+        warp::reply::json(&response),
         StatusCode::OK,
     ))
 }
+#[derive(Serialize)]
+struct ConfigResponse {
+    main_dir: String,
+    storage_dir: String,
+    log_dir: String,
+    address: String,
+    default_collection: &'static str,
+}
+
+#[instrument(name = "handlers.get_config", level = "info")]
+pub(crate) fn get_config(address: &str) -> Result<Response<Body>, Infallible> {
+    // Where the server keeps its data, for checking that it's using the directories
+    // you expect. Nothing in here should ever be secret.
+    let storage_dir = match get_default_storage_dir() {
+        Ok(storage_dir) => storage_dir,
+        Err(e) => return Ok(e.into_response()),
+    };
+    let response = ConfigResponse {
+        main_dir: get_main_dir().to_str().unwrap().to_string(),
+        storage_dir: storage_dir.to_str().unwrap().to_string(),
+        log_dir: get_log_dir().to_str().unwrap().to_string(),
+        address: address.to_string(),
+        default_collection: get_default_collection(),
+    };
+    Ok(warp::reply::json(&response).into_response())
+}
+
 #[instrument(
     name = "handlers.list_collections",
     level = "info",
@@ -27,9 +72,9 @@ pub(crate) fn get_version() -> Result<impl warp::Reply, Infallible> {
         show_hidden = %show_hidden
     )
 )]
-pub(crate) fn list_collections(show_hidden: bool) -> Result<impl warp::Reply, Infallible> {
+pub(crate) fn list_collections(show_hidden: bool) -> Result<Response<Body>, Infallible> {
     let collections = get_collection_names(show_hidden);
-    Ok(warp::reply::json(&collections.unwrap()))
+    Ok(warp::reply::json(&collections.unwrap()).into_response())
 }
 
 #[
@@ -39,7 +84,9 @@ instrument(
     skip(project_manager),
     fields(
         collection = %collection,
-        show_hidden = %show_hidden
+        show_hidden = %show_hidden,
+        sort = ?sort,
+        descending = %descending
     )
 )
 ]
@@ -47,17 +94,88 @@ pub(crate) fn list_projects(
     project_manager: Arc<Mutex<ProjectManager>>,
     collection: String,
     show_hidden: bool,
-) -> Result<impl warp::Reply, Infallible> {
+    sort: ProjectSort,
+    descending: bool,
+) -> Result<Response<Body>, Infallible> {
+    let projects = project_manager.lock().unwrap().sorted_project_names(
+        collection.clone(),
+        show_hidden,
+        sort,
+        descending,
+    );
+    match projects {
+        Ok(project_list) => Ok(warp::reply::json(&project_list).into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(name = "handlers.validate_glob", level = "info")]
+pub(crate) fn validate_glob(pattern: String) -> Result<Response<Body>, Infallible> {
+    match glob_to_regex(&pattern) {
+        Ok(regex) => Ok(warp::reply::with_status(
+            warp::reply::json(&regex.as_str()),
+            StatusCode::OK,
+        )
+        .into_response()),
+        Err(e) => Ok(GodataError::from(e).into_response()),
+    }
+}
+
+#[instrument(name = "handlers.validate_name", level = "info")]
+pub(crate) fn validate_name(name: String) -> Result<Response<Body>, Infallible> {
+    match crate::locations::validate_name(&name) {
+        Ok(_) => Ok(warp::reply::json(&true).into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.doctor",
+    level = "info",
+    skip(project_manager),
+    fields(
+        repair = %repair
+    )
+)]
+pub(crate) fn doctor(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    repair: bool,
+) -> Result<Response<Body>, Infallible> {
+    let issues = project_manager.lock().unwrap().doctor(repair);
+    match issues {
+        Ok(issues) => Ok(warp::reply::json(&issues).into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.projects_under",
+    level = "info",
+    skip(project_manager),
+    fields(
+        prefix = %prefix
+    )
+)]
+pub(crate) fn projects_under(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    prefix: String,
+) -> Result<Response<Body>, Infallible> {
     let projects = project_manager
         .lock()
         .unwrap()
-        .get_project_names(collection.clone(), show_hidden);
+        .projects_under(PathBuf::from(prefix));
     match projects {
-        Ok(project_list) => Ok(warp::reply::json(&project_list).into_response()),
+        Ok(projects) => Ok(warp::reply::json(&projects).into_response()),
         Err(e) => Ok(e.into_response()),
     }
 }
 
+#[derive(Serialize)]
+struct LoadResponse {
+    message: String,
+    load_id: String,
+}
+
 #[instrument(
     name = "handlers.load_project",
     level = "info",
@@ -83,11 +201,9 @@ pub(crate) fn load_project(
         Ok(project_list) => {
             if !project_list.contains(&project_name) {
                 tracing::error!("Tried to load project {project_name} in collection {collection}, but it does not exist.");
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&format!(
-                        "No project named {project_name} in collection {collection}"
-                    )),
-                    StatusCode::NOT_FOUND,
+                return Ok(GodataError::new(
+                    GodataErrorType::NotFound,
+                    format!("No project named {project_name} in collection {collection}"),
                 )
                 .into_response());
             }
@@ -99,15 +215,48 @@ pub(crate) fn load_project(
             return Ok(e.into_response());
         }
     }
-    let message = format!("Sucessfully loaded project {collection}/{project_name}");
+    let message = format!("Started loading project {collection}/{project_name}");
     tracing::info!(message);
-    tokio::task::spawn(async move {
-        let _ = project_manager
+    let load_id = project_manager.lock().unwrap().start_load();
+    let task_load_id = load_id.clone();
+    tokio::task::spawn_blocking(move || {
+        // Loading counts the client as using the project, until it drops it
+        let result = retry_while_locked(|| {
+            project_manager
+                .lock()
+                .unwrap()
+                .connect_project(&project_name, &collection)
+                .map(|_| ())
+        });
+        if let Err(e) = &result {
+            tracing::error!("Failed to load project {collection}/{project_name}: {e}");
+        }
+        project_manager
             .lock()
             .unwrap()
-            .load_project(&project_name, &collection);
+            .finish_load(&task_load_id, result);
     });
-    Ok(warp::reply::with_status(warp::reply::json(&message), StatusCode::OK).into_response())
+    let output = LoadResponse { message, load_id };
+    Ok(warp::reply::with_status(warp::reply::json(&output), StatusCode::OK).into_response())
+}
+
+#[instrument(
+    name = "handlers.load_status",
+    level = "info",
+    skip(project_manager),
+    fields(
+        load_id = %load_id
+    )
+)]
+pub(crate) fn load_status(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    load_id: String,
+) -> Result<impl warp::Reply, Infallible> {
+    let status = project_manager.lock().unwrap().load_status(&load_id);
+    match status {
+        Ok(status) => Ok(warp::reply::json(&status).into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
 }
 
 #[instrument(
@@ -139,441 +288,2627 @@ pub(crate) fn drop_project(
 }
 
 #[instrument(
-    name = "handlers.list_project",
+    name = "handlers.unload_project",
     level = "info",
     skip(project_manager),
     fields(
+        project_name = %project_name,
         collection = %collection,
+        flush = %flush,
+        force = %force
+    )
+)]
+pub(crate) fn unload_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    flush: bool,
+    force: bool,
+) -> Result<Response<Body>, Infallible> {
+    let result =
+        project_manager
+            .lock()
+            .unwrap()
+            .unload_project(&project_name, &collection, flush, force);
+    match result {
+        Ok(_) => Ok(warp::reply::with_status(
+            warp::reply::json(&format!("Project {} unloaded.", project_name)),
+            StatusCode::OK,
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.compact_project",
+    level = "info",
+    skip(project_manager),
+    fields(
         project_name = %project_name,
-        project_path = format!("{:?}", project_path),
-        show_hidden = %_show_hidden
+        collection = %collection
     )
 )]
-pub(crate) fn list_project(
+pub(crate) fn compact_project(
     project_manager: Arc<Mutex<ProjectManager>>,
     collection: String,
     project_name: String,
-    project_path: Option<String>,
-    _show_hidden: bool,
 ) -> Result<impl warp::Reply, Infallible> {
-    let project = project_manager
+    let report = project_manager
         .lock()
         .unwrap()
-        .load_project(&project_name, &collection);
-    match project {
-        Ok(project) => {
-            let project = project.lock().unwrap();
-            let result = project.list(project_path);
-            match result {
-                Ok(list) => Ok(warp::reply::json(&list).into_response()),
-                Err(e) => Ok(e.into_response()),
-            }
-        }
+        .compact_project(&project_name, &collection);
+    match report {
+        Ok(report) => Ok(warp::reply::json(&report).into_response()),
         Err(e) => Ok(e.into_response()),
     }
 }
 
 #[instrument(
-    name = "handlers.create_project",
+    name = "handlers.get_project_metadata",
     level = "info",
     skip(project_manager),
     fields(
-        collection = %collection,
         project_name = %project_name,
-        force = %force,
-        storage_location = format!("{:?}", storage_location)
+        collection = %collection
     )
 )]
-pub(crate) fn create_project(
+pub(crate) fn get_project_metadata(
     project_manager: Arc<Mutex<ProjectManager>>,
     collection: String,
     project_name: String,
-    force: bool,
-    storage_location: Option<String>,
 ) -> Result<impl warp::Reply, Infallible> {
-    let project = project_manager.lock().unwrap().create_project(
-        &project_name,
-        &collection,
-        force,
-        storage_location,
-    );
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
     match project {
-        Ok(_) => Ok(warp::reply::with_status(
-            warp::reply::json(&format!(
-                "Project {project_name} created in collection {collection}"
-            )),
-            StatusCode::CREATED,
-        )
-        .into_response()),
+        Ok(project) => match project.lock().unwrap().get_metadata() {
+            Ok(metadata) => Ok(warp::reply::json(&metadata).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
         Err(e) => Ok(e.into_response()),
     }
 }
 
 #[instrument(
-    name = "handlers.delete_project",
+    name = "handlers.set_project_metadata",
     level = "info",
     skip(project_manager),
     fields(
-        collection = %collection,
         project_name = %project_name,
-        force = %force
+        collection = %collection
     )
 )]
-pub(crate) fn delete_project(
+pub(crate) fn set_project_metadata(
     project_manager: Arc<Mutex<ProjectManager>>,
     collection: String,
     project_name: String,
-    force: bool,
+    metadata: HashMap<String, String>,
 ) -> Result<impl warp::Reply, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .delete_project(&project_name, &collection, force);
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
     match project {
-        Ok(_) => Ok(warp::reply::with_status(
-            warp::reply::json(&format!(
-                "Project {project_name} deleted from collection {collection}"
-            )),
-            StatusCode::OK,
-        )
-        .into_response()),
+        Ok(project) => match project.lock().unwrap().set_metadata(metadata) {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "Metadata updated for project {project_name} in collection {collection}"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
         Err(e) => Ok(e.into_response()),
     }
 }
 
 #[derive(Serialize)]
-struct LinkResponse {
-    message: String,
-    removed: Vec<String>,
+struct LoadedResponse {
+    loaded: bool,
+    references: usize,
 }
 
 #[instrument(
-    name = "handlers.link_file",
+    name = "handlers.is_loaded",
     level = "info",
     skip(project_manager),
     fields(
-        collection = %collection,
         project_name = %project_name,
-        project_path = %project_path,
-        file_path = %file_path,
-        force = %force
+        collection = %collection
     )
 )]
-pub(crate) fn link_file(
+pub(crate) fn is_loaded(
     project_manager: Arc<Mutex<ProjectManager>>,
     collection: String,
     project_name: String,
-    project_path: String,
-    file_path: String,
-    metadata: HashMap<String, String>,
-    force: bool,
-) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-
-    match project {
-        Err(e) => return Ok(e.into_response()),
-        Ok(project) => {
-            let parsed_file_path = PathBuf::from(&file_path);
-            let result =
-                project
-                    .lock()
-                    .unwrap()
-                    .add_file(&project_path, parsed_file_path, metadata, force);
-
-            match result {
-                Ok(previous_paths) => {
-                    let output: LinkResponse = LinkResponse {
-                        message: format!("File {file_path} linked to {project_path} in project {project_name} in collection {collection}"),
-                        removed: previous_paths.unwrap_or(Vec::new()),
-                    };
-
-                    return Ok(warp::reply::with_status(
-                        warp::reply::json(&output),
-                        StatusCode::CREATED,
-                    )
-                    .into_response());
-                }
-                Err(e) => Ok(e.into_response()),
-            }
-        }
-    }
+) -> Result<impl warp::Reply, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let response = LoadedResponse {
+        loaded: manager.is_loaded(&project_name, &collection),
+        references: manager.ref_count(&project_name, &collection),
+    };
+    Ok(warp::reply::json(&response))
 }
 
 #[instrument(
-    name = "handlers.link_folder",
+    name = "handlers.project_stats",
     level = "info",
     skip(project_manager),
     fields(
-        collection = %collection,
         project_name = %project_name,
-        project_path = %project_path,
-        folder_path = %folder_path,
-        recursive = %recursive
+        collection = %collection
     )
 )]
-pub(crate) fn link_folder(
+pub(crate) fn project_stats(
     project_manager: Arc<Mutex<ProjectManager>>,
     collection: String,
     project_name: String,
-    project_path: String,
-    folder_path: String,
-    recursive: bool,
-) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
+) -> Result<impl warp::Reply, Infallible> {
+    // Hold the manager for the whole read so the project can't be loaded meanwhile
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
     match project {
-        Ok(project) => {
-            let parsed_folder_path = PathBuf::from(&folder_path);
-            let result =
-                project
-                    .lock()
-                    .unwrap()
-                    .add_folder(&project_path, parsed_folder_path, recursive);
-            match result {
-                Ok(_) => {
-                    let out = LinkResponse {
-                        message: format!("Folder {folder_path} linked to {project_path} in project {project_name} in collection {collection}"),
-                        removed: Vec::new(),
-                    };
-                    return Ok(warp::reply::with_status(
-                        warp::reply::json(&out),
-                        StatusCode::CREATED,
-                    )
-                    .into_response());
-                }
-
-                Err(e) => {
-                    return Ok(e.into_response());
-                }
-            };
-        }
+        Ok(project) => match project.lock().unwrap().stats() {
+            Ok(stats) => Ok(warp::reply::json(&stats).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
         Err(e) => Ok(e.into_response()),
     }
 }
 
 #[instrument(
-    name = "handlers.get_file",
+    name = "handlers.project_shape",
     level = "info",
     skip(project_manager),
     fields(
-        collection = %collection,
         project_name = %project_name,
-        project_path = %project_path
+        collection = %collection
     )
 )]
-pub(crate) fn get_file(
+pub(crate) fn project_shape(
     project_manager: Arc<Mutex<ProjectManager>>,
     collection: String,
     project_name: String,
-    project_path: String,
-) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project.lock().unwrap().get_file(&project_path);
-        match result {
-            Ok(file) => {
-                return Ok(
-                    warp::reply::with_status(warp::reply::json(&file), StatusCode::OK)
-                        .into_response(),
-                )
-            }
-
-            Err(e) => return Ok(e.into_response()),
-        }
+) -> Result<impl warp::Reply, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().shape() {
+            Ok(shape) => Ok(warp::reply::json(&shape).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
     }
-    Ok(warp::reply::with_status(
-        warp::reply::json(&format!(
-            "No project named {project_name} in collection {collection}"
-        )),
-        StatusCode::NOT_FOUND,
-    )
-    .into_response())
 }
 
 #[instrument(
-    name = "handlers.get_files_with_pattern",
+    name = "handlers.reload_project",
     level = "info",
     skip(project_manager),
     fields(
-        collection = %collection,
+        project_name = %project_name,
+        collection = %collection
+    )
+)]
+pub(crate) fn reload_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<impl warp::Reply, Infallible> {
+    // Reloading shouldn't change who is connected, so the reference count is left alone
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().reload() {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "Reloaded project {} in collection {}",
+                    project_name, collection
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.project_integrity",
+    level = "info",
+    skip(project_manager),
+    fields(
+        project_name = %project_name,
+        collection = %collection
+    )
+)]
+pub(crate) fn project_integrity(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<impl warp::Reply, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().integrity() {
+            Ok(summary) => Ok(warp::reply::json(&summary).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.disk_diff",
+    level = "info",
+    skip(project_manager),
+    fields(
+        project_name = %project_name,
+        collection = %collection
+    )
+)]
+pub(crate) fn disk_diff(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().disk_diff() {
+            Ok(diff) => Ok(warp::reply::json(&diff).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.manifest_hash",
+    level = "info",
+    skip(project_manager),
+    fields(
+        project_name = %project_name,
+        collection = %collection
+    )
+)]
+pub(crate) fn manifest_hash(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().manifest_hash() {
+            Ok(hash) => Ok(warp::reply::json(&hash).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.gc_project",
+    level = "info",
+    skip(project_manager),
+    fields(
+        project_name = %project_name,
+        collection = %collection,
+        dry_run = %dry_run
+    )
+)]
+pub(crate) fn gc_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    dry_run: bool,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().gc(dry_run) {
+            Ok(orphans) => Ok(warp::reply::json(&orphans).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.export_json",
+    level = "info",
+    skip(project_manager),
+    fields(
+        project_name = %project_name,
+        collection = %collection,
+        anonymize = %anonymize
+    )
+)]
+pub(crate) fn export_json(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    anonymize: bool,
+) -> Result<Response<Body>, Infallible> {
+    // Hold the manager for the whole read so the project can't be loaded meanwhile
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().export_json(anonymize) {
+            Ok(tree) => Ok(warp::reply::json(&tree).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.subtree",
+    level = "info",
+    skip(project_manager),
+    fields(
+        project_name = %project_name,
+        collection = %collection,
+        project_path = ?project_path,
+        max_depth = ?max_depth,
+        with_metadata = %with_metadata
+    )
+)]
+pub(crate) fn subtree(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: Option<String>,
+    max_depth: Option<usize>,
+    with_metadata: bool,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => {
+            let tree =
+                project
+                    .lock()
+                    .unwrap()
+                    .subtree(project_path.as_deref(), max_depth, with_metadata);
+            match tree {
+                Ok(tree) => Ok(warp::reply::json(&tree).into_response()),
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.relocate_storage",
+    level = "info",
+    skip(project_manager),
+    fields(
+        project_name = %project_name,
+        collection = %collection,
+        storage_location = %storage_location,
+        ignore_references = %ignore_references
+    )
+)]
+pub(crate) fn relocate_storage(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    storage_location: String,
+    ignore_references: bool,
+) -> Result<Response<Body>, Infallible> {
+    let report = project_manager.lock().unwrap().relocate_storage(
+        &project_name,
+        &collection,
+        PathBuf::from(&storage_location),
+        ignore_references,
+    );
+    match report {
+        Ok(report) => Ok(warp::reply::json(&report).into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[derive(Serialize)]
+struct EndpointResponse {
+    #[serde(rename = "type")]
+    kind: String,
+    root: String,
+}
+
+#[instrument(
+    name = "handlers.endpoint_info",
+    level = "info",
+    skip(project_manager),
+    fields(
+        project_name = %project_name,
+        collection = %collection
+    )
+)]
+pub(crate) fn endpoint_info(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<impl warp::Reply, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let (kind, root) = project.lock().unwrap().endpoint_info();
+            Ok(warp::reply::json(&EndpointResponse { kind, root }).into_response())
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.file_endpoint",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path
+    )
+)]
+pub(crate) fn file_endpoint(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().file_endpoint(&project_path) {
+            Ok((kind, root)) => {
+                Ok(warp::reply::json(&EndpointResponse { kind, root }).into_response())
+            }
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.list_project",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = format!("{:?}", project_path),
+        kind = format!("{:?}", kind),
+        flat = %flat,
+        show_hidden = %_show_hidden
+    )
+)]
+pub(crate) fn list_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: Option<String>,
+    kind: Option<ObjectKind>,
+    flat: bool,
+    _show_hidden: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let project = project.lock().unwrap();
+            let result = project.list(project_path, kind);
+            match result {
+                Ok(mut list) if flat => {
+                    let mut names = list.remove("folders").unwrap_or_default();
+                    names.append(&mut list.remove("files").unwrap_or_default());
+                    Ok(warp::reply::json(&names).into_response())
+                }
+                Ok(list) => Ok(warp::reply::json(&list).into_response()),
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.list_project_detailed",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = ?project_path,
+        kind = ?kind,
+        fields = ?fields
+    )
+)]
+pub(crate) fn list_project_detailed(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: Option<String>,
+    kind: Option<ObjectKind>,
+    fields: Option<Vec<String>>,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let project = project.lock().unwrap();
+            let result = project.list_detailed(project_path.as_deref(), kind, fields.as_deref());
+            match result {
+                Ok(listing) => Ok(warp::reply::json(&listing).into_response()),
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.create_project",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        force = %force,
+        storage_location = format!("{:?}", storage_location),
+        temporary = %temporary,
+        template = format!("{:?}", template)
+    )
+)]
+pub(crate) fn create_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    force: bool,
+    storage_location: Option<String>,
+    temporary: bool,
+    template: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    let mut manager = project_manager.lock().unwrap();
+    let project = match (temporary, template) {
+        (true, _) => manager.create_temp_project(&project_name, &collection),
+        (false, Some(template)) => manager.create_from_template(
+            &project_name,
+            &collection,
+            &template,
+            force,
+            storage_location,
+        ),
+        (false, None) => {
+            manager.create_project(&project_name, &collection, force, storage_location)
+        }
+    };
+    match project {
+        Ok(_) => Ok(warp::reply::with_status(
+            warp::reply::json(&format!(
+                "Project {project_name} created in collection {collection}"
+            )),
+            StatusCode::CREATED,
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[derive(Serialize)]
+struct EnsureResponse {
+    message: String,
+    created: bool,
+}
+
+#[instrument(
+    name = "handlers.ensure_project",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        storage_location = format!("{:?}", storage_location)
+    )
+)]
+pub(crate) fn ensure_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    storage_location: Option<String>,
+) -> Result<impl warp::Reply, Infallible> {
+    let result = project_manager.lock().unwrap().get_or_create_project(
+        &project_name,
+        &collection,
+        storage_location,
+    );
+    match result {
+        Ok((_, created)) => {
+            let (message, status) = match created {
+                true => ("created", StatusCode::CREATED),
+                false => ("loaded", StatusCode::OK),
+            };
+            let response = EnsureResponse {
+                message: format!("Project {project_name} {message} in collection {collection}"),
+                created,
+            };
+            Ok(warp::reply::with_status(warp::reply::json(&response), status).into_response())
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.delete_project",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        force = %force,
+        ignore_references = %ignore_references,
+        confirm_count = format!("{:?}", confirm_count)
+    )
+)]
+pub(crate) fn delete_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    force: bool,
+    ignore_references: bool,
+    confirm_count: Option<usize>,
+) -> Result<Response<Body>, Infallible> {
+    let project = project_manager.lock().unwrap().delete_project(
+        &project_name,
+        &collection,
+        force,
+        ignore_references,
+        confirm_count,
+    );
+    match project {
+        Ok(_) => Ok(warp::reply::with_status(
+            warp::reply::json(&format!(
+                "Project {project_name} deleted from collection {collection}"
+            )),
+            StatusCode::OK,
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.trash_project",
+    level = "info",
+    skip(project_manager),
+    fields(
+        project_name = %project_name,
+        collection = %collection,
+        ignore_references = %ignore_references
+    )
+)]
+pub(crate) fn trash_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    ignore_references: bool,
+) -> Result<Response<Body>, Infallible> {
+    let result = project_manager.lock().unwrap().trash_project(
+        &project_name,
+        &collection,
+        ignore_references,
+    );
+    match result {
+        Ok(_) => Ok(warp::reply::with_status(
+            warp::reply::json(&format!(
+                "Project {project_name} moved to the trash in collection {collection}"
+            )),
+            StatusCode::OK,
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.restore_project",
+    level = "info",
+    skip(project_manager),
+    fields(
+        project_name = %project_name,
+        collection = %collection
+    )
+)]
+pub(crate) fn restore_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<impl warp::Reply, Infallible> {
+    let result = project_manager
+        .lock()
+        .unwrap()
+        .restore_project(&project_name, &collection);
+    match result {
+        Ok(_) => Ok(warp::reply::with_status(
+            warp::reply::json(&format!(
+                "Project {project_name} restored in collection {collection}"
+            )),
+            StatusCode::OK,
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.purge_project",
+    level = "info",
+    skip(project_manager),
+    fields(
+        project_name = %project_name,
+        collection = %collection
+    )
+)]
+pub(crate) fn purge_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<impl warp::Reply, Infallible> {
+    let result = project_manager
+        .lock()
+        .unwrap()
+        .purge_project(&project_name, &collection);
+    match result {
+        Ok(_) => Ok(warp::reply::with_status(
+            warp::reply::json(&format!(
+                "Project {project_name} purged from the trash in collection {collection}"
+            )),
+            StatusCode::OK,
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.list_trash",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection
+    )
+)]
+pub(crate) fn list_trash(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+) -> Result<impl warp::Reply, Infallible> {
+    let names = project_manager.lock().unwrap().list_trash(&collection);
+    match names {
+        Ok(names) => Ok(warp::reply::json(&names).into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[derive(Serialize)]
+struct LinkResponse {
+    message: String,
+    removed: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    backup: Option<String>,
+}
+
+#[derive(Serialize)]
+struct MoveResponse {
+    message: String,
+    #[serde(flatten)]
+    report: MoveReport,
+}
+
+#[instrument(
+    name = "handlers.link_file",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path,
+        file_path = %file_path,
+        force = %force,
+        backup = %backup
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn link_file(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+    file_path: String,
+    metadata: HashMap<String, String>,
+    force: bool,
+    backup: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+
+    match project {
+        Err(e) => return Ok(e.into_response()),
+        Ok(project) => {
+            let parsed_file_path = PathBuf::from(&file_path);
+            let result = project.lock().unwrap().add_file(
+                &project_path,
+                parsed_file_path,
+                metadata,
+                force,
+                backup,
+            );
+
+            match result {
+                Ok((previous_paths, backup)) => {
+                    let output: LinkResponse = LinkResponse {
+                        message: format!("File {file_path} linked to {project_path} in project {project_name} in collection {collection}"),
+                        removed: previous_paths.unwrap_or(Vec::new()),
+                        backup,
+                    };
+
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&output),
+                        StatusCode::CREATED,
+                    )
+                    .into_response());
+                }
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+    }
+}
+
+#[instrument(
+    name = "handlers.link_reference",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path,
+        target = %target,
+        force = %force
+    )
+)]
+pub(crate) fn link_reference(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+    target: FileRef,
+    force: bool,
+) -> Result<Response<Body>, Infallible> {
+    let message = format!(
+        "Reference to {} linked to {} in project {} in collection {}",
+        target, project_path, project_name, collection
+    );
+    let result = ProjectManager::add_reference(
+        &project_manager,
+        &project_name,
+        &collection,
+        &project_path,
+        target,
+        force,
+    );
+    match result {
+        Ok(previous_paths) => {
+            let output = LinkResponse {
+                message,
+                removed: previous_paths.unwrap_or_default(),
+                backup: None,
+            };
+            Ok(
+                warp::reply::with_status(warp::reply::json(&output), StatusCode::CREATED)
+                    .into_response(),
+            )
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.relink_file",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path,
+        file_path = %file_path
+    )
+)]
+pub(crate) fn relink_file(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+    file_path: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project
+                .lock()
+                .unwrap()
+                .relink(&project_path, PathBuf::from(&file_path));
+            match result {
+                Ok(_) => Ok(warp::reply::with_status(
+                    warp::reply::json(&format!(
+                        "{project_path} in project {project_name} now points to {file_path}"
+                    )),
+                    StatusCode::OK,
+                )
+                .into_response()),
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.reserve_and_link",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path,
+        suffix = format!("{:?}", suffix)
+    )
+)]
+pub(crate) fn reserve_and_link(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+    suffix: Option<String>,
+    metadata: HashMap<String, String>,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project.lock().unwrap().reserve_and_link(
+                &project_path,
+                suffix.as_deref(),
+                metadata,
+            );
+            match result {
+                Ok(path) => Ok(warp::reply::with_status(
+                    warp::reply::json(&path),
+                    StatusCode::CREATED,
+                )
+                .into_response()),
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[derive(Serialize)]
+struct FolderLinkResponse {
+    message: String,
+    removed: Vec<String>,
+    linked: usize,
+    skipped: usize,
+    skipped_folders: Vec<String>,
+}
+
+#[instrument(
+    name = "handlers.link_folder",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path,
+        folder_path = %folder_path,
+        recursive = %recursive,
+        max_depth = format!("{:?}", max_depth),
+        resume = %resume
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn link_folder(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+    folder_path: String,
+    recursive: bool,
+    max_depth: Option<usize>,
+    resume: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let parsed_folder_path = PathBuf::from(&folder_path);
+            let result = project.lock().unwrap().add_folder(
+                &project_path,
+                parsed_folder_path,
+                recursive,
+                max_depth,
+                resume,
+            );
+            match result {
+                Ok(report) => {
+                    let out = FolderLinkResponse {
+                        message: format!("Folder {folder_path} linked to {project_path} in project {project_name} in collection {collection}"),
+                        removed: Vec::new(),
+                        linked: report.linked,
+                        skipped: report.skipped,
+                        skipped_folders: report.skipped_folders,
+                    };
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&out),
+                        StatusCode::CREATED,
+                    )
+                    .into_response());
+                }
+
+                Err(e) => {
+                    return Ok(e.into_response());
+                }
+            };
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.get_file",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path
+    )
+)]
+pub(crate) fn get_file(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    let project = match project {
+        Ok(project) => project,
+        Err(e) => return Ok(e.into_response()),
+    };
+    // References are followed into the projects they point at, with the project
+    // unlocked in case the chain comes back through it
+    let reference = project.lock().unwrap().reference(&project_path);
+    let result = match reference {
+        Ok(Some(reference)) => {
+            let origin = FileRef {
+                collection: collection.clone(),
+                project: project_name.clone(),
+                path: project_path.clone(),
+            };
+            project_manager
+                .lock()
+                .unwrap()
+                .resolve_reference(&origin, &reference)
+        }
+        _ => project.lock().unwrap().get_file(&project_path),
+    };
+    match result {
+        Ok(file) => {
+            Ok(warp::reply::with_status(warp::reply::json(&file), StatusCode::OK).into_response())
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.set_track_access",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        enabled = %enabled
+    )
+)]
+pub(crate) fn set_track_access(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    enabled: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().set_track_access(enabled) {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "Access tracking set to {enabled} for project {project_name} in collection {collection}"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.set_path_template",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        template = ?template
+    )
+)]
+pub(crate) fn set_path_template(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    template: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().set_path_template(template) {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "Path template updated for project {project_name} in collection {collection}"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.set_quota",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        quota = ?quota
+    )
+)]
+pub(crate) fn set_quota(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    quota: Option<u64>,
+) -> Result<Response<Body>, Infallible> {
+    let result = project_manager
+        .lock()
+        .unwrap()
+        .set_quota(&project_name, &collection, quota);
+    match result {
+        Ok(_) => Ok(warp::reply::with_status(
+            warp::reply::json(&format!(
+                "Quota updated for project {project_name} in collection {collection}"
+            )),
+            StatusCode::OK,
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.get_quota",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name
+    )
+)]
+pub(crate) fn get_quota(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<Response<Body>, Infallible> {
+    let usage = project_manager
+        .lock()
+        .unwrap()
+        .get_quota(&project_name, &collection);
+    match usage {
+        Ok(usage) => Ok(warp::reply::json(&usage).into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.set_watch",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        enabled = %enabled
+    )
+)]
+pub(crate) fn set_watch(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    enabled: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().set_watch(enabled) {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "Watching set to {enabled} for project {project_name} in collection {collection}"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.dangling",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name
+    )
+)]
+pub(crate) fn dangling(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().dangling() {
+            Ok(paths) => Ok(warp::reply::json(&paths).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.file_context",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path
+    )
+)]
+pub(crate) fn file_context(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().file_context(&project_path) {
+            Ok(context) => Ok(warp::reply::json(&context).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.get_by_uuid",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        uuid = %uuid
+    )
+)]
+pub(crate) fn get_by_uuid(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    uuid: String,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().get_by_uuid(&uuid) {
+            Ok(info) => Ok(warp::reply::json(&info).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.read_content",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path
+    )
+)]
+pub(crate) fn read_content(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().read_content(&project_path) {
+            Ok((content, content_type)) => {
+                Ok(warp::reply::with_header(content, "content-type", content_type).into_response())
+            }
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.read_preview",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path
+    )
+)]
+pub(crate) fn read_preview(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().read_preview(&project_path) {
+            Ok((content, content_type)) => {
+                Ok(warp::reply::with_header(content, "content-type", content_type).into_response())
+            }
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.set_preview",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path,
+        preview_path = ?preview_path
+    )
+)]
+pub(crate) fn set_preview(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+    preview_path: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project
+                .lock()
+                .unwrap()
+                .set_preview(&project_path, preview_path.as_ref().map(PathBuf::from));
+            let message = match &preview_path {
+                Some(preview_path) => format!("Preview for {project_path} set to {preview_path}"),
+                None => format!("Preview for {project_path} removed"),
+            };
+            match result {
+                Ok(_) => Ok(
+                    warp::reply::with_status(warp::reply::json(&message), StatusCode::OK)
+                        .into_response(),
+                ),
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.copy_metadata",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        source = %source,
+        dest = %dest,
+        overwrite_existing = %overwrite_existing
+    )
+)]
+pub(crate) fn copy_metadata(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    source: String,
+    dest: String,
+    overwrite_existing: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project
+                .lock()
+                .unwrap()
+                .copy_metadata(&source, &dest, overwrite_existing);
+            match result {
+                Ok(count) => Ok(warp::reply::with_status(
+                    warp::reply::json(&format!(
+                        "Copied {count} metadata keys from {source} to {dest}"
+                    )),
+                    StatusCode::OK,
+                )
+                .into_response()),
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.rename_metadata_key",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        old_key = %old_key,
+        new_key = %new_key,
+        overwrite = %overwrite
+    )
+)]
+pub(crate) fn rename_metadata_key(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    old_key: String,
+    new_key: String,
+    overwrite: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project
+                .lock()
+                .unwrap()
+                .rename_metadata_key(&old_key, &new_key, overwrite);
+            match result {
+                Ok(count) => Ok(warp::reply::json(&count).into_response()),
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.set_metadata_matching",
+    level = "info",
+    skip(project_manager, metadata),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = format!("{:?}", project_path),
+        pattern = %pattern,
+        merge = %merge
+    )
+)]
+pub(crate) fn set_metadata_matching(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: Option<String>,
+    pattern: String,
+    metadata: HashMap<String, String>,
+    merge: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project.lock().unwrap().set_metadata_matching(
+                project_path.as_deref(),
+                &pattern,
+                metadata,
+                merge,
+            );
+            match result {
+                Ok(count) => Ok(warp::reply::json(&count).into_response()),
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.delete_metadata_key",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        key = %key
+    )
+)]
+pub(crate) fn delete_metadata_key(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    key: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().delete_metadata_key(&key) {
+            Ok(count) => Ok(warp::reply::json(&count).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.cas_metadata",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path,
+        key = %key,
+        expected = ?expected,
+        value = %value
+    )
+)]
+pub(crate) fn cas_metadata(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+    key: String,
+    expected: Option<String>,
+    value: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project
+                .lock()
+                .unwrap()
+                .cas_metadata(&project_path, &key, expected, value);
+            match result {
+                Ok(swapped) => Ok(warp::reply::json(&swapped).into_response()),
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.set_checksums",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        enabled = %enabled,
+        background = %background
+    )
+)]
+pub(crate) fn set_checksums(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    enabled: bool,
+    background: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    let hashed = match background {
+        true => "queued for hashing",
+        false => "hashed",
+    };
+    match project {
+        Ok(project) => match project.lock().unwrap().set_checksums(enabled, background) {
+            Ok(count) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "Checksums set to {enabled} for project {project_name} in collection {collection}, {count} files {hashed}"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.checksum_status",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name
+    )
+)]
+pub(crate) fn checksum_status(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = project_manager
+        .lock()
+        .unwrap()
+        .open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => {
+            let status = project.lock().unwrap().checksum_status();
+            Ok(warp::reply::json(&status).into_response())
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.set_external_metadata",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        enabled = %enabled
+    )
+)]
+pub(crate) fn set_external_metadata(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    enabled: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().set_external_metadata(enabled) {
+            Ok(count) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "External metadata set to {enabled} for project {project_name} in collection {collection}, {count} files migrated"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.find_duplicates",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name
+    )
+)]
+pub(crate) fn find_duplicates(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().find_duplicates() {
+            Ok(groups) => Ok(warp::reply::json(&groups).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.recently_accessed",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        limit = %limit
+    )
+)]
+pub(crate) fn recently_accessed(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    limit: usize,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().recently_accessed(limit) {
+            Ok(files) => Ok(
+                warp::reply::with_status(warp::reply::json(&files), StatusCode::OK).into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.recently_modified",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        limit = %limit
+    )
+)]
+pub(crate) fn recently_modified(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    limit: usize,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().recent(limit) {
+            Ok(files) => Ok(warp::reply::json(&files).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+// Chunks of a zip download waiting to be sent. Keeps memory bounded when the client
+// reads slower than files can be compressed.
+const ZIP_CHANNEL_SIZE: usize = 16;
+
+struct ChannelWriter {
+    sender: tokio::sync::mpsc::Sender<std::io::Result<bytes::Bytes>>,
+}
+
+impl std::io::Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.sender
+            .blocking_send(Ok(bytes::Bytes::copy_from_slice(buf)))
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    "Client stopped reading the download",
+                )
+            })?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[instrument(
+    name = "handlers.download_zip",
+    level = "info",
+    skip(project_manager, selection),
+    fields(
+        collection = %collection,
+        project_name = %project_name
+    )
+)]
+pub(crate) fn download_zip(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    selection: ZipSelection,
+) -> Result<Response<Body>, Infallible> {
+    // The files to send are worked out while holding the project, but the archive is
+    // written on a blocking thread as the client reads it
+    let contents = {
+        let manager = project_manager.lock().unwrap();
+        let project = match manager.open_uncached(&project_name, &collection) {
+            Ok(project) => project,
+            Err(e) => return Ok(e.into_response()),
+        };
+        let result = project.lock().unwrap().zip_contents(selection);
+        result
+    };
+    let contents = match contents {
+        Ok(contents) => contents,
+        Err(e) => return Ok(e.into_response()),
+    };
+    let (sender, receiver) = tokio::sync::mpsc::channel(ZIP_CHANNEL_SIZE);
+    let errors = sender.clone();
+    tokio::task::spawn_blocking(move || {
+        let out = std::io::BufWriter::with_capacity(64 * 1024, ChannelWriter { sender });
+        if let Err(e) = contents.write(out) {
+            tracing::error!(
+                "Zip download from project `{}/{}` failed: {}",
+                collection,
+                project_name,
+                e
+            );
+            // The status has already gone out, so the only way left to tell the client
+            // is to break off the body instead of letting it end as if it were complete
+            let _ = errors.blocking_send(Err(std::io::Error::other(e.message)));
+        }
+    });
+    let body = Body::wrap_stream(tokio_stream::wrappers::ReceiverStream::new(receiver));
+    let mut response = Response::new(body);
+    let headers = response.headers_mut();
+    headers.insert("content-type", "application/zip".parse().unwrap());
+    Ok(response)
+}
+
+#[instrument(
+    name = "handlers.export_csv",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name
+    )
+)]
+pub(crate) fn export_csv(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => {
+            let mut csv = Vec::new();
+            match project.lock().unwrap().export_csv(&mut csv) {
+                Ok(_) => {
+                    Ok(warp::reply::with_header(csv, "content-type", "text/csv").into_response())
+                }
+                Err(e) => Ok(e.into_response()),
+            }
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.modified_since",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        since = %since
+    )
+)]
+pub(crate) fn modified_since(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    since: i64,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().modified_since(since) {
+            Ok(files) => Ok(warp::reply::json(&files).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.largest_files",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        limit = %limit
+    )
+)]
+pub(crate) fn largest_files(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    limit: usize,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().largest(limit) {
+            Ok(files) => Ok(warp::reply::json(&files).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.history",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        limit = %limit
+    )
+)]
+pub(crate) fn history(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    limit: usize,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().history(limit) {
+            Ok(entries) => Ok(warp::reply::json(&entries).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.undo",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        count = %count
+    )
+)]
+pub(crate) fn undo(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    count: usize,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().undo(count) {
+            Ok(undone) => Ok(warp::reply::json(&undone).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.metadata_keys",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        counts = %counts
+    )
+)]
+pub(crate) fn metadata_keys(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    counts: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let keys = match project.lock().unwrap().metadata_keys() {
+                Ok(keys) => keys,
+                Err(e) => return Ok(e.into_response()),
+            };
+            if counts {
+                return Ok(warp::reply::json(&keys).into_response());
+            }
+            let keys: Vec<String> = keys.into_keys().collect();
+            Ok(warp::reply::json(&keys).into_response())
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.metadata_values",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        key = %key
+    )
+)]
+pub(crate) fn metadata_values(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    key: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().metadata_values(&key) {
+            Ok(values) => Ok(warp::reply::json(&values).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.metadata_histogram",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        key = %key
+    )
+)]
+pub(crate) fn metadata_histogram(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    key: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = project_manager
+        .lock()
+        .unwrap()
+        .open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().metadata_histogram(&key) {
+            Ok(counts) => Ok(warp::reply::json(&counts).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.get_files_with_pattern",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
         project_name = %project_name,
         project_path = format!("{:?}", project_path),
         pattern = %pattern
     )
 )]
-pub(crate) fn get_files_with_pattern(
+pub(crate) fn get_files_with_pattern(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: Option<&str>,
+    pattern: &str,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().get_files(project_path, pattern) {
+            Ok(files) => Ok(
+                warp::reply::with_status(warp::reply::json(&files), StatusCode::OK).into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.generate_path",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path
+    )
+)]
+pub(crate) fn generate_path(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().generate_path(&project_path) {
+            Ok(path) => Ok(
+                warp::reply::with_status(warp::reply::json(&path), StatusCode::OK).into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.ancestors",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path
+    )
+)]
+pub(crate) fn ancestors(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().ancestors(&project_path) {
+            Ok(chain) => Ok(
+                warp::reply::with_status(warp::reply::json(&chain), StatusCode::OK).into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.usage_breakdown",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = ?project_path
+    )
+)]
+pub(crate) fn usage_breakdown(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    let manager = project_manager.lock().unwrap();
+    let project = manager.open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project
+            .lock()
+            .unwrap()
+            .usage_breakdown(project_path.as_deref())
+        {
+            Ok(usage) => Ok(warp::reply::json(&usage).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.child_counts",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = ?project_path
+    )
+)]
+pub(crate) fn child_counts(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project
+            .lock()
+            .unwrap()
+            .child_counts(project_path.as_deref())
+        {
+            Ok(counts) => Ok(
+                warp::reply::with_status(warp::reply::json(&counts), StatusCode::OK)
+                    .into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+pub(crate) fn path_exists(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project.lock().unwrap().exists(project_path);
+            Ok(
+                warp::reply::with_status(warp::reply::json(&result), StatusCode::OK)
+                    .into_response(),
+            )
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.path_kind",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path
+    )
+)]
+pub(crate) fn path_kind(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = project_manager
+        .lock()
+        .unwrap()
+        .open_uncached(&project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().kind(&project_path) {
+            Ok(kind) => Ok(warp::reply::json(&kind).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.folder_contains",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = format!("{:?}", project_path),
+        name = %name
+    )
+)]
+pub(crate) fn folder_contains(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: Option<String>,
+    name: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project
+                .lock()
+                .unwrap()
+                .folder_contains(project_path.as_deref(), &name);
+            Ok(
+                warp::reply::with_status(warp::reply::json(&result), StatusCode::OK)
+                    .into_response(),
+            )
+        }
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.move_",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path,
+        new_project_path = %new_project_path,
+        overwrite = %overwrite,
+        backup = %backup
+    )
+)]
+pub(crate) fn move_(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+    new_project_path: String,
+    overwrite: bool,
+    backup: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    let project = match project {
+        Ok(project) => project,
+        Err(e) => return Ok(e.into_response()),
+    };
+    let result = project
+        .lock()
+        .unwrap()
+        .move_(&project_path, &new_project_path, overwrite, backup);
+    match result {
+        Ok(report) => Ok(warp::reply::with_status(
+            warp::reply::json(&MoveResponse {
+                message: format!("File {project_path} moved to {new_project_path} in project {project_name} in collection {collection}"),
+                report,
+            }),
+            StatusCode::OK,
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.rename",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path,
+        new_name = %new_name
+    )
+)]
+pub(crate) fn rename(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+    new_name: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().rename(&project_path, &new_name) {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "{project_path} renamed to {new_name} in project {project_name} in collection {collection}"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.swap",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        path_a = %path_a,
+        path_b = %path_b
+    )
+)]
+pub(crate) fn swap(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    path_a: String,
+    path_b: String,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().swap(&path_a, &path_b) {
+            Ok(_) => Ok(warp::reply::with_status(
+                warp::reply::json(&format!(
+                    "{path_a} swapped with {path_b} in project {project_name} in collection {collection}"
+                )),
+                StatusCode::OK,
+            )
+            .into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.import_manifest",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        manifest_path = %manifest_path,
+        format = ?format
+    )
+)]
+pub(crate) fn import_manifest(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    manifest_path: String,
+    format: Option<ManifestFormat>,
+) -> Result<Response<Body>, Infallible> {
+    let result = project_manager.lock().unwrap().import_manifest(
+        &project_name,
+        &collection,
+        PathBuf::from(&manifest_path),
+        format,
+    );
+    match result {
+        Ok(report) => Ok(warp::reply::json(&report).into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.move_matching",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        pattern = %pattern,
+        destination_path = %destination_path,
+        overwrite = %overwrite
+    )
+)]
+pub(crate) fn move_matching(
     project_manager: Arc<Mutex<ProjectManager>>,
     collection: String,
     project_name: String,
-    project_path: Option<&str>,
-    pattern: &str,
+    pattern: String,
+    destination_path: String,
+    overwrite: bool,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project.lock().unwrap().get_files(project_path, pattern);
-        match result {
-            Ok(files) => {
-                return Ok(
-                    warp::reply::with_status(warp::reply::json(&files), StatusCode::OK)
-                        .into_response(),
-                )
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result =
+                project
+                    .lock()
+                    .unwrap()
+                    .move_matching(&pattern, &destination_path, overwrite);
+            match result {
+                Ok(report) => Ok(warp::reply::json(&report).into_response()),
+                Err(e) => Ok(e.into_response()),
             }
-            Err(e) => return Ok(e.into_response()),
         }
+        Err(e) => Ok(e.into_response()),
     }
-    Ok(GodataError::new(
-        GodataErrorType::NotFound,
-        format!("No project named {project_name} in collection {collection}"),
-    )
-    .into_response())
 }
 
 #[instrument(
-    name = "handlers.generate_path",
+    name = "handlers.flatten",
     level = "info",
     skip(project_manager),
     fields(
         collection = %collection,
         project_name = %project_name,
-        project_path = %project_path
+        source_path = %source_path,
+        destination_path = %destination_path,
+        strategy = ?strategy
     )
 )]
-pub(crate) fn generate_path(
+pub(crate) fn flatten(
     project_manager: Arc<Mutex<ProjectManager>>,
     collection: String,
     project_name: String,
-    project_path: String,
+    source_path: String,
+    destination_path: String,
+    strategy: CollisionStrategy,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project.lock().unwrap().generate_path(&project_path);
-        match result {
-            Ok(path) => {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&path),
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project
+                .lock()
+                .unwrap()
+                .flatten(&source_path, &destination_path, strategy);
+            match result {
+                Ok(report) => Ok(warp::reply::with_status(
+                    warp::reply::json(&report),
                     StatusCode::OK,
-                ).into_response())
-            }
-
-            Err(e) => {
-                return Ok(e.into_response());
+                )
+                .into_response()),
+                Err(e) => Ok(e.into_response()),
             }
         }
-    };
-
-    Ok(warp::reply::with_status(
-        warp::reply::json(&format!(
-            "No project named {project_name} in collection {collection}"
-        )),
-        StatusCode::NOT_FOUND,
-    ).into_response())
+        Err(e) => Ok(e.into_response()),
+    }
 }
 
-pub(crate) fn path_exists(
+#[instrument(
+    name = "handlers.internalize",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path
+    )
+)]
+pub(crate) fn internalize(
     project_manager: Arc<Mutex<ProjectManager>>,
     collection: String,
     project_name: String,
     project_path: String,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project.lock().unwrap().exists(project_path);
-        if result {
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&true),
-                StatusCode::OK,
-            ).into_response());
-        } else {
-            return Ok(warp::reply::with_status(
-                warp::reply::json(&false),
-                StatusCode::OK,
-            ).into_response());
-        }
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().internalize(&project_path) {
+            Ok(change) => Ok(
+                warp::reply::with_status(warp::reply::json(&change), StatusCode::OK)
+                    .into_response(),
+            ),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
     }
-    Ok(warp::reply::with_status(
-        warp::reply::json(&format!(
-            "No project named {project_name} in collection {collection}"
-        )),
-        StatusCode::NOT_FOUND,
-    ).into_response())
 }
 
 #[instrument(
-    name = "handlers.move_",
+    name = "handlers.externalize",
     level = "info",
     skip(project_manager),
     fields(
         collection = %collection,
         project_name = %project_name,
         project_path = %project_path,
-        new_project_path = %new_project_path,
-        overwrite = %overwrite
+        destination = %destination,
+        force = %force
     )
 )]
-pub(crate) fn move_(
+pub(crate) fn externalize(
     project_manager: Arc<Mutex<ProjectManager>>,
     collection: String,
     project_name: String,
     project_path: String,
-    new_project_path: String,
-    overwrite: bool,
+    destination: String,
+    force: bool,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project
-            .lock()
-            .unwrap()
-            .move_(&project_path, &new_project_path, overwrite);
-        match result {
-            Ok(v) => {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(
-                        &LinkResponse {
-                            message: format!("File {project_path} moved to {new_project_path} in project {project_name} in collection {collection}"),
-                            removed: v.unwrap_or(Vec::new()),
-                        }
-                    ),
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project.lock().unwrap().externalize(
+                &project_path,
+                PathBuf::from(&destination),
+                force,
+            );
+            match result {
+                Ok(change) => Ok(warp::reply::with_status(
+                    warp::reply::json(&change),
                     StatusCode::OK,
-                ).into_response())
-            }
-
-            Err(e) => {
-                return Ok(e.into_response());
-            
+                )
+                .into_response()),
+                Err(e) => Ok(e.into_response()),
             }
         }
+        Err(e) => Ok(e.into_response()),
     }
-    Ok(warp::reply::with_status(
-        warp::reply::json(&format!(
-            "No project named {project_name} in collection {collection}"
-        )),
-        StatusCode::NOT_FOUND,
-    ).into_response())
 }
 
 #[instrument(
@@ -592,32 +2927,82 @@ pub(crate) fn remove_file(
     project_name: String,
     project_path: String,
 ) -> Result<Response<Body>, Infallible> {
-    let project = project_manager
-        .lock()
-        .unwrap()
-        .load_project(&project_name, &collection);
-    if project.is_ok() {
-        let project = project.unwrap();
-        let result = project.lock().unwrap().remove_file(&project_path);
-        match result {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().remove_file(&project_path) {
             Ok(v) => {
-                return Ok(warp::reply::with_status(
-                    warp::reply::json(&v),
-                    StatusCode::OK,
-                ).into_response())
+                Ok(warp::reply::with_status(warp::reply::json(&v), StatusCode::OK).into_response())
             }
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.remove_many",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_paths = format!("{:?}", project_paths)
+    )
+)]
+pub(crate) fn remove_many(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_paths: Vec<String>,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => match project.lock().unwrap().remove_many(project_paths) {
+            Ok(report) => Ok(warp::reply::json(&report).into_response()),
+            Err(e) => Ok(e.into_response()),
+        },
+        Err(e) => Ok(e.into_response()),
+    }
+}
 
-            Err(e) => {
-                return Ok(e.into_response());
+#[instrument(
+    name = "handlers.remove_matching",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = format!("{:?}", project_path),
+        pattern = %pattern,
+        dry_run = %dry_run,
+        confirm = %confirm
+    )
+)]
+pub(crate) fn remove_matching(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: Option<String>,
+    pattern: String,
+    dry_run: bool,
+    confirm: bool,
+) -> Result<Response<Body>, Infallible> {
+    let project = ProjectManager::load_retrying(&project_manager, &project_name, &collection);
+    match project {
+        Ok(project) => {
+            let result = project.lock().unwrap().remove_matching(
+                project_path.as_deref(),
+                &pattern,
+                dry_run,
+                confirm,
+            );
+            match result {
+                Ok(report) => Ok(warp::reply::json(&report).into_response()),
+                Err(e) => Ok(e.into_response()),
             }
         }
+        Err(e) => Ok(e.into_response()),
     }
-    Ok(warp::reply::with_status(
-        warp::reply::json(&format!(
-            "No project named {project_name} in collection {collection}"
-        )),
-        StatusCode::NOT_FOUND,
-    ).into_response())
 }
 
 #[instrument(
@@ -627,7 +3012,8 @@ pub(crate) fn remove_file(
     fields(
         collection = %collection,
         project_name = %project_name,
-        output_path = %output_path
+        output_path = %output_path,
+        compression = format!("{:?}", compression)
     )
 )
 ]
@@ -636,11 +3022,13 @@ pub(crate) fn export_project_tree(
     collection: String,
     project_name: String,
     output_path: String,
-) -> Result<WithStatus<warp::reply::Json>, Infallible> {
+    compression: Compression,
+) -> Result<Response<Body>, Infallible> {
     let result = project_manager.lock().unwrap().export_project(
         &project_name,
         &collection,
         PathBuf::from(&output_path),
+        compression,
     );
     match result {
         Ok(_) => Ok(warp::reply::with_status(
@@ -648,11 +3036,9 @@ pub(crate) fn export_project_tree(
                 "tree for project {project_name} in collection {collection} exported"
             )),
             StatusCode::OK,
-        )),
-        Err(e) => Ok(warp::reply::with_status(
-            warp::reply::json(&e.to_string()),
-            StatusCode::CONFLICT,
-        )),
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
     }
 }
 
@@ -672,7 +3058,7 @@ pub(crate) fn import_project_tree(
     collection: String,
     project_name: String,
     input_path: String,
-) -> Result<WithStatus<warp::reply::Json>, Infallible> {
+) -> Result<Response<Body>, Infallible> {
     let storage_path = PathBuf::from(&input_path);
     let result = project_manager.lock().unwrap().import_project(
         &project_name,
@@ -686,10 +3072,286 @@ pub(crate) fn import_project_tree(
                 "tree for project {project_name} in collection {collection} imported"
             )),
             StatusCode::OK,
-        )),
-        Err(e) => Ok(warp::reply::with_status(
-            warp::reply::json(&e.to_string()),
-            StatusCode::CONFLICT,
-        )),
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.export_bundle",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        output_path = %output_path
+    )
+)]
+pub(crate) fn export_bundle(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    output_path: String,
+) -> Result<Response<Body>, Infallible> {
+    let result = project_manager.lock().unwrap().export_bundle(
+        &project_name,
+        &collection,
+        PathBuf::from(&output_path),
+    );
+    match result {
+        Ok(manifest) => Ok(warp::reply::json(&manifest).into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.export_subtree",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        project_path = %project_path,
+        dest_collection = %dest_collection,
+        dest_name = %dest_name
+    )
+)]
+pub(crate) fn export_subtree(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    project_path: String,
+    dest_collection: String,
+    dest_name: String,
+) -> Result<Response<Body>, Infallible> {
+    let result = project_manager.lock().unwrap().export_subtree_as_project(
+        &collection,
+        &project_name,
+        &project_path,
+        &dest_collection,
+        &dest_name,
+    );
+    match result {
+        Ok(count) => Ok(warp::reply::with_status(
+            warp::reply::json(&format!(
+                "Exported {} files under {} to project {} in collection {}",
+                count, project_path, dest_name, dest_collection
+            )),
+            StatusCode::CREATED,
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[instrument(
+    name = "handlers.import_bundle",
+    level = "info",
+    skip(project_manager),
+    fields(
+        collection = %collection,
+        project_name = %project_name,
+        input_path = %input_path,
+        storage_location = format!("{:?}", storage_location)
+    )
+)]
+pub(crate) fn import_bundle(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    collection: String,
+    project_name: String,
+    input_path: String,
+    storage_location: Option<String>,
+) -> Result<Response<Body>, Infallible> {
+    let result = project_manager.lock().unwrap().import_bundle(
+        &project_name,
+        &collection,
+        PathBuf::from(&input_path),
+        storage_location.map(PathBuf::from),
+    );
+    match result {
+        Ok(_) => Ok(warp::reply::with_status(
+            warp::reply::json(&format!(
+                "bundle for project {project_name} in collection {collection} imported"
+            )),
+            StatusCode::OK,
+        )
+        .into_response()),
+        Err(e) => Ok(e.into_response()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{manager, new_project, read_zip, scratch_dir, write_file};
+
+    fn store(project: &mut crate::project::Project, path: &str, contents: &str) {
+        let real_path = PathBuf::from(project.generate_path(path).unwrap());
+        let real_path = write_file(&real_path, contents);
+        project
+            .add_file(path, real_path, HashMap::new(), false, false)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn zip_download_holds_the_selected_files() {
+        let (collection, name, project) = new_project();
+        {
+            let mut project = project.lock().unwrap();
+            store(&mut project, "a.txt", "first");
+            store(&mut project, "sub/b.txt", "second");
+            let external = write_file(&scratch_dir().join("c.txt"), "third");
+            project
+                .add_file("c.txt", external, HashMap::new(), false, false)
+                .unwrap();
+        }
+        let selection = ZipSelection::Paths(vec![
+            "a.txt".to_string(),
+            "sub/b.txt".to_string(),
+            "c.txt".to_string(),
+        ]);
+        let response = download_zip(manager(), collection, name, selection).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let entries = read_zip(&body);
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "sub/b.txt", "godata-manifest.json"]);
+        assert_eq!(entries[0].1, b"first");
+        assert_eq!(entries[1].1, b"second");
+        let manifest: serde_json::Value = serde_json::from_slice(&entries[2].1).unwrap();
+        assert_eq!(manifest["skipped"][0]["path"], "c.txt");
+    }
+
+    #[tokio::test]
+    async fn failed_zip_download_breaks_off_the_body() {
+        let (collection, name, project) = new_project();
+        {
+            let mut project = project.lock().unwrap();
+            store(&mut project, "a.txt", "first");
+            // A folder opens like a file but can't be read, so the archive fails partway
+            let folder = PathBuf::from(project.generate_path("folder/inner").unwrap());
+            std::fs::create_dir_all(&folder).unwrap();
+            project
+                .add_file("broken.bin", folder, HashMap::new(), false, false)
+                .unwrap();
+        }
+        let selection = ZipSelection::Paths(vec!["a.txt".to_string(), "broken.bin".to_string()]);
+        let response = download_zip(manager(), collection, name, selection).unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .is_err());
+    }
+
+    async fn error_body(response: Response<Body>) -> serde_json::Value {
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn get_file_errors_carry_their_type() {
+        let (collection, name, _project) = new_project();
+        let response = get_file(manager(), collection.clone(), name, "missing.txt".into()).unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = error_body(response).await;
+        assert_eq!(body["error_type"], "not_found");
+        assert!(body["message"].is_string());
+
+        let response = get_file(
+            manager(),
+            collection,
+            "no_such_project".into(),
+            "a.txt".into(),
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_body(response).await["error_type"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn missing_projects_carry_their_type() {
+        let (collection, _name, _project) = new_project();
+        let response = generate_path(
+            manager(),
+            collection.clone(),
+            "no_such_project".into(),
+            "a.txt".into(),
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_body(response).await["error_type"], "not_found");
+
+        let response = path_exists(
+            manager(),
+            collection.clone(),
+            "no_such_project".into(),
+            "a.txt".into(),
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_body(response).await["error_type"], "not_found");
+
+        let response = remove_file(
+            manager(),
+            collection.clone(),
+            "no_such_project".into(),
+            "a.txt".into(),
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_body(response).await["error_type"], "not_found");
+
+        let response = get_files_with_pattern(
+            manager(),
+            collection,
+            "no_such_project".into(),
+            None,
+            "*.txt",
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_body(response).await["error_type"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn move_errors_carry_their_type() {
+        let (collection, name, project) = new_project();
+        {
+            let mut project = project.lock().unwrap();
+            store(&mut project, "a.txt", "first");
+            store(&mut project, "b.txt", "second");
+        }
+        let response = move_(
+            manager(),
+            collection.clone(),
+            name.clone(),
+            "missing.txt".into(),
+            "c.txt".into(),
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(error_body(response).await["error_type"], "not_found");
+
+        let response = move_(
+            manager(),
+            collection,
+            name,
+            "a.txt".into(),
+            "b.txt".into(),
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = error_body(response).await;
+        assert_eq!(body["error_type"], "already_exists");
+        assert!(body["message"].is_string());
     }
 }
@@ -1,8 +1,20 @@
 use crate::errors::{GodataError, GodataErrorType, Result};
 use directories::BaseDirs;
+use once_cell::sync::OnceCell;
 use std::fs;
 use std::path::{Path, PathBuf};
 
+// Name of the folder inside a collection that holds soft-deleted projects
+pub(crate) const TRASH_DIR_NAME: &str = ".trash";
+
+// Replaces `~/godata` as the storage dir when set from the config file
+static STORAGE_ROOT: OnceCell<PathBuf> = OnceCell::new();
+
+pub(crate) fn set_storage_root(path: PathBuf) {
+    // Only the first call has any effect, and it has to come before anything is stored
+    let _ = STORAGE_ROOT.set(path);
+}
+
 pub(crate) fn get_main_dir() -> PathBuf {
     let base_dir: BaseDirs = BaseDirs::new().unwrap();
     let user_data_dir: &Path = base_dir.data_dir();
@@ -13,7 +25,43 @@ pub(crate) fn get_main_dir() -> PathBuf {
     package_root
 }
 
+// Names Windows keeps for devices, which can't be used as directory names there
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+pub(crate) fn validate_name(name: &str) -> Result<()> {
+    // Project and collection names become directory names and storage keys, so anything
+    // that would escape the directory or confuse the keys is refused. Leading dots are
+    // kept for hidden entries like the trash.
+    let problem = if name.is_empty() {
+        Some("it is empty")
+    } else if name.contains(['/', '\\']) {
+        Some("it contains a path separator")
+    } else if name.contains(['\0', ':']) {
+        Some("it contains a null byte or `:`")
+    } else if name.starts_with('.') {
+        Some("names starting with `.` are reserved")
+    } else if RESERVED_NAMES
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(name.split('.').next().unwrap()))
+    {
+        Some("it is reserved by the operating system")
+    } else {
+        None
+    };
+    match problem {
+        Some(problem) => Err(GodataError::new(
+            GodataErrorType::InvalidPath,
+            format!("`{}` is not a valid name, {}", name, problem),
+        )),
+        None => Ok(()),
+    }
+}
+
 fn create_collection_dir(name: &str) -> Result<PathBuf> {
+    validate_name(name)?;
     let main_directory = get_main_dir();
     let collection_path = main_directory.join(name);
     if !collection_path.exists() {
@@ -59,15 +107,12 @@ pub(crate) fn create_project_dir(
     collection_name: &str,
     force: bool,
 ) -> Result<PathBuf> {
-    let mut collection_dir = load_collection_dir(collection_name);
-    if collection_dir.is_err() {
-        if force {
-            collection_dir = create_collection_dir(collection_name);
-        } else {
-            return Err(collection_dir.err().unwrap());
-        }
-    }
-    let collection_dir = collection_dir.unwrap();
+    validate_name(name)?;
+    let collection_dir = match load_collection_dir(collection_name) {
+        Ok(collection_dir) => collection_dir,
+        Err(_) if force => create_collection_dir(collection_name)?,
+        Err(e) => return Err(e),
+    };
 
     let project_path = collection_dir.join(name);
     if !project_path.exists() {
@@ -118,10 +163,41 @@ pub(crate) fn delete_project_dir(name: &str, collection_name: &str) -> Result<()
     Ok(())
 }
 
+pub(crate) fn get_trash_dir(collection_name: &str) -> Result<PathBuf> {
+    let collection_dir = load_collection_dir(collection_name)?;
+    Ok(collection_dir.join(TRASH_DIR_NAME))
+}
+
+pub(crate) fn delete_trash_dir(name: &str, collection_name: &str) -> Result<()> {
+    let trash_dir = get_trash_dir(collection_name)?;
+    let project_path = trash_dir.join(name);
+    if project_path.exists() {
+        std::fs::remove_dir_all(&project_path)?;
+    } else {
+        return Err(GodataError::new(
+            GodataErrorType::NotFound,
+            format!("Project `{}` is not in the trash", name),
+        ));
+    }
+    if fs::read_dir(&trash_dir)?.next().is_none() {
+        std::fs::remove_dir(&trash_dir)?;
+    }
+    let collection_dir = load_collection_dir(collection_name)?;
+    for entry in fs::read_dir(&collection_dir)? {
+        let entry = entry?;
+        if entry.path().is_dir() {
+            return Ok(());
+        }
+    }
+    delete_collection_dir(collection_name)?;
+    Ok(())
+}
+
 pub(crate) fn get_default_storage_dir() -> Result<PathBuf> {
-    let base_dirs = BaseDirs::new().unwrap();
-    let home = base_dirs.home_dir();
-    let main_dir = home.join("godata");
+    let main_dir = match STORAGE_ROOT.get() {
+        Some(root) => root.clone(),
+        None => BaseDirs::new().unwrap().home_dir().join("godata"),
+    };
     if !main_dir.exists() {
         std::fs::create_dir_all(&main_dir).unwrap();
     }
@@ -142,3 +218,36 @@ pub(crate) fn get_default_project_storage_dir(
     let project_dir = collection_dir.join(name);
     Ok(project_dir)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problem(name: &str) -> GodataErrorType {
+        match validate_name(name) {
+            Ok(_) => panic!("`{}` should have been refused", name),
+            Err(e) => e.error_type,
+        }
+    }
+
+    #[test]
+    fn each_kind_of_bad_name_is_refused() {
+        for name in [
+            "", "a/b", "a\\b", "a\0b", "a:b", ".hidden", "..", "CON", "nul", "com1.txt",
+        ] {
+            assert_eq!(problem(name), GodataErrorType::InvalidPath);
+        }
+        for name in ["survey", "my-project_2", "v1.0", "console"] {
+            assert!(validate_name(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn bad_names_are_refused_before_anything_is_created() {
+        let error = create_project_dir("a/b", "collection", true).unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::InvalidPath);
+        let error = create_project_dir("project", "../escape", true).unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::InvalidPath);
+        assert!(!get_main_dir().join("../escape").exists());
+    }
+}
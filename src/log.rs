@@ -1,12 +1,29 @@
 use crate::locations::get_default_storage_dir;
 use chrono::Utc;
 use std::path::PathBuf;
+use tracing::Subscriber;
 use tracing_appender;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
-use tracing_subscriber::{layer::SubscriberExt, Registry};
+use tracing_subscriber::{fmt, fmt::MakeWriter, layer::SubscriberExt, Registry};
 
-pub(crate) fn init_logging() -> tracing_appender::non_blocking::WorkerGuard {
-    let log_file = get_log_location();
+enum LogFormat {
+    Json,
+    Pretty,
+}
+
+fn log_format() -> LogFormat {
+    // Set with GODATA_LOG_FORMAT. Anything other than `pretty` gets the usual json logs
+    match std::env::var("GODATA_LOG_FORMAT").as_deref() {
+        Ok("pretty") => LogFormat::Pretty,
+        _ => LogFormat::Json,
+    }
+}
+
+// Log files older than this many days are deleted at startup
+pub(crate) const LOG_RETENTION_DAYS: i64 = 30;
+
+pub(crate) fn init_logging(retention_days: i64) -> tracing_appender::non_blocking::WorkerGuard {
+    let log_file = get_log_location(retention_days);
     let file = std::fs::OpenOptions::new()
         .create(true)
         .append(true)
@@ -15,34 +32,49 @@ pub(crate) fn init_logging() -> tracing_appender::non_blocking::WorkerGuard {
 
     let (non_blocking, guard) = tracing_appender::non_blocking(file);
 
+    tracing::subscriber::set_global_default(build_subscriber(log_format(), non_blocking)).unwrap();
+
+    return guard;
+}
+
+fn build_subscriber<W>(format: LogFormat, writer: W) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
     let env_filter = tracing_subscriber::EnvFilter::builder()
         .with_default_directive(tracing::Level::INFO.into())
         .from_env_lossy();
     // The subscriber should be an append-only file
 
-    let formatter = BunyanFormattingLayer::new("godata".into(), non_blocking);
-
-    let subscriber = Registry::default()
-        .with(env_filter)
-        .with(JsonStorageLayer)
-        .with(formatter);
-    tracing::subscriber::set_global_default(subscriber).unwrap();
+    let subscriber = Registry::default().with(env_filter);
+    match format {
+        LogFormat::Json => {
+            let formatter = BunyanFormattingLayer::new("godata".into(), writer);
+            Box::new(subscriber.with(JsonStorageLayer).with(formatter))
+        }
+        LogFormat::Pretty => {
+            // Plain text for reading the log directly while debugging
+            let formatter = fmt::layer().with_writer(writer).with_ansi(false);
+            Box::new(subscriber.with(formatter))
+        }
+    }
+}
 
-    return guard;
+pub(crate) fn get_log_dir() -> PathBuf {
+    get_default_storage_dir().unwrap().join("logs")
 }
 
-fn get_log_location() -> PathBuf {
-    let storage_dir = get_default_storage_dir().unwrap();
-    let log_dir = storage_dir.join("logs");
+fn get_log_location(retention_days: i64) -> PathBuf {
+    let log_dir = get_log_dir();
     std::fs::create_dir_all(&log_dir).unwrap();
     let timestamp = Utc::now().format("%Y-%m-%d-%H-%M-%S");
     let log_file = log_dir.join(format!("godata-{}.log", timestamp));
-    clean_logfiles(&log_dir);
+    clean_logfiles(&log_dir, retention_days);
     log_file
 }
 
-fn clean_logfiles(log_dir: &PathBuf) {
-    // Logfiles from more than 30 days ago are deleted
+fn clean_logfiles(log_dir: &PathBuf, retention_days: i64) {
+    // Logfiles from more than retention_days ago are deleted
     let files = std::fs::read_dir(log_dir).unwrap();
     for file in files {
         let file = file.unwrap();
@@ -51,8 +83,38 @@ fn clean_logfiles(log_dir: &PathBuf) {
         // convert the modified time to a DateTime<Utc>
         let modified: chrono::DateTime<Utc> = chrono::DateTime::from(modified);
         let duration = Utc::now().signed_duration_since(modified);
-        if duration.num_days() > 30 {
+        if duration.num_days() > retention_days {
             std::fs::remove_file(file.path()).unwrap();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    fn log_with(format: LogFormat) -> String {
+        let path = scratch_dir().join("test.log");
+        let file = std::fs::File::create(&path).unwrap();
+        let (non_blocking, guard) = tracing_appender::non_blocking(file);
+        let subscriber = build_subscriber(format, non_blocking);
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("Loaded project `test`");
+        });
+        // Dropping the guard writes out anything still buffered
+        drop(guard);
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn both_log_formats_write_lines() {
+        let json = log_with(LogFormat::Json);
+        let line: serde_json::Value = serde_json::from_str(json.lines().next().unwrap()).unwrap();
+        assert_eq!(line["msg"], "Loaded project `test`");
+        let pretty = log_with(LogFormat::Pretty);
+        assert!(pretty.contains("INFO"));
+        assert!(pretty.contains("Loaded project `test`"));
+        assert!(!pretty.starts_with('{'));
+    }
+}
@@ -1,16 +1,31 @@
+// The route filters nest deeper than the compiler allows by default
+#![recursion_limit = "256"]
+
+mod archive;
+mod checksum;
+mod config;
 mod errors;
 mod fsystem;
 mod handlers;
 mod locations;
 mod log;
+mod manifest;
 mod project;
 mod routes;
 mod server;
 mod storage;
+#[cfg(test)]
+mod testing;
+mod watch;
+mod zip;
 
 use clap::Parser;
-// Allow the server to return its version with a --version flag
-const VERSION: &str = env!("CARGO_PKG_VERSION");
+use std::path::PathBuf;
+// Allow the server to return its version with a --version flag. The commit and build
+// date are set by build.rs.
+pub(crate) const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub(crate) const BUILD_COMMIT: &str = env!("GODATA_BUILD_COMMIT");
+pub(crate) const BUILD_DATE: &str = env!("GODATA_BUILD_DATE");
 #[derive(Parser)]
 struct Opts {
     #[clap(short, long)]
@@ -19,16 +34,51 @@ struct Opts {
     debug: bool,
     #[clap(short, long)]
     port: Option<u16>,
+    // Seconds between flushes of loaded projects with unsaved changes. Off by default.
+    #[clap(long)]
+    flush_interval: Option<u64>,
+    // Unix socket to listen on when no port is given. Defaults to ~/.godata.sock
+    #[clap(long)]
+    socket_path: Option<PathBuf>,
+    // Collection reported to clients for use when they aren't given one
+    #[clap(long)]
+    default_collection: Option<String>,
 }
 
 #[tokio::main]
 async fn main() {
     let opts: Opts = Opts::parse();
     if opts.version {
-        println!("{}", VERSION);
+        println!("{} ({} {})", VERSION, BUILD_COMMIT, BUILD_DATE);
         return;
     }
-    let _log_guard = log::init_logging();
-    let srv = server::get_server(opts.port);
+    // Flags on the command line win over the config file, which wins over the defaults
+    let config = match config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let config = config.overridden_by(config::Config {
+        port: opts.port,
+        socket_path: opts.socket_path,
+        flush_interval: opts.flush_interval,
+        default_collection: opts.default_collection,
+        ..Default::default()
+    });
+    if let Some(root) = config.storage_root {
+        locations::set_storage_root(root);
+    }
+    if let Some(collection) = config.default_collection {
+        if let Err(e) = locations::validate_name(&collection) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        config::set_default_collection(collection);
+    }
+    let retention_days = config.log_retention_days.unwrap_or(log::LOG_RETENTION_DAYS);
+    let _log_guard = log::init_logging(retention_days);
+    let srv = server::get_server(config.port, config.socket_path, config.flush_interval);
     srv.start().await;
 }
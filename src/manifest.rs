@@ -0,0 +1,208 @@
+// Parsing for manifests that list files to link into a project. Each row holds the
+// project path and the real path of a file, followed by any metadata columns. A header
+// row (starting with `path,real_path` or `project_path,real_path`) names the metadata
+// columns. Fields can be quoted, with `""` standing for a literal quote.
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ManifestFormat {
+    Csv,
+    Tsv,
+}
+
+impl std::str::FromStr for ManifestFormat {
+    type Err = GodataError;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(ManifestFormat::Csv),
+            "tsv" => Ok(ManifestFormat::Tsv),
+            _ => Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Unknown manifest format `{}`, expected `csv` or `tsv`", s),
+            )),
+        }
+    }
+}
+
+impl ManifestFormat {
+    pub(crate) fn from_path(path: &Path) -> ManifestFormat {
+        // Anything that isn't obviously tab separated is read as csv
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("tsv") | Some("tab") => ManifestFormat::Tsv,
+            _ => ManifestFormat::Csv,
+        }
+    }
+
+    fn delimiter(&self) -> char {
+        match self {
+            ManifestFormat::Csv => ',',
+            ManifestFormat::Tsv => '\t',
+        }
+    }
+}
+
+pub(crate) struct ManifestRow {
+    pub(crate) line: usize,
+    pub(crate) project_path: String,
+    pub(crate) real_path: String,
+    pub(crate) metadata: HashMap<String, String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RowError {
+    pub(crate) line: usize,
+    pub(crate) error: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ManifestReport {
+    pub(crate) linked: usize,
+    pub(crate) errors: Vec<RowError>,
+}
+
+pub(crate) fn parse(text: &str, format: ManifestFormat) -> (Vec<ManifestRow>, Vec<RowError>) {
+    // Rows that can't be read are reported by line number rather than failing the
+    // whole manifest. Empty metadata values are left off the file.
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    let records = split_records(text, format.delimiter());
+    let keys = match records.first() {
+        Some(Ok((_, fields))) if is_header(fields) => Some(fields[2..].to_vec()),
+        _ => None,
+    };
+    let skip = usize::from(keys.is_some());
+    for record in records.into_iter().skip(skip) {
+        let (line, fields) = match record {
+            Ok(record) => record,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        match to_row(line, fields, keys.as_deref()) {
+            Ok(row) => rows.push(row),
+            Err(error) => errors.push(RowError { line, error }),
+        }
+    }
+    (rows, errors)
+}
+
+pub(crate) fn write_row<W: std::io::Write>(mut out: W, fields: &[&str]) -> std::io::Result<()> {
+    // Write one csv row, quoting any field that wouldn't read back as it is
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            write!(out, "\"{}\"", field.replace('"', "\"\""))?;
+        } else {
+            out.write_all(field.as_bytes())?;
+        }
+    }
+    out.write_all(b"\n")
+}
+
+fn is_header(fields: &[String]) -> bool {
+    matches!(
+        fields,
+        [path, real_path, ..] if (path == "path" || path == "project_path") && real_path == "real_path"
+    )
+}
+
+fn to_row(
+    line: usize,
+    fields: Vec<String>,
+    keys: Option<&[String]>,
+) -> std::result::Result<ManifestRow, String> {
+    if fields.len() < 2 {
+        return Err("Expected at least a path and a real_path".to_string());
+    }
+    let expected = keys.map_or(2, |keys| keys.len() + 2);
+    if fields.len() != expected {
+        return Err(match keys {
+            Some(_) => format!("Expected {} columns, found {}", expected, fields.len()),
+            None => "Metadata columns need a header row naming them".to_string(),
+        });
+    }
+    let mut fields = fields.into_iter();
+    let project_path = fields.next().unwrap();
+    let real_path = fields.next().unwrap();
+    if project_path.is_empty() || real_path.is_empty() {
+        return Err("Path and real_path can't be empty".to_string());
+    }
+    let metadata = keys
+        .unwrap_or_default()
+        .iter()
+        .cloned()
+        .zip(fields)
+        .filter(|(_, value)| !value.is_empty())
+        .collect();
+    Ok(ManifestRow {
+        line,
+        project_path,
+        real_path,
+        metadata,
+    })
+}
+
+type Record = std::result::Result<(usize, Vec<String>), RowError>;
+
+fn split_records(text: &str, delimiter: char) -> Vec<Record> {
+    // Quoted fields can run over several lines, so records are split by hand rather
+    // than line by line. Each record carries the line it started on.
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\n' => {
+                fields.push(std::mem::take(&mut field));
+                if !(fields.len() == 1 && fields[0].is_empty()) {
+                    records.push(Ok((start, std::mem::take(&mut fields))));
+                }
+                fields.clear();
+                line += 1;
+                start = line;
+            }
+            c if c == delimiter => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        records.push(Err(RowError {
+            line: start,
+            error: "Quoted field is never closed".to_string(),
+        }));
+        return records;
+    }
+    fields.push(field);
+    if !(fields.len() == 1 && fields[0].is_empty()) {
+        records.push(Ok((start, fields)));
+    }
+    records
+}
@@ -1,24 +1,405 @@
+use chrono::Utc;
 use fnmatch_regex::glob_to_regex;
 use tracing::instrument;
 
+use crate::archive::{self, Compression};
+use crate::checksum::{sha256_bytes, sha256_file};
+use crate::config::ProjectTemplate;
 use crate::errors::{GodataError, GodataErrorType, Result};
-use crate::fsystem::{is_empty, FileSystem};
+use crate::fsystem::{
+    db_open_delays, is_empty, is_reserved_key, stored_system_value, DetailedListing, File, FileRef,
+    FileSystem, LogEntry, ObjectKind, TreeDiff, TreeNode, TreeOptions, TreeShape, CREATED_KEY,
+    MANIFEST_HASH_KEY, UPDATED_KEY,
+};
 use crate::locations::{
-    create_project_dir, delete_project_dir, load_collection_dir, load_project_dir,
+    create_project_dir, delete_project_dir, delete_trash_dir, get_trash_dir, load_collection_dir,
+    load_project_dir, validate_name, TRASH_DIR_NAME,
 };
-use crate::storage::{LocalEndpoint, StorageEndpoint, StorageManager};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use crate::manifest::{self, ManifestFormat, ManifestReport, ManifestRow, RowError};
+use crate::storage::{LocalEndpoint, PathTemplate, StorageEndpoint, StorageManager};
+use crate::watch::StorageWatcher;
+use crate::zip::{self, ZipWriter};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use uuid::Uuid;
+
+// How many files add_folder links between progress messages
+const LINK_PROGRESS_INTERVAL: usize = 1000;
+
+// Hidden folder that overwritten entries are moved into when a backup is requested
+const BACKUP_DIR: &str = ".backup";
+
+#[derive(Serialize, Default)]
+pub(crate) struct FolderLinkReport {
+    pub(crate) linked: usize,
+    // Files left alone because a resumed link found them already in the project
+    pub(crate) skipped: usize,
+    pub(crate) skipped_folders: Vec<String>,
+}
+
+// File name of a compressed tree export
+const TREE_ARCHIVE_NAME: &str = ".tree.tar.gz";
+
+// Forcing the delete of a project with more internal files than this requires the
+// caller to confirm the file count. Can be overridden with GODATA_DELETE_CONFIRM_THRESHOLD.
+const DELETE_CONFIRM_THRESHOLD: usize = 100;
+
+// Largest internal file whose contents can be fetched directly through the server.
+// Can be overridden with GODATA_INLINE_CONTENT_LIMIT.
+const INLINE_CONTENT_LIMIT: u64 = 1024 * 1024;
+
+// Files under the storage root modified more recently than this many seconds are never
+// collected, in case they are still being written. Can be overridden with
+// GODATA_GC_GRACE_PERIOD.
+const GC_GRACE_PERIOD: u64 = 3600;
+
+// How long the outcome of a background load is kept around for clients to ask about
+const LOAD_STATUS_TTL: Duration = Duration::from_secs(600);
+
+// Name of the entry at the end of a zip download listing what went into it
+const ZIP_MANIFEST: &str = "godata-manifest.json";
+
+// Root system metadata key that turns on access tracking for a project
+const TRACK_ACCESS_KEY: &str = "__track_access__";
+
+// Root system metadata key that turns on checksums for files linked into a project.
+// Set to "background" when files are hashed after they're linked rather than during.
+const CHECKSUMS_KEY: &str = "__checksums__";
+const BACKGROUND_CHECKSUMS: &str = "background";
+
+// Bucket in a metadata histogram for files that don't have the key
+const MISSING_BUCKET: &str = "__missing__";
+
+// Root system metadata key holding the most bytes the project's storage may use
+const QUOTA_KEY: &str = "__quota__";
+
+// Root system metadata key holding the project's storage layout template
+const PATH_TEMPLATE_KEY: &str = "__path_template__";
+
+fn unused_name(name: &str, taken: &HashSet<String>) -> String {
+    // `name` with the first numbered suffix that isn't in `taken`, so `a.fits` becomes
+    // `a_1.fits`, then `a_2.fits` and so on
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    (1..)
+        .map(|i| format!("{}_{}{}", stem, i, extension))
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap()
+}
+
+#[derive(Serialize)]
+pub(crate) struct FileInfo {
+    pub(crate) uuid: String,
+    pub(crate) project_path: String,
+    pub(crate) real_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) origin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) accessed_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) modified_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) checksum: Option<String>,
+    // Where a reference points, in place of a real path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reference: Option<String>,
+    pub(crate) metadata: HashMap<String, String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SizedFileInfo {
+    #[serde(flatten)]
+    pub(crate) file: FileInfo,
+    pub(crate) size: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct MoveReport {
+    // Every file that moved, at its new path
+    pub(crate) moved: Vec<FileInfo>,
+    // Files that were overwritten at the destination, as they were before the move
+    pub(crate) displaced: Vec<FileInfo>,
+    // Internal files that belonged to displaced entries, for the caller to delete
+    pub(crate) removed: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) backup: Option<String>,
+    // Files a bulk move left in place because their name was already taken
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub(crate) collisions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum CollisionStrategy {
+    Error,
+    Suffix,
+    Skip,
+}
+
+impl std::str::FromStr for CollisionStrategy {
+    type Err = GodataError;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "error" => Ok(CollisionStrategy::Error),
+            "suffix" => Ok(CollisionStrategy::Suffix),
+            "skip" => Ok(CollisionStrategy::Skip),
+            _ => Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!(
+                    "Unknown collision strategy `{}`, expected `error`, `suffix` or `skip`",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ProjectSort {
+    Name,
+    Created,
+    Updated,
+    Size,
+}
+
+impl std::str::FromStr for ProjectSort {
+    type Err = GodataError;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "name" => Ok(ProjectSort::Name),
+            "created" => Ok(ProjectSort::Created),
+            "updated" => Ok(ProjectSort::Updated),
+            "size" => Ok(ProjectSort::Size),
+            _ => Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!(
+                    "Unknown sort `{}`, expected `name`, `created`, `updated` or `size`",
+                    s
+                ),
+            )),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct ChecksumStatus {
+    background: bool,
+    // Files waiting to be hashed, and how many have been hashed or failed since the
+    // project was loaded
+    pending: usize,
+    hashed: usize,
+    failed: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct QuotaUsage {
+    quota: Option<u64>,
+    usage: u64,
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct IntegritySummary {
+    total: usize,
+    internal_present: usize,
+    internal_missing: usize,
+    external_present: usize,
+    external_missing: usize,
+    // References to files in other projects, which aren't checked here
+    references: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct StorageChange {
+    project_path: String,
+    // Where the file lives now, and whether it had to be copied to get there
+    real_path: String,
+    changed: bool,
+    // An internal copy that nothing points at any more, which the caller should delete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    removed: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct FlattenReport {
+    moved: usize,
+    // Project paths of files whose name was already taken in the destination. With the
+    // suffix strategy these were renamed, with the skip strategy they were left in place.
+    collisions: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct FileContextInfo {
+    file: FileInfo,
+    inherited: HashMap<String, String>,
+    siblings: Vec<String>,
+}
+
+#[derive(Serialize, Default)]
+pub(crate) struct RemovedFiles {
+    // Project paths that were removed, and the ones that could not be along with why
+    removed: Vec<String>,
+    failed: HashMap<String, String>,
+    // Files inside the project's storage that should now be deleted from disk
+    internal_paths: Vec<String>,
+}
+
+// Files for a zip download, either listed by path or picked out with a glob over a folder
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ZipSelection {
+    Paths(Vec<String>),
+    Matching {
+        pattern: String,
+        project_path: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+pub(crate) struct SkippedFile {
+    path: String,
+    reason: String,
+}
+
+pub(crate) struct ZipContents {
+    // Virtual paths and the files on disk that go under them
+    files: Vec<(String, PathBuf)>,
+    skipped: Vec<SkippedFile>,
+}
+
+impl ZipContents {
+    pub(crate) fn write<W: std::io::Write>(self, out: W) -> Result<()> {
+        // Files that have disappeared since the contents were gathered are added to
+        // the skipped list rather than failing the archive halfway through
+        let mut archive = ZipWriter::new(out);
+        let mut written = Vec::new();
+        let mut skipped = self.skipped;
+        for (path, real_path) in self.files {
+            match std::fs::File::open(&real_path) {
+                Ok(file) => {
+                    archive.add(&path, file)?;
+                    written.push(path);
+                }
+                Err(e) => skipped.push(SkippedFile {
+                    path,
+                    reason: format!("Could not read `{}`: {}", real_path.display(), e),
+                }),
+            }
+        }
+        let manifest = serde_json::json!({"files": written, "skipped": skipped});
+        archive.add(
+            ZIP_MANIFEST,
+            serde_json::to_vec_pretty(&manifest).unwrap().as_slice(),
+        )?;
+        archive.finish()?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct ProjectStats {
+    files: usize,
+    folders: usize,
+    total_size: u64,
+    // Bytes used in the project's own storage, which is what the quota limits
+    internal_size: u64,
+    quota: Option<u64>,
+    metadata_keys: usize,
+    max_depth: usize,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+}
+
+struct StorageUsage {
+    // The tree generation the figures are current for
+    generation: u64,
+    // Each file in the project's storage, with how many entries point at it and its size
+    files: HashMap<PathBuf, (usize, u64)>,
+    total: u64,
+}
 
 pub struct Project {
     pub(crate) tree: FileSystem,
     _name: String,
     _collection: String,
     _endpoint: Box<dyn StorageEndpoint + Send>,
+    _watcher: Option<StorageWatcher>,
+    // Storage for a temporary project, which is removed along with the project
+    _temp_dir: Option<PathBuf>,
+    // Project paths of files waiting to be hashed in the background. The queue only
+    // lives in memory, anything left in it is queued again the next time the project
+    // is loaded.
+    _pending_checksums: VecDeque<String>,
+    _checksums_hashed: usize,
+    _checksums_failed: usize,
+    // Kept up to date as files are added, so checking the quota doesn't have to look at
+    // every stored file. Any other change to the tree means working it out again.
+    _usage: Option<StorageUsage>,
 }
 
 impl Project {
+    fn new(
+        tree: FileSystem,
+        name: &str,
+        collection: &str,
+        endpoint: Box<dyn StorageEndpoint + Send>,
+    ) -> Project {
+        let mut project = Project {
+            tree,
+            _name: name.to_string(),
+            _collection: collection.to_string(),
+            _endpoint: endpoint,
+            _watcher: None,
+            _temp_dir: None,
+            _pending_checksums: VecDeque::new(),
+            _checksums_hashed: 0,
+            _checksums_failed: 0,
+            _usage: None,
+        };
+        project.load_path_template();
+        if project.hashes_in_background() {
+            project.queue_missing_checksums();
+        }
+        project
+    }
+
+    fn load_path_template(&mut self) {
+        // Pick up the stored path template, if there is one
+        let template = self
+            .tree
+            .system_value(PATH_TEMPLATE_KEY)
+            .map(|t| t.to_string());
+        let parsed = match template {
+            Some(template) => match PathTemplate::new(&template, &self._name, &self._collection) {
+                Ok(template) => Some(template),
+                Err(e) => {
+                    tracing::warn!(
+                        "Ignoring invalid path template for project `{}/{}`: {}",
+                        self._collection,
+                        self._name,
+                        e
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        self._endpoint.set_path_template(parsed);
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn reload(&mut self) -> Result<()> {
+        // Throw away the in-memory tree and read it back from the database. Anyone
+        // holding the project sees the fresh tree the next time they lock it.
+        self.tree.reload()?;
+        self.load_path_template();
+        Ok(())
+    }
+
     #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
     pub(crate) fn add_file(
         &mut self,
@@ -26,26 +407,162 @@ impl Project {
         real_path: PathBuf,
         metadata: HashMap<String, String>,
         overwrite: bool,
-    ) -> Result<Option<Vec<String>>> {
+        backup: bool,
+    ) -> Result<(Option<Vec<String>>, Option<String>)> {
+        self.check_storage()?;
+        // Only files in the project's own storage count towards its quota. A file that
+        // is overwritten rather than backed up stops counting.
+        let generation = self.tree.generation();
+        let incoming = match self._endpoint.is_internal(&real_path) {
+            true => vec![real_path.clone()],
+            false => Vec::new(),
+        };
+        let replaced = match overwrite && !backup {
+            true => self.internal_files_at(project_path),
+            false => Vec::new(),
+        };
+        self.check_quota(&incoming, &replaced)?;
+        let origin = real_path.to_str().map(|p| p.to_string());
         let relpath = self._endpoint.get_relative_path(&real_path);
-        let previous_entry = self
-            .tree
-            .insert(project_path, relpath, metadata, overwrite)?;
-        if previous_entry.is_none() {
-            return Ok(None);
+        let hash = self.computes_checksums() && real_path.is_file();
+        let background = self.hashes_in_background();
+        let checksum = match hash && !background {
+            true => Some(sha256_file(&real_path)?),
+            false => None,
+        };
+        let backup_path = match backup && overwrite {
+            true => self.backup_entry(project_path)?,
+            false => None,
+        };
+        let previous_entry =
+            match self
+                .tree
+                .insert(project_path, relpath, metadata, origin, overwrite)
+            {
+                Ok(previous_entry) => previous_entry,
+                Err(e) => {
+                    self.restore_backup(project_path, backup_path);
+                    return Err(e);
+                }
+            };
+        if let Some(checksum) = checksum {
+            self.tree
+                .set_checksums(vec![(project_path.to_string(), checksum)])?;
         }
-        let previous_entries = previous_entry.unwrap();
-        if previous_entries.is_empty() {
-            return Ok(None);
+        if hash && background {
+            self._pending_checksums.push_back(project_path.to_string());
         }
-        let output: Vec<String> = previous_entries
-            .into_iter()
+        let previous_entries = previous_entry.unwrap_or_default();
+        let removed: Vec<PathBuf> = previous_entries
+            .iter()
+            .filter(|x| x.reference.is_none())
             .map(|x| self._endpoint.resolve(&x.real_path))
             .filter(|x| self._endpoint.is_internal(x))
+            .collect();
+        self.track_usage(generation, &incoming, &removed);
+        if previous_entries.is_empty() {
+            return Ok((None, backup_path));
+        }
+        let output: Vec<String> = removed
+            .into_iter()
             .map(|x| x.to_str().unwrap().to_string())
             .collect();
 
-        Ok(Some(output))
+        Ok((Some(output), backup_path))
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn add_reference(
+        &mut self,
+        project_path: &str,
+        target: FileRef,
+        overwrite: bool,
+    ) -> Result<Option<Vec<String>>> {
+        // Stand in for a file in another project without copying it. Like add_file,
+        // hands back the internal files of anything that was overwritten.
+        let previous = self
+            .tree
+            .insert_reference(project_path, target, overwrite)?;
+        Ok(previous.map(|files| {
+            files
+                .into_iter()
+                .map(|f| self._endpoint.resolve(&f.real_path))
+                .filter(|p| self._endpoint.is_internal(p))
+                .map(|p| p.to_str().unwrap().to_string())
+                .collect()
+        }))
+    }
+
+    pub(crate) fn reference(&self, project_path: &str) -> Result<Option<FileRef>> {
+        Ok(self.tree.get(project_path)?.reference.clone())
+    }
+
+    fn stored_path(&self, project_path: &str, file: &File) -> Result<PathBuf> {
+        // Where a file's data is. References have none of their own, so anything that
+        // works on the data refuses them. Their target has to be used instead.
+        match &file.reference {
+            Some(reference) => Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!(
+                    "`{}` refers to `{}` and has no data of its own",
+                    project_path, reference
+                ),
+            )),
+            None => Ok(self._endpoint.resolve(&file.real_path)),
+        }
+    }
+
+    fn check_storage(&self) -> Result<()> {
+        // Fail before touching anything if the project's storage can't be reached
+        self._endpoint.is_available().map_err(|e| {
+            GodataError::new(
+                GodataErrorType::IOError,
+                format!(
+                    "Storage for project `{}` in collection `{}` is unavailable: {}",
+                    self._name, self._collection, e.message
+                ),
+            )
+        })
+    }
+
+    fn backup_entry(&mut self, project_path: &str) -> Result<Option<String>> {
+        // Move whatever is at project_path into the backup folder so an overwrite
+        // can be undone. Returns where it was moved to, if there was anything there.
+        if !self.tree.exists(project_path) {
+            return Ok(None);
+        }
+        let backup_path = format!(
+            "{}/{}/{}",
+            BACKUP_DIR,
+            Utc::now().timestamp_micros(),
+            project_path
+        );
+        self.tree.move_(project_path, &backup_path, false)?;
+        tracing::info!("Backed up `{}` to `{}`", project_path, backup_path);
+        Ok(Some(backup_path))
+    }
+
+    fn restore_backup(&mut self, project_path: &str, backup_path: Option<String>) {
+        // Put a backed up entry back where it was, after the overwrite it made way
+        // for has failed
+        let backup_path = match backup_path {
+            Some(backup_path) => backup_path,
+            None => return,
+        };
+        match self.tree.move_(&backup_path, project_path, false) {
+            Ok(_) => {
+                let folder = backup_path.strip_suffix(&format!("/{}", project_path));
+                if let Some(folder) = folder {
+                    let _ = self.tree.remove(folder);
+                }
+            }
+            Err(e) => tracing::error!(
+                "Could not restore `{}` from `{}`: {}",
+                project_path,
+                backup_path,
+                e
+            ),
+        }
     }
 
     #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
@@ -68,40 +585,258 @@ impl Project {
         project_path: &str,
         real_path: PathBuf,
         recursive: bool,
-    ) -> Result<()> {
-        let mut folders: Vec<PathBuf> = Vec::new();
-        let files = std::fs::read_dir(real_path)?
-            .filter(|x| x.is_ok())
-            .filter_map(|x| {
-                let path = x.unwrap().path();
-                if path.is_file() {
-                    Some(path)
-                } else {
-                    if recursive {
-                        folders.push(path);
+        max_depth: Option<usize>,
+        resume: bool,
+    ) -> Result<FolderLinkReport> {
+        // Folders are walked with an explicit stack rather than by recursion, so a
+        // pathologically deep tree can't blow the stack. Folders below max_depth, and
+        // folders we have already visited through a symlink, are skipped and reported.
+        // When resuming an interrupted link, files already in the project are left as
+        // they are so any metadata added since isn't lost.
+        let mut report = FolderLinkReport::default();
+        let hash = self.computes_checksums();
+        let background = self.hashes_in_background();
+        let mut visited: HashSet<PathBuf> = HashSet::new();
+        let mut pending: Vec<(String, PathBuf, usize)> =
+            vec![(project_path.to_string(), real_path, 0)];
+        let mut next_report = LINK_PROGRESS_INTERVAL;
+        while let Some((folder_project_path, folder_real_path, depth)) = pending.pop() {
+            let canonical = folder_real_path.canonicalize()?;
+            if !visited.insert(canonical) {
+                tracing::warn!(
+                    "Skipping `{}`, it has already been linked (symlink cycle?)",
+                    folder_real_path.display()
+                );
+                report
+                    .skipped_folders
+                    .push(folder_real_path.to_string_lossy().to_string());
+                continue;
+            }
+            let mut folders: Vec<PathBuf> = Vec::new();
+            let files: Vec<PathBuf> = std::fs::read_dir(folder_real_path)?
+                .filter(|x| x.is_ok())
+                .filter_map(|x| {
+                    let path = x.unwrap().path();
+                    if path.is_file() {
+                        Some(path)
+                    } else {
+                        if recursive {
+                            folders.push(path);
+                        }
+                        None
                     }
-                    None
+                })
+                .collect();
+            let files: Vec<PathBuf> = match resume {
+                true => files
+                    .into_iter()
+                    .filter(|path| {
+                        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                        let exists = self
+                            .tree
+                            .exists(&format!("{}/{}", folder_project_path, name));
+                        report.skipped += usize::from(exists);
+                        !exists
+                    })
+                    .collect(),
+                false => files,
+            };
+            let internal: Vec<PathBuf> = files
+                .iter()
+                .filter(|path| self._endpoint.is_internal(path))
+                .cloned()
+                .collect();
+            self.check_quota(&internal, &[])?;
+            let generation = self.tree.generation();
+            report.linked += files.len();
+            // Linked files get checksums the same way files added one at a time do
+            let to_hash: Vec<(String, &PathBuf)> = match hash {
+                true => files
+                    .iter()
+                    .map(|path| {
+                        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+                        (format!("{}/{}", folder_project_path, name), path)
+                    })
+                    .collect(),
+                false => Vec::new(),
+            };
+            let (queued, checksums) = match background {
+                true => (to_hash.into_iter().map(|(p, _)| p).collect(), Vec::new()),
+                false => {
+                    let checksums = to_hash
+                        .into_iter()
+                        .map(|(p, path)| Ok((p, sha256_file(path)?)))
+                        .collect::<Result<Vec<(String, String)>>>()?;
+                    (Vec::new(), checksums)
                 }
-            });
-        self.tree.insert_many(files, project_path)?;
-        if recursive {
+            };
+            let files = files
+                .into_iter()
+                .map(|path| (self._endpoint.get_relative_path(&path), path));
+            self.tree.insert_many(files, &folder_project_path)?;
+            if !checksums.is_empty() {
+                self.tree.set_checksums(checksums)?;
+            }
+            self.track_usage(generation, &internal, &[]);
+            self._pending_checksums.extend(queued);
+            if report.linked >= next_report {
+                tracing::info!(
+                    "Linked {} files into `{}` so far",
+                    report.linked,
+                    project_path
+                );
+                next_report = report.linked + LINK_PROGRESS_INTERVAL;
+            }
             for folder in folders {
-                let folder_name = folder.file_name().unwrap().to_str().unwrap().to_string();
-                let folder_project_path = format!("{}/{}", project_path, folder_name);
-                self.add_folder(&folder_project_path, folder, recursive)?;
+                if max_depth.is_some_and(|max| depth + 1 > max) {
+                    report
+                        .skipped_folders
+                        .push(folder.to_string_lossy().to_string());
+                    continue;
+                }
+                // Folder names become part of project paths, which have to be valid utf-8
+                let folder_name = match folder.file_name().and_then(|n| n.to_str()) {
+                    Some(name) => name.to_string(),
+                    None => {
+                        tracing::warn!(
+                            "Skipping `{}`, its name is not valid utf-8",
+                            folder.display()
+                        );
+                        report
+                            .skipped_folders
+                            .push(folder.to_string_lossy().to_string());
+                        continue;
+                    }
+                };
+                pending.push((
+                    format!("{}/{}", folder_project_path, folder_name),
+                    folder,
+                    depth + 1,
+                ));
             }
         }
+        tracing::info!("Linked {} files into `{}`", report.linked, project_path);
+        if report.skipped > 0 {
+            tracing::info!(
+                "Skipped {} files already in `{}`",
+                report.skipped,
+                project_path
+            );
+        }
+        if !report.skipped_folders.is_empty() {
+            tracing::info!(
+                "Skipped {} folders while linking `{}`",
+                report.skipped_folders.len(),
+                project_path
+            );
+        }
 
-        Ok(())
+        Ok(report)
+    }
+
+    #[instrument(skip(self, rows, errors), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    fn link_manifest_rows(
+        &mut self,
+        rows: Vec<ManifestRow>,
+        base_dir: &Path,
+        mut errors: Vec<RowError>,
+    ) -> Result<ManifestReport> {
+        // Relative real paths are taken to be relative to the manifest itself
+        self.check_storage()?;
+        let checksums_enabled = self.computes_checksums() && !self.hashes_in_background();
+        let mut entries = Vec::new();
+        let mut lines = Vec::new();
+        let mut checksums = Vec::new();
+        let mut internal = Vec::new();
+        for row in rows {
+            let real_path = base_dir.join(&row.real_path);
+            if !real_path.is_file() {
+                errors.push(RowError {
+                    line: row.line,
+                    error: format!("No file found at `{}`", real_path.display()),
+                });
+                continue;
+            }
+            let checksum = match checksums_enabled {
+                true => Some(sha256_file(&real_path)?),
+                false => None,
+            };
+            let relpath = self._endpoint.get_relative_path(&real_path);
+            let origin = real_path.to_str().map(|p| p.to_string());
+            if self._endpoint.is_internal(&real_path) {
+                internal.push((entries.len(), real_path.clone()));
+            }
+            lines.push(row.line);
+            checksums.push(checksum.map(|c| (row.project_path.clone(), c)));
+            entries.push((row.project_path, relpath, row.metadata, origin));
+        }
+        let total = entries.len();
+        let incoming: Vec<PathBuf> = internal.iter().map(|(_, path)| path.clone()).collect();
+        self.check_quota(&incoming, &[])?;
+        let generation = self.tree.generation();
+        let failed = self.tree.insert_each(entries.into_iter())?;
+        let added: Vec<PathBuf> = internal
+            .into_iter()
+            .filter(|(index, _)| !failed.iter().any(|(i, _)| i == index))
+            .map(|(_, path)| path)
+            .collect();
+        self.track_usage(generation, &added, &[]);
+        for (index, e) in failed.iter() {
+            errors.push(RowError {
+                line: lines[*index],
+                error: e.message.clone(),
+            });
+            checksums[*index] = None;
+        }
+        self.tree
+            .set_checksums(checksums.into_iter().flatten().collect())?;
+        if self.hashes_in_background() {
+            self.queue_missing_checksums();
+        }
+        errors.sort_by_key(|e| e.line);
+        Ok(ManifestReport {
+            linked: total - failed.len(),
+            errors,
+        })
     }
 
     #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
-    pub(crate) fn get_file(&self, project_path: &str) -> Result<HashMap<String, String>> {
-        let file = self.tree.get(project_path)?;
-        let fpath = self._endpoint.resolve(&file.real_path);
-        let mut meta = file.metadata.clone();
+    pub(crate) fn relativize_paths(&mut self) -> Result<usize> {
+        // Older projects may have internal files stored with absolute paths, which break
+        // if the storage root moves. Rewrite them relative to the root.
+        relativize_tree(&mut self.tree, self._endpoint.as_ref())
+    }
 
-        meta.insert("real_path".to_string(), fpath.to_str().unwrap().to_string());
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn get_file(&mut self, project_path: &str) -> Result<HashMap<String, String>> {
+        self.check_storage()?;
+        if self.tracks_access() {
+            self.tree.touch(project_path, Utc::now().timestamp())?;
+        }
+        let file = self.tree.get(project_path)?;
+        let mut meta = self.tree.file_metadata(file)?;
+        // A reference is reported as where it points, the manager can follow it from there
+        match &file.reference {
+            Some(reference) => meta.insert("reference".to_string(), reference.to_string()),
+            None => {
+                let fpath = self._endpoint.resolve(&file.real_path);
+                meta.insert("real_path".to_string(), fpath.to_str().unwrap().to_string())
+            }
+        };
+        // These share the map with the user's metadata, which wins if a key is taken
+        if let Some(origin) = &file.origin {
+            meta.entry("origin".to_string())
+                .or_insert_with(|| origin.clone());
+        }
+        if let Some(checksum) = &file.checksum {
+            meta.entry("checksum".to_string())
+                .or_insert_with(|| checksum.clone());
+        }
+        if let Some(preview) = &file.preview {
+            let preview = self._endpoint.resolve(Path::new(preview));
+            meta.entry("preview".to_string())
+                .or_insert_with(|| preview.to_str().unwrap().to_string());
+        }
 
         Ok(meta)
     }
@@ -118,294 +853,5418 @@ impl Project {
         let results = matching_files
             .iter()
             .map(|f| {
-                let mut meta = f.metadata.clone();
+                let mut meta = self.tree.file_metadata(f)?;
+                if let Some(reference) = &f.reference {
+                    meta.insert("reference".to_string(), reference.to_string());
+                    return Ok((f.name.clone(), meta));
+                }
                 let real_path = self._endpoint.resolve(&f.real_path);
                 meta.insert(
                     "real_path".to_string(),
                     real_path.to_str().unwrap().to_string(),
                 );
-                (f.name.clone(), meta)
+                Ok((f.name.clone(), meta))
             })
-            .collect::<HashMap<_, _>>();
+            .collect::<Result<HashMap<_, _>>>()?;
         Ok(results)
     }
 
-    pub(crate) fn list(
-        &self,
-        project_path: Option<String>,
-    ) -> Result<HashMap<String, Vec<String>>> {
-        let list = self.tree.list(project_path)?;
-        Ok(list)
+    pub(crate) fn get_metadata(&self) -> Result<HashMap<String, String>> {
+        Ok(self.tree.get_folder_metadata(None)?.clone())
     }
 
     #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
-    pub(crate) fn remove_file(&mut self, project_path: &str) -> Result<Vec<PathBuf>> {
-        let removed_internal_paths = self.tree.remove(project_path)?;
-        // filter out paths that are not internal
-        let need_to_remove: Vec<PathBuf> = removed_internal_paths
-            .into_iter()
-            .map(|x| self._endpoint.resolve(&x.real_path))
-            .filter(|x| self._endpoint.is_internal(x))
-            .collect();
-        Ok(need_to_remove)
+    pub(crate) fn set_metadata(&mut self, metadata: HashMap<String, String>) -> Result<()> {
+        // Replace the user-facing root metadata. Reserved keys can't be set here, since
+        // they belong to the system metadata.
+        if let Some(key) = metadata.keys().find(|k| is_reserved_key(k)) {
+            return Err(GodataError::new(
+                GodataErrorType::NotPermitted,
+                format!("Metadata key `{}` is reserved", key),
+            ));
+        }
+        self.tree.set_folder_metadata(None, metadata)
+    }
+
+    pub(crate) fn metadata_keys(&self) -> Result<BTreeMap<String, usize>> {
+        // Every metadata key used by a file in the project, with how many files use it
+        let mut keys = BTreeMap::new();
+        for (_, file) in self.tree.files() {
+            let metadata = self.tree.file_metadata(file)?;
+            for key in metadata.into_keys().filter(|k| !is_reserved_key(k)) {
+                *keys.entry(key).or_insert(0) += 1;
+            }
+        }
+        Ok(keys)
+    }
+
+    pub(crate) fn metadata_values(&self, key: &str) -> Result<Vec<String>> {
+        let mut values = BTreeSet::new();
+        for (_, file) in self.tree.files() {
+            if let Some(value) = self.tree.file_metadata(file)?.remove(key) {
+                values.insert(value);
+            }
+        }
+        Ok(values.into_iter().collect())
+    }
+
+    pub(crate) fn metadata_histogram(&self, key: &str) -> Result<BTreeMap<String, usize>> {
+        // How many files have each value of the key
+        let mut counts = BTreeMap::new();
+        for (_, file) in self.tree.files() {
+            let value = self
+                .tree
+                .file_metadata(file)?
+                .remove(key)
+                .unwrap_or_else(|| MISSING_BUCKET.to_string());
+            *counts.entry(value).or_insert(0) += 1;
+        }
+        Ok(counts)
     }
 
     #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
-    pub(crate) fn move_(
-        &mut self,
-        from: &str,
-        to: &str,
-        overwrite: bool,
-    ) -> Result<Option<Vec<String>>> {
-        let result = self.tree.move_(from, to, overwrite)?;
-        if result.is_none() {
-            return Ok(None);
+    pub(crate) fn stats(&self) -> Result<ProjectStats> {
+        let summary = self.tree.summarize();
+        let mut keys = HashSet::new();
+        for file in summary.files.iter() {
+            let metadata = self.tree.file_metadata(file)?;
+            keys.extend(metadata.into_keys().filter(|k| !is_reserved_key(k)));
         }
-        let result = result.unwrap();
-        let moved: Vec<String> = result
+        let total_size = self.total_size(&summary.files);
+        let timestamp = |key: &str| self.tree.system_value(key).and_then(|v| v.parse().ok());
+        Ok(ProjectStats {
+            files: summary.files.len(),
+            folders: summary.folders,
+            total_size,
+            internal_size: self.internal_size(),
+            quota: self.quota(),
+            metadata_keys: keys.len(),
+            max_depth: summary.max_depth,
+            created_at: timestamp(CREATED_KEY),
+            updated_at: timestamp(UPDATED_KEY),
+        })
+    }
+
+    fn internal_paths(&self) -> HashSet<PathBuf> {
+        // Files in the project's storage that the tree points at, each counted once
+        // however many entries share it
+        self.tree
+            .files()
             .into_iter()
-            .map(|x| self._endpoint.resolve(&x.real_path))
-            .filter(|x| self._endpoint.is_internal(x))
-            .map(|x| x.to_str().unwrap().to_string())
-            .collect();
-        Ok(Some(moved))
+            .filter(|(_, file)| file.reference.is_none())
+            .map(|(_, file)| self._endpoint.resolve(&file.real_path))
+            .filter(|path| self._endpoint.is_internal(path))
+            .collect()
     }
 
-    pub(crate) fn exists(&self, project_path: String) -> bool {
-        self.tree.exists(&project_path)
+    pub(crate) fn internal_size(&self) -> u64 {
+        self.internal_paths()
+            .iter()
+            .filter_map(|path| std::fs::metadata(path).ok())
+            .map(|meta| meta.len())
+            .sum()
     }
 
-    pub(crate) fn generate_path(&self, project_path: &str) -> Result<String> {
-        let path = self._endpoint.generate_path(project_path)?;
-        Ok(path.to_str().unwrap().to_owned())
+    pub(crate) fn quota(&self) -> Option<u64> {
+        self.tree
+            .system_value(QUOTA_KEY)
+            .and_then(|v| v.parse().ok())
     }
-}
 
-pub fn get_project_manager() -> Result<ProjectManager> {
-    let storage_manager = StorageManager::get_manager()?;
-    Ok(ProjectManager {
-        storage_manager,
-        projects: HashMap::new(),
-        counts: HashMap::new(),
-    })
-}
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn set_quota(&mut self, quota: Option<u64>) -> Result<()> {
+        // A quota below what's already stored is allowed, it just stops anything new
+        // from being added
+        self.tree
+            .set_system_value(QUOTA_KEY, quota.map(|q| q.to_string()))
+    }
 
-pub struct ProjectManager {
-    storage_manager: StorageManager,
-    projects: HashMap<String, Arc<Mutex<Project>>>,
-    counts: HashMap<String, usize>,
-}
+    fn storage_usage(&mut self) -> &StorageUsage {
+        // Worked out from scratch the first time, and whenever the tree has changed in a
+        // way the running total didn't follow
+        let generation = self.tree.generation();
+        if self._usage.as_ref().map(|u| u.generation) != Some(generation) {
+            let mut files: HashMap<PathBuf, (usize, u64)> = HashMap::new();
+            for (_, file) in self.tree.files() {
+                if file.reference.is_some() {
+                    continue;
+                }
+                let path = self._endpoint.resolve(&file.real_path);
+                if self._endpoint.is_internal(&path) {
+                    files.entry(path).or_insert((0, 0)).0 += 1;
+                }
+            }
+            for (path, (_, size)) in files.iter_mut() {
+                *size = file_size(path);
+            }
+            let total = files.values().map(|(_, size)| size).sum();
+            self._usage = Some(StorageUsage {
+                generation,
+                files,
+                total,
+            });
+        }
+        self._usage.as_ref().unwrap()
+    }
 
-impl ProjectManager {
-    #[instrument(skip(self))]
-    pub fn create_project(
-        &mut self,
-        name: &str,
-        collection: &str,
-        force: bool,
-        storage_location: Option<String>,
-    ) -> Result<Arc<Mutex<Project>>> {
-        let key = format!("{}/{}", collection, name);
-        let project_dir = create_project_dir(name, collection, force)?;
-        let tree = FileSystem::new(name.to_string(), project_dir)?;
-        let base_path = match storage_location {
-            Some(path) => PathBuf::from(path),
-            None => crate::locations::get_default_project_storage_dir(name, collection)?,
+    fn check_quota(&mut self, incoming: &[PathBuf], replaced: &[PathBuf]) -> Result<()> {
+        // Refuse files that would take the project's storage over its quota. A file that
+        // is being replaced stops counting unless another entry still points at it.
+        let quota = match self.quota() {
+            Some(quota) => quota,
+            None => return Ok(()),
         };
-        self.storage_manager
-            .add(name, collection, "local", base_path.clone())?;
-        let endpoint = LocalEndpoint::new(base_path);
-        let p = Project {
-            tree,
-            _name: name.to_string(),
-            _collection: collection.to_string(),
-            _endpoint: Box::new(endpoint),
+        let usage = self.storage_usage();
+        let mut projected = usage.total;
+        for path in replaced.iter().filter(|p| !incoming.contains(p)) {
+            if let Some((1, size)) = usage.files.get(path) {
+                projected = projected.saturating_sub(*size);
+            }
+        }
+        let mut seen = HashSet::new();
+        for path in incoming {
+            if !usage.files.contains_key(path) && seen.insert(path) {
+                projected += file_size(path);
+            }
+        }
+        if projected > quota {
+            return Err(self.over_quota(projected, quota));
+        }
+        Ok(())
+    }
+
+    fn over_quota(&self, usage: u64, quota: u64) -> GodataError {
+        tracing::error!(
+            "Project `{}/{}` would use {} bytes, over its quota of {}",
+            self._collection,
+            self._name,
+            usage,
+            quota
+        );
+        GodataError::new(
+            GodataErrorType::NotPermitted,
+            format!(
+                "Project `{}/{}` would use {} bytes, over its quota of {} bytes",
+                self._collection, self._name, usage, quota
+            ),
+        )
+    }
+
+    fn track_usage(&mut self, generation: u64, added: &[PathBuf], removed: &[PathBuf]) {
+        // Carry the running total over a change that was made to the tree as it was at
+        // `generation`. A total that was already out of date is left to be redone.
+        let current = self.tree.generation();
+        let usage = match self._usage.as_mut() {
+            Some(usage) if usage.generation == generation => usage,
+            _ => return,
         };
-        let project = Arc::new(Mutex::new(p));
-        self.projects.insert(key.clone(), project.clone());
-        self.counts.insert(key, 1);
-        Ok(project)
+        for path in added {
+            let entry = usage
+                .files
+                .entry(path.clone())
+                .or_insert_with(|| (0, file_size(path)));
+            if entry.0 == 0 {
+                usage.total += entry.1;
+            }
+            entry.0 += 1;
+        }
+        for path in removed {
+            if let Some(entry) = usage.files.get_mut(path) {
+                entry.0 -= 1;
+                if entry.0 == 0 {
+                    usage.total = usage.total.saturating_sub(entry.1);
+                    usage.files.remove(path);
+                }
+            }
+        }
+        usage.generation = current;
     }
 
-    #[instrument(skip(self))]
-    pub fn import_project(
-        &self,
-        name: &str,
-        collection: &str,
-        endpoint: &str,
-        path: PathBuf,
-    ) -> Result<PathBuf> {
-        // The assumption is that the path points to a folder which contains the project data
-        // Aditionally, it should contain a .tree folder which contains the tree data
+    fn internal_files_at(&self, project_path: &str) -> Vec<PathBuf> {
+        // The stored files of whatever is at a path, which an overwrite would replace
+        let files = match self.tree.get(project_path) {
+            Ok(file) => vec![file],
+            Err(_) => match self.tree.files_under(project_path) {
+                Ok(files) => files.into_iter().map(|(_, file)| file).collect(),
+                Err(_) => Vec::new(),
+            },
+        };
+        files
+            .into_iter()
+            .filter(|file| file.reference.is_none())
+            .map(|file| self._endpoint.resolve(&file.real_path))
+            .filter(|path| self._endpoint.is_internal(path))
+            .collect()
+    }
 
-        let project_dir = create_project_dir(name, collection, true)?;
-        let tree_path = path.join(".tree");
-        let db = sled::open(tree_path)?;
+    fn total_size(&self, files: &[&File]) -> u64 {
+        // Files that have gone missing on disk just don't count towards the size, and
+        // references have no size of their own
+        files
+            .iter()
+            .filter(|file| file.reference.is_none())
+            .filter_map(|file| std::fs::metadata(self._endpoint.resolve(&file.real_path)).ok())
+            .map(|meta| meta.len())
+            .sum()
+    }
 
-        let _root = db.get("root").unwrap().unwrap();
+    fn sort_key(&self, sort: ProjectSort) -> Result<Option<i64>> {
+        let timestamp = |key: &str| self.tree.system_value(key).and_then(|v| v.parse().ok());
+        Ok(match sort {
+            ProjectSort::Name => None,
+            ProjectSort::Created => timestamp(CREATED_KEY),
+            ProjectSort::Updated => timestamp(UPDATED_KEY),
+            ProjectSort::Size => Some(self.total_size(&self.tree.summarize().files) as i64),
+        })
+    }
 
-        let db_export = db.export();
-        let final_db = sled::open(&project_dir)?;
-        final_db.import(db_export);
+    pub(crate) fn integrity(&self) -> Result<IntegritySummary> {
+        // Only checks that every file is still there, not that its contents are intact
+        let mut summary = IntegritySummary::default();
+        for (_, file) in self.tree.files() {
+            summary.total += 1;
+            if file.reference.is_some() {
+                summary.references += 1;
+                continue;
+            }
+            let real_path = self._endpoint.resolve(&file.real_path);
+            let present = real_path.exists();
+            match (self._endpoint.is_internal(&real_path), present) {
+                (true, true) => summary.internal_present += 1,
+                (true, false) => summary.internal_missing += 1,
+                (false, true) => summary.external_present += 1,
+                (false, false) => summary.external_missing += 1,
+            }
+        }
+        Ok(summary)
+    }
 
-        self.storage_manager.add(name, collection, endpoint, path)?;
-        Ok(project_dir)
+    pub(crate) fn pending_changes(&self) -> bool {
+        self.tree.has_pending_changes()
     }
 
-    #[instrument(skip(self))]
-    pub fn export_project(
-        &mut self,
-        name: &str,
-        collection: &str,
-        output_path: PathBuf,
-    ) -> Result<()> {
-        let output_tree_path = output_path.join(".tree");
-        let project = self.load_project(name, collection)?;
-        let mut project = project.lock().unwrap();
-        project.duplicate_tree(output_tree_path)?;
-        Ok(())
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn disk_diff(&self) -> Result<TreeDiff> {
+        // Where the cached tree and the stored one disagree, for checking before a reload
+        self.tree.disk_diff()
     }
 
-    #[instrument(skip(self))]
-    pub fn load_project(&mut self, name: &str, collection: &str) -> Result<Arc<Mutex<Project>>> {
-        let key = format!("{}/{}", collection, name);
-        if self.projects.contains_key(&key) {
-            let count = self.counts.get(&key).unwrap_or(&0);
-            self.counts.insert(key.clone(), count + 1);
-            return Ok(self.projects.get(&key).unwrap().clone());
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn manifest_hash(&mut self) -> Result<String> {
+        // A hash over every file's path, real path and metadata, so two projects with
+        // the same contents hash the same. It is kept until the tree next changes.
+        if let Some(hash) = self.tree.system_value(MANIFEST_HASH_KEY) {
+            return Ok(hash.to_string());
         }
-        let project_dir = load_project_dir(name, collection)?;
-        let storage_dir = self.storage_manager.get(name, collection)?;
-        let tree = FileSystem::load(name, project_dir)?;
-        let endpoint = LocalEndpoint::new(storage_dir.1);
+        let mut files = self.tree.files();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut entries = Vec::with_capacity(files.len());
+        for (path, file) in files {
+            let metadata: BTreeMap<String, String> =
+                self.tree.file_metadata(file)?.into_iter().collect();
+            let target = match &file.reference {
+                Some(reference) => reference.to_string(),
+                None => file.real_path.to_string_lossy().to_string(),
+            };
+            entries.push((path, target, metadata));
+        }
+        let bytes = serde_json::to_vec(&entries).map_err(|e| {
+            GodataError::new(
+                GodataErrorType::InternalError,
+                format!("Failed to serialize manifest: {}", e),
+            )
+        })?;
+        let hash = sha256_bytes(&bytes);
+        self.tree.cache_manifest_hash(hash.clone())?;
+        Ok(hash)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn gc(&self, dry_run: bool) -> Result<Vec<PathBuf>> {
+        // Files under the storage root that nothing in the tree points to, usually left
+        // behind by an operation that failed part way through.
+        let root = PathBuf::from(self._endpoint.root());
+        if !root.exists() {
+            return Ok(Vec::new());
+        }
+        let mut referenced = HashSet::new();
+        for (path, file) in self.tree.files() {
+            if let Ok(real_path) = self.stored_path(&path, file) {
+                referenced.insert(real_path);
+            }
+            if let Some(preview) = &file.preview {
+                referenced.insert(self._endpoint.resolve(Path::new(preview)));
+            }
+        }
+        let cutoff = SystemTime::now() - Duration::from_secs(gc_grace_period());
+        let mut orphans = Vec::new();
+        for path in list_files(&root)? {
+            if referenced.contains(&path) {
+                continue;
+            }
+            if std::fs::symlink_metadata(&path)?.modified()? > cutoff {
+                continue;
+            }
+            orphans.push(path);
+        }
+        orphans.sort();
+        if dry_run {
+            return Ok(orphans);
+        }
+        for path in &orphans {
+            std::fs::remove_file(path)?;
+            // Clean up any folders the file leaves empty, but never the root itself
+            let mut parent = path.parent();
+            while let Some(dir) = parent {
+                if dir == root || dir.read_dir()?.next().is_some() {
+                    break;
+                }
+                std::fs::remove_dir(dir)?;
+                parent = dir.parent();
+            }
+        }
+        if !orphans.is_empty() {
+            tracing::info!("Removed {} orphaned files from storage", orphans.len());
+        }
+        Ok(orphans)
+    }
 
-        let count = self.counts.get(&key).unwrap_or(&0);
-        self.counts.insert(key.clone(), count + 1);
+    pub(crate) fn shape(&self) -> Result<TreeShape> {
+        Ok(self.tree.shape())
+    }
 
-        let project = Project {
-            tree,
-            _name: name.to_string(),
-            _collection: collection.to_string(),
-            _endpoint: Box::new(endpoint),
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn export_json(&self, anonymize: bool) -> Result<TreeNode> {
+        let resolve = |path: &Path| self._endpoint.resolve(path);
+        let options = TreeOptions {
+            resolve: match anonymize {
+                true => None,
+                false => Some(&resolve),
+            },
+            with_metadata: true,
+            max_depth: None,
         };
-        let project = Arc::new(Mutex::new(project));
-        self.projects.insert(key, project.clone());
-        Ok(project)
+        self.tree.to_tree_node(None, &options)
     }
 
-    #[instrument(skip(self))]
-    pub(crate) fn drop_project(&mut self, name: &str, collection: &str) -> Result<()> {
-        let key = format!("{}/{}", collection, name);
-        let count = self.counts.get(&key);
-        if count.is_none() {
-            let message = format!(
-                "Tried to drop a project `{}` that was not in the cache",
-                key
-            );
-            tracing::error!(message);
-            return Err(GodataError::new(GodataErrorType::NotFound, message));
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn subtree(
+        &self,
+        project_path: Option<&str>,
+        max_depth: Option<usize>,
+        with_metadata: bool,
+    ) -> Result<TreeNode> {
+        let options = TreeOptions {
+            with_metadata,
+            max_depth,
+            ..Default::default()
+        };
+        self.tree.to_tree_node(project_path, &options)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn set_watch(&mut self, enabled: bool) -> Result<()> {
+        // Watching only covers the storage root, since that is where internal files live
+        if !enabled {
+            self._watcher = None;
+            return Ok(());
         }
-        let count = count.unwrap();
-        if count == &1 {
-            tracing::info!(
-                "Last connection to project `{}` dropped, removing from cache",
-                key
-            );
-            self.projects.remove(&key);
-            self.counts.remove(&key);
-        } else if count < &0 {
-            self.counts.remove(&key);
-            tracing::error!(
-                "Count for project `{}` is negative, this should not happen",
-                key
-            );
-            return Err(GodataError::new(
-                GodataErrorType::InternalError,
-                format!("Tried to drop a project `{}` that does not exist", key),
-            ));
-        } else {
-            tracing::info!("Dropping connection to project `{}`", key);
-            self.counts.insert(key, count - 1);
+        if self._watcher.is_none() {
+            let root = PathBuf::from(self._endpoint.root());
+            self._watcher = Some(StorageWatcher::new(&root)?);
         }
         Ok(())
     }
 
-    #[instrument(skip(self))]
-    pub fn delete_project(&mut self, name: &str, collection: &str, force: bool) -> Result<()> {
-        let key = format!("{}/{}", collection, name);
-        let pobj = self.projects.remove(&key);
-        if let Some(obj) = pobj {
-            let obj = obj.lock().unwrap();
-            drop(obj);
+    pub(crate) fn dangling(&self) -> Result<Vec<String>> {
+        // Files whose backing file has been removed since the watch started. Moving a
+        // folder away only reports the folder, so files inside removed folders count too.
+        let watcher = match &self._watcher {
+            Some(watcher) => watcher,
+            None => {
+                return Err(GodataError::new(
+                    GodataErrorType::NotPermitted,
+                    format!(
+                        "Project `{}/{}` is not being watched",
+                        self._collection, self._name
+                    ),
+                ))
+            }
+        };
+        let removed = watcher.removed();
+        if removed.is_empty() {
+            return Ok(Vec::new());
         }
+        let mut dangling: Vec<String> = self
+            .tree
+            .files()
+            .into_iter()
+            .filter(|(_, f)| f.reference.is_none())
+            .filter(|(_, f)| {
+                let path = self._endpoint.resolve(&f.real_path);
+                path.ancestors().any(|p| removed.contains(p)) && !path.exists()
+            })
+            .map(|(path, _)| path)
+            .collect();
+        dangling.sort();
+        Ok(dangling)
+    }
 
-        let project_dir = load_project_dir(name, collection)?;
-        let storage_dir = self.storage_manager.get(name, collection);
-        let project_is_empty = is_empty(&project_dir);
-        let mut storage_is_empty = storage_dir.is_err();
-        if storage_dir.is_ok() {
-            let storage_dir = storage_dir.unwrap();
-            let storage_path = storage_dir.1;
-            let mut files_in_storage = std::fs::read_dir(storage_path)?;
-            storage_is_empty = files_in_storage.next().is_none();
+    pub(crate) fn tracks_access(&self) -> bool {
+        self.tree.system_value(TRACK_ACCESS_KEY) == Some("true")
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn set_track_access(&mut self, enabled: bool) -> Result<()> {
+        // Tracking access means every get_file changes the tree, so it is off unless a
+        // project asks for it.
+        let value = enabled.then(|| "true".to_string());
+        self.tree.set_system_value(TRACK_ACCESS_KEY, value)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn set_path_template(&mut self, template: Option<String>) -> Result<()> {
+        // Only affects paths generated from now on, existing files stay where they are
+        let parsed = match &template {
+            Some(template) => Some(PathTemplate::new(template, &self._name, &self._collection)?),
+            None => None,
+        };
+        self.tree.set_system_value(PATH_TEMPLATE_KEY, template)?;
+        self._endpoint.set_path_template(parsed);
+        Ok(())
+    }
+
+    pub(crate) fn computes_checksums(&self) -> bool {
+        matches!(
+            self.tree.system_value(CHECKSUMS_KEY),
+            Some("true") | Some(BACKGROUND_CHECKSUMS)
+        )
+    }
+
+    pub(crate) fn hashes_in_background(&self) -> bool {
+        self.tree.system_value(CHECKSUMS_KEY) == Some(BACKGROUND_CHECKSUMS)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn set_external_metadata(&mut self, enabled: bool) -> Result<usize> {
+        // Returns how many files had their metadata moved
+        self.tree.set_external_metadata(enabled)
+    }
+
+    pub(crate) fn set_checksums(&mut self, enabled: bool, background: bool) -> Result<usize> {
+        // Turning checksums on also fills them in for files that don't have one yet.
+        // Returns how many files were hashed, or queued to be hashed in the background.
+        let value = match (enabled, background) {
+            (false, _) => None,
+            (true, false) => Some("true".to_string()),
+            (true, true) => Some(BACKGROUND_CHECKSUMS.to_string()),
+        };
+        self.tree.set_system_value(CHECKSUMS_KEY, value)?;
+        if !background {
+            self._pending_checksums.clear();
+        }
+        if !enabled {
+            return Ok(0);
+        }
+        if background {
+            return Ok(self.queue_missing_checksums());
         }
+        let mut checksums = Vec::new();
+        for (project_path, file) in self.tree.files() {
+            if file.checksum.is_some() || file.reference.is_some() {
+                continue;
+            }
+            let real_path = self._endpoint.resolve(&file.real_path);
+            match sha256_file(&real_path) {
+                Ok(checksum) => checksums.push((project_path, checksum)),
+                Err(e) => tracing::warn!("Could not checksum `{}`: {}", project_path, e),
+            }
+        }
+        let count = checksums.len();
+        self.tree.set_checksums(checksums)?;
+        Ok(count)
+    }
 
-        if (project_is_empty && storage_is_empty) || force {
-            delete_project_dir(name, collection)?;
-            let storage_dir = self.storage_manager.get(name, collection);
-            if storage_dir.is_ok() {
-                self.storage_manager.delete(name, collection)?;
+    fn queue_missing_checksums(&mut self) -> usize {
+        // Queue up every file that should have a checksum but doesn't
+        let queued: HashSet<&String> = self._pending_checksums.iter().collect();
+        let missing: Vec<String> = self
+            .tree
+            .files()
+            .into_iter()
+            .filter(|(path, file)| {
+                file.checksum.is_none() && file.reference.is_none() && !queued.contains(path)
+            })
+            .map(|(path, _)| path)
+            .collect();
+        let count = missing.len();
+        self._pending_checksums.extend(missing);
+        count
+    }
+
+    pub(crate) fn next_checksum(&mut self) -> Option<(String, PathBuf)> {
+        // The next queued file that still needs hashing, along with where it lives.
+        // Files that were removed or hashed since they were queued are skipped.
+        while let Some(project_path) = self._pending_checksums.pop_front() {
+            match self.tree.get(&project_path) {
+                Ok(file) if file.checksum.is_none() && file.reference.is_none() => {
+                    return Some((project_path, self._endpoint.resolve(&file.real_path)));
+                }
+                _ => continue,
             }
-            return Ok(());
         }
-        tracing::error!(
-            "Project `{}` is not empty, not deleting",
-            format!("{}/{}", collection, name)
-        );
-        Err(GodataError::new(
-            GodataErrorType::NotPermitted,
-            "Project is not empty".to_string(),
-        ))
+        None
     }
 
-    #[instrument(skip(self))]
-    pub fn get_project_names(&self, collection: String, show_hidden: bool) -> Result<Vec<String>> {
-        let collection_dir = load_collection_dir(&collection);
-        if collection_dir.is_err() {
+    #[instrument(skip(self, checksum), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn finish_checksum(
+        &mut self,
+        project_path: &str,
+        real_path: &Path,
+        checksum: Result<String>,
+    ) {
+        // Store a checksum computed in the background. The file may have been removed
+        // or relinked while it was being hashed, in which case the result is thrown away.
+        let checksum = match checksum {
+            Ok(checksum) => checksum,
+            Err(e) => {
+                tracing::warn!("Could not checksum `{}`: {}", project_path, e);
+                self._checksums_failed += 1;
+                return;
+            }
+        };
+        let unchanged = match self.tree.get(project_path) {
+            Ok(file) => {
+                file.checksum.is_none() && self._endpoint.resolve(&file.real_path) == real_path
+            }
+            Err(_) => false,
+        };
+        if !unchanged {
+            tracing::info!("`{}` changed while it was being hashed", project_path);
+            return;
+        }
+        match self
+            .tree
+            .set_checksums(vec![(project_path.to_string(), checksum)])
+        {
+            Ok(_) => self._checksums_hashed += 1,
+            Err(e) => {
+                tracing::error!("Failed to store checksum for `{}`: {}", project_path, e);
+                self._checksums_failed += 1;
+            }
+        }
+    }
+
+    pub(crate) fn checksum_status(&self) -> ChecksumStatus {
+        ChecksumStatus {
+            background: self.hashes_in_background(),
+            pending: self._pending_checksums.len(),
+            hashed: self._checksums_hashed,
+            failed: self._checksums_failed,
+        }
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn find_duplicates(&self) -> Result<Vec<Vec<String>>> {
+        // Groups of files with identical contents. Files without a checksum are left out.
+        if !self.computes_checksums() {
             return Err(GodataError::new(
-                GodataErrorType::NotFound,
-                format!("Collection `{}` does not exist", collection),
+                GodataErrorType::NotPermitted,
+                format!(
+                    "Project `{}/{}` does not compute checksums",
+                    self._collection, self._name
+                ),
             ));
         }
-        let collection_dir = collection_dir.unwrap();
-
-        let mut names = Vec::new();
-        for entry in std::fs::read_dir(collection_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir()
-                && (!path.file_name().unwrap().to_str().unwrap().starts_with('.') || show_hidden)
-            {
-                let name = path.file_name().unwrap().to_str().unwrap().to_string();
-                names.push(name);
+        let mut groups: HashMap<&str, Vec<String>> = HashMap::new();
+        let files = self.tree.files();
+        for (project_path, file) in files.iter() {
+            if let Some(checksum) = &file.checksum {
+                groups
+                    .entry(checksum.as_str())
+                    .or_default()
+                    .push(project_path.clone());
             }
         }
-        Ok(names)
+        let mut duplicates: Vec<Vec<String>> = groups
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .map(|mut group| {
+                group.sort();
+                group
+            })
+            .collect();
+        duplicates.sort();
+        Ok(duplicates)
     }
-}
 
-pub fn get_collection_names(show_hidden: bool) -> Result<Vec<String>> {
-    let main_dir = crate::locations::get_main_dir();
-    let mut names = Vec::new();
-    for entry in std::fs::read_dir(main_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir()
-            && (!path.file_name().unwrap().to_str().unwrap().starts_with('.') || show_hidden)
-        {
-            let name = path.file_name().unwrap().to_str().unwrap().to_string();
-            names.push(name);
-        }
+    pub(crate) fn recently_accessed(&self, limit: usize) -> Result<Vec<FileInfo>> {
+        let mut files: Vec<(String, &File)> = self
+            .tree
+            .files()
+            .into_iter()
+            .filter(|(_, f)| f.accessed_at.is_some())
+            .collect();
+        files.sort_by_key(|(_, f)| std::cmp::Reverse(f.accessed_at));
+        files
+            .into_iter()
+            .take(limit)
+            .map(|(path, f)| self.file_info(path, f))
+            .collect::<Result<Vec<_>>>()
+    }
+
+    pub(crate) fn recent(&self, limit: usize) -> Result<Vec<FileInfo>> {
+        // Files that haven't been changed since modification times were tracked are left out
+        let mut files: Vec<(String, &File)> = self
+            .tree
+            .files()
+            .into_iter()
+            .filter(|(_, f)| f.modified_at.is_some())
+            .collect();
+        files.sort_by_key(|(_, f)| std::cmp::Reverse(f.modified_at));
+        files
+            .into_iter()
+            .take(limit)
+            .map(|(path, f)| self.file_info(path, f))
+            .collect::<Result<Vec<_>>>()
+    }
+
+    #[instrument(skip(self, selection), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn zip_contents(&self, selection: ZipSelection) -> Result<ZipContents> {
+        // Only internal files go into a zip download. External files and references
+        // are left out and noted in the archive's manifest.
+        let paths = match selection {
+            ZipSelection::Paths(paths) => paths,
+            ZipSelection::Matching {
+                pattern,
+                project_path,
+            } => {
+                let regex = glob_to_regex(&pattern)?;
+                self.tree
+                    .get_many(project_path.as_deref(), &regex)?
+                    .into_iter()
+                    .map(|f| match &project_path {
+                        Some(folder) => format!("{}/{}", folder, f.name),
+                        None => f.name.clone(),
+                    })
+                    .collect()
+            }
+        };
+        let mut contents = ZipContents {
+            files: Vec::new(),
+            skipped: Vec::new(),
+        };
+        let mut sizes = Vec::new();
+        for path in paths {
+            let file = self.tree.get(&path)?;
+            let real_path = self._endpoint.resolve(&file.real_path);
+            let reason = match &file.reference {
+                Some(reference) => Some(format!("Refers to `{}`", reference)),
+                None if !self._endpoint.is_internal(&real_path) => Some(format!(
+                    "External file, read it from `{}` instead",
+                    real_path.display()
+                )),
+                None => None,
+            };
+            match reason {
+                Some(reason) => contents.skipped.push(SkippedFile { path, reason }),
+                None => {
+                    sizes.push(file_size(&real_path));
+                    contents.files.push((path, real_path));
+                }
+            }
+        }
+        // Catch archives that are too big up front, rather than partway through sending
+        let entries: Vec<(&str, u64)> = contents
+            .files
+            .iter()
+            .zip(sizes.iter())
+            .map(|((path, _), size)| (path.as_str(), *size))
+            .collect();
+        if !zip::fits(&entries) {
+            return Err(GodataError::new(
+                GodataErrorType::TooLarge,
+                format!(
+                    "{} files totalling {} bytes is too much for a single zip download",
+                    contents.files.len(),
+                    sizes.iter().sum::<u64>()
+                ),
+            ));
+        }
+        Ok(contents)
+    }
+
+    pub(crate) fn modified_since(&self, since: i64) -> Result<Vec<FileInfo>> {
+        // Files changed after `since`, oldest first. Files without a modification time
+        // are left out, as they haven't changed since modification times were tracked.
+        let mut files: Vec<(String, &File)> = self
+            .tree
+            .files()
+            .into_iter()
+            .filter(|(_, f)| f.modified_at.is_some_and(|t| t > since))
+            .collect();
+        files.sort_by(|a, b| (a.1.modified_at, &a.0).cmp(&(b.1.modified_at, &b.0)));
+        files
+            .into_iter()
+            .map(|(path, f)| self.file_info(path, f))
+            .collect::<Result<Vec<_>>>()
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn largest(&self, limit: usize) -> Result<Vec<SizedFileInfo>> {
+        // Sizes aren't stored in the tree, so every file has to be stat'ed, which can be
+        // slow for big projects. Files that have gone missing on disk are left out, as
+        // are references.
+        let mut files: Vec<(u64, String, &File)> = self
+            .tree
+            .files()
+            .into_iter()
+            .filter_map(|(path, f)| {
+                let meta = std::fs::metadata(self.stored_path(&path, f).ok()?).ok()?;
+                Some((meta.len(), path, f))
+            })
+            .collect();
+        files.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        files
+            .into_iter()
+            .take(limit)
+            .map(|(size, path, f)| {
+                Ok(SizedFileInfo {
+                    file: self.file_info(path, f)?,
+                    size,
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+    }
+
+    #[instrument(skip(self, out), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn export_csv<W: std::io::Write>(&self, mut out: W) -> Result<()> {
+        // One row per file, with a column for every metadata key any file has. Keys are
+        // gathered on a first pass so every row lines up with the header. Sizes are
+        // left empty for files missing from disk, and references have no real path.
+        let mut keys = BTreeSet::new();
+        let mut rows = Vec::new();
+        for (path, file) in self.tree.files() {
+            let metadata = self.tree.file_metadata(file)?;
+            keys.extend(metadata.keys().cloned());
+            let (real_path, size) = match file.reference {
+                Some(_) => (String::new(), String::new()),
+                None => {
+                    let real_path = self._endpoint.resolve(&file.real_path);
+                    let size = std::fs::metadata(&real_path)
+                        .map_or(String::new(), |m| m.len().to_string());
+                    (real_path.to_str().unwrap().to_string(), size)
+                }
+            };
+            rows.push((path, real_path, size, metadata));
+        }
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut header = vec!["virtual_path", "real_path", "size"];
+        header.extend(keys.iter().map(|k| k.as_str()));
+        manifest::write_row(&mut out, &header)?;
+        for (path, real_path, size, metadata) in &rows {
+            let mut fields = vec![path.as_str(), real_path.as_str(), size.as_str()];
+            fields.extend(
+                keys.iter()
+                    .map(|k| metadata.get(k).map_or("", |v| v.as_str())),
+            );
+            manifest::write_row(&mut out, &fields)?;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn file_context(&self, project_path: &str) -> Result<FileContextInfo> {
+        // A file together with what it inherits from its folders and what sits next to it
+        let context = self.tree.file_context(project_path)?;
+        Ok(FileContextInfo {
+            file: self.file_info(project_path.to_string(), context.file)?,
+            inherited: context.inherited,
+            siblings: context.siblings,
+        })
+    }
+
+    fn file_info(&self, project_path: String, file: &File) -> Result<FileInfo> {
+        let real_path = match &file.reference {
+            Some(_) => String::new(),
+            None => {
+                let real_path = self._endpoint.resolve(&file.real_path);
+                real_path.to_str().unwrap().to_string()
+            }
+        };
+        Ok(FileInfo {
+            uuid: file.uuid().to_string(),
+            project_path,
+            real_path,
+            origin: file.origin.clone(),
+            accessed_at: file.accessed_at,
+            modified_at: file.modified_at,
+            checksum: file.checksum.clone(),
+            reference: file.reference.as_ref().map(|r| r.to_string()),
+            metadata: self.tree.file_metadata(file)?,
+        })
+    }
+
+    pub(crate) fn list(
+        &self,
+        project_path: Option<String>,
+        kind: Option<ObjectKind>,
+    ) -> Result<HashMap<String, Vec<String>>> {
+        let list = self.tree.list(project_path, kind)?;
+        Ok(list)
+    }
+
+    pub(crate) fn list_detailed(
+        &self,
+        project_path: Option<&str>,
+        kind: Option<ObjectKind>,
+        fields: Option<&[String]>,
+    ) -> Result<DetailedListing> {
+        self.tree.list_detailed(project_path, kind, fields)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn relink(&mut self, project_path: &str, real_path: PathBuf) -> Result<()> {
+        let relpath = self._endpoint.get_relative_path(&real_path);
+        self.tree.relink(project_path, relpath)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn internalize(&mut self, project_path: &str) -> Result<StorageChange> {
+        // Copy an externally linked file into the project's storage and point the entry
+        // at the copy. The external file is left where it is.
+        self.check_storage()?;
+        let file = self.tree.get(project_path)?;
+        let real_path = self.stored_path(project_path, file)?;
+        if self._endpoint.is_internal(&real_path) {
+            return Ok(StorageChange {
+                project_path: project_path.to_string(),
+                real_path: real_path.to_str().unwrap().to_string(),
+                changed: false,
+                removed: None,
+            });
+        }
+        self.check_quota(std::slice::from_ref(&real_path), &[])?;
+        let generation = self.tree.generation();
+        let suffix = real_path.extension().and_then(|e| e.to_str());
+        let internal_path = self._endpoint.generate_unique_path(project_path, suffix)?;
+        if let Err(e) = self._endpoint.copy_file(&real_path, &internal_path) {
+            let _ = std::fs::remove_file(&internal_path);
+            return Err(e);
+        }
+        let relpath = self._endpoint.get_relative_path(&internal_path);
+        self.tree.relink(project_path, relpath)?;
+        self.track_usage(generation, std::slice::from_ref(&internal_path), &[]);
+        Ok(StorageChange {
+            project_path: project_path.to_string(),
+            real_path: internal_path.to_str().unwrap().to_string(),
+            changed: true,
+            removed: None,
+        })
+    }
+
+    fn copy_subtree_from(&mut self, source: &Project, folder: &str) -> Result<usize> {
+        // Add every file under `folder` in `source` to this project, with paths
+        // re-rooted at the folder. Internal files are copied into this project's
+        // storage, external files are linked where they are.
+        source.check_storage()?;
+        self.check_storage()?;
+        let folder = folder.trim_end_matches('/');
+        let prefix = format!("{}/", folder);
+        let mut checksums = Vec::new();
+        let files = source.tree.files_under(folder)?;
+        // Internal files are copied, so they count towards this project's quota
+        let incoming: Vec<PathBuf> = files
+            .iter()
+            .filter(|(_, file)| file.reference.is_none())
+            .map(|(_, file)| source._endpoint.resolve(&file.real_path))
+            .filter(|path| source._endpoint.is_internal(path))
+            .collect();
+        self.check_quota(&incoming, &[])?;
+        for (source_path, file) in files.iter() {
+            let project_path = source_path.strip_prefix(&prefix).unwrap();
+            if let Some(reference) = &file.reference {
+                // References are kept as references, they don't point into storage
+                self.tree
+                    .insert_reference(project_path, reference.clone(), false)?;
+                continue;
+            }
+            let real_path = source._endpoint.resolve(&file.real_path);
+            let stored_path = match source._endpoint.is_internal(&real_path) {
+                true => {
+                    let suffix = real_path.extension().and_then(|e| e.to_str());
+                    let new_path = self._endpoint.generate_unique_path(project_path, suffix)?;
+                    self._endpoint.copy_file(&real_path, &new_path)?;
+                    new_path
+                }
+                false => real_path,
+            };
+            let relpath = self._endpoint.get_relative_path(&stored_path);
+            let metadata = source.tree.file_metadata(file)?;
+            self.tree
+                .insert(project_path, relpath, metadata, file.origin.clone(), false)?;
+            if let Some(checksum) = &file.checksum {
+                checksums.push((project_path.to_string(), checksum.clone()));
+            }
+        }
+        self.tree.set_checksums(checksums)?;
+        Ok(files.len())
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn externalize(
+        &mut self,
+        project_path: &str,
+        dest: PathBuf,
+        force: bool,
+    ) -> Result<StorageChange> {
+        // Copy an internal file out of the project's storage and point the entry at the
+        // copy, so the project no longer owns the data. The internal copy is handed back
+        // for the caller to delete, like any other file the project stops using.
+        self.check_storage()?;
+        let file = self.tree.get(project_path)?;
+        let real_path = self.stored_path(project_path, file)?;
+        if !self._endpoint.is_internal(&real_path) {
+            return Err(GodataError::new(
+                GodataErrorType::NotPermitted,
+                format!(
+                    "`{}` is already stored outside the project at `{}`",
+                    project_path,
+                    real_path.display()
+                ),
+            ));
+        }
+        if !dest.is_absolute() || self._endpoint.is_internal(&dest) {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!(
+                    "`{}` must be an absolute path outside of the project's storage",
+                    dest.display()
+                ),
+            ));
+        }
+        if dest.exists() && !force {
+            return Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("`{}` already exists", dest.display()),
+            ));
+        }
+        self._endpoint.copy_file(&real_path, &dest)?;
+        self.tree.relink(project_path, dest.clone())?;
+        Ok(StorageChange {
+            project_path: project_path.to_string(),
+            real_path: dest.to_str().unwrap().to_string(),
+            changed: true,
+            removed: Some(real_path.to_str().unwrap().to_string()),
+        })
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn remove_file(&mut self, project_path: &str) -> Result<Vec<PathBuf>> {
+        self.check_storage()?;
+        let removed_internal_paths = self.tree.remove(project_path)?;
+        // filter out paths that are not internal
+        let need_to_remove: Vec<PathBuf> = removed_internal_paths
+            .into_iter()
+            .map(|x| self._endpoint.resolve(&x.real_path))
+            .filter(|x| self._endpoint.is_internal(x))
+            .collect();
+        Ok(need_to_remove)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn remove_many(&mut self, project_paths: Vec<String>) -> Result<RemovedFiles> {
+        let results = self.tree.remove_many(&project_paths)?;
+        let mut report = RemovedFiles::default();
+        for (path, result) in results {
+            match result {
+                Ok(files) => {
+                    report.internal_paths.extend(
+                        files
+                            .into_iter()
+                            .map(|x| self._endpoint.resolve(&x.real_path))
+                            .filter(|x| self._endpoint.is_internal(x))
+                            .map(|x| x.to_str().unwrap().to_string()),
+                    );
+                    report.removed.push(path);
+                }
+                Err(e) => {
+                    report.failed.insert(path, e.message);
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn remove_matching(
+        &mut self,
+        folder_path: Option<&str>,
+        pattern: &str,
+        dry_run: bool,
+        confirm: bool,
+    ) -> Result<RemovedFiles> {
+        if pattern.is_empty() {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                "Pattern cannot be empty".to_string(),
+            ));
+        }
+        if let Some(folder) = folder_path {
+            if self.tree.kind(folder)? == "file" {
+                return Err(GodataError::new(
+                    GodataErrorType::InvalidPath,
+                    format!("Path `{}` is a file", folder),
+                ));
+            }
+        }
+        // The folder is known to exist, so not finding anything just means there is
+        // nothing to remove
+        let matching = |regex| match self.tree.get_many(folder_path, regex) {
+            Ok(files) => Ok(files),
+            Err(e) if e.error_type == GodataErrorType::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        };
+        let regex = glob_to_regex(pattern)?;
+        let everything = glob_to_regex("*")?;
+        let matching_files = matching(&regex)?;
+        // A wildcard pattern that catches every file in the folder clears it out, so
+        // make sure the caller actually meant to do that. Looking at what it matched
+        // catches patterns like `*.*` as well as `*`.
+        let clears_folder = pattern.contains(['*', '?', '['])
+            && !matching_files.is_empty()
+            && matching_files.len() == matching(&everything)?.len();
+        if clears_folder && !confirm && !dry_run {
+            return Err(GodataError::new(
+                GodataErrorType::NotPermitted,
+                format!(
+                    "Pattern `{}` matches every file, pass confirm=true to remove them",
+                    pattern
+                ),
+            ));
+        }
+        let paths: Vec<String> = matching_files
+            .iter()
+            .map(|f| match folder_path {
+                Some(folder) => format!("{}/{}", folder, f.name),
+                None => f.name.clone(),
+            })
+            .collect();
+        if !dry_run {
+            return self.remove_many(paths);
+        }
+        let internal_paths = matching_files
+            .iter()
+            .map(|f| self._endpoint.resolve(&f.real_path))
+            .filter(|x| self._endpoint.is_internal(x))
+            .map(|x| x.to_str().unwrap().to_string())
+            .collect();
+        Ok(RemovedFiles {
+            removed: paths,
+            failed: HashMap::new(),
+            internal_paths,
+        })
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn move_(
+        &mut self,
+        from: &str,
+        to: &str,
+        overwrite: bool,
+        backup: bool,
+    ) -> Result<MoveReport> {
+        if !self.tree.exists(from) {
+            return Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!("Source path `{}` does not exist", from),
+            ));
+        }
+        if to.starts_with(&format!("{}/", from)) {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Cannot move `{}` into itself", from),
+            ));
+        }
+        let backup_path = match backup && overwrite && from != to {
+            true => self.backup_entry(to)?,
+            false => None,
+        };
+        // Whatever is still at the destination gets overwritten, so it is described
+        // before its metadata goes away
+        let displaced = match overwrite && from != to && self.tree.exists(to) {
+            true => self.file_infos(to)?,
+            false => Vec::new(),
+        };
+        let result = match self.tree.move_(from, to, overwrite) {
+            Ok(result) => result,
+            Err(e) => {
+                self.restore_backup(to, backup_path);
+                return Err(e);
+            }
+        };
+        let removed: Vec<String> = result
+            .unwrap_or_default()
+            .into_iter()
+            .map(|x| self._endpoint.resolve(&x.real_path))
+            .filter(|x| self._endpoint.is_internal(x))
+            .map(|x| x.to_str().unwrap().to_string())
+            .collect();
+        Ok(MoveReport {
+            moved: self.file_infos(to)?,
+            displaced,
+            removed,
+            backup: backup_path,
+            collisions: Vec::new(),
+        })
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn move_matching(
+        &mut self,
+        pattern: &str,
+        dest_folder: &str,
+        overwrite: bool,
+    ) -> Result<MoveReport> {
+        // Move every file in the project whose name matches the glob into dest_folder,
+        // keeping its name. Only files are matched, so a folder can never end up inside
+        // itself. Files whose name is already taken in the destination are left where
+        // they are unless `overwrite` is set, and two matches with the same name never
+        // replace each other.
+        if pattern.is_empty() {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                "Pattern cannot be empty".to_string(),
+            ));
+        }
+        if self.tree.get(dest_folder).is_ok() {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("`{}` is a file", dest_folder),
+            ));
+        }
+        let regex = glob_to_regex(pattern)?;
+        let (dest_files, dest_folders): (HashSet<String>, HashSet<String>) =
+            match self.tree.list(Some(dest_folder.to_string()), None) {
+                Ok(mut children) => (
+                    children
+                        .remove("files")
+                        .unwrap_or_default()
+                        .into_iter()
+                        .collect(),
+                    children
+                        .remove("folders")
+                        .unwrap_or_default()
+                        .into_iter()
+                        .collect(),
+                ),
+                Err(e) if e.error_type == GodataErrorType::NotFound => Default::default(),
+                Err(e) => return Err(e),
+            };
+        let mut files: Vec<(String, String)> = self
+            .tree
+            .files()
+            .into_iter()
+            .filter(|(_, f)| regex.is_match(&f.name))
+            .map(|(path, f)| (path, f.name.clone()))
+            .collect();
+        files.sort();
+
+        let mut report = MoveReport {
+            moved: Vec::new(),
+            displaced: Vec::new(),
+            removed: Vec::new(),
+            backup: None,
+            collisions: Vec::new(),
+        };
+        let mut claimed: HashSet<String> = HashSet::new();
+        let mut moves = Vec::new();
+        for (path, name) in files {
+            if path.rsplit_once('/').map(|(parent, _)| parent) == Some(dest_folder) {
+                // Already directly in the destination
+                continue;
+            }
+            let taken = dest_folders.contains(&name)
+                || (dest_files.contains(&name) && !overwrite)
+                || claimed.contains(&name);
+            if taken {
+                report.collisions.push(path);
+                continue;
+            }
+            claimed.insert(name.clone());
+            moves.push((path, format!("{}/{}", dest_folder, name)));
+        }
+
+        for (from, to) in moves {
+            if self.tree.exists(&to) {
+                report.displaced.extend(self.file_infos(&to)?);
+            }
+            let displaced = self.tree.move_(&from, &to, overwrite)?;
+            report.removed.extend(
+                displaced
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|x| self._endpoint.resolve(&x.real_path))
+                    .filter(|x| self._endpoint.is_internal(x))
+                    .map(|x| x.to_str().unwrap().to_string()),
+            );
+            report
+                .moved
+                .push(self.file_info(to.clone(), self.tree.get(&to)?)?);
+        }
+        Ok(report)
+    }
+
+    pub(crate) fn get_by_uuid(&self, uuid: &str) -> Result<FileInfo> {
+        // Files keep their uuid through renames and moves, so it works as a permanent
+        // handle where a path wouldn't
+        let project_path = self.tree.path_of(uuid)?;
+        let file = self.tree.get(&project_path)?;
+        self.file_info(project_path, file)
+    }
+
+    fn file_infos(&self, project_path: &str) -> Result<Vec<FileInfo>> {
+        // Describe the file at a path, or every file under it if it is a folder
+        if let Ok(file) = self.tree.get(project_path) {
+            return Ok(vec![self.file_info(project_path.to_string(), file)?]);
+        }
+        self.tree
+            .files_under(project_path)?
+            .into_iter()
+            .map(|(path, file)| self.file_info(path, file))
+            .collect()
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn flatten(
+        &mut self,
+        source_folder: &str,
+        dest_folder: &str,
+        strategy: CollisionStrategy,
+    ) -> Result<FlattenReport> {
+        // Move every file below source_folder directly into dest_folder. Names are
+        // all worked out before anything moves, so the error strategy leaves the tree
+        // untouched. Folders emptied by the moves are dropped as usual.
+        let mut taken: HashSet<String> = match self.tree.list(Some(dest_folder.to_string()), None) {
+            Ok(children) => children.into_values().flatten().collect(),
+            Err(e) if e.error_type == GodataErrorType::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        let mut files: Vec<(String, String)> = self
+            .tree
+            .files_under(source_folder)?
+            .into_iter()
+            .map(|(path, f)| (path, f.name.clone()))
+            .collect();
+        files.sort();
+
+        let mut report = FlattenReport::default();
+        let mut moves = Vec::new();
+        for (path, name) in files {
+            if path.rsplit_once('/').map(|(parent, _)| parent) == Some(dest_folder) {
+                // Already directly in the destination
+                continue;
+            }
+            let mut new_name = name.clone();
+            if taken.contains(&name) {
+                report.collisions.push(path.clone());
+                match strategy {
+                    CollisionStrategy::Error => {
+                        return Err(GodataError::new(
+                            GodataErrorType::AlreadyExists,
+                            format!(
+                                "`{}` would collide with another `{}` in `{}`",
+                                path, name, dest_folder
+                            ),
+                        ))
+                    }
+                    CollisionStrategy::Skip => continue,
+                    CollisionStrategy::Suffix => new_name = unused_name(&name, &taken),
+                }
+            }
+            taken.insert(new_name.clone());
+            moves.push((path, format!("{}/{}", dest_folder, new_name)));
+        }
+
+        for (from, to) in moves {
+            self.tree.move_(&from, &to, false)?;
+            report.moved += 1;
+        }
+        Ok(report)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn rename(&mut self, project_path: &str, new_name: &str) -> Result<()> {
+        self.tree.rename(project_path, new_name)
+    }
+
+    pub(crate) fn history(&self, limit: usize) -> Result<Vec<LogEntry>> {
+        self.tree.history(limit)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn undo(&mut self, count: usize) -> Result<Vec<String>> {
+        // Returns a description of each operation that was undone, newest first
+        self.tree.undo(count)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn swap(&mut self, path_a: &str, path_b: &str) -> Result<()> {
+        self.tree.swap(path_a, path_b)
+    }
+
+    pub(crate) fn child_counts(
+        &self,
+        project_path: Option<&str>,
+    ) -> Result<HashMap<String, usize>> {
+        self.tree.child_counts(project_path)
+    }
+
+    pub(crate) fn usage_breakdown(
+        &self,
+        project_path: Option<&str>,
+    ) -> Result<HashMap<String, u64>> {
+        // Bytes used under each immediate subfolder, with files directly in the folder
+        // under `.`
+        let groups = self.tree.files_by_child(project_path)?;
+        Ok(groups
+            .into_iter()
+            .map(|(name, files)| (name, self.total_size(&files)))
+            .collect())
+    }
+
+    pub(crate) fn ancestors(&self, project_path: &str) -> Result<Vec<String>> {
+        self.tree.ancestors(project_path)
+    }
+
+    pub(crate) fn exists(&self, project_path: String) -> bool {
+        self.tree.exists(&project_path)
+    }
+
+    pub(crate) fn kind(&self, project_path: &str) -> Result<&str> {
+        self.tree.kind(project_path)
+    }
+
+    pub(crate) fn folder_contains(&self, folder_path: Option<&str>, name: &str) -> bool {
+        self.tree.folder_contains(folder_path, name)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn read_content(&self, project_path: &str) -> Result<(Vec<u8>, String)> {
+        // The contents of a small internal file, along with a guess at its content type.
+        // External files are refused the same way oversized files are, since the client
+        // has to read either one from disk itself.
+        let file = self.tree.get(project_path)?;
+        let real_path = self.stored_path(project_path, file)?;
+        if !self._endpoint.is_internal(&real_path) {
+            return Err(GodataError::new(
+                GodataErrorType::TooLarge,
+                format!(
+                    "`{}` is an external file, read it from `{}` instead",
+                    project_path,
+                    real_path.display()
+                ),
+            ));
+        }
+        let content = self.read_inline(&format!("`{}`", project_path), &file.real_path)?;
+        let content_type = mime_guess::from_path(project_path)
+            .first_or_octet_stream()
+            .to_string();
+        Ok((content, content_type))
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn read_preview(&self, project_path: &str) -> Result<(Vec<u8>, String)> {
+        let file = self.tree.get(project_path)?;
+        let preview = match &file.preview {
+            Some(preview) => PathBuf::from(preview),
+            None => {
+                return Err(GodataError::new(
+                    GodataErrorType::NotFound,
+                    format!("`{}` has no preview", project_path),
+                ))
+            }
+        };
+        let label = format!("The preview for `{}`", project_path);
+        let content = self.read_inline(&label, &preview)?;
+        let content_type = mime_guess::from_path(&preview)
+            .first_or_octet_stream()
+            .to_string();
+        Ok((content, content_type))
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn set_preview(
+        &mut self,
+        project_path: &str,
+        preview: Option<PathBuf>,
+    ) -> Result<()> {
+        // Previews can live inside or outside of the project's storage, but they have
+        // to exist when they are attached
+        let preview = match preview {
+            Some(path) if !path.is_file() => {
+                return Err(GodataError::new(
+                    GodataErrorType::NotFound,
+                    format!("No file found at `{}`", path.display()),
+                ))
+            }
+            Some(path) => Some(
+                self._endpoint
+                    .get_relative_path(&path)
+                    .to_str()
+                    .unwrap()
+                    .to_string(),
+            ),
+            None => None,
+        };
+        self.tree.set_preview(project_path, preview)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn copy_metadata(
+        &mut self,
+        source: &str,
+        dest: &str,
+        overwrite_existing: bool,
+    ) -> Result<usize> {
+        // Merge the source file's metadata into the destination's. Keys the destination
+        // already has are only replaced if `overwrite_existing` is set. Returns the
+        // number of keys written.
+        let source_metadata = self.tree.file_metadata(self.tree.get(source)?)?;
+        let mut metadata = self.tree.file_metadata(self.tree.get(dest)?)?;
+        let mut count = 0;
+        for (key, value) in source_metadata {
+            if is_reserved_key(&key) || (!overwrite_existing && metadata.contains_key(&key)) {
+                continue;
+            }
+            metadata.insert(key, value);
+            count += 1;
+        }
+        self.tree.set_file_metadata(dest, metadata)?;
+        Ok(count)
+    }
+
+    #[instrument(skip(self, metadata), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn set_metadata_matching(
+        &mut self,
+        folder_path: Option<&str>,
+        pattern: &str,
+        metadata: HashMap<String, String>,
+        merge: bool,
+    ) -> Result<usize> {
+        // Apply `metadata` to every file in the folder whose name matches the glob. With
+        // `merge` the new keys are added to what each file already has, otherwise they
+        // replace it. Returns the number of files changed.
+        if pattern.is_empty() {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                "Pattern cannot be empty".to_string(),
+            ));
+        }
+        if let Some(key) = metadata.keys().find(|key| is_reserved_key(key)) {
+            return Err(GodataError::new(
+                GodataErrorType::NotPermitted,
+                format!("Metadata key `{}` is reserved", key),
+            ));
+        }
+        let regex = glob_to_regex(pattern)?;
+        let mut updates = Vec::new();
+        for file in self.tree.get_many(folder_path, &regex)? {
+            let path = match folder_path {
+                Some(folder) => format!("{}/{}", folder, file.name),
+                None => file.name.clone(),
+            };
+            let file_metadata = match merge {
+                true => {
+                    let mut existing = self.tree.file_metadata(file)?;
+                    existing.extend(metadata.clone());
+                    existing
+                }
+                false => metadata.clone(),
+            };
+            updates.push((path, file_metadata));
+        }
+        let count = updates.len();
+        if count > 0 {
+            self.tree.set_files_metadata(updates)?;
+        }
+        Ok(count)
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn rename_metadata_key(
+        &mut self,
+        old_key: &str,
+        new_key: &str,
+        overwrite: bool,
+    ) -> Result<usize> {
+        // Move the value under `old_key` to `new_key` on every file that has one. Files
+        // that already have `new_key` are left alone unless `overwrite` is set. Returns
+        // the number of files changed.
+        for key in [old_key, new_key] {
+            if is_reserved_key(key) {
+                return Err(GodataError::new(
+                    GodataErrorType::NotPermitted,
+                    format!("Metadata key `{}` is reserved", key),
+                ));
+            }
+        }
+        if old_key == new_key {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Can't rename metadata key `{}` to itself", old_key),
+            ));
+        }
+        self.tree.update_file_metadata(|metadata| {
+            if !metadata.contains_key(old_key) || (!overwrite && metadata.contains_key(new_key)) {
+                return false;
+            }
+            let value = metadata.remove(old_key).unwrap();
+            metadata.insert(new_key.to_string(), value);
+            true
+        })
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn delete_metadata_key(&mut self, key: &str) -> Result<usize> {
+        // Drop `key` from every file that has it, returning the number of files changed
+        if is_reserved_key(key) {
+            return Err(GodataError::new(
+                GodataErrorType::NotPermitted,
+                format!("Metadata key `{}` is reserved", key),
+            ));
+        }
+        self.tree
+            .update_file_metadata(|metadata| metadata.remove(key).is_some())
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn cas_metadata(
+        &mut self,
+        project_path: &str,
+        key: &str,
+        expected: Option<String>,
+        new: String,
+    ) -> Result<bool> {
+        // Set a metadata value only if the current one is `expected`, where None means
+        // the key isn't set. Callers hold the project lock throughout, so two workers
+        // can't both win.
+        if is_reserved_key(key) {
+            return Err(GodataError::new(
+                GodataErrorType::NotPermitted,
+                format!("Metadata key `{}` is reserved", key),
+            ));
+        }
+        let mut metadata = self.tree.file_metadata(self.tree.get(project_path)?)?;
+        if metadata.get(key) != expected.as_ref() {
+            return Ok(false);
+        }
+        metadata.insert(key.to_string(), new);
+        self.tree.set_file_metadata(project_path, metadata)?;
+        Ok(true)
+    }
+
+    fn read_inline(&self, label: &str, relpath: &Path) -> Result<Vec<u8>> {
+        // Read a whole file, as long as it's under the inline content limit
+        let limit = inline_content_limit();
+        let size = std::fs::metadata(self._endpoint.resolve(relpath))?.len();
+        if size > limit {
+            return Err(GodataError::new(
+                GodataErrorType::TooLarge,
+                format!(
+                    "{} is {} bytes, which is over the {} byte limit for inline content",
+                    label, size, limit
+                ),
+            ));
+        }
+        let mut content = Vec::with_capacity(size as usize);
+        self._endpoint
+            .open_read(relpath)?
+            .take(limit)
+            .read_to_end(&mut content)?;
+        Ok(content)
+    }
+
+    pub(crate) fn endpoint_info(&self) -> (String, String) {
+        (self._endpoint.kind().to_string(), self._endpoint.root())
+    }
+
+    pub(crate) fn file_endpoint(&self, project_path: &str) -> Result<(String, String)> {
+        // The endpoint a file is stored on. External files aren't on any endpoint, so
+        // they're reported as `external` along with the folder they're in.
+        let file = self.tree.get(project_path)?;
+        if let Some(reference) = &file.reference {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!(
+                    "`{}` refers to `{}`, ask for that file's endpoint instead",
+                    project_path, reference
+                ),
+            ));
+        }
+        let real_path = self._endpoint.resolve(&file.real_path);
+        if self._endpoint.is_internal(&real_path) {
+            return Ok(self.endpoint_info());
+        }
+        let folder = real_path.parent().unwrap_or(&real_path);
+        Ok(("external".to_string(), folder.to_str().unwrap().to_string()))
+    }
+
+    #[instrument(skip(self), fields(name = self._name.as_str(), collection = self._collection.as_str()))]
+    pub(crate) fn reserve_and_link(
+        &mut self,
+        project_path: &str,
+        suffix: Option<&str>,
+        metadata: HashMap<String, String>,
+    ) -> Result<String> {
+        // Generate a storage location for a file and link it in one go. The location is
+        // claimed with an empty file, which the caller then writes over.
+        if self.tree.exists(project_path) {
+            return Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("Path `{}` already exists", project_path),
+            ));
+        }
+        // The reserved file starts out empty, so there has to be room left for whatever
+        // the caller writes into it
+        if let Some(quota) = self.quota() {
+            let usage = self.storage_usage().total;
+            if usage >= quota {
+                return Err(self.over_quota(usage, quota));
+            }
+        }
+        let path = self._endpoint.generate_unique_path(project_path, suffix)?;
+        if let Err(e) = self.add_file(project_path, path.clone(), metadata, false, false) {
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+        // Its size isn't known until it has been written
+        self._usage = None;
+        Ok(path.to_str().unwrap().to_owned())
+    }
+
+    pub(crate) fn generate_path(&self, project_path: &str) -> Result<String> {
+        let path = self._endpoint.generate_path(project_path)?;
+        Ok(path.to_str().unwrap().to_owned())
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum IssueKind {
+    MissingStorageEntry,
+    CorruptStorageEntry,
+    MissingProjectDir,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct Issue {
+    pub(crate) kind: IssueKind,
+    pub(crate) collection: String,
+    pub(crate) project: String,
+    pub(crate) message: String,
+    pub(crate) repaired: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct StoredProject {
+    pub(crate) collection: String,
+    pub(crate) project: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct CompactReport {
+    pub(crate) before: u64,
+    pub(crate) after: u64,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RelocateReport {
+    rewritten: usize,
+    storage_location: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ExternalFile {
+    project_path: String,
+    real_path: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct BundleManifest {
+    collection: String,
+    project: String,
+    // Project paths of the files included in the bundle
+    internal: Vec<String>,
+    // Files linked from outside the project's storage, which are not bundled
+    external: Vec<ExternalFile>,
+    // Project paths of references to other projects, which stay references
+    references: Vec<String>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum LoadStatus {
+    Loading,
+    Ready,
+    Failed { error: String },
+}
+
+pub(crate) fn retry_while_locked<T>(mut attempt: impl FnMut() -> Result<T>) -> Result<T> {
+    // A project that was only just let go of can keep its database locked for a moment,
+    // so a locked database is tried again a few times. Other errors come straight back.
+    let mut delays = db_open_delays();
+    loop {
+        match (attempt(), delays.next()) {
+            (Err(e), Some(delay)) if e.error_type == GodataErrorType::Locked => {
+                tracing::warn!("{}, retrying in {:?}", e.message, delay);
+                std::thread::sleep(delay);
+            }
+            (result, _) => return result,
+        }
+    }
+}
+
+pub fn get_project_manager() -> Result<ProjectManager> {
+    let storage_manager = StorageManager::get_manager()?;
+    Ok(ProjectManager {
+        storage_manager,
+        projects: HashMap::new(),
+        counts: HashMap::new(),
+        loads: HashMap::new(),
+    })
+}
+
+impl Drop for Project {
+    fn drop(&mut self) {
+        if let Some(dir) = &self._temp_dir {
+            if let Err(e) = std::fs::remove_dir_all(dir) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!(
+                        "Failed to clean up storage for temporary project `{}/{}` at `{}`: {}",
+                        self._collection,
+                        self._name,
+                        dir.display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+}
+
+pub struct ProjectManager {
+    storage_manager: StorageManager,
+    projects: HashMap<String, Arc<Mutex<Project>>>,
+    // Clients using each project, from when they create or load it until they drop it.
+    // Requests that only work with a project for a moment don't count.
+    counts: HashMap<String, usize>,
+    // Background loads by id, along with when they finished
+    loads: HashMap<String, (LoadStatus, Option<Instant>)>,
+}
+
+impl ProjectManager {
+    #[instrument(skip(self))]
+    pub fn create_project(
+        &mut self,
+        name: &str,
+        collection: &str,
+        force: bool,
+        storage_location: Option<String>,
+    ) -> Result<Arc<Mutex<Project>>> {
+        let key = format!("{}/{}", collection, name);
+        let project_dir = create_project_dir(name, collection, force)?;
+        let tree = FileSystem::new(name.to_string(), project_dir)?;
+        let base_path = match storage_location {
+            Some(path) => PathBuf::from(path),
+            None => crate::locations::get_default_project_storage_dir(name, collection)?,
+        };
+        self.storage_manager
+            .add(name, collection, "local", base_path.clone())?;
+        let endpoint = LocalEndpoint::new(base_path);
+        let p = Project::new(tree, name, collection, Box::new(endpoint));
+        let project = Arc::new(Mutex::new(p));
+        self.projects.insert(key.clone(), project.clone());
+        self.counts.insert(key, 1);
+        Ok(project)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn create_from_template(
+        &mut self,
+        name: &str,
+        collection: &str,
+        template_name: &str,
+        force: bool,
+        storage_location: Option<String>,
+    ) -> Result<Arc<Mutex<Project>>> {
+        // Create a project with the template's folders already in place. The template
+        // is read before anything is created, and a project whose folders can't all be
+        // made is removed again.
+        let template = ProjectTemplate::load(template_name)?;
+        let project = self.create_project(name, collection, force, storage_location)?;
+        let result = template
+            .folders
+            .iter()
+            .try_for_each(|folder| project.lock().unwrap().tree.create_folder(folder));
+        match result {
+            Ok(_) => {
+                tracing::info!(
+                    "Created project `{}/{}` from template `{}`",
+                    collection,
+                    name,
+                    template_name
+                );
+                Ok(project)
+            }
+            Err(e) => {
+                drop(project);
+                self.discard_project(name, collection);
+                Err(GodataError::new(
+                    e.error_type,
+                    format!("Template `{}` is invalid: {}", template_name, e.message),
+                ))
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn create_temp_project(
+        &mut self,
+        name: &str,
+        collection: &str,
+    ) -> Result<Arc<Mutex<Project>>> {
+        // A project that never touches the data directory. Its tree is held in a
+        // temporary database and its files in the system temp dir, and it only lives
+        // in the cache, so all of it goes away once the last client drops it.
+        validate_name(collection)?;
+        validate_name(name)?;
+        let key = format!("{}/{}", collection, name);
+        if self.projects.contains_key(&key) || load_project_dir(name, collection).is_ok() {
+            return Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("Project `{}` already exists", key),
+            ));
+        }
+        let tree = FileSystem::temporary(name.to_string())?;
+        let storage_dir = std::env::temp_dir().join(format!("godata-{}", Uuid::new_v4()));
+        let endpoint = LocalEndpoint::new(storage_dir.clone());
+        let mut p = Project::new(tree, name, collection, Box::new(endpoint));
+        p._temp_dir = Some(storage_dir);
+        let project = Arc::new(Mutex::new(p));
+        self.projects.insert(key.clone(), project.clone());
+        self.counts.insert(key, 1);
+        Ok(project)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn export_subtree_as_project(
+        &mut self,
+        source_collection: &str,
+        source_name: &str,
+        subtree_path: &str,
+        dest_collection: &str,
+        dest_name: &str,
+    ) -> Result<usize> {
+        // Promote a folder of an existing project to a project of its own. The source
+        // project is left as it was. Returns the number of files in the new project.
+        let source = self.open_uncached(source_name, source_collection)?;
+        let source = source.lock().unwrap();
+        // Make sure the folder is there before anything gets created
+        source
+            .tree
+            .files_under(subtree_path.trim_end_matches('/'))?;
+        let dest = self.create_project(dest_name, dest_collection, true, None)?;
+        // The new project is held to the same quota as the one it came from
+        let result = {
+            let mut dest = dest.lock().unwrap();
+            dest.set_quota(source.quota())
+                .and_then(|_| dest.copy_subtree_from(&source, subtree_path))
+        };
+        drop(dest);
+        match result {
+            Ok(count) => {
+                self.unload_project(dest_name, dest_collection, true, false)?;
+                tracing::info!(
+                    "Exported {} files under `{}` to project `{}/{}`",
+                    count,
+                    subtree_path,
+                    dest_collection,
+                    dest_name
+                );
+                Ok(count)
+            }
+            Err(e) => {
+                self.discard_project(dest_name, dest_collection);
+                Err(e)
+            }
+        }
+    }
+
+    fn discard_project(&mut self, name: &str, collection: &str) {
+        // Back out of a project that couldn't be filled in. This is best effort, the
+        // original error is the one worth reporting.
+        let key = format!("{}/{}", collection, name);
+        self.projects.remove(&key);
+        self.counts.remove(&key);
+        if let Ok((_, storage_dir)) = self.storage_manager.get(name, collection) {
+            let _ = std::fs::remove_dir_all(storage_dir);
+            let _ = self.storage_manager.delete(name, collection);
+        }
+        let _ = delete_project_dir(name, collection);
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn import_manifest(
+        &mut self,
+        name: &str,
+        collection: &str,
+        manifest_path: PathBuf,
+        format: Option<ManifestFormat>,
+    ) -> Result<ManifestReport> {
+        // Link every file listed in a csv or tsv manifest. Rows that can't be linked are
+        // reported back rather than stopping the import.
+        let text = match std::fs::read_to_string(&manifest_path) {
+            Ok(text) => text,
+            Err(e) => {
+                return Err(GodataError::new(
+                    GodataErrorType::NotFound,
+                    format!(
+                        "Could not read manifest `{}`: {}",
+                        manifest_path.display(),
+                        e
+                    ),
+                ))
+            }
+        };
+        let format = format.unwrap_or_else(|| ManifestFormat::from_path(&manifest_path));
+        let (rows, errors) = manifest::parse(&text, format);
+        let base_dir = manifest_path.parent().unwrap_or(Path::new("/"));
+        let project = self.open_uncached(name, collection)?;
+        let report = project
+            .lock()
+            .unwrap()
+            .link_manifest_rows(rows, base_dir, errors)?;
+        tracing::info!(
+            "Linked {} files from manifest `{}`, {} rows failed",
+            report.linked,
+            manifest_path.display(),
+            report.errors.len()
+        );
+        Ok(report)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn get_or_create_project(
+        &mut self,
+        name: &str,
+        collection: &str,
+        storage_location: Option<String>,
+    ) -> Result<(Arc<Mutex<Project>>, bool)> {
+        // Both halves run under the manager lock, so concurrent callers can't both end
+        // up creating the project. The flag says whether this call created it.
+        if load_project_dir(name, collection).is_ok() {
+            return Ok((self.connect_project(name, collection)?, false));
+        }
+        let project = self.create_project(name, collection, true, storage_location)?;
+        Ok((project, true))
+    }
+
+    #[instrument(skip(self))]
+    pub fn import_project(
+        &self,
+        name: &str,
+        collection: &str,
+        endpoint: &str,
+        path: PathBuf,
+    ) -> Result<PathBuf> {
+        // The assumption is that the path points to a folder which contains the project data
+        // Aditionally, it should contain a .tree folder which contains the tree data
+
+        // The tree may also have been exported as a compressed archive, in which case it
+        // is unpacked somewhere temporary first. The scratch dir is removed when it goes
+        // out of scope, whether or not the import succeeds.
+        let archive_path = path.join(TREE_ARCHIVE_NAME);
+        let mut extracted = None;
+        let mut tree_path = path.join(".tree");
+        if !tree_path.exists() && archive_path.exists() && archive::is_gzip(&archive_path)? {
+            let extract_dir = archive::ScratchDir::new("godata-import");
+            archive::extract(&archive_path, extract_dir.path())?;
+            tree_path = extract_dir.path().join(".tree");
+            extracted = Some(extract_dir);
+        }
+
+        // Check the tree before creating anything, so a bad import leaves nothing behind.
+        // Opening a path that doesn't exist would create an empty database there.
+        if !tree_path.exists() {
+            return Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!("No project tree found in `{}`", path.display()),
+            ));
+        }
+        let db = sled::open(&tree_path)?;
+        if db.get("root")?.is_none() {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("`{}` does not contain a project tree", path.display()),
+            ));
+        }
+
+        let project_dir = create_project_dir(name, collection, true)?;
+        let db_export = db.export();
+        let final_db = sled::open(&project_dir)?;
+        final_db.import(db_export);
+        drop(db);
+        drop(extracted);
+
+        self.storage_manager.add(name, collection, endpoint, path)?;
+        Ok(project_dir)
+    }
+
+    #[instrument(skip(self))]
+    pub fn export_project(
+        &mut self,
+        name: &str,
+        collection: &str,
+        output_path: PathBuf,
+        compression: Compression,
+    ) -> Result<()> {
+        let project = self.open_uncached(name, collection)?;
+        let mut project = project.lock().unwrap();
+        if compression == Compression::None {
+            project.duplicate_tree(output_path.join(".tree"))?;
+            return Ok(());
+        }
+        let staging_path = output_path.join(".tree.partial");
+        project.duplicate_tree(staging_path.clone())?;
+        archive::compress_dir(&staging_path, ".tree", &output_path.join(TREE_ARCHIVE_NAME))?;
+        std::fs::remove_dir_all(&staging_path)?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn export_bundle(
+        &mut self,
+        name: &str,
+        collection: &str,
+        output: PathBuf,
+    ) -> Result<BundleManifest> {
+        // Write the tree, along with every internal file it references, into one tar file
+        let project = self.open_uncached(name, collection)?;
+        let mut project = project.lock().unwrap();
+        let staging = archive::ScratchDir::new("godata-bundle");
+        project.duplicate_tree(staging.path().to_path_buf())?;
+        // Internal paths need to be relative so they resolve against the new storage root.
+        // Only the copy is rewritten, the project itself is left as it was.
+        let mut tree = FileSystem::load(name, staging.path().to_path_buf())?;
+        let endpoint = &project._endpoint;
+        relativize_tree(&mut tree, endpoint.as_ref())?;
+
+        let mut manifest = BundleManifest {
+            collection: collection.to_string(),
+            project: name.to_string(),
+            internal: Vec::new(),
+            external: Vec::new(),
+            references: Vec::new(),
+        };
+        let mut files = Vec::new();
+        for (project_path, file) in tree.files() {
+            if file.reference.is_some() {
+                manifest.references.push(project_path);
+                continue;
+            }
+            let real_path = endpoint.resolve(&file.real_path);
+            if endpoint.is_internal(&real_path) {
+                files.push((real_path, file.real_path.clone()));
+                manifest.internal.push(project_path);
+            } else {
+                manifest.external.push(ExternalFile {
+                    project_path,
+                    real_path: real_path.to_str().unwrap().to_string(),
+                });
+            }
+        }
+        tree.flush()?;
+        drop(tree);
+
+        let manifest_data = serde_json::to_vec_pretty(&manifest).unwrap();
+        archive::write_bundle(&output, staging.path(), &files, &manifest_data)?;
+        tracing::info!(
+            "Bundled project `{}/{}` with {} internal files into `{}`",
+            collection,
+            name,
+            files.len(),
+            output.display()
+        );
+        Ok(manifest)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn import_bundle(
+        &mut self,
+        name: &str,
+        collection: &str,
+        bundle: PathBuf,
+        storage_location: Option<PathBuf>,
+    ) -> Result<()> {
+        let storage_path = match storage_location {
+            Some(path) => path,
+            None => crate::locations::get_default_project_storage_dir(name, collection)?,
+        };
+        // Check everything that can be checked before writing anything
+        if self.storage_manager.get(name, collection).is_ok()
+            || load_project_dir(name, collection).is_ok()
+        {
+            return Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("Project `{}/{}` already exists", collection, name),
+            ));
+        }
+        if storage_path.exists() && storage_path.read_dir()?.next().is_some() {
+            return Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("Storage location `{}` is not empty", storage_path.display()),
+            ));
+        }
+        let storage_existed = storage_path.exists();
+
+        if let Err(e) = self.unpack_bundle(name, collection, &bundle, &storage_path) {
+            // Undo whatever made it to disk. The project directory and the storage
+            // location were both absent or empty before, so nothing else is lost.
+            if let Ok(project_dir) = load_project_dir(name, collection) {
+                let _ = std::fs::remove_dir_all(project_dir);
+            }
+            let _ = std::fs::remove_dir_all(&storage_path);
+            if storage_existed {
+                let _ = std::fs::create_dir_all(&storage_path);
+            }
+            return Err(e);
+        }
+        tracing::info!(
+            "Imported project `{}/{}` from bundle `{}`",
+            collection,
+            name,
+            bundle.display()
+        );
+        Ok(())
+    }
+
+    fn unpack_bundle(
+        &mut self,
+        name: &str,
+        collection: &str,
+        bundle: &Path,
+        storage_path: &Path,
+    ) -> Result<()> {
+        let staging = archive::ScratchDir::new("godata-bundle");
+        archive::read_bundle(bundle, staging.path(), storage_path)?;
+        let db = sled::open(staging.path())?;
+        if db.get("root")?.is_none() {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("`{}` does not contain a project tree", bundle.display()),
+            ));
+        }
+        // A project exported with a quota comes back with it, and has to fit inside it
+        let quota = stored_system_value(&db, QUOTA_KEY)?.and_then(|q| q.parse::<u64>().ok());
+        if let Some(quota) = quota {
+            let usage = dir_size(storage_path)?;
+            if usage > quota {
+                return Err(GodataError::new(
+                    GodataErrorType::NotPermitted,
+                    format!(
+                        "Project `{}/{}` would use {} bytes, over its quota of {} bytes",
+                        collection, name, usage, quota
+                    ),
+                ));
+            }
+        }
+        let project_dir = create_project_dir(name, collection, true)?;
+        let final_db = sled::open(&project_dir)?;
+        final_db.import(db.export());
+        final_db.flush()?;
+        drop(db);
+        self.storage_manager
+            .add(name, collection, "local", storage_path.to_path_buf())?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn compact_project(
+        &mut self,
+        name: &str,
+        collection: &str,
+    ) -> Result<CompactReport> {
+        // Rewrite the project's database into a fresh one, which drops any dead pages
+        // sled has accumulated. The project is evicted from the cache first, so the next
+        // load picks up the compacted database.
+        let key = format!("{}/{}", collection, name);
+        let project_dir = load_project_dir(name, collection)?;
+        let collection_dir = project_dir.parent().unwrap().to_path_buf();
+        let compact_dir = collection_dir.join(format!(".{}.compact", name));
+        let old_dir = collection_dir.join(format!(".{}.old", name));
+        if compact_dir.exists() {
+            std::fs::remove_dir_all(&compact_dir)?;
+        }
+        let before = dir_size(&project_dir)?;
+
+        let fresh = sled::open(&compact_dir)?;
+        match self.projects.remove(&key) {
+            Some(project) => {
+                if Arc::strong_count(&project) > 1 {
+                    self.projects.insert(key.clone(), project);
+                    return Err(GodataError::new(
+                        GodataErrorType::NotPermitted,
+                        format!("Project `{}` is currently in use", key),
+                    ));
+                }
+                let mut project = project.lock().unwrap();
+                fresh.import(project.tree.export()?);
+            }
+            None => {
+                let db = sled::open(&project_dir)?;
+                db.flush()?;
+                fresh.import(db.export());
+            }
+        }
+        fresh.flush()?;
+        drop(fresh);
+
+        // The project is evicted either way, so nobody is counted as holding it
+        self.counts.remove(&key);
+        std::fs::rename(&project_dir, &old_dir)?;
+        if let Err(e) = std::fs::rename(&compact_dir, &project_dir) {
+            // Put the original database back rather than leave the project without one
+            std::fs::rename(&old_dir, &project_dir)?;
+            let _ = std::fs::remove_dir_all(&compact_dir);
+            return Err(e.into());
+        }
+        std::fs::remove_dir_all(&old_dir)?;
+        let after = dir_size(&project_dir)?;
+        tracing::info!(
+            "Compacted project `{}` from {} to {} bytes",
+            key,
+            before,
+            after
+        );
+        Ok(CompactReport { before, after })
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn relocate_storage(
+        &mut self,
+        name: &str,
+        collection: &str,
+        storage_location: PathBuf,
+        ignore_references: bool,
+    ) -> Result<RelocateReport> {
+        // Point a project at storage that has been moved on disk. Internal paths are
+        // made relative to the old root first, so they resolve against the new one.
+        let key = format!("{}/{}", collection, name);
+        if !storage_location.is_dir() {
+            return Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!(
+                    "Storage location `{}` does not exist",
+                    storage_location.display()
+                ),
+            ));
+        }
+        let (endpoint, _) = self.storage_manager.get(name, collection)?;
+        // Relocating evicts the project, so connected clients get a say as they do for
+        // deletes. Anyone actually holding it would carry on writing to the old location.
+        self.check_references(&key, ignore_references, "relocate")?;
+        if let Some(project) = self.projects.get(&key) {
+            if Arc::strong_count(project) > 1 {
+                return Err(GodataError::new(
+                    GodataErrorType::NotPermitted,
+                    format!("Project `{}` is currently in use", key),
+                ));
+            }
+        }
+        let project = self.open_uncached(name, collection)?;
+        let rewritten = project.lock().unwrap().relativize_paths()?;
+        drop(project);
+        // Evict the project so the next load picks up the new location
+        self.projects.remove(&key);
+        self.counts.remove(&key);
+
+        self.storage_manager
+            .update(name, collection, &endpoint, storage_location.clone())?;
+        tracing::info!(
+            "Relocated storage for project `{}` to `{}`",
+            key,
+            storage_location.display()
+        );
+        Ok(RelocateReport {
+            rewritten,
+            storage_location: storage_location.to_str().unwrap().to_string(),
+        })
+    }
+
+    #[instrument(skip(self))]
+    pub fn load_project(&mut self, name: &str, collection: &str) -> Result<Arc<Mutex<Project>>> {
+        let key = format!("{}/{}", collection, name);
+        if let Some(project) = self.projects.get(&key) {
+            return Ok(project.clone());
+        }
+        let project_dir = load_project_dir(name, collection)?;
+        let storage_dir = self.storage_manager.get(name, collection)?;
+        let tree = FileSystem::load(name, project_dir)?;
+        let endpoint = LocalEndpoint::new(storage_dir.1);
+
+        let project = Project::new(tree, name, collection, Box::new(endpoint));
+        let project = Arc::new(Mutex::new(project));
+        self.projects.insert(key, project.clone());
+        Ok(project)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn connect_project(
+        &mut self,
+        name: &str,
+        collection: &str,
+    ) -> Result<Arc<Mutex<Project>>> {
+        // Load a project on behalf of a client, which counts as using it until the
+        // client drops it
+        let project = self.load_project(name, collection)?;
+        *self
+            .counts
+            .entry(format!("{}/{}", collection, name))
+            .or_insert(0) += 1;
+        Ok(project)
+    }
+
+    pub(crate) fn load_retrying(
+        manager: &Mutex<ProjectManager>,
+        name: &str,
+        collection: &str,
+    ) -> Result<Arc<Mutex<Project>>> {
+        // Load a project, waiting for its database if it's briefly locked. The manager
+        // is only taken for each attempt, so other requests can use it in between.
+        retry_while_locked(|| manager.lock().unwrap().load_project(name, collection))
+    }
+
+    pub(crate) fn is_loaded(&self, name: &str, collection: &str) -> bool {
+        self.projects
+            .contains_key(&format!("{}/{}", collection, name))
+    }
+
+    pub(crate) fn ref_count(&self, name: &str, collection: &str) -> usize {
+        let key = format!("{}/{}", collection, name);
+        *self.counts.get(&key).unwrap_or(&0)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn open_uncached(
+        &self,
+        name: &str,
+        collection: &str,
+    ) -> Result<Arc<Mutex<Project>>> {
+        // Transient operations shouldn't keep a project alive, so this neither bumps the
+        // reference count nor adds the project to the cache. A project that is already
+        // loaded is shared, since its tree can't be opened twice. Writes go through to
+        // disk like any other project, so callers are free to modify it.
+        let key = format!("{}/{}", collection, name);
+        if let Some(project) = self.projects.get(&key) {
+            return Ok(project.clone());
+        }
+        let project_dir = load_project_dir(name, collection)?;
+        let storage_dir = self.storage_manager.get(name, collection)?;
+        let tree = FileSystem::load(name, project_dir)?;
+        let endpoint = LocalEndpoint::new(storage_dir.1);
+        let project = Project::new(tree, name, collection, Box::new(endpoint));
+        Ok(Arc::new(Mutex::new(project)))
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn follow_reference(
+        &self,
+        origin: &FileRef,
+        reference: &FileRef,
+    ) -> Result<FileRef> {
+        // Follow a reference, and any references it leads to, through to a real file.
+        // A chain that comes back to `origin` or loops on itself is refused.
+        let mut visited = HashSet::from([origin.clone()]);
+        let mut target = reference.clone();
+        loop {
+            if !visited.insert(target.clone()) {
+                return Err(GodataError::new(
+                    GodataErrorType::InvalidPath,
+                    format!("Reference from `{}` leads back to `{}`", origin, target),
+                ));
+            }
+            let broken = |e: GodataError| {
+                GodataError::new(
+                    GodataErrorType::NotFound,
+                    format!(
+                        "Reference from `{}` to `{}` is broken: {}",
+                        origin, target, e.message
+                    ),
+                )
+            };
+            let project = self
+                .open_uncached(&target.project, &target.collection)
+                .map_err(broken)?;
+            let next = project
+                .lock()
+                .unwrap()
+                .reference(&target.path)
+                .map_err(broken)?;
+            match next {
+                Some(next) => target = next,
+                None => return Ok(target),
+            }
+        }
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn resolve_reference(
+        &self,
+        origin: &FileRef,
+        reference: &FileRef,
+    ) -> Result<HashMap<String, String>> {
+        // What get_file would return for the file a reference ends up at, along with
+        // where that is
+        let target = self.follow_reference(origin, reference)?;
+        let project = self.open_uncached(&target.project, &target.collection)?;
+        let mut file = project.lock().unwrap().get_file(&target.path)?;
+        file.insert("reference".to_string(), target.to_string());
+        Ok(file)
+    }
+
+    #[instrument(skip(manager))]
+    pub(crate) fn add_reference(
+        manager: &Mutex<ProjectManager>,
+        name: &str,
+        collection: &str,
+        project_path: &str,
+        target: FileRef,
+        overwrite: bool,
+    ) -> Result<Option<Vec<String>>> {
+        // The target has to resolve before the reference is added, which also keeps
+        // references from being chained into a loop. The project is loaded like for any
+        // other change, and only locked once the manager has been let go of.
+        let origin = FileRef {
+            collection: collection.to_string(),
+            project: name.to_string(),
+            path: project_path.to_string(),
+        };
+        manager.lock().unwrap().follow_reference(&origin, &target)?;
+        let project = ProjectManager::load_retrying(manager, name, collection)?;
+        let result = project
+            .lock()
+            .unwrap()
+            .add_reference(project_path, target, overwrite);
+        result
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn set_quota(
+        &mut self,
+        name: &str,
+        collection: &str,
+        quota: Option<u64>,
+    ) -> Result<()> {
+        let project = self.load_project(name, collection)?;
+        let result = project.lock().unwrap().set_quota(quota);
+        result
+    }
+
+    pub(crate) fn get_quota(&self, name: &str, collection: &str) -> Result<QuotaUsage> {
+        let project = self.open_uncached(name, collection)?;
+        let project = project.lock().unwrap();
+        Ok(QuotaUsage {
+            quota: project.quota(),
+            usage: project.internal_size(),
+        })
+    }
+
+    pub(crate) fn start_load(&mut self) -> String {
+        // Background loads are tracked by id so clients can find out whether they worked
+        let load_id = Uuid::new_v4().to_string();
+        self.prune_loads();
+        self.loads
+            .insert(load_id.clone(), (LoadStatus::Loading, None));
+        load_id
+    }
+
+    pub(crate) fn finish_load(&mut self, load_id: &str, result: Result<()>) {
+        let status = match result {
+            Ok(_) => LoadStatus::Ready,
+            Err(e) => LoadStatus::Failed { error: e.message },
+        };
+        self.prune_loads();
+        self.loads
+            .insert(load_id.to_string(), (status, Some(Instant::now())));
+    }
+
+    fn prune_loads(&mut self) {
+        // Finished loads are forgotten after a while, so the map doesn't grow forever
+        self.loads.retain(|_, (_, finished)| {
+            finished.is_none_or(|finished| finished.elapsed() < LOAD_STATUS_TTL)
+        });
+    }
+
+    pub(crate) fn load_status(&self, load_id: &str) -> Result<LoadStatus> {
+        match self.loads.get(load_id) {
+            Some((status, _)) => Ok(status.clone()),
+            None => Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!("No load with id `{}`", load_id),
+            )),
+        }
+    }
+
+    pub(crate) fn loaded_projects(&self) -> Vec<(String, Arc<Mutex<Project>>)> {
+        self.projects
+            .iter()
+            .map(|(key, project)| (key.clone(), project.clone()))
+            .collect()
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn unload_project(
+        &mut self,
+        name: &str,
+        collection: &str,
+        flush: bool,
+        force: bool,
+    ) -> Result<()> {
+        // Evict the project from the cache, so the next load reads it from disk. This is
+        // refused while a request is still working with the project, unless forced. A
+        // forced unload leaves those requests with their copy, and the database stays
+        // open until they finish, so loading the project again fails until then.
+        let key = format!("{}/{}", collection, name);
+        let project = match self.projects.remove(&key) {
+            Some(project) => project,
+            None => {
+                return Err(GodataError::new(
+                    GodataErrorType::NotFound,
+                    format!("Project `{}` is not loaded", key),
+                ))
+            }
+        };
+        if Arc::strong_count(&project) > 1 && !force {
+            self.projects.insert(key.clone(), project);
+            return Err(GodataError::new(
+                GodataErrorType::NotPermitted,
+                format!("Project `{}` is currently in use", key),
+            ));
+        }
+        self.counts.remove(&key);
+        if flush {
+            project.lock().unwrap().tree.flush()?;
+        }
+        tracing::info!("Unloaded project `{}`", key);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn drop_project(&mut self, name: &str, collection: &str) -> Result<()> {
+        let key = format!("{}/{}", collection, name);
+        let count = self.counts.get(&key);
+        if count.is_none() {
+            let message = format!(
+                "Tried to drop a project `{}` that was not in the cache",
+                key
+            );
+            tracing::error!(message);
+            return Err(GodataError::new(GodataErrorType::NotFound, message));
+        }
+        let count = count.unwrap();
+        if count == &1 {
+            tracing::info!(
+                "Last connection to project `{}` dropped, removing from cache",
+                key
+            );
+            self.projects.remove(&key);
+            self.counts.remove(&key);
+        } else if count < &0 {
+            self.counts.remove(&key);
+            tracing::error!(
+                "Count for project `{}` is negative, this should not happen",
+                key
+            );
+            return Err(GodataError::new(
+                GodataErrorType::InternalError,
+                format!("Tried to drop a project `{}` that does not exist", key),
+            ));
+        } else {
+            tracing::info!("Dropping connection to project `{}`", key);
+            self.counts.insert(key, count - 1);
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub fn delete_project(
+        &mut self,
+        name: &str,
+        collection: &str,
+        force: bool,
+        ignore_references: bool,
+        confirm_count: Option<usize>,
+    ) -> Result<()> {
+        let key = format!("{}/{}", collection, name);
+        // `force` is only about the project's contents, deleting it out from under
+        // other clients takes `ignore_references`
+        self.check_references(&key, ignore_references, "delete")?;
+        let project_dir = load_project_dir(name, collection)?;
+        let storage_dir = self.storage_manager.get(name, collection);
+        let mut storage_is_empty = storage_dir.is_err();
+        if storage_dir.is_ok() {
+            let storage_dir = storage_dir.unwrap();
+            let storage_path = storage_dir.1;
+            let mut files_in_storage = std::fs::read_dir(&storage_path)?;
+            storage_is_empty = files_in_storage.next().is_none();
+            if force && !storage_is_empty {
+                let count = count_files(&storage_path)?;
+                if count > delete_confirm_threshold() && confirm_count != Some(count) {
+                    tracing::error!(
+                        "Refusing to force delete project `{}` with {} files without confirmation",
+                        key,
+                        count
+                    );
+                    return Err(GodataError::new(
+                        GodataErrorType::NotPermitted,
+                        format!(
+                            "Project `{}` contains {} files, pass confirm_count={} to delete it",
+                            key, count, count
+                        ),
+                    ));
+                }
+            }
+        }
+
+        let pobj = self.projects.remove(&key);
+        if let Some(obj) = pobj {
+            let obj = obj.lock().unwrap();
+            drop(obj);
+        }
+        // The database can only be opened once the cached project has let go of it
+        if force || (storage_is_empty && is_empty(&project_dir)?) {
+            delete_project_dir(name, collection)?;
+            self.counts.remove(&key);
+            let storage_dir = self.storage_manager.get(name, collection);
+            if storage_dir.is_ok() {
+                self.storage_manager.delete(name, collection)?;
+            }
+            return Ok(());
+        }
+        tracing::error!(
+            "Project `{}` is not empty, not deleting",
+            format!("{}/{}", collection, name)
+        );
+        Err(GodataError::new(
+            GodataErrorType::NotPermitted,
+            "Project is not empty".to_string(),
+        ))
+    }
+
+    fn check_references(&self, key: &str, ignore_references: bool, action: &str) -> Result<()> {
+        // The caller may hold one reference itself, any others belong to clients that
+        // are still using the project
+        let references = self.counts.get(key).copied().unwrap_or(0);
+        if references > 1 && !ignore_references {
+            tracing::error!(
+                "Refusing to {} project `{}` with {} open references",
+                action,
+                key,
+                references
+            );
+            return Err(GodataError::new(
+                GodataErrorType::NotPermitted,
+                format!(
+                    "Project `{}` is in use by {} other clients, pass ignore_references to {} it anyway",
+                    key,
+                    references - 1,
+                    action
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn trash_project(
+        &mut self,
+        name: &str,
+        collection: &str,
+        ignore_references: bool,
+    ) -> Result<()> {
+        // Soft delete. The project's tree is moved into the collection's trash folder,
+        // along with its storage information, so it can be restored later. The data in
+        // storage is left alone until the project is purged.
+        let key = format!("{}/{}", collection, name);
+        self.check_references(&key, ignore_references, "trash")?;
+        if let Some(project) = self.projects.get(&key) {
+            if Arc::strong_count(project) > 1 {
+                return Err(GodataError::new(
+                    GodataErrorType::NotPermitted,
+                    format!("Project `{}` is currently in use", key),
+                ));
+            }
+        }
+        let project_dir = load_project_dir(name, collection)?;
+        let trash_dir = get_trash_dir(collection)?.join(name);
+        if trash_dir.exists() {
+            return Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("Project `{}` is already in the trash", key),
+            ));
+        }
+        let (endpoint, path) = self.storage_manager.get(name, collection)?;
+        self.projects.remove(&key);
+        self.counts.remove(&key);
+
+        // The tree is moved first and put back if anything after it fails, so a failed
+        // attempt doesn't leave a half-made entry in the trash blocking the next one
+        std::fs::create_dir_all(&trash_dir)?;
+        if let Err(e) = std::fs::rename(&project_dir, trash_dir.join("tree")) {
+            let _ = std::fs::remove_dir_all(&trash_dir);
+            return Err(e.into());
+        }
+        let written = std::fs::write(
+            trash_dir.join("storage"),
+            format!("{}:{}", endpoint, path.to_str().unwrap()),
+        );
+        if let Err(e) = written {
+            let _ = std::fs::rename(trash_dir.join("tree"), &project_dir);
+            let _ = std::fs::remove_dir_all(&trash_dir);
+            return Err(e.into());
+        }
+        self.storage_manager.remove(name, collection)?;
+        tracing::info!("Moved project `{}` to the trash", key);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn list_trash(&self, collection: &str) -> Result<Vec<String>> {
+        let trash_dir = get_trash_dir(collection)?;
+        let mut names = Vec::new();
+        if !trash_dir.exists() {
+            return Ok(names);
+        }
+        for entry in std::fs::read_dir(trash_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.is_dir() {
+                names.push(path.file_name().unwrap().to_str().unwrap().to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn restore_project(&mut self, name: &str, collection: &str) -> Result<()> {
+        let trash_dir = get_trash_dir(collection)?.join(name);
+        if !trash_dir.exists() {
+            return Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!("Project `{}/{}` is not in the trash", collection, name),
+            ));
+        }
+        if load_project_dir(name, collection).is_ok() {
+            return Err(GodataError::new(
+                GodataErrorType::AlreadyExists,
+                format!("Project `{}/{}` already exists", collection, name),
+            ));
+        }
+        let (endpoint, path) = read_trashed_storage(&trash_dir)?;
+        self.storage_manager
+            .add(name, collection, &endpoint, path)?;
+        let project_dir = load_collection_dir(collection)?.join(name);
+        std::fs::rename(trash_dir.join("tree"), project_dir)?;
+        delete_trash_dir(name, collection)?;
+        tracing::info!("Restored project `{}/{}` from the trash", collection, name);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn purge_project(&mut self, name: &str, collection: &str) -> Result<()> {
+        // Permanently remove a trashed project, including its data in storage
+        let trash_dir = get_trash_dir(collection)?.join(name);
+        if !trash_dir.exists() {
+            return Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!("Project `{}/{}` is not in the trash", collection, name),
+            ));
+        }
+        let (_, path) = read_trashed_storage(&trash_dir)?;
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        delete_trash_dir(name, collection)?;
+        tracing::info!("Purged project `{}/{}` from the trash", collection, name);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub fn get_project_names(&self, collection: String, show_hidden: bool) -> Result<Vec<String>> {
+        let collection_dir = load_collection_dir(&collection);
+        if collection_dir.is_err() {
+            return Err(GodataError::new(
+                GodataErrorType::NotFound,
+                format!("Collection `{}` does not exist", collection),
+            ));
+        }
+        let collection_dir = collection_dir.unwrap();
+
+        // The trash lives alongside the projects but isn't one
+        let mut names = list_dir_names(&collection_dir, show_hidden)?;
+        names.retain(|name| name != TRASH_DIR_NAME);
+        Ok(names)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn sorted_project_names(
+        &self,
+        collection: String,
+        show_hidden: bool,
+        sort: ProjectSort,
+        descending: bool,
+    ) -> Result<Vec<String>> {
+        // Anything other than sorting by name opens every project in the collection, and
+        // sorting by size also stats every file in them, so it can be slow. Projects that
+        // can't be opened sort as if they had no value.
+        let mut names = self.get_project_names(collection.clone(), show_hidden)?;
+        names.sort();
+        if sort != ProjectSort::Name {
+            let mut keyed = Vec::new();
+            for name in names {
+                let key = match self.open_uncached(&name, &collection) {
+                    Ok(project) => project.lock().unwrap().sort_key(sort)?,
+                    Err(e) => {
+                        tracing::warn!("Could not open project `{}` to sort it: {}", name, e);
+                        None
+                    }
+                };
+                keyed.push((key, name));
+            }
+            keyed.sort();
+            names = keyed.into_iter().map(|(_, name)| name).collect();
+        }
+        if descending {
+            names.reverse();
+        }
+        Ok(names)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn projects_under(&self, prefix: PathBuf) -> Result<Vec<StoredProject>> {
+        // Find the projects whose storage lives under a directory, e.g. before moving it
+        let mut projects: Vec<StoredProject> = self
+            .storage_manager
+            .list_under(prefix)?
+            .into_iter()
+            .map(|(project, collection)| StoredProject {
+                collection,
+                project,
+            })
+            .collect();
+        projects.sort_by(|a, b| (&a.collection, &a.project).cmp(&(&b.collection, &b.project)));
+        Ok(projects)
+    }
+
+    #[instrument(skip(self))]
+    pub(crate) fn doctor(&self, repair: bool) -> Result<Vec<Issue>> {
+        // Cross-check the project directories against the storage database. Projects
+        // without a storage entry can be repaired by pointing them at the default
+        // storage location. Storage entries without a project are only reported, since
+        // the data they point to may still be wanted.
+        let mut issues = Vec::new();
+        let mut known = HashSet::new();
+        for collection in get_collection_names(true)? {
+            for name in self.get_project_names(collection.clone(), true)? {
+                known.insert((name.clone(), collection.clone()));
+                let storage = self.storage_manager.get(&name, &collection);
+                let err = match storage {
+                    Ok(_) => continue,
+                    Err(e) => e,
+                };
+                if err.error_type != GodataErrorType::NotFound {
+                    issues.push(Issue {
+                        kind: IssueKind::CorruptStorageEntry,
+                        collection: collection.clone(),
+                        project: name,
+                        message: err.message,
+                        repaired: false,
+                    });
+                    continue;
+                }
+                let mut repaired = false;
+                if repair {
+                    let path =
+                        crate::locations::get_default_project_storage_dir(&name, &collection)?;
+                    self.storage_manager
+                        .add(&name, &collection, "local", path)?;
+                    tracing::info!(
+                        "Recreated storage entry for project `{}/{}`",
+                        collection,
+                        name
+                    );
+                    repaired = true;
+                }
+                issues.push(Issue {
+                    kind: IssueKind::MissingStorageEntry,
+                    collection: collection.clone(),
+                    project: name,
+                    message: err.message,
+                    repaired,
+                });
+            }
+        }
+
+        for (name, collection) in self.storage_manager.entries()? {
+            if known.contains(&(name.clone(), collection.clone())) {
+                continue;
+            }
+            let message = format!(
+                "Storage information exists for project `{}/{}`, but the project does not",
+                collection, name
+            );
+            issues.push(Issue {
+                kind: IssueKind::MissingProjectDir,
+                collection,
+                project: name,
+                message,
+                repaired: false,
+            });
+        }
+        if !issues.is_empty() {
+            tracing::warn!("Found {} issues in the godata store", issues.len());
+        }
+        Ok(issues)
+    }
+}
+
+fn read_trashed_storage(trash_dir: &Path) -> Result<(String, PathBuf)> {
+    let value = std::fs::read_to_string(trash_dir.join("storage"))?;
+    match value.split_once(':') {
+        Some((endpoint, path)) => Ok((endpoint.to_string(), PathBuf::from(path))),
+        None => {
+            tracing::error!("Trashed storage information is corrupted, found {}", value);
+            Err(GodataError::new(
+                GodataErrorType::InternalError,
+                format!(
+                    "Storage information in `{}` is corrupted",
+                    trash_dir.display()
+                ),
+            ))
+        }
+    }
+}
+
+fn delete_confirm_threshold() -> usize {
+    std::env::var("GODATA_DELETE_CONFIRM_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(DELETE_CONFIRM_THRESHOLD)
+}
+
+fn inline_content_limit() -> u64 {
+    std::env::var("GODATA_INLINE_CONTENT_LIMIT")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(INLINE_CONTENT_LIMIT)
+}
+
+fn gc_grace_period() -> u64 {
+    std::env::var("GODATA_GC_GRACE_PERIOD")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(GC_GRACE_PERIOD)
+}
+
+fn list_files(path: &Path) -> Result<Vec<PathBuf>> {
+    // Symlinked folders aren't followed, since whatever they point at isn't ours
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            files.extend(list_files(&entry.path())?);
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+fn relativize_tree(tree: &mut FileSystem, endpoint: &dyn StorageEndpoint) -> Result<usize> {
+    // Rewrite absolute paths to internal files relative to the storage root
+    tree.rewrite_paths(|path| {
+        if path.is_absolute() && endpoint.is_internal(path) {
+            endpoint.get_relative_path(path)
+        } else {
+            path.to_path_buf()
+        }
+    })
+}
+
+fn count_files(path: &Path) -> Result<usize> {
+    let mut count = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.metadata()?.is_dir() {
+            count += count_files(&entry.path())?;
+        } else {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn file_size(path: &Path) -> u64 {
+    // Files that have gone missing take up no space
+    std::fs::metadata(path).map(|meta| meta.len()).unwrap_or(0)
+}
+
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+pub fn get_collection_names(show_hidden: bool) -> Result<Vec<String>> {
+    let main_dir = crate::locations::get_main_dir();
+    list_dir_names(&main_dir, show_hidden)
+}
+
+fn list_dir_names(dir: &Path, show_hidden: bool) -> Result<Vec<String>> {
+    // Entries we can't read are logged and skipped, so a single unreadable directory
+    // in a shared data dir doesn't hide everything else.
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                tracing::warn!("Skipping unreadable entry in {}: {}", dir.display(), err);
+                continue;
+            }
+        };
+        let path = entry.path();
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name.to_string(),
+            None => {
+                tracing::warn!("Skipping entry with invalid name: {}", path.display());
+                continue;
+            }
+        };
+        if path.is_dir() && (!name.starts_with('.') || show_hidden) {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsystem::Operation;
+    use crate::testing::{lock_manager, new_project, scratch_dir, write_file};
+
+    #[test]
+    fn add_folder_links_large_trees() {
+        let (_, _, project) = new_project();
+        let dir = scratch_dir();
+        for i in 0..3000 {
+            write_file(&dir.join(format!("sub{}/file{}.txt", i % 10, i)), "data");
+        }
+        let mut project = project.lock().unwrap();
+        let report = project.add_folder("data", dir, true, None, false).unwrap();
+        assert_eq!(report.linked, 3000);
+        assert!(report.skipped_folders.is_empty());
+        assert_eq!(project.tree.files().len(), 3000);
+        assert!(project.exists("data/sub3/file1233.txt".to_string()));
+    }
+
+    #[test]
+    fn add_folder_stops_at_max_depth() {
+        let (_, _, project) = new_project();
+        let dir = scratch_dir();
+        let mut deepest = dir.clone();
+        for level in 0..50 {
+            deepest = deepest.join(format!("level{}", level));
+        }
+        write_file(&deepest.join("bottom.txt"), "data");
+        write_file(&dir.join("level0/level1/shallow.txt"), "data");
+        let mut project = project.lock().unwrap();
+        let report = project
+            .add_folder("deep", dir, true, Some(3), false)
+            .unwrap();
+        assert_eq!(report.linked, 1);
+        assert!(project.exists("deep/level0/level1/shallow.txt".to_string()));
+        assert_eq!(report.skipped_folders.len(), 1);
+        assert!(report.skipped_folders[0].ends_with("level0/level1/level2/level3"));
+    }
+
+    #[test]
+    fn get_file_keeps_user_metadata_named_origin() {
+        let (_, _, project) = new_project();
+        let dir = scratch_dir();
+        let plain = write_file(&dir.join("plain.txt"), "data");
+        let tagged = write_file(&dir.join("tagged.txt"), "data");
+        let mut project = project.lock().unwrap();
+        project
+            .add_file("plain.txt", plain.clone(), HashMap::new(), false, false)
+            .unwrap();
+        let metadata = HashMap::from([("origin".to_string(), "telescope".to_string())]);
+        project
+            .add_file("tagged.txt", tagged, metadata, false, false)
+            .unwrap();
+        let file = project.get_file("plain.txt").unwrap();
+        assert_eq!(file["origin"], plain.to_str().unwrap());
+        let file = project.get_file("tagged.txt").unwrap();
+        assert_eq!(file["origin"], "telescope");
+    }
+
+    fn file_paths(project: &Project) -> Vec<String> {
+        let mut paths: Vec<String> = project.tree.files().into_iter().map(|(p, _)| p).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn compact_keeps_tree_contents() {
+        let (collection, name, project) = new_project();
+        let dir = scratch_dir();
+        for i in 0..200 {
+            write_file(&dir.join(format!("file{}.txt", i)), "data");
+        }
+        let expected = {
+            let mut project = project.lock().unwrap();
+            project.add_folder("data", dir, false, None, false).unwrap();
+            for i in 0..150 {
+                project.remove_file(&format!("data/file{}.txt", i)).unwrap();
+            }
+            let metadata = HashMap::from([("kept".to_string(), "yes".to_string())]);
+            let tagged = write_file(&scratch_dir().join("tagged.txt"), "data");
+            project
+                .add_file("data/tagged.txt", tagged, metadata, false, false)
+                .unwrap();
+            file_paths(&project)
+        };
+        assert_eq!(expected.len(), 51);
+        drop(project);
+
+        let mut manager = lock_manager();
+        manager.compact_project(&name, &collection).unwrap();
+        assert!(!manager.is_loaded(&name, &collection));
+        assert_eq!(manager.ref_count(&name, &collection), 0);
+        let project = retry_while_locked(|| manager.load_project(&name, &collection)).unwrap();
+        let mut project = project.lock().unwrap();
+        assert_eq!(file_paths(&project), expected);
+        let file = project.get_file("data/tagged.txt").unwrap();
+        assert_eq!(file["kept"], "yes");
+    }
+
+    #[test]
+    fn access_times_are_not_updates() {
+        let (collection, name, project) = new_project();
+        let path = write_file(&scratch_dir().join("a.txt"), "data");
+        {
+            let mut project = project.lock().unwrap();
+            project
+                .add_file("a.txt", path, HashMap::new(), false, false)
+                .unwrap();
+            project.set_track_access(true).unwrap();
+            let hash = project.manifest_hash().unwrap();
+            let updated = project.tree.system_value(UPDATED_KEY).map(String::from);
+            project.get_file("a.txt").unwrap();
+            assert_eq!(
+                project.tree.system_value(MANIFEST_HASH_KEY),
+                Some(hash.as_str())
+            );
+            assert_eq!(
+                project.tree.system_value(UPDATED_KEY).map(String::from),
+                updated
+            );
+            assert_eq!(project.recently_accessed(10).unwrap().len(), 1);
+            project.tree.flush().unwrap();
+        }
+        drop(project);
+
+        // The access time is written out with the next flush. The reload waits for sled
+        // to let go of the database.
+        let mut manager = lock_manager();
+        manager
+            .unload_project(&name, &collection, false, false)
+            .unwrap();
+        let project = retry_while_locked(|| manager.load_project(&name, &collection)).unwrap();
+        assert_eq!(
+            project.lock().unwrap().recently_accessed(10).unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn trash_restore_and_purge() {
+        let (collection, name, project) = new_project();
+        let storage_file = {
+            let mut project = project.lock().unwrap();
+            let path = PathBuf::from(project.generate_path("a.txt").unwrap());
+            write_file(&path, "data");
+            project
+                .add_file("a.txt", path.clone(), HashMap::new(), false, false)
+                .unwrap();
+            path
+        };
+        drop(project);
+        let mut manager = lock_manager();
+        // A second project keeps the collection around while the first is trashed
+        manager
+            .create_project("other", &collection, false, None)
+            .unwrap();
+
+        manager.trash_project(&name, &collection, false).unwrap();
+        assert_eq!(manager.list_trash(&collection).unwrap(), vec![name.clone()]);
+        for show_hidden in [false, true] {
+            let names = manager
+                .get_project_names(collection.clone(), show_hidden)
+                .unwrap();
+            assert_eq!(names, vec!["other"]);
+        }
+        assert!(manager.load_project(&name, &collection).is_err());
+        assert!(storage_file.exists());
+
+        manager.restore_project(&name, &collection).unwrap();
+        assert!(manager.list_trash(&collection).unwrap().is_empty());
+        let project = retry_while_locked(|| manager.load_project(&name, &collection)).unwrap();
+        let file = project.lock().unwrap().get_file("a.txt").unwrap();
+        assert_eq!(file["real_path"], storage_file.to_str().unwrap());
+        drop(project);
+
+        manager.trash_project(&name, &collection, false).unwrap();
+        manager.purge_project(&name, &collection).unwrap();
+        assert!(manager.list_trash(&collection).unwrap().is_empty());
+        assert!(!storage_file.exists());
+        assert!(manager.restore_project(&name, &collection).is_err());
+    }
+
+    #[test]
+    fn relink_keeps_metadata() {
+        let (_, _, project) = new_project();
+        let dir = scratch_dir();
+        let old = write_file(&dir.join("old.txt"), "data");
+        let new = write_file(&dir.join("new.txt"), "data");
+        let mut project = project.lock().unwrap();
+        let metadata = HashMap::from([("band".to_string(), "r".to_string())]);
+        project
+            .add_file("a.txt", old, metadata, false, false)
+            .unwrap();
+        project.relink("a.txt", new.clone()).unwrap();
+        let file = project.get_file("a.txt").unwrap();
+        assert_eq!(file["real_path"], new.to_str().unwrap());
+        assert_eq!(file["band"], "r");
+    }
+
+    #[test]
+    fn remove_many_reports_partial_success() {
+        let (_, _, project) = new_project();
+        let dir = scratch_dir();
+        let mut project = project.lock().unwrap();
+        let internal = PathBuf::from(project.generate_path("a.txt").unwrap());
+        write_file(&internal, "data");
+        project
+            .add_file("a.txt", internal.clone(), HashMap::new(), false, false)
+            .unwrap();
+        let external = write_file(&dir.join("b.txt"), "data");
+        project
+            .add_file("b.txt", external, HashMap::new(), false, false)
+            .unwrap();
+
+        let paths = vec!["a.txt", "missing.txt", "b.txt"];
+        let report = project
+            .remove_many(paths.into_iter().map(String::from).collect())
+            .unwrap();
+        assert_eq!(report.removed, vec!["a.txt", "b.txt"]);
+        assert_eq!(report.failed.len(), 1);
+        assert!(report.failed.contains_key("missing.txt"));
+        assert_eq!(report.internal_paths, vec![internal.to_str().unwrap()]);
+        assert!(!project.exists("a.txt".to_string()));
+        assert!(!project.exists("b.txt".to_string()));
+    }
+
+    #[test]
+    fn remove_matching_guards_patterns_that_match_everything() {
+        let (_, _, project) = new_project();
+        let dir = scratch_dir();
+        for name in ["a.fits", "b.fits", "c.txt"] {
+            write_file(&dir.join(name), "data");
+        }
+        let mut project = project.lock().unwrap();
+        project.add_folder("data", dir, false, None, false).unwrap();
+
+        for pattern in ["*", "*.*", "?*"] {
+            let result = project.remove_matching(Some("data"), pattern, false, false);
+            assert_eq!(
+                result.err().unwrap().error_type,
+                GodataErrorType::NotPermitted
+            );
+            // A dry run only reports, so it doesn't need confirming
+            let report = project
+                .remove_matching(Some("data"), pattern, true, false)
+                .unwrap();
+            assert_eq!(report.removed.len(), 3);
+        }
+
+        let report = project
+            .remove_matching(Some("data"), "*.png", true, false)
+            .unwrap();
+        assert!(report.removed.is_empty());
+        let report = project
+            .remove_matching(Some("data"), "*.png", false, false)
+            .unwrap();
+        assert!(report.removed.is_empty());
+
+        let report = project
+            .remove_matching(Some("data"), "*.fits", false, false)
+            .unwrap();
+        assert_eq!(report.removed.len(), 2);
+        assert!(project.exists("data/c.txt".to_string()));
+        project
+            .remove_matching(Some("data"), "*", false, true)
+            .unwrap();
+        assert!(!project.exists("data/c.txt".to_string()));
+    }
+
+    fn project_with_stored_files(count: usize) -> (String, String) {
+        let (collection, name, project) = new_project();
+        let mut project = project.lock().unwrap();
+        for i in 0..count {
+            let path = format!("file{}.txt", i);
+            let real_path = PathBuf::from(project.generate_path(&path).unwrap());
+            write_file(&real_path, "data");
+            project
+                .add_file(&path, real_path, HashMap::new(), false, false)
+                .unwrap();
+        }
+        (collection, name)
+    }
+
+    #[test]
+    fn forced_delete_under_threshold_needs_no_confirmation() {
+        let (collection, name) = project_with_stored_files(5);
+        let mut manager = lock_manager();
+        manager
+            .delete_project(&name, &collection, true, false, None)
+            .unwrap();
+        assert!(load_project_dir(&name, &collection).is_err());
+    }
+
+    #[test]
+    fn forced_delete_over_threshold_needs_the_file_count() {
+        let count = DELETE_CONFIRM_THRESHOLD + 1;
+        let (collection, name) = project_with_stored_files(count);
+        let mut manager = lock_manager();
+        for confirm_count in [None, Some(count - 1)] {
+            let result = manager.delete_project(&name, &collection, true, false, confirm_count);
+            let error = result.err().unwrap();
+            assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+            assert!(error.message.contains(&format!("confirm_count={}", count)));
+        }
+        assert!(load_project_dir(&name, &collection).is_ok());
+        manager
+            .delete_project(&name, &collection, true, false, Some(count))
+            .unwrap();
+        assert!(load_project_dir(&name, &collection).is_err());
+    }
+
+    #[test]
+    fn finished_loads_are_forgotten() {
+        let mut manager = lock_manager();
+        let load_id = manager.start_load();
+        assert!(matches!(
+            manager.load_status(&load_id),
+            Ok(LoadStatus::Loading)
+        ));
+        manager.finish_load(&load_id, Ok(()));
+        assert!(matches!(
+            manager.load_status(&load_id),
+            Ok(LoadStatus::Ready)
+        ));
+
+        // Pretend the load finished long enough ago to have expired
+        if let Some(expired) = Instant::now().checked_sub(LOAD_STATUS_TTL * 2) {
+            manager.loads.get_mut(&load_id).unwrap().1 = Some(expired);
+            manager.start_load();
+            assert!(manager.load_status(&load_id).is_err());
+        }
+    }
+
+    #[test]
+    fn reserved_paths_are_never_shared() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        let first = project
+            .reserve_and_link("x", Some("fits"), HashMap::new())
+            .unwrap();
+        let second = project
+            .reserve_and_link("x.fits", None, HashMap::new())
+            .unwrap();
+        assert_ne!(first, second);
+        assert!(first.ends_with("/x.fits"));
+        assert!(Path::new(&second).is_file());
+        let file = project.get_file("x").unwrap();
+        assert_eq!(file["real_path"], first);
+    }
+
+    fn link_with_metadata(
+        project: &mut Project,
+        path: &str,
+        contents: &str,
+        keys: &[(&str, &str)],
+    ) {
+        let real_path = write_file(&scratch_dir().join("file"), contents);
+        let metadata = keys
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        project
+            .add_file(path, real_path, metadata, false, false)
+            .unwrap();
+    }
+
+    #[test]
+    fn metadata_keys_are_counted_across_files() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(
+            &mut project,
+            "a.txt",
+            "",
+            &[("band", "r"), ("exposure", "30")],
+        );
+        link_with_metadata(&mut project, "b.txt", "", &[("band", "g"), ("night", "1")]);
+        link_with_metadata(&mut project, "c/d.txt", "", &[("observer", "me")]);
+        link_with_metadata(&mut project, "e.txt", "", &[]);
+        let keys = project.metadata_keys().unwrap();
+        let expected = BTreeMap::from([
+            ("band".to_string(), 2),
+            ("exposure".to_string(), 1),
+            ("night".to_string(), 1),
+            ("observer".to_string(), 1),
+        ]);
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn stats_on_a_known_tree() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "a.txt", "1234", &[("band", "r")]);
+        link_with_metadata(&mut project, "d1/b.txt", "0123456789", &[("band", "g")]);
+        link_with_metadata(&mut project, "d1/d2/c.txt", "x", &[("night", "1")]);
+        let stored = project
+            .reserve_and_link("d3/e.txt", None, HashMap::new())
+            .unwrap();
+        write_file(Path::new(&stored), "12345");
+
+        let stats = project.stats().unwrap();
+        assert_eq!(stats.files, 4);
+        assert_eq!(stats.folders, 3);
+        assert_eq!(stats.total_size, 20);
+        assert_eq!(stats.internal_size, 5);
+        assert_eq!(stats.quota, None);
+        assert_eq!(stats.metadata_keys, 2);
+        assert_eq!(stats.max_depth, 3);
+        assert!(stats.created_at.is_some());
+        assert!(stats.updated_at >= stats.created_at);
+    }
+
+    #[test]
+    fn internal_files_follow_relocated_storage() {
+        let (collection, name, project) = new_project();
+        let old_path = {
+            let mut project = project.lock().unwrap();
+            let path = project
+                .reserve_and_link("data/a.txt", None, HashMap::new())
+                .unwrap();
+            write_file(Path::new(&path), "data");
+            PathBuf::from(path)
+        };
+        let mut manager = lock_manager();
+        let old_root = manager.storage_manager.get(&name, &collection).unwrap().1;
+        let new_root = scratch_dir().join("moved");
+
+        // Refused while someone is still using the project
+        let result = manager.relocate_storage(&name, &collection, scratch_dir(), false);
+        assert_eq!(
+            result.err().unwrap().error_type,
+            GodataErrorType::NotPermitted
+        );
+        drop(project);
+
+        std::fs::rename(&old_root, &new_root).unwrap();
+        manager
+            .relocate_storage(&name, &collection, new_root.clone(), false)
+            .unwrap();
+        assert!(!manager.is_loaded(&name, &collection));
+        assert_eq!(manager.ref_count(&name, &collection), 0);
+        let project = retry_while_locked(|| manager.load_project(&name, &collection)).unwrap();
+        let file = project.lock().unwrap().get_file("data/a.txt").unwrap();
+        let new_path = new_root.join(old_path.strip_prefix(&old_root).unwrap());
+        assert_eq!(file["real_path"], new_path.to_str().unwrap());
+        assert_eq!(std::fs::read_to_string(new_path).unwrap(), "data");
+    }
+
+    #[test]
+    fn rename_files_and_folders() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "d/a.txt", "", &[("band", "r")]);
+        link_with_metadata(&mut project, "d/sub/b.txt", "", &[]);
+
+        project.rename("d/a.txt", "c.txt").unwrap();
+        assert!(!project.exists("d/a.txt".to_string()));
+        assert_eq!(project.get_file("d/c.txt").unwrap()["band"], "r");
+
+        project.rename("d", "e").unwrap();
+        assert!(!project.exists("d".to_string()));
+        assert!(project.exists("e/c.txt".to_string()));
+        assert!(project.exists("e/sub/b.txt".to_string()));
+
+        let result = project.rename("e/c.txt", "sub/c.txt");
+        assert_eq!(
+            result.err().unwrap().error_type,
+            GodataErrorType::InvalidPath
+        );
+        assert!(project.exists("e/c.txt".to_string()));
+    }
+
+    fn import_dirs() -> usize {
+        std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter(|e| {
+                let name = e.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with("godata-import-")
+            })
+            .count()
+    }
+
+    #[test]
+    fn compressed_export_round_trip() {
+        let (collection, name, project) = new_project();
+        let dir = scratch_dir();
+        for i in 0..20 {
+            let path = write_file(&dir.join(format!("file{}.txt", i)), "data");
+            project
+                .lock()
+                .unwrap()
+                .add_file(
+                    &format!("data/file{}.txt", i),
+                    path,
+                    HashMap::new(),
+                    false,
+                    false,
+                )
+                .unwrap();
+        }
+        let before = file_paths(&project.lock().unwrap());
+        let mut manager = lock_manager();
+        let output = scratch_dir();
+        manager
+            .export_project(&name, &collection, output.clone(), Compression::Gzip)
+            .unwrap();
+        assert!(output.join(TREE_ARCHIVE_NAME).exists());
+        assert!(!output.join(".tree").exists());
+
+        let leftover = import_dirs();
+        manager
+            .import_project("imported", &collection, "local", output)
+            .unwrap();
+        assert_eq!(import_dirs(), leftover);
+        let imported =
+            retry_while_locked(|| manager.load_project("imported", &collection)).unwrap();
+        assert_eq!(file_paths(&imported.lock().unwrap()), before);
+
+        // A corrupt archive fails the import without leaving anything behind
+        let corrupt = scratch_dir();
+        let mut bytes = vec![0x1f, 0x8b];
+        bytes.extend_from_slice(b"not really gzip");
+        std::fs::write(corrupt.join(TREE_ARCHIVE_NAME), bytes).unwrap();
+        assert!(manager
+            .import_project("corrupt", &collection, "local", corrupt.clone())
+            .is_err());
+        assert_eq!(import_dirs(), leftover);
+        assert!(load_project_dir("corrupt", &collection).is_err());
+
+        // So does a folder with no tree at all
+        let empty = scratch_dir();
+        let error = manager
+            .import_project("empty", &collection, "local", empty.clone())
+            .unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::NotFound);
+        assert!(!empty.join(".tree").exists());
+        assert!(load_project_dir("empty", &collection).is_err());
+    }
+
+    #[test]
+    fn bundle_round_trip() {
+        let (collection, name, project) = new_project();
+        let external = write_file(&scratch_dir().join("external.txt"), "outside");
+        {
+            let mut project = project.lock().unwrap();
+            let stored = PathBuf::from(project.generate_path("stored.txt").unwrap());
+            write_file(&stored, "inside");
+            project
+                .add_file("stored.txt", stored, HashMap::new(), false, false)
+                .unwrap();
+            project
+                .add_file(
+                    "external.txt",
+                    external.clone(),
+                    HashMap::new(),
+                    false,
+                    false,
+                )
+                .unwrap();
+            // Give the stored file an absolute path, like older projects have
+            let endpoint = &project._endpoint;
+            let root = endpoint.resolve(Path::new(""));
+            project
+                .tree
+                .rewrite_paths(|path| {
+                    if path.is_relative() {
+                        root.join(path)
+                    } else {
+                        path.to_path_buf()
+                    }
+                })
+                .unwrap();
+        }
+        let stored_path = |project: &Arc<Mutex<Project>>| {
+            let project = project.lock().unwrap();
+            project.tree.get("stored.txt").unwrap().real_path.clone()
+        };
+        let before = stored_path(&project);
+        assert!(before.is_absolute());
+
+        let mut manager = lock_manager();
+        let bundle = scratch_dir().join("project.tar");
+        manager
+            .export_bundle(&name, &collection, bundle.clone())
+            .unwrap();
+        // Exporting leaves the project itself alone
+        assert_eq!(stored_path(&project), before);
+
+        let storage = scratch_dir().join("storage");
+        manager
+            .import_bundle(
+                "imported",
+                &collection,
+                bundle.clone(),
+                Some(storage.clone()),
+            )
+            .unwrap();
+        let imported =
+            retry_while_locked(|| manager.load_project("imported", &collection)).unwrap();
+        let mut imported = imported.lock().unwrap();
+        let file = imported.get_file("stored.txt").unwrap();
+        let real_path = PathBuf::from(&file["real_path"]);
+        assert!(real_path.starts_with(&storage));
+        assert_eq!(std::fs::read_to_string(real_path).unwrap(), "inside");
+        let file = imported.get_file("external.txt").unwrap();
+        assert_eq!(file["real_path"], external.to_str().unwrap());
+
+        // Storage that already holds files is refused before anything is written
+        let error = manager
+            .import_bundle("clash", &collection, bundle.clone(), Some(storage.clone()))
+            .unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::AlreadyExists);
+        assert!(load_project_dir("clash", &collection).is_err());
+
+        // A bundle without a tree fails without leaving anything behind
+        let empty = scratch_dir();
+        write_file(&empty.join("data/file.txt"), "data");
+        let broken = scratch_dir().join("broken.tar");
+        archive::write_bundle(
+            &broken,
+            &scratch_dir(),
+            &[(empty.join("data/file.txt"), PathBuf::from("file.txt"))],
+            b"{}",
+        )
+        .unwrap();
+        let storage = scratch_dir().join("broken");
+        assert!(manager
+            .import_bundle("broken", &collection, broken, Some(storage.clone()))
+            .is_err());
+        assert!(!storage.exists());
+        assert!(load_project_dir("broken", &collection).is_err());
+        assert!(manager.storage_manager.get("broken", &collection).is_err());
+    }
+
+    #[test]
+    fn watch_reports_files_in_moved_folders() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        // The storage root is created when the watch starts if it isn't there yet
+        let root = PathBuf::from(project._endpoint.root());
+        let _ = std::fs::remove_dir_all(&root);
+        project.set_watch(true).unwrap();
+        assert!(root.exists());
+
+        let folder = root.join("moved");
+        for name in ["a.txt", "b.txt"] {
+            let path = write_file(&folder.join(name), "data");
+            project
+                .add_file(
+                    &format!("moved/{}", name),
+                    path,
+                    HashMap::new(),
+                    false,
+                    false,
+                )
+                .unwrap();
+        }
+        let kept = write_file(&root.join("kept.txt"), "data");
+        project
+            .add_file("kept.txt", kept, HashMap::new(), false, false)
+            .unwrap();
+        std::fs::rename(&folder, scratch_dir().join("moved")).unwrap();
+
+        let expected = vec!["moved/a.txt".to_string(), "moved/b.txt".to_string()];
+        let mut dangling = Vec::new();
+        for _ in 0..50 {
+            dangling = project.dangling().unwrap();
+            if dangling == expected {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(dangling, expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn listings_skip_entries_they_cannot_use() {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::PermissionsExt;
+        let dir = scratch_dir();
+        for name in ["readable", "locked", ".hidden"] {
+            std::fs::create_dir(dir.join(name)).unwrap();
+        }
+        std::fs::create_dir(dir.join(std::ffi::OsStr::from_bytes(b"bad\xff"))).unwrap();
+        write_file(&dir.join("file.txt"), "data");
+        std::fs::set_permissions(dir.join("locked"), std::fs::Permissions::from_mode(0o000))
+            .unwrap();
+
+        let mut names = list_dir_names(&dir, false).unwrap();
+        names.sort();
+        assert_eq!(names, vec!["locked", "readable"]);
+        let mut names = list_dir_names(&dir, true).unwrap();
+        names.sort();
+        assert_eq!(names, vec![".hidden", "locked", "readable"]);
+        std::fs::set_permissions(dir.join("locked"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+    }
+
+    #[test]
+    fn anonymized_export_has_no_real_paths() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "raw/image.fits", "data", &[("band", "r")]);
+        let real_path = project.get_file("raw/image.fits").unwrap()["real_path"].clone();
+
+        let full = serde_json::to_string(&project.export_json(false).unwrap()).unwrap();
+        assert!(full.contains(&real_path));
+        let anonymized = serde_json::to_string(&project.export_json(true).unwrap()).unwrap();
+        assert!(!anonymized.contains(&real_path));
+        assert!(!anonymized.contains("real_path"));
+        assert!(!anonymized.contains("origin"));
+        assert!(anonymized.contains(r#""name":"image.fits""#));
+        assert!(anonymized.contains(r#""band":"r""#));
+    }
+
+    #[test]
+    fn subtree_keeps_hierarchy_and_stops_at_depth() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "top.txt", "data", &[]);
+        link_with_metadata(&mut project, "a/one.txt", "data", &[("kind", "one")]);
+        link_with_metadata(&mut project, "a/b/two.txt", "data", &[]);
+
+        let tree = serde_json::to_value(project.subtree(None, None, false).unwrap()).unwrap();
+        assert_eq!(
+            tree,
+            serde_json::json!({
+                "type": "folder",
+                "name": "root",
+                "children": [
+                    {"type": "folder", "name": "a", "children": [
+                        {"type": "folder", "name": "b", "children": [
+                            {"type": "file", "name": "two.txt"}
+                        ]},
+                        {"type": "file", "name": "one.txt"}
+                    ]},
+                    {"type": "file", "name": "top.txt"}
+                ]
+            })
+        );
+
+        let tree = serde_json::to_value(project.subtree(Some("a"), Some(0), false).unwrap());
+        assert!(tree.unwrap().get("children").is_none());
+        let tree = serde_json::to_value(project.subtree(Some("a"), Some(1), true).unwrap());
+        let tree = tree.unwrap();
+        assert_eq!(tree["name"], "a");
+        let children = tree["children"].as_array().unwrap();
+        assert_eq!(children.len(), 2);
+        assert!(children[0].get("children").is_none());
+        assert_eq!(children[1]["metadata"]["kind"], "one");
+    }
+
+    #[test]
+    fn get_or_create_creates_once() {
+        let collection = crate::testing::unique("collection");
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let collection = collection.clone();
+                std::thread::spawn(move || {
+                    let (_, created) = lock_manager()
+                        .get_or_create_project("shared", &collection, None)
+                        .unwrap();
+                    created
+                })
+            })
+            .collect();
+        let created: Vec<bool> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(created.iter().filter(|c| **c).count(), 1);
+    }
+
+    #[test]
+    fn unload_waits_for_users_unless_forced() {
+        let (collection, name, project) = new_project();
+        link_with_metadata(&mut project.lock().unwrap(), "a.txt", "data", &[("k", "v")]);
+        let mut manager = lock_manager();
+        let error = manager
+            .unload_project(&name, &collection, true, false)
+            .unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+        assert!(manager.is_loaded(&name, &collection));
+
+        drop(project);
+        manager
+            .unload_project(&name, &collection, true, false)
+            .unwrap();
+        assert!(!manager.is_loaded(&name, &collection));
+        let project = retry_while_locked(|| manager.load_project(&name, &collection)).unwrap();
+        assert_eq!(project.lock().unwrap().get_file("a.txt").unwrap()["k"], "v");
+
+        // Forcing evicts it anyway, and it can be loaded again once it is let go. Sled
+        // can hold on to the database for a moment after that, so the reload waits.
+        manager
+            .unload_project(&name, &collection, true, true)
+            .unwrap();
+        assert!(!manager.is_loaded(&name, &collection));
+        let e = manager
+            .load_project(&name, &collection)
+            .err()
+            .map(|e| e.error_type);
+        assert_eq!(e, Some(GodataErrorType::Locked));
+        drop(project);
+        let project = retry_while_locked(|| manager.load_project(&name, &collection)).unwrap();
+        assert_eq!(project.lock().unwrap().get_file("a.txt").unwrap()["k"], "v");
+    }
+
+    #[test]
+    fn duplicates_found_in_linked_folders() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        project.set_checksums(true, false).unwrap();
+        let dir = scratch_dir();
+        for name in ["a.txt", "b.txt", "sub/c.txt"] {
+            write_file(&dir.join(name), "same bytes");
+        }
+        write_file(&dir.join("other.txt"), "other bytes");
+        project.add_folder("data", dir, true, None, false).unwrap();
+        assert_eq!(
+            project.find_duplicates().unwrap(),
+            vec![vec!["data/a.txt", "data/b.txt", "data/sub/c.txt"]]
+        );
+
+        // In the background, linked files are queued instead
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        project.set_checksums(true, true).unwrap();
+        let dir = scratch_dir();
+        for name in ["a.txt", "sub/b.txt"] {
+            write_file(&dir.join(name), "data");
+        }
+        project.add_folder("data", dir, true, None, false).unwrap();
+        assert_eq!(project.checksum_status().pending, 2);
+    }
+
+    #[test]
+    fn content_is_only_served_for_small_internal_files() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        for (name, size) in [("small.json", 16), ("large.bin", INLINE_CONTENT_LIMIT + 1)] {
+            let path = PathBuf::from(project.generate_path(name).unwrap());
+            write_file(&path, &"x".repeat(size as usize));
+            project
+                .add_file(name, path, HashMap::new(), false, false)
+                .unwrap();
+        }
+        link_with_metadata(&mut project, "external.json", "{}", &[]);
+
+        let (content, content_type) = project.read_content("small.json").unwrap();
+        assert_eq!(content, "x".repeat(16).into_bytes());
+        assert_eq!(content_type, "application/json");
+        for path in ["large.bin", "external.json"] {
+            let error = project.read_content(path).unwrap_err();
+            assert_eq!(error.error_type, GodataErrorType::TooLarge, "{}", path);
+        }
+    }
+
+    #[test]
+    fn overwrites_can_back_up_the_old_entry() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "data/a.txt", "old", &[("version", "1")]);
+        let new_file = write_file(&scratch_dir().join("a.txt"), "new");
+        let metadata = HashMap::from([("version".to_string(), "2".to_string())]);
+        let (_, backup) = project
+            .add_file("data/a.txt", new_file, metadata, true, true)
+            .unwrap();
+        let backup = backup.unwrap();
+        assert!(backup.starts_with(BACKUP_DIR));
+        assert_eq!(project.get_file(&backup).unwrap()["version"], "1");
+        assert_eq!(project.get_file("data/a.txt").unwrap()["version"], "2");
+
+        // A move that can't go through leaves the destination where it was
+        link_with_metadata(&mut project, "data/sub/b.txt", "data", &[]);
+        let before = file_paths(&project);
+        let result = project.move_("data", "data/sub", true, true);
+        assert_eq!(
+            result.err().unwrap().error_type,
+            GodataErrorType::InvalidPath
+        );
+        assert_eq!(file_paths(&project), before);
+    }
+
+    fn runs_to_flatten() -> Arc<Mutex<Project>> {
+        let (_, _, project) = new_project();
+        for path in [
+            "run/1/calib/a.fits",
+            "run/2/calib/a.fits",
+            "run/2/calib/b.fits",
+        ] {
+            link_with_metadata(&mut project.lock().unwrap(), path, "data", &[]);
+        }
+        project
+    }
+
+    #[test]
+    fn flatten_with_each_collision_strategy() {
+        let project = runs_to_flatten();
+        let mut project = project.lock().unwrap();
+        let before = file_paths(&project);
+        let result = project.flatten("run", "calib", CollisionStrategy::Error);
+        assert_eq!(
+            result.err().unwrap().error_type,
+            GodataErrorType::AlreadyExists
+        );
+        assert_eq!(file_paths(&project), before);
+
+        let project = runs_to_flatten();
+        let mut project = project.lock().unwrap();
+        let report = project
+            .flatten("run", "calib", CollisionStrategy::Skip)
+            .unwrap();
+        assert_eq!(report.moved, 2);
+        assert_eq!(report.collisions, vec!["run/2/calib/a.fits"]);
+        assert_eq!(
+            file_paths(&project),
+            vec!["calib/a.fits", "calib/b.fits", "run/2/calib/a.fits"]
+        );
+
+        let project = runs_to_flatten();
+        let mut project = project.lock().unwrap();
+        let report = project
+            .flatten("run", "calib", CollisionStrategy::Suffix)
+            .unwrap();
+        assert_eq!(report.moved, 3);
+        assert_eq!(report.collisions, vec!["run/2/calib/a.fits"]);
+        assert_eq!(
+            file_paths(&project),
+            vec!["calib/a.fits", "calib/a_1.fits", "calib/b.fits"]
+        );
+    }
+
+    // Local storage that reports itself as unreachable
+    struct OfflineEndpoint(LocalEndpoint);
+
+    impl StorageEndpoint for OfflineEndpoint {
+        fn generate_path(&self, project_path: &str) -> Result<PathBuf> {
+            self.0.generate_path(project_path)
+        }
+        fn generate_unique_path(
+            &self,
+            project_path: &str,
+            suffix: Option<&str>,
+        ) -> Result<PathBuf> {
+            self.0.generate_unique_path(project_path, suffix)
+        }
+        fn is_available(&self) -> Result<()> {
+            Err(GodataError::new(
+                GodataErrorType::NotFound,
+                "disk is offline".to_string(),
+            ))
+        }
+        fn discover_file(&self, project_path: &str, file_extension: String) -> Result<PathBuf> {
+            self.0.discover_file(project_path, file_extension)
+        }
+        fn move_file(&self, from: &str, to: &str) -> Result<()> {
+            self.0.move_file(from, to)
+        }
+        fn copy_file(&self, from: &Path, to: &Path) -> Result<()> {
+            self.0.copy_file(from, to)
+        }
+        fn delete_file(&self, path: &str) -> Result<()> {
+            self.0.delete_file(path)
+        }
+        fn is_internal(&self, path: &Path) -> bool {
+            self.0.is_internal(path)
+        }
+        fn get_relative_path(&self, path: &Path) -> PathBuf {
+            self.0.get_relative_path(path)
+        }
+        fn resolve(&self, relpath: &Path) -> PathBuf {
+            self.0.resolve(relpath)
+        }
+        fn open_read(&self, relpath: &Path) -> Result<Box<dyn Read>> {
+            self.0.open_read(relpath)
+        }
+        fn kind(&self) -> &'static str {
+            "offline"
+        }
+        fn root(&self) -> String {
+            self.0.root()
+        }
+        fn set_path_template(&mut self, template: Option<PathTemplate>) {
+            self.0.set_path_template(template)
+        }
+    }
+
+    #[test]
+    fn unavailable_storage_fails_fast() {
+        let mut tree = FileSystem::temporary("offline".to_string()).unwrap();
+        tree.insert("a.txt", PathBuf::from("a.txt"), HashMap::new(), None, false)
+            .unwrap();
+        let endpoint = OfflineEndpoint(LocalEndpoint::new(scratch_dir()));
+        let mut project = Project::new(tree, "offline", "tests", Box::new(endpoint));
+        let file = write_file(&scratch_dir().join("b.txt"), "data");
+
+        let errors = [
+            project
+                .add_file("b.txt", file, HashMap::new(), false, false)
+                .err(),
+            project.get_file("a.txt").err(),
+            project.remove_file("a.txt").err(),
+        ];
+        for error in errors {
+            let error = error.unwrap();
+            assert_eq!(error.error_type, GodataErrorType::IOError);
+            assert!(error.message.contains("disk is offline"));
+        }
+        // Nothing was changed on the way to the error
+        assert_eq!(file_paths(&project), vec!["a.txt"]);
+    }
+
+    #[test]
+    fn projects_sort_by_each_key() {
+        let collection = crate::testing::unique("collection");
+        let mut manager = lock_manager();
+        // (name, created, updated, size)
+        for (name, created, updated, size) in [
+            ("gamma", 100, 3, 10),
+            ("alpha", 300, 1, 30),
+            ("beta", 200, 2, 20),
+        ] {
+            let project = manager
+                .create_project(name, &collection, true, None)
+                .unwrap();
+            let mut project = project.lock().unwrap();
+            link_with_metadata(&mut project, "data.bin", &"x".repeat(size), &[]);
+            let tree = &mut project.tree;
+            tree.set_system_value(CREATED_KEY, Some(created.to_string()))
+                .unwrap();
+            tree.set_system_value(UPDATED_KEY, Some(updated.to_string()))
+                .unwrap();
+        }
+        let sorted = |sort, descending| {
+            manager
+                .sorted_project_names(collection.clone(), false, sort, descending)
+                .unwrap()
+        };
+        assert_eq!(sorted(ProjectSort::Name, false), ["alpha", "beta", "gamma"]);
+        assert_eq!(
+            sorted(ProjectSort::Created, false),
+            ["gamma", "beta", "alpha"]
+        );
+        assert_eq!(
+            sorted(ProjectSort::Updated, false),
+            ["alpha", "beta", "gamma"]
+        );
+        assert_eq!(sorted(ProjectSort::Size, false), ["gamma", "beta", "alpha"]);
+        assert_eq!(sorted(ProjectSort::Size, true), ["alpha", "beta", "gamma"]);
+    }
+
+    #[test]
+    fn internalize_copies_external_files_in() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        let external = write_file(&scratch_dir().join("image.fits"), "pixels");
+        project
+            .add_file("image.fits", external.clone(), HashMap::new(), false, false)
+            .unwrap();
+        let change = project.internalize("image.fits").unwrap();
+        assert!(change.changed);
+        let real_path = PathBuf::from(&change.real_path);
+        assert!(project._endpoint.is_internal(&real_path));
+        assert_eq!(std::fs::read_to_string(&real_path).unwrap(), "pixels");
+        assert_eq!(
+            project.get_file("image.fits").unwrap()["real_path"],
+            change.real_path
+        );
+        // The external file is left alone, and a second call has nothing to do
+        assert!(external.exists());
+        assert!(!project.internalize("image.fits").unwrap().changed);
+    }
+
+    #[test]
+    fn externalize_hands_back_the_internal_copy() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        let internal = PathBuf::from(project.generate_path("out.txt").unwrap());
+        write_file(&internal, "result");
+        project
+            .add_file("out.txt", internal.clone(), HashMap::new(), false, false)
+            .unwrap();
+
+        let dest = scratch_dir().join("kept.txt");
+        write_file(&dest, "in the way");
+        let result = project.externalize("out.txt", dest.clone(), false);
+        assert_eq!(
+            result.err().unwrap().error_type,
+            GodataErrorType::AlreadyExists
+        );
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "in the way");
+
+        let change = project.externalize("out.txt", dest.clone(), true).unwrap();
+        assert_eq!(change.removed.as_deref(), internal.to_str());
+        // Nothing points at the internal copy any more, so it can go
+        std::fs::remove_file(&internal).unwrap();
+        let file = project.get_file("out.txt").unwrap();
+        assert_eq!(file["real_path"], dest.to_str().unwrap());
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "result");
+    }
+
+    #[test]
+    fn integrity_counts_present_and_missing_files() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        let mut paths = Vec::new();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            let path = PathBuf::from(project.generate_path(name).unwrap());
+            write_file(&path, "data");
+            project
+                .add_file(name, path.clone(), HashMap::new(), false, false)
+                .unwrap();
+            paths.push(path);
+        }
+        for name in ["d.txt", "e.txt"] {
+            let path = write_file(&scratch_dir().join(name), "data");
+            project
+                .add_file(name, path.clone(), HashMap::new(), false, false)
+                .unwrap();
+            paths.push(path);
+        }
+        // Lose one internal and one external file
+        std::fs::remove_file(&paths[0]).unwrap();
+        std::fs::remove_file(&paths[4]).unwrap();
+        let summary = project.integrity().unwrap();
+        assert_eq!(summary.total, 5);
+        assert_eq!(summary.internal_present, 2);
+        assert_eq!(summary.internal_missing, 1);
+        assert_eq!(summary.external_present, 1);
+        assert_eq!(summary.external_missing, 1);
+    }
+
+    #[test]
+    fn previews_are_attached_and_served() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "image.fits", "pixels", &[]);
+        let error = project.read_preview("image.fits").unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::NotFound);
+        let missing = scratch_dir().join("missing.png");
+        let error = project
+            .set_preview("image.fits", Some(missing))
+            .unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::NotFound);
+
+        let preview = write_file(&scratch_dir().join("thumb.png"), "png bytes");
+        project
+            .set_preview("image.fits", Some(preview.clone()))
+            .unwrap();
+        let file = project.get_file("image.fits").unwrap();
+        assert_eq!(file["preview"], preview.to_str().unwrap());
+        let (content, content_type) = project.read_preview("image.fits").unwrap();
+        assert_eq!(content, b"png bytes");
+        assert_eq!(content_type, "image/png");
+
+        project.set_preview("image.fits", None).unwrap();
+        assert!(!project
+            .get_file("image.fits")
+            .unwrap()
+            .contains_key("preview"));
+    }
+
+    #[test]
+    fn copied_metadata_keeps_destination_keys() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(
+            &mut project,
+            "source.fits",
+            "data",
+            &[("band", "r"), ("exptime", "30")],
+        );
+        link_with_metadata(&mut project, "dest.fits", "data", &[("band", "g")]);
+        link_with_metadata(&mut project, "folder/file.fits", "data", &[]);
+
+        assert_eq!(
+            project
+                .copy_metadata("source.fits", "dest.fits", false)
+                .unwrap(),
+            1
+        );
+        let dest = project.get_file("dest.fits").unwrap();
+        assert_eq!(dest["band"], "g");
+        assert_eq!(dest["exptime"], "30");
+        assert_eq!(
+            project
+                .copy_metadata("source.fits", "dest.fits", true)
+                .unwrap(),
+            2
+        );
+        assert_eq!(project.get_file("dest.fits").unwrap()["band"], "r");
+
+        for (source, dest) in [("folder", "dest.fits"), ("source.fits", "missing.fits")] {
+            assert!(project.copy_metadata(source, dest, false).is_err());
+        }
+    }
+
+    #[test]
+    fn reload_picks_up_changes_made_elsewhere() {
+        let (_, _, project) = new_project();
+        let dir = scratch_dir();
+        let first = write_file(&dir.join("first.txt"), "data");
+        let second = write_file(&dir.join("second.txt"), "data");
+        let mut project = project.lock().unwrap();
+        project
+            .add_file("first.txt", first, HashMap::new(), false, false)
+            .unwrap();
+        project.tree.flush().unwrap();
+
+        let mut other = project.tree.second_handle().unwrap();
+        other
+            .insert("data/second.txt", second, HashMap::new(), None, false)
+            .unwrap();
+        other.remove("first.txt").unwrap();
+        other.flush().unwrap();
+        assert_eq!(file_paths(&project), vec!["first.txt"]);
+
+        project.reload().unwrap();
+        assert_eq!(file_paths(&project), vec!["data/second.txt"]);
+    }
+
+    #[test]
+    fn usage_breakdown_sums_each_subfolder() {
+        let (_, _, project) = new_project();
+        let dir = scratch_dir();
+        write_file(&dir.join("raw/a.fits"), &"x".repeat(100));
+        write_file(&dir.join("raw/night1/b.fits"), &"x".repeat(250));
+        write_file(&dir.join("reduced/c.fits"), &"x".repeat(40));
+        write_file(&dir.join("notes.txt"), &"x".repeat(7));
+        let mut project = project.lock().unwrap();
+        project.add_folder("data", dir, true, None, false).unwrap();
+
+        let usage = project.usage_breakdown(Some("data")).unwrap();
+        assert_eq!(usage.len(), 3);
+        assert_eq!(usage["raw"], 350);
+        assert_eq!(usage["reduced"], 40);
+        assert_eq!(usage["."], 7);
+        let usage = project.usage_breakdown(None).unwrap();
+        assert_eq!(usage, HashMap::from([("data".to_string(), 397)]));
+    }
+
+    #[test]
+    fn export_subtree_re_roots_files_and_leaves_source() {
+        let (collection, name, project) = new_project();
+        let dir = scratch_dir();
+        let (internal, before) = {
+            let mut project = project.lock().unwrap();
+            let internal = PathBuf::from(project.generate_path("keep/inner/x.txt").unwrap());
+            write_file(&internal, "internal");
+            project
+                .add_file(
+                    "keep/inner/x.txt",
+                    internal.clone(),
+                    HashMap::new(),
+                    false,
+                    false,
+                )
+                .unwrap();
+            let linked = write_file(&dir.join("y.txt"), "linked");
+            project
+                .add_file("keep/y.txt", linked, HashMap::new(), false, false)
+                .unwrap();
+            let other = write_file(&dir.join("z.txt"), "other");
+            project
+                .add_file("other/z.txt", other, HashMap::new(), false, false)
+                .unwrap();
+            (internal, file_paths(&project))
+        };
+
+        let dest_collection = crate::testing::unique("collection");
+        let mut manager = lock_manager();
+        let count = manager
+            .export_subtree_as_project(&collection, &name, "keep", &dest_collection, "kept")
+            .unwrap();
+        assert_eq!(count, 2);
+
+        let dest = retry_while_locked(|| manager.open_uncached("kept", &dest_collection)).unwrap();
+        let dest = dest.lock().unwrap();
+        assert_eq!(file_paths(&dest), vec!["inner/x.txt", "y.txt"]);
+        let (content, _) = dest.read_content("inner/x.txt").unwrap();
+        assert_eq!(content, b"internal");
+        let copied = dest.tree.get("inner/x.txt").unwrap();
+        assert_ne!(dest._endpoint.resolve(&copied.real_path), internal);
+
+        let project = project.lock().unwrap();
+        assert_eq!(file_paths(&project), before);
+        assert_eq!(std::fs::read_to_string(&internal).unwrap(), "internal");
+    }
+
+    #[test]
+    fn import_manifest_links_rows_with_metadata() {
+        let (collection, name, project) = new_project();
+        let dir = scratch_dir();
+        write_file(&dir.join("frames/a.fits"), "data");
+        let absolute = write_file(&dir.join("b.fits"), "data");
+        let manifest = write_file(
+            &dir.join("manifest.csv"),
+            &format!(
+                "path,real_path,exposure,comment\n\
+                 raw/a.fits,frames/a.fits,30,\"cloudy, \"\"thin\"\"\"\n\
+                 raw/missing.fits,frames/missing.fits,10,\n\
+                 raw/b.fits,{},60,\n",
+                absolute.display()
+            ),
+        );
+        let report = lock_manager()
+            .import_manifest(&name, &collection, manifest, None)
+            .unwrap();
+        assert_eq!(report.linked, 2);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].line, 3);
+
+        let mut project = project.lock().unwrap();
+        assert_eq!(file_paths(&project), vec!["raw/a.fits", "raw/b.fits"]);
+        let file = project.get_file("raw/a.fits").unwrap();
+        assert_eq!(file["exposure"], "30");
+        assert_eq!(file["comment"], "cloudy, \"thin\"");
+        let file = project.get_file("raw/b.fits").unwrap();
+        assert_eq!(file["exposure"], "60");
+        assert!(!file.contains_key("comment"));
+    }
+
+    #[test]
+    fn move_with_overwrite_reports_moved_and_displaced() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "a.txt", "moved", &[("stage", "raw")]);
+        project
+            .tree
+            .set_checksums(vec![("a.txt".to_string(), "abc123".to_string())])
+            .unwrap();
+        let internal = PathBuf::from(project.generate_path("out/b.txt").unwrap());
+        write_file(&internal, "displaced");
+        let metadata = HashMap::from([("stage".to_string(), "old".to_string())]);
+        project
+            .add_file("out/b.txt", internal.clone(), metadata, false, false)
+            .unwrap();
+        let source = project.file_infos("a.txt").unwrap().remove(0);
+        let dest = project.file_infos("out/b.txt").unwrap().remove(0);
+
+        let report = project.move_("a.txt", "out/b.txt", true, false).unwrap();
+        assert_eq!(report.moved.len(), 1);
+        let moved = &report.moved[0];
+        assert_eq!(moved.uuid, source.uuid);
+        assert_eq!(moved.project_path, "out/b.txt");
+        assert_eq!(moved.real_path, source.real_path);
+        assert_eq!(moved.metadata["stage"], "raw");
+        assert_eq!(moved.checksum.as_deref(), Some("abc123"));
+        assert_eq!(report.displaced.len(), 1);
+        let displaced = &report.displaced[0];
+        assert_eq!(displaced.uuid, dest.uuid);
+        assert_eq!(displaced.metadata["stage"], "old");
+        assert_eq!(report.removed, vec![internal.to_str().unwrap().to_string()]);
+        assert!(!project.exists("a.txt".to_string()));
+    }
+
+    #[test]
+    fn cas_metadata_lets_one_claim_win() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "frame.fits", "data", &[("band", "r")]);
+        let claim = |project: &mut Project, worker: &str| {
+            project
+                .cas_metadata("frame.fits", "status", None, worker.to_string())
+                .unwrap()
+        };
+        assert!(claim(&mut project, "worker-1"));
+        assert!(!claim(&mut project, "worker-2"));
+        assert_eq!(
+            project.get_file("frame.fits").unwrap()["status"],
+            "worker-1"
+        );
+
+        // A stale expected value loses too
+        let swapped = project
+            .cas_metadata("frame.fits", "status", Some("queued".into()), "done".into())
+            .unwrap();
+        assert!(!swapped);
+        let swapped = project
+            .cas_metadata(
+                "frame.fits",
+                "status",
+                Some("worker-1".into()),
+                "done".into(),
+            )
+            .unwrap();
+        assert!(swapped);
+        let file = project.get_file("frame.fits").unwrap();
+        assert_eq!(file["status"], "done");
+        assert_eq!(file["band"], "r");
+    }
+
+    #[test]
+    fn gc_removes_old_orphans_only() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        let kept = PathBuf::from(project.generate_path("kept.txt").unwrap());
+        write_file(&kept, "kept");
+        project
+            .add_file("kept.txt", kept.clone(), HashMap::new(), false, false)
+            .unwrap();
+        let root = PathBuf::from(project._endpoint.root());
+        let orphan = write_file(&root.join("crashed/orphan.dat"), "orphan");
+        let two_hours_ago = SystemTime::now() - Duration::from_secs(7200);
+        std::fs::File::options()
+            .write(true)
+            .open(&orphan)
+            .unwrap()
+            .set_modified(two_hours_ago)
+            .unwrap();
+        // Could still be in the middle of being written
+        let fresh = write_file(&root.join("fresh.dat"), "fresh");
+
+        assert_eq!(project.gc(true).unwrap(), vec![orphan.clone()]);
+        assert!(orphan.exists());
+        assert_eq!(project.gc(false).unwrap(), vec![orphan.clone()]);
+        assert!(!orphan.exists());
+        assert!(!root.join("crashed").exists());
+        assert!(fresh.exists());
+        assert!(kept.exists());
+        assert!(project.gc(true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn uuid_follows_renames_and_moves() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "raw/a.fits", "data", &[]);
+        let uuid = project.file_infos("raw/a.fits").unwrap().remove(0).uuid;
+        assert_eq!(
+            project.get_by_uuid(&uuid).unwrap().project_path,
+            "raw/a.fits"
+        );
+
+        project
+            .move_("raw/a.fits", "raw/b.fits", false, false)
+            .unwrap();
+        assert_eq!(
+            project.get_by_uuid(&uuid).unwrap().project_path,
+            "raw/b.fits"
+        );
+        // Moving the folder moves everything in it
+        project.move_("raw", "archive/raw", false, false).unwrap();
+        assert_eq!(
+            project.get_by_uuid(&uuid).unwrap().project_path,
+            "archive/raw/b.fits"
+        );
+        project.tree.flush().unwrap();
+        project.reload().unwrap();
+        assert_eq!(
+            project.get_by_uuid(&uuid).unwrap().project_path,
+            "archive/raw/b.fits"
+        );
+
+        project.remove_file("archive/raw/b.fits").unwrap();
+        assert_eq!(
+            project.get_by_uuid(&uuid).err().unwrap().error_type,
+            GodataErrorType::NotFound
+        );
+    }
+
+    #[test]
+    fn manifest_hash_tracks_changes() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "a.txt", "data", &[("band", "r")]);
+        let before = project.manifest_hash().unwrap();
+        assert_eq!(
+            project.tree.system_value(MANIFEST_HASH_KEY),
+            Some(before.as_str())
+        );
+
+        link_with_metadata(&mut project, "b.txt", "data", &[]);
+        let added = project.manifest_hash().unwrap();
+        assert_ne!(added, before);
+        assert_eq!(project.manifest_hash().unwrap(), added);
+
+        project.remove_file("b.txt").unwrap();
+        assert_eq!(project.manifest_hash().unwrap(), before);
+
+        let metadata = HashMap::from([("band".to_string(), "g".to_string())]);
+        project.tree.set_file_metadata("a.txt", metadata).unwrap();
+        assert_ne!(project.manifest_hash().unwrap(), before);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn add_folder_skips_folders_with_invalid_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let (_, _, project) = new_project();
+        let dir = scratch_dir();
+        write_file(&dir.join("good/a.txt"), "data");
+        write_file(
+            &dir.join(OsStr::from_bytes(b"bad\xff")).join("b.txt"),
+            "data",
+        );
+        let mut project = project.lock().unwrap();
+        let report = project.add_folder("data", dir, true, None, false).unwrap();
+        assert_eq!(report.linked, 1);
+        assert_eq!(report.skipped_folders.len(), 1);
+        assert!(project.exists("data/good/a.txt".to_string()));
+    }
+
+    fn unloaded_project() -> (String, String) {
+        let (collection, name, project) = new_project();
+        drop(project);
+        lock_manager()
+            .unload_project(&name, &collection, true, true)
+            .unwrap();
+        (collection, name)
+    }
+
+    fn hold_database(name: &str, collection: &str) -> sled::Db {
+        // Stands in for another process with the project open. Sled can take a moment
+        // to let go of the database after the project is unloaded.
+        let path = load_project_dir(name, collection).unwrap();
+        for _ in 0..100 {
+            if let Ok(db) = sled::open(&path) {
+                return db;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("could not open the database for {}/{}", collection, name);
+    }
+
+    #[test]
+    fn load_waits_for_a_briefly_locked_database() {
+        let (collection, name) = unloaded_project();
+        let db = hold_database(&name, &collection);
+        let release = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            drop(db);
+        });
+        let mut attempts = 0;
+        let result = retry_while_locked(|| {
+            attempts += 1;
+            lock_manager().load_project(&name, &collection)
+        });
+        release.join().unwrap();
+        assert!(result.is_ok());
+        assert!(attempts > 1);
+    }
+
+    #[test]
+    fn load_gives_up_on_a_database_that_stays_locked() {
+        let (collection, name) = unloaded_project();
+        let db = hold_database(&name, &collection);
+        let mut attempts = 0;
+        let result = retry_while_locked(|| {
+            attempts += 1;
+            lock_manager().load_project(&name, &collection)
+        });
+        assert_eq!(result.err().unwrap().error_type, GodataErrorType::Locked);
+        assert_eq!(attempts, db_open_delays().count() + 1);
+        drop(db);
+        // Other refusals aren't retried
+        let mut attempts = 0;
+        let result: Result<()> = retry_while_locked(|| {
+            attempts += 1;
+            Err(GodataError::new(
+                GodataErrorType::NotPermitted,
+                "Not allowed".to_string(),
+            ))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn manager_is_free_while_a_load_waits() {
+        let (collection, name) = unloaded_project();
+        let db = hold_database(&name, &collection);
+        let manager = crate::testing::manager();
+        let waiting = {
+            let manager = manager.clone();
+            let (name, collection) = (name.clone(), collection.clone());
+            std::thread::spawn(move || {
+                ProjectManager::load_retrying(&manager, &name, &collection).map(|_| ())
+            })
+        };
+        std::thread::sleep(Duration::from_millis(30));
+        // Another request gets the manager while the load is still retrying
+        assert!(!lock_manager().is_loaded(&name, &collection));
+        drop(db);
+        waiting.join().unwrap().unwrap();
+        assert!(lock_manager().is_loaded(&name, &collection));
+    }
+
+    #[test]
+    fn references_resolve_through_to_their_target() {
+        let (collection, name, project) = new_project();
+        link_with_metadata(&mut project.lock().unwrap(), "a.txt", "data", &[("k", "v")]);
+        drop(project);
+        let (ref_collection, ref_name, referring) = new_project();
+        let at = |collection: &str, project: &str, path: &str| FileRef {
+            collection: collection.to_string(),
+            project: project.to_string(),
+            path: path.to_string(),
+        };
+        let target = at(&collection, &name, "a.txt");
+        let manager = crate::testing::manager();
+        ProjectManager::add_reference(
+            &manager,
+            &ref_name,
+            &ref_collection,
+            "b.txt",
+            target.clone(),
+            false,
+        )
+        .unwrap();
+        let origin = at(&ref_collection, &ref_name, "b.txt");
+        let file = lock_manager().resolve_reference(&origin, &target).unwrap();
+        assert_eq!(file["k"], "v");
+        assert_eq!(file["reference"], target.to_string());
+
+        // A reference to a reference ends up at the same file, but one that would lead
+        // back to where it starts is refused
+        ProjectManager::add_reference(
+            &manager,
+            &ref_name,
+            &ref_collection,
+            "c.txt",
+            origin.clone(),
+            false,
+        )
+        .unwrap();
+        let chained = at(&ref_collection, &ref_name, "c.txt");
+        let file = lock_manager().resolve_reference(&chained, &origin).unwrap();
+        assert_eq!(file["reference"], target.to_string());
+        let error =
+            ProjectManager::add_reference(&manager, &name, &collection, "a.txt", chained, true)
+                .unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::InvalidPath);
+
+        // The referring project doesn't treat the reference as a file of its own
+        {
+            let mut referring = referring.lock().unwrap();
+            let file = referring.get_file("b.txt").unwrap();
+            assert_eq!(file["reference"], target.to_string());
+            assert!(!file.contains_key("real_path"));
+            let error = referring.read_content("b.txt").unwrap_err();
+            assert_eq!(error.error_type, GodataErrorType::InvalidPath);
+            assert!(referring.internalize("b.txt").is_err());
+            assert_eq!(referring.integrity().unwrap().references, 2);
+            assert!(referring.largest(10).unwrap().is_empty());
+            assert!(referring.gc(true).unwrap().is_empty());
+        }
+        drop(referring);
+
+        // Once the target project is gone the reference is broken
+        lock_manager()
+            .delete_project(&name, &collection, true, false, None)
+            .unwrap();
+        let error = lock_manager()
+            .resolve_reference(&origin, &target)
+            .unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::NotFound);
+        assert!(error.message.contains("broken"));
+    }
+
+    #[test]
+    fn delete_is_refused_while_other_clients_use_the_project() {
+        let (collection, name, project) = new_project();
+        drop(project);
+        let (other_collection, other_name) = project_with_stored_files(1);
+        let mut manager = lock_manager();
+        // Requests that only work with the project for a moment aren't clients
+        for _ in 0..3 {
+            manager.load_project(&name, &collection).unwrap();
+        }
+        assert_eq!(manager.ref_count(&name, &collection), 1);
+        manager.connect_project(&name, &collection).unwrap();
+        assert_eq!(manager.ref_count(&name, &collection), 2);
+        for force in [false, true] {
+            let error = manager
+                .delete_project(&name, &collection, force, false, None)
+                .unwrap_err();
+            assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+            assert!(error.message.contains("in use by 1 other clients"));
+        }
+        manager.drop_project(&name, &collection).unwrap();
+        retry_while_locked(|| manager.delete_project(&name, &collection, false, false, None))
+            .unwrap();
+        assert!(load_project_dir(&name, &collection).is_err());
+
+        // Other clients can be ignored on purpose
+        let (collection, name) = (other_collection, other_name);
+        manager.connect_project(&name, &collection).unwrap();
+        let error = manager
+            .delete_project(&name, &collection, true, false, None)
+            .unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+        manager
+            .delete_project(&name, &collection, true, true, None)
+            .unwrap();
+        assert!(load_project_dir(&name, &collection).is_err());
+    }
+
+    #[test]
+    fn history_lists_operations_in_order() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "d/a.txt", "a", &[]);
+        link_with_metadata(&mut project, "d/b.txt", "b", &[]);
+        project.move_("d/a.txt", "e/a.txt", false, false).unwrap();
+        project.rename("d/b.txt", "c.txt").unwrap();
+        project.remove_file("d/c.txt").unwrap();
+
+        let history = project.history(10).unwrap();
+        let ops = history.iter().map(|e| e.op).collect::<Vec<_>>();
+        assert_eq!(
+            ops,
+            vec![
+                Operation::Link,
+                Operation::Link,
+                Operation::Move,
+                Operation::Rename,
+                Operation::Remove
+            ]
+        );
+        assert_eq!(history[2].paths, vec!["d/a.txt", "e/a.txt"]);
+        assert_eq!(history[3].paths, vec!["d/b.txt", "d/c.txt"]);
+        assert!(history.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+
+        // A limit keeps the most recent entries
+        let recent = project.history(2).unwrap();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].op, Operation::Rename);
+        assert_eq!(recent[1].op, Operation::Remove);
+    }
+
+    #[test]
+    fn undo_links_and_a_move() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "keep.txt", "k", &[]);
+        // Folders made along the way to a file are left behind, only files are undone
+        let paths = |project: &Project| {
+            let mut paths = project
+                .tree
+                .files()
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+
+        for name in ["a", "b", "c"] {
+            link_with_metadata(&mut project, &format!("d/{}.txt", name), name, &[]);
+        }
+        let undone = project.undo(3).unwrap();
+        assert_eq!(undone.len(), 3);
+        assert_eq!(paths(&project), vec!["keep.txt"]);
+
+        link_with_metadata(&mut project, "x.txt", "x", &[]);
+        project.move_("x.txt", "y/x.txt", false, false).unwrap();
+        project.undo(1).unwrap();
+        assert_eq!(paths(&project), vec!["keep.txt", "x.txt"]);
+        // The move is already undone, so the link that came before it is next
+        project.undo(1).unwrap();
+        assert_eq!(paths(&project), vec!["keep.txt"]);
+
+        // Removals keep nothing to put back
+        project.remove_file("keep.txt").unwrap();
+        let error = project.undo(1).unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+    }
+
+    #[test]
+    fn linking_over_the_quota_is_refused() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        let store = |project: &Project, path: &str, contents: &str| {
+            write_file(
+                &PathBuf::from(project.generate_path(path).unwrap()),
+                contents,
+            )
+        };
+        project.set_quota(Some(10)).unwrap();
+        let a = store(&project, "a.txt", "123456");
+        project
+            .add_file("a.txt", a, HashMap::new(), false, false)
+            .unwrap();
+        let b = store(&project, "b.txt", "123456");
+        let error = project
+            .add_file("b.txt", b, HashMap::new(), false, false)
+            .unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+        assert!(!project.exists("b.txt".to_string()));
+        // Reads still work
+        assert!(project.get_file("a.txt").is_ok());
+        assert_eq!(project.list(None, None).unwrap()["files"], vec!["a.txt"]);
+
+        // The file being overwritten doesn't count against its replacement
+        let bigger = store(&project, "a2.txt", "12345678");
+        project
+            .add_file("a.txt", bigger, HashMap::new(), true, false)
+            .unwrap();
+        assert_eq!(project.storage_usage().total, 8);
+
+        // Files stored elsewhere only count once they are copied in
+        let external = write_file(&scratch_dir().join("big.txt"), &"x".repeat(100));
+        project
+            .add_file("big.txt", external, HashMap::new(), false, false)
+            .unwrap();
+        let error = project.internalize("big.txt").err().unwrap();
+        assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+
+        // Folders of stored files are held to it too
+        let c = store(&project, "dir/c.txt", "123");
+        let error = project
+            .add_folder("dir", c.parent().unwrap().to_path_buf(), true, None, false)
+            .err()
+            .unwrap();
+        assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+        assert!(!project.exists("dir/c.txt".to_string()));
+
+        // Nothing can be reserved once the quota is used up
+        project.set_quota(Some(8)).unwrap();
+        let error = project
+            .reserve_and_link("r.txt", None, HashMap::new())
+            .unwrap_err();
+        assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+        project.set_quota(None).unwrap();
+        project
+            .reserve_and_link("r.txt", None, HashMap::new())
+            .unwrap();
+    }
+
+    #[test]
+    fn temp_projects_stay_out_of_the_data_directory() {
+        let collection = crate::testing::unique("collection");
+        let name = crate::testing::unique("project");
+        let project = lock_manager()
+            .create_temp_project(&name, &collection)
+            .unwrap();
+        let storage = {
+            let mut project = project.lock().unwrap();
+            let stored = PathBuf::from(project.generate_path("data/a.txt").unwrap());
+            write_file(&stored, "stored");
+            project
+                .add_file("data/a.txt", stored, HashMap::new(), false, false)
+                .unwrap();
+            link_with_metadata(&mut project, "data/b.txt", "linked", &[("band", "r")]);
+            assert_eq!(project.get_file("data/b.txt").unwrap()["band"], "r");
+            assert_eq!(project.tree.files().len(), 2);
+            PathBuf::from(project.endpoint_info().1)
+        };
+        assert!(storage.starts_with(std::env::temp_dir()));
+        assert!(storage.join("data/a.txt").is_file());
+        for root in [
+            crate::locations::get_main_dir(),
+            crate::locations::get_default_storage_dir().unwrap(),
+        ] {
+            assert!(!storage.starts_with(&root));
+            assert!(!root.join(&collection).exists());
+        }
+
+        // Once the last client lets go, its storage goes with it
+        drop(project);
+        let mut manager = lock_manager();
+        manager.drop_project(&name, &collection).unwrap();
+        assert!(!storage.exists());
+        let error = manager.load_project(&name, &collection).err().unwrap();
+        assert_eq!(error.error_type, GodataErrorType::NotFound);
+    }
+
+    #[test]
+    fn recent_files_are_newest_first_up_to_the_limit() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        // Modification times are kept to the second
+        for path in ["a.txt", "b.txt", "c.txt"] {
+            link_with_metadata(&mut project, path, "data", &[]);
+            std::thread::sleep(Duration::from_millis(1100));
+        }
+        project
+            .relink("a.txt", write_file(&scratch_dir().join("file"), "new"))
+            .unwrap();
+        let paths = |files: Vec<FileInfo>| -> Vec<String> {
+            files.into_iter().map(|f| f.project_path).collect()
+        };
+        assert_eq!(
+            paths(project.recent(10).unwrap()),
+            vec!["a.txt", "c.txt", "b.txt"]
+        );
+        assert_eq!(paths(project.recent(2).unwrap()), vec!["a.txt", "c.txt"]);
+        assert!(project.recent(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn metadata_key_renames_count_and_respect_overwrite() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "a.txt", "", &[("expTime", "30")]);
+        link_with_metadata(
+            &mut project,
+            "b.txt",
+            "",
+            &[("expTime", "60"), ("exptime", "90")],
+        );
+        link_with_metadata(&mut project, "c.txt", "", &[("band", "r")]);
+        // b.txt already has the new key, so only a.txt changes
+        assert_eq!(
+            project
+                .rename_metadata_key("expTime", "exptime", false)
+                .unwrap(),
+            1
+        );
+        let a = project.get_file("a.txt").unwrap();
+        assert_eq!(a["exptime"], "30");
+        assert!(!a.contains_key("expTime"));
+        let b = project.get_file("b.txt").unwrap();
+        assert_eq!(b["expTime"], "60");
+        assert_eq!(b["exptime"], "90");
+
+        assert_eq!(
+            project
+                .rename_metadata_key("expTime", "exptime", true)
+                .unwrap(),
+            1
+        );
+        let b = project.get_file("b.txt").unwrap();
+        assert_eq!(b["exptime"], "60");
+        assert!(!b.contains_key("expTime"));
+        let c = project.get_file("c.txt").unwrap();
+        assert_eq!(c["band"], "r");
+    }
+
+    #[test]
+    fn metadata_key_deletes_leave_other_keys_and_reserved_keys() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "a.txt", "", &[("band", "r"), ("night", "1")]);
+        link_with_metadata(&mut project, "b/c.txt", "", &[("band", "g")]);
+        link_with_metadata(&mut project, "d.txt", "", &[("night", "2")]);
+        assert_eq!(project.delete_metadata_key("band").unwrap(), 2);
+        assert_eq!(project.delete_metadata_key("band").unwrap(), 0);
+        let a = project.get_file("a.txt").unwrap();
+        assert!(!a.contains_key("band"));
+        assert_eq!(a["night"], "1");
+        assert!(!project.get_file("b/c.txt").unwrap().contains_key("band"));
+        assert_eq!(project.get_file("d.txt").unwrap()["night"], "2");
+
+        let error = project
+            .delete_metadata_key("__description__")
+            .err()
+            .unwrap();
+        assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+    }
+
+    #[test]
+    fn resumed_folder_link_keeps_existing_entries() {
+        let (_, _, project) = new_project();
+        let dir = scratch_dir();
+        for name in ["a1.txt", "a2.txt", "b.txt", "sub/c.txt"] {
+            write_file(&dir.join(name), "data");
+        }
+        let mut project = project.lock().unwrap();
+        let report = project
+            .add_folder("data", dir.clone(), true, None, false)
+            .unwrap();
+        assert_eq!(report.linked, 4);
+        let tag = HashMap::from([("checked".to_string(), "yes".to_string())]);
+        assert_eq!(
+            project
+                .set_metadata_matching(Some("data"), "a*.txt", tag, true)
+                .unwrap(),
+            2
+        );
+
+        // Pick up where an interrupted link left off, with one new file since
+        write_file(&dir.join("sub/d.txt"), "data");
+        let report = project.add_folder("data", dir, true, None, true).unwrap();
+        assert_eq!(report.linked, 1);
+        assert_eq!(report.skipped, 4);
+        assert_eq!(project.get_file("data/a1.txt").unwrap()["checked"], "yes");
+        assert_eq!(project.get_file("data/a2.txt").unwrap()["checked"], "yes");
+        assert!(project.exists("data/sub/d.txt".to_string()));
+    }
+
+    #[test]
+    fn largest_files_come_biggest_first() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        for (path, size) in [
+            ("small.txt", 10),
+            ("big.txt", 1000),
+            ("sub/medium.txt", 100),
+        ] {
+            link_with_metadata(&mut project, path, &"x".repeat(size), &[]);
+        }
+        // Files missing from disk can't be sized, so they're left out
+        let gone = write_file(&scratch_dir().join("gone.txt"), &"x".repeat(5000));
+        project
+            .add_file("gone.txt", gone.clone(), HashMap::new(), false, false)
+            .unwrap();
+        std::fs::remove_file(gone).unwrap();
+
+        let largest = project.largest(10).unwrap();
+        let found: Vec<(&str, u64)> = largest
+            .iter()
+            .map(|f| (f.file.project_path.as_str(), f.size))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("big.txt", 1000),
+                ("sub/medium.txt", 100),
+                ("small.txt", 10)
+            ]
+        );
+        assert_eq!(project.largest(1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn matching_files_are_tagged_and_others_untouched() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "data/a.fits", "", &[("band", "r")]);
+        link_with_metadata(&mut project, "data/b.fits", "", &[]);
+        link_with_metadata(&mut project, "data/c.txt", "", &[("band", "g")]);
+        link_with_metadata(&mut project, "other/d.fits", "", &[]);
+        let tag = HashMap::from([("night".to_string(), "1".to_string())]);
+        assert_eq!(
+            project
+                .set_metadata_matching(Some("data"), "*.fits", tag.clone(), true)
+                .unwrap(),
+            2
+        );
+        let a = project.get_file("data/a.fits").unwrap();
+        assert_eq!((a["band"].as_str(), a["night"].as_str()), ("r", "1"));
+        assert_eq!(project.get_file("data/b.fits").unwrap()["night"], "1");
+        let c = project.get_file("data/c.txt").unwrap();
+        assert!(!c.contains_key("night"));
+        assert_eq!(c["band"], "g");
+        assert!(!project
+            .get_file("other/d.fits")
+            .unwrap()
+            .contains_key("night"));
+
+        // Without merge the tag replaces what was there
+        project
+            .set_metadata_matching(Some("data"), "*.fits", tag, false)
+            .unwrap();
+        assert!(!project
+            .get_file("data/a.fits")
+            .unwrap()
+            .contains_key("band"));
+    }
+
+    #[test]
+    fn file_endpoints_for_internal_and_external_files() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        let internal = PathBuf::from(project.generate_path("inside.txt").unwrap());
+        let internal = write_file(&internal, "data");
+        project
+            .add_file("inside.txt", internal, HashMap::new(), false, false)
+            .unwrap();
+        let dir = scratch_dir();
+        let external = write_file(&dir.join("outside.txt"), "data");
+        project
+            .add_file("outside.txt", external, HashMap::new(), false, false)
+            .unwrap();
+
+        assert_eq!(
+            project.file_endpoint("inside.txt").unwrap(),
+            project.endpoint_info()
+        );
+        let (kind, root) = project.file_endpoint("outside.txt").unwrap();
+        assert_eq!(kind, "external");
+        assert_eq!(PathBuf::from(root), dir);
+        let error = project.file_endpoint("missing.txt").err().unwrap();
+        assert_eq!(error.error_type, GodataErrorType::NotFound);
+    }
+
+    #[test]
+    fn move_matching_gathers_files_and_reports_collisions() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        for path in ["a/x.fits", "b/y.fits", "c/x.fits", "dest/y.fits", "a/z.txt"] {
+            link_with_metadata(&mut project, path, "", &[]);
+        }
+        let report = project.move_matching("*.fits", "dest", false).unwrap();
+        let moved: Vec<&str> = report
+            .moved
+            .iter()
+            .map(|f| f.project_path.as_str())
+            .collect();
+        assert_eq!(moved, vec!["dest/x.fits"]);
+        // One name is taken in the destination, the other by an earlier match
+        assert_eq!(report.collisions, vec!["b/y.fits", "c/x.fits"]);
+        assert!(report.displaced.is_empty());
+        assert!(!project.exists("a/x.fits".to_string()));
+        assert!(project.exists("a/z.txt".to_string()));
+
+        let report = project.move_matching("*.fits", "dest", true).unwrap();
+        assert_eq!(report.moved.len(), 2);
+        assert_eq!(report.displaced.len(), 2);
+        assert!(report.collisions.is_empty());
+        assert_eq!(
+            file_paths(&project),
+            vec!["a/z.txt", "dest/x.fits", "dest/y.fits"]
+        );
+    }
+
+    #[test]
+    fn csv_export_lines_rows_up_with_the_key_union() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "a.txt", "abc", &[("band", "r")]);
+        link_with_metadata(
+            &mut project,
+            "b/c.txt",
+            "abcdef",
+            &[("night", "1"), ("note", "cloudy, windy")],
+        );
+        let mut out = Vec::new();
+        project.export_csv(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        let a = project.get_file("a.txt").unwrap()["real_path"].clone();
+        let c = project.get_file("b/c.txt").unwrap()["real_path"].clone();
+        assert_eq!(
+            lines,
+            vec![
+                "virtual_path,real_path,size,band,night,note".to_string(),
+                format!("a.txt,{},3,r,,", a),
+                format!("b/c.txt,{},6,,1,\"cloudy, windy\"", c),
+            ]
+        );
+    }
+
+    #[test]
+    fn modified_since_returns_later_changes_oldest_first() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "a.txt", "", &[]);
+        link_with_metadata(&mut project, "b.txt", "", &[]);
+        // Modification times are kept to the second
+        let cutoff = Utc::now().timestamp();
+        std::thread::sleep(Duration::from_millis(1100));
+        link_with_metadata(&mut project, "c.txt", "", &[]);
+        std::thread::sleep(Duration::from_millis(1100));
+        project
+            .relink("a.txt", write_file(&scratch_dir().join("file"), "new"))
+            .unwrap();
+        let changed: Vec<String> = project
+            .modified_since(cutoff)
+            .unwrap()
+            .into_iter()
+            .map(|f| f.project_path)
+            .collect();
+        assert_eq!(changed, vec!["c.txt", "a.txt"]);
+        assert!(project
+            .modified_since(Utc::now().timestamp())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn template_projects_start_with_their_folders() {
+        let collection = crate::testing::unique("collection");
+        let name = crate::testing::unique("project");
+        let mut manager = lock_manager();
+        let project = manager
+            .create_from_template(&name, &collection, "observation", true, None)
+            .unwrap();
+        let project = project.lock().unwrap();
+        for folder in ["raw", "calib", "processed"] {
+            assert_eq!(project.kind(folder).unwrap(), "folder");
+        }
+        assert!(project.tree.files().is_empty());
+        drop(project);
+
+        // An unknown template leaves nothing behind
+        let other = crate::testing::unique("project");
+        let error = manager
+            .create_from_template(&other, &collection, "no_such_template", true, None)
+            .err()
+            .unwrap();
+        assert_eq!(error.error_type, GodataErrorType::NotFound);
+        assert!(load_project_dir(&other, &collection).is_err());
+    }
+
+    #[test]
+    fn histogram_counts_each_value_and_missing_files() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        for (path, band) in [("a", "r"), ("b", "r"), ("c", "g"), ("d/e", "r"), ("f", "i")] {
+            link_with_metadata(&mut project, path, "", &[("band", band)]);
+        }
+        link_with_metadata(&mut project, "g", "", &[("night", "1")]);
+        link_with_metadata(&mut project, "h", "", &[]);
+        let expected = BTreeMap::from([
+            ("r".to_string(), 3),
+            ("g".to_string(), 1),
+            ("i".to_string(), 1),
+            (MISSING_BUCKET.to_string(), 2),
+        ]);
+        assert_eq!(project.metadata_histogram("band").unwrap(), expected);
+        let expected = BTreeMap::from([(MISSING_BUCKET.to_string(), 7)]);
+        assert_eq!(project.metadata_histogram("exposure").unwrap(), expected);
+    }
+
+    #[test]
+    fn swapped_files_trade_places_with_their_metadata() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "raw/a.fits", "", &[("band", "r")]);
+        link_with_metadata(&mut project, "calib/b.fits", "", &[("band", "g")]);
+        let a = project.get_file("raw/a.fits").unwrap();
+        let b = project.get_file("calib/b.fits").unwrap();
+        project.swap("raw/a.fits", "calib/b.fits").unwrap();
+        // The swap is saved, so it holds after a reload
+        project.tree.reload().unwrap();
+        let at_a = project.get_file("raw/a.fits").unwrap();
+        let at_b = project.get_file("calib/b.fits").unwrap();
+        assert_eq!(at_a["real_path"], b["real_path"]);
+        assert_eq!(at_a["band"], "g");
+        assert_eq!(at_b["real_path"], a["real_path"]);
+        assert_eq!(at_b["band"], "r");
+
+        let error = project.swap("raw/a.fits", "missing.fits").err().unwrap();
+        assert_eq!(error.error_type, GodataErrorType::NotFound);
+        assert_eq!(project.get_file("raw/a.fits").unwrap()["band"], "g");
+    }
+
+    #[test]
+    fn kind_tells_files_from_folders() {
+        let (_, _, project) = new_project();
+        let mut project = project.lock().unwrap();
+        link_with_metadata(&mut project, "data/a.txt", "", &[]);
+        assert_eq!(project.kind("data/a.txt").unwrap(), "file");
+        assert_eq!(project.kind("data").unwrap(), "folder");
+        for missing in ["data/b.txt", "other"] {
+            let error = project.kind(missing).err().unwrap();
+            assert_eq!(error.error_type, GodataErrorType::NotFound, "{}", missing);
+        }
+        // A path through a file can't exist at all
+        let error = project.kind("data/a.txt/inner").err().unwrap();
+        assert_eq!(error.error_type, GodataErrorType::InvalidPath);
+    }
+
+    #[test]
+    fn trash_is_refused_while_other_clients_use_the_project() {
+        let (collection, name, project) = new_project();
+        drop(project);
+        let mut manager = lock_manager();
+        manager.connect_project(&name, &collection).unwrap();
+        let error = manager
+            .trash_project(&name, &collection, false)
+            .err()
+            .unwrap();
+        assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+        assert!(error.message.contains("in use by 1 other clients"));
+        assert!(manager.list_trash(&collection).unwrap().is_empty());
+        assert!(load_project_dir(&name, &collection).is_ok());
+
+        manager.trash_project(&name, &collection, true).unwrap();
+        assert_eq!(manager.list_trash(&collection).unwrap(), vec![name.clone()]);
+        assert!(load_project_dir(&name, &collection).is_err());
+    }
+
+    #[test]
+    fn relocate_is_refused_while_other_clients_use_the_project() {
+        let (collection, name, project) = new_project();
+        drop(project);
+        let mut manager = lock_manager();
+        manager.connect_project(&name, &collection).unwrap();
+        let old_root = manager.storage_manager.get(&name, &collection).unwrap().1;
+        let new_root = scratch_dir().join("moved");
+        std::fs::rename(&old_root, &new_root).unwrap();
+
+        let error = manager
+            .relocate_storage(&name, &collection, new_root.clone(), false)
+            .err()
+            .unwrap();
+        assert_eq!(error.error_type, GodataErrorType::NotPermitted);
+        assert!(error.message.contains("in use by 1 other clients"));
+        assert!(manager.is_loaded(&name, &collection));
+        assert_eq!(
+            manager.storage_manager.get(&name, &collection).unwrap().1,
+            old_root
+        );
+
+        manager
+            .relocate_storage(&name, &collection, new_root.clone(), true)
+            .unwrap();
+        assert!(!manager.is_loaded(&name, &collection));
+        assert_eq!(
+            manager.storage_manager.get(&name, &collection).unwrap().1,
+            new_root
+        );
     }
-    Ok(names)
 }
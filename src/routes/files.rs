@@ -1,11 +1,13 @@
 use crate::errors::{GodataError, GodataErrorType};
+use crate::fsystem::{FileRef, ObjectKind};
 use crate::handlers;
-use crate::project::ProjectManager;
+use crate::manifest::ManifestFormat;
+use crate::project::{CollisionStrategy, ProjectManager, ZipSelection};
+use crate::routes::{parse_flag, require_flag, MAX_BODY_SIZE};
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::sync::{Arc, Mutex};
 use tracing::instrument;
-use warp::http::StatusCode;
 use warp::Filter;
 use warp::Reply;
 use warp::{http::Response, hyper::Body};
@@ -15,11 +17,59 @@ pub(super) fn routes(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     project_list(project_manager.clone())
         .or(project_link(project_manager.clone()))
+        .or(project_relink(project_manager.clone()))
+        .or(link_reference(project_manager.clone()))
+        .or(reserve_and_link(project_manager.clone()))
         .or(projects_get(project_manager.clone()))
         .or(projects_path_exists(project_manager.clone()))
+        .or(path_kind(project_manager.clone()))
+        .or(folder_contains(project_manager.clone()))
         .or(project_generate_path(project_manager.clone()))
         .or(project_remove_file(project_manager.clone()))
+        .or(remove_batch(project_manager.clone()))
+        .or(remove_matching(project_manager.clone()))
+        .or(download_zip(project_manager.clone()))
         .or(move_file(project_manager.clone()))
+        .or(rename(project_manager.clone()))
+        .or(swap(project_manager.clone()))
+        .or(flatten(project_manager.clone()))
+        .or(move_matching(project_manager.clone()))
+        .or(import_manifest(project_manager.clone()))
+        .or(internalize(project_manager.clone()))
+        .or(externalize(project_manager.clone()))
+        .or(track_access(project_manager.clone()))
+        .or(path_template(project_manager.clone()))
+        .or(checksums(project_manager.clone()))
+        .or(checksum_status(project_manager.clone()))
+        .or(set_quota(project_manager.clone()))
+        .or(get_quota(project_manager.clone()))
+        .or(external_metadata(project_manager.clone()))
+        .or(duplicates(project_manager.clone()))
+        .or(recently_accessed(project_manager.clone()))
+        .or(recently_modified(project_manager.clone()))
+        .or(largest_files(project_manager.clone()))
+        .or(history(project_manager.clone()))
+        .or(undo(project_manager.clone()))
+        .or(modified_since(project_manager.clone()))
+        .or(file_endpoint(project_manager.clone()))
+        .or(watch(project_manager.clone()))
+        .or(dangling(project_manager.clone()))
+        .or(ancestors(project_manager.clone()))
+        .or(child_counts(project_manager.clone()))
+        .or(usage(project_manager.clone()))
+        .or(content(project_manager.clone()))
+        .or(get_preview(project_manager.clone()))
+        .or(copy_metadata(project_manager.clone()))
+        .or(cas_metadata(project_manager.clone()))
+        .or(rename_metadata_key(project_manager.clone()))
+        .or(delete_metadata_key(project_manager.clone()))
+        .or(set_metadata_matching(project_manager.clone()))
+        .or(set_preview(project_manager.clone()))
+        .or(file_context(project_manager.clone()))
+        .or(get_by_uuid(project_manager.clone()))
+        .or(metadata_keys(project_manager.clone()))
+        .or(metadata_values(project_manager.clone()))
+        .or(metadata_histogram(project_manager.clone()))
 }
 
 #[instrument(skip(project_manager))]
@@ -34,18 +84,22 @@ fn project_link(
                   project_name,
                   mut params: HashMap<String, String>|
                   -> Result<Response<Body>, _> {
-                let force = match params.remove("force") {
-                    Some(force) => force.parse::<bool>().unwrap(),
-                    None => false,
+                let force = match parse_flag("force", params.remove("force"), false) {
+                    Ok(force) => force,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                let backup = match parse_flag("backup", params.remove("backup"), false) {
+                    Ok(backup) => backup,
+                    Err(e) => return Ok(e.into_response()),
                 };
                 let ppath = match params.remove("project_path") {
                     Some(project_path) => project_path.to_owned(),
                     None => {
                         tracing::error!("Query missing project_path argument");
                         return Ok::<Response<Body>, Infallible>(
-                            warp::reply::with_status(
-                                warp::reply::json(&"Missing project_path argument".to_string()),
-                                StatusCode::BAD_REQUEST,
+                            GodataError::new(
+                                GodataErrorType::InvalidPath,
+                                "Missing project_path argument".to_string(),
                             )
                             .into_response(),
                         );
@@ -55,9 +109,9 @@ fn project_link(
                     Some(storage_location) => storage_location.to_owned(),
                     None => {
                         tracing::error!("Query missing real_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing real_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing real_path argument".to_string(),
                         )
                         .into_response());
                     } // invalid request
@@ -76,11 +130,28 @@ fn project_link(
                         rpath,
                         params,
                         force,
+                        backup,
                     )
                 } else if type_ == "folder" {
-                    let recursive = match params.get("recursive") {
-                        Some(recursive) => recursive.parse::<bool>().unwrap(),
-                        None => false,
+                    let recursive = match parse_flag("recursive", params.get("recursive"), false) {
+                        Ok(recursive) => recursive,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                    let max_depth = match params.get("max_depth").map(|d| d.parse::<usize>()) {
+                        Some(Ok(max_depth)) => Some(max_depth),
+                        Some(Err(_)) => {
+                            tracing::error!("Request included invalid max_depth argument");
+                            return Ok(GodataError::new(
+                                GodataErrorType::InvalidPath,
+                                "Invalid max_depth argument".to_string(),
+                            )
+                            .into_response());
+                        }
+                        None => None,
+                    };
+                    let resume = match parse_flag("resume", params.get("resume"), false) {
+                        Ok(resume) => resume,
+                        Err(e) => return Ok(e.into_response()),
                     };
                     return handlers::link_folder(
                         project_manager.clone(),
@@ -89,12 +160,14 @@ fn project_link(
                         ppath,
                         rpath,
                         recursive,
+                        max_depth,
+                        resume,
                     );
                 } else {
                     tracing::error!("Request included invalid type argument {}", type_);
-                    return Ok(warp::reply::with_status(
-                        warp::reply::json(&format!("Invalid type argument {}", type_)),
-                        StatusCode::BAD_REQUEST,
+                    return Ok(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        format!("Invalid type argument {}", type_),
                     )
                     .into_response()); // invalid request
                 }
@@ -102,25 +175,150 @@ fn project_link(
         )
 }
 
+#[instrument(skip(project_manager))]
+fn project_relink(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "files")
+        .and(warp::patch())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let ppath = match params.remove("project_path") {
+                    Some(project_path) => project_path,
+                    None => {
+                        tracing::error!("Query missing project_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let rpath = match params.remove("real_path") {
+                    Some(real_path) => real_path,
+                    None => {
+                        tracing::error!("Query missing real_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing real_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::relink_file(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    ppath,
+                    rpath,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn reserve_and_link(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "files" / "reserve")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let ppath = match params.remove("project_path") {
+                    Some(project_path) => project_path,
+                    None => {
+                        tracing::error!("Query missing project_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let suffix = params.remove("suffix");
+                // Anything left over is metadata for the new file
+                handlers::reserve_and_link(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    ppath,
+                    suffix,
+                    params,
+                )
+            },
+        )
+}
+
 #[instrument(skip(project_manager))]
 fn project_list(
     project_manager: Arc<Mutex<ProjectManager>>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("projects" / String / String / "list")
         .and(warp::get())
-        .and(warp::query::<HashMap<String, String>>())
+        .and(warp::query::<Vec<(String, String)>>())
         .map(
-            move |collection, project_name, params: HashMap<String, String>| {
-                let show_hidden = match params.get("show_hidden") {
-                    Some(show_hidden) => show_hidden.parse::<bool>().unwrap(),
-                    None => false,
+            move |collection, project_name, query: Vec<(String, String)>| {
+                // `field` can be repeated to pick which metadata keys come back
+                let mut fields = Vec::new();
+                let mut params = HashMap::new();
+                for (key, value) in query {
+                    match key.as_str() {
+                        "field" => fields.push(value),
+                        _ => {
+                            params.insert(key, value);
+                        }
+                    }
+                }
+                let show_hidden = match parse_flag("show_hidden", params.get("show_hidden"), false)
+                {
+                    Ok(show_hidden) => show_hidden,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                let kind = match params.get("type").map(|t| t.parse::<ObjectKind>()) {
+                    Some(Ok(kind)) => Some(kind),
+                    Some(Err(e)) => {
+                        tracing::error!("Request included invalid type argument");
+                        return Ok(e.into_response());
+                    }
+                    None => None,
+                };
+                let flat = match parse_flag("flat", params.get("flat"), false) {
+                    Ok(flat) => flat,
+                    Err(e) => return Ok(e.into_response()),
                 };
+                let with_metadata =
+                    match parse_flag("with_metadata", params.get("with_metadata"), false) {
+                        Ok(with_metadata) => with_metadata,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                if with_metadata && flat {
+                    tracing::error!("Request asked for a flat listing with metadata");
+                    return Ok(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        "flat and with_metadata can't be combined".to_string(),
+                    )
+                    .into_response());
+                }
+                if with_metadata {
+                    return handlers::list_project_detailed(
+                        project_manager.clone(),
+                        collection,
+                        project_name,
+                        params.remove("project_path"),
+                        kind,
+                        (!fields.is_empty()).then_some(fields),
+                    );
+                }
                 match params.get("project_path") {
                     Some(path) => handlers::list_project(
                         project_manager.clone(),
                         collection,
                         project_name,
                         Some(path.to_owned()),
+                        kind,
+                        flat,
                         show_hidden,
                     ),
                     None => handlers::list_project(
@@ -128,6 +326,8 @@ fn project_list(
                         collection,
                         project_name,
                         None,
+                        kind,
+                        flat,
                         show_hidden,
                     ),
                 }
@@ -185,9 +385,9 @@ fn projects_path_exists(
                     Some(project_path) => project_path.to_owned(),
                     None => {
                         tracing::error!("Query missing project_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing project_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
                         )
                         .into_response());
                     } // invalid request
@@ -202,6 +402,68 @@ fn projects_path_exists(
         )
 }
 
+#[instrument(skip(project_manager))]
+fn path_kind(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Whether a path is a "file" or a "folder", 404 if there's nothing there
+    warp::path!("projects" / String / String / "kind")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let project_path = match params.remove("project_path") {
+                    Some(project_path) => project_path,
+                    None => {
+                        tracing::error!("Query missing project_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                };
+                handlers::path_kind(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn folder_contains(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "contains")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let name = match params.remove("name") {
+                    Some(name) => name,
+                    None => {
+                        tracing::error!("Query missing name argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing name argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::folder_contains(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    params.remove("project_path"),
+                    name,
+                )
+            },
+        )
+}
+
 #[instrument(skip(project_manager))]
 fn project_generate_path(
     project_manager: Arc<Mutex<ProjectManager>>,
@@ -215,9 +477,9 @@ fn project_generate_path(
                     Some(project_path) => project_path.to_owned(),
                     None => {
                         tracing::error!("Query missing project_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing project_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
                         )
                         .into_response());
                     } // invalid request
@@ -245,9 +507,9 @@ fn project_remove_file(
                     Some(project_path) => project_path.to_owned(),
                     None => {
                         tracing::error!("Query missing project_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing project_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
                         )
                         .into_response());
                     } // invalid request
@@ -262,6 +524,165 @@ fn project_remove_file(
         )
 }
 
+#[instrument(skip(project_manager))]
+fn remove_batch(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "files" / "remove-batch")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json::<Vec<String>>())
+        .map(move |collection, project_name, project_paths| {
+            handlers::remove_many(
+                project_manager.clone(),
+                collection,
+                project_name,
+                project_paths,
+            )
+        })
+}
+
+#[instrument(skip(project_manager))]
+fn download_zip(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // The body is either a list of project paths or an object with a `pattern` glob and
+    // an optional `project_path` folder to match it in
+    warp::path!("projects" / String / String / "download-zip")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json::<ZipSelection>())
+        .map(move |collection, project_name, selection| {
+            handlers::download_zip(project_manager.clone(), collection, project_name, selection)
+        })
+}
+
+#[instrument(skip(project_manager))]
+fn remove_matching(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "files" / "remove-matching")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let pattern = match params.remove("pattern") {
+                    Some(pattern) => pattern,
+                    None => {
+                        tracing::error!("Query missing pattern argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing pattern argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let dry_run = match parse_flag("dry_run", params.get("dry_run"), false) {
+                    Ok(dry_run) => dry_run,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                let confirm = match parse_flag("confirm", params.get("confirm"), false) {
+                    Ok(confirm) => confirm,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::remove_matching(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    params.remove("project_path"),
+                    pattern,
+                    dry_run,
+                    confirm,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn rename(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "files" / "rename")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let project_path = match params.remove("project_path") {
+                    Some(project_path) => project_path,
+                    None => {
+                        tracing::error!("Query missing project_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let new_name = match params.remove("new_name") {
+                    Some(new_name) => new_name,
+                    None => {
+                        tracing::error!("Query missing new_name argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing new_name argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::rename(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                    new_name,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn swap(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Exchange the files at path_a and path_b
+    warp::path!("projects" / String / String / "files" / "swap")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let path_a = match params.remove("path_a") {
+                    Some(path_a) => path_a,
+                    None => {
+                        tracing::error!("Query missing path_a argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing path_a argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                };
+                let path_b = match params.remove("path_b") {
+                    Some(path_b) => path_b,
+                    None => {
+                        tracing::error!("Query missing path_b argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing path_b argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                };
+                handlers::swap(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    path_a,
+                    path_b,
+                )
+            },
+        )
+}
+
 #[instrument(skip(project_manager))]
 fn move_file(
     project_manager: Arc<Mutex<ProjectManager>>,
@@ -274,10 +695,10 @@ fn move_file(
                 let project_path = match params.get("source_path") {
                     Some(project_path) => project_path.to_owned(),
                     None => {
-                        tracing::error!("Query missing project_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing project_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
+                        tracing::error!("Query missing source_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing source_path argument".to_string(),
                         )
                         .into_response());
                     } // invalid request
@@ -285,17 +706,21 @@ fn move_file(
                 let new_path = match params.get("destination_path") {
                     Some(new_path) => new_path.to_owned(),
                     None => {
-                        tracing::error!("Query missing new_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing new_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
+                        tracing::error!("Query missing destination_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing destination_path argument".to_string(),
                         )
                         .into_response());
                     } // invalid request
                 };
-                let overwrite = match params.get("overwrite") {
-                    Some(overwrite) => overwrite.parse::<bool>().unwrap(),
-                    None => false,
+                let overwrite = match parse_flag("overwrite", params.get("overwrite"), false) {
+                    Ok(overwrite) => overwrite,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                let backup = match parse_flag("backup", params.get("backup"), false) {
+                    Ok(backup) => backup,
+                    Err(e) => return Ok(e.into_response()),
                 };
                 handlers::move_(
                     project_manager.clone(),
@@ -304,7 +729,1122 @@ fn move_file(
                     project_path,
                     new_path,
                     overwrite,
+                    backup,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn import_manifest(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // The format is worked out from the manifest's extension if it isn't given
+    warp::path!("projects" / String / String / "files" / "import-manifest")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let manifest_path = match params.remove("manifest_path") {
+                    Some(manifest_path) => manifest_path,
+                    None => {
+                        tracing::error!("Query missing manifest_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing manifest_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let format = match params.get("format").map(|f| f.parse::<ManifestFormat>()) {
+                    Some(Ok(format)) => Some(format),
+                    Some(Err(e)) => {
+                        tracing::error!("Request included invalid format argument");
+                        return Ok(e.into_response());
+                    }
+                    None => None,
+                };
+                handlers::import_manifest(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    manifest_path,
+                    format,
                 )
             },
         )
 }
+
+#[instrument(skip(project_manager))]
+fn move_matching(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // `pattern` is matched against file names anywhere in the project
+    warp::path!("projects" / String / String / "files" / "move-matching")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let pattern = match params.remove("pattern") {
+                    Some(pattern) => pattern,
+                    None => {
+                        tracing::error!("Query missing pattern argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing pattern argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let destination_path = match params.remove("destination_path") {
+                    Some(destination_path) => destination_path,
+                    None => {
+                        tracing::error!("Query missing destination_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing destination_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let overwrite = match parse_flag("overwrite", params.get("overwrite"), false) {
+                    Ok(overwrite) => overwrite,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::move_matching(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    pattern,
+                    destination_path,
+                    overwrite,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn flatten(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "files" / "flatten")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let source_path = match params.remove("source_path") {
+                    Some(source_path) => source_path,
+                    None => {
+                        tracing::error!("Query missing source_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing source_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let destination_path = match params.remove("destination_path") {
+                    Some(destination_path) => destination_path,
+                    None => {
+                        tracing::error!("Query missing destination_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing destination_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let strategy = match params
+                    .get("on_collision")
+                    .map(|s| s.parse::<CollisionStrategy>())
+                {
+                    Some(Ok(strategy)) => strategy,
+                    Some(Err(e)) => {
+                        tracing::error!("Request included invalid on_collision argument");
+                        return Ok(e.into_response());
+                    }
+                    None => CollisionStrategy::Error,
+                };
+                handlers::flatten(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    source_path,
+                    destination_path,
+                    strategy,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn internalize(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "files" / "internalize")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| match params
+                .remove("project_path")
+            {
+                Some(project_path) => handlers::internalize(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                ),
+                None => {
+                    tracing::error!("Query missing project_path argument");
+                    Ok(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        "Missing project_path argument".to_string(),
+                    )
+                    .into_response())
+                }
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn externalize(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "files" / "externalize")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let project_path = match params.remove("project_path") {
+                    Some(project_path) => project_path,
+                    None => {
+                        tracing::error!("Query missing project_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let destination = match params.remove("destination") {
+                    Some(destination) => destination,
+                    None => {
+                        tracing::error!("Query missing destination argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing destination argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let force = match parse_flag("force", params.get("force"), false) {
+                    Ok(force) => force,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::externalize(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                    destination,
+                    force,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn track_access(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "track-access")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let enabled = match require_flag("enabled", params.get("enabled")) {
+                    Ok(enabled) => enabled,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::set_track_access(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    enabled,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn path_template(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Leaving out the template goes back to the default layout
+    warp::path!("projects" / String / String / "path-template")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                handlers::set_path_template(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    params.get("template").cloned(),
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn set_quota(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Limit the project's storage to `bytes`, or remove the limit if it isn't given
+    warp::path!("projects" / String / String / "quota")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let quota = match params.get("bytes").map(|b| b.parse::<u64>()) {
+                    Some(Ok(bytes)) => Some(bytes),
+                    Some(Err(_)) => {
+                        tracing::error!("Request included invalid bytes argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Invalid bytes argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                    None => None,
+                };
+                handlers::set_quota(project_manager.clone(), collection, project_name, quota)
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn get_quota(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "quota")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::get_quota(project_manager.clone(), collection, project_name)
+        })
+}
+
+#[instrument(skip(project_manager))]
+fn checksums(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "checksums")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let enabled = match require_flag("enabled", params.get("enabled")) {
+                    Ok(enabled) => enabled,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                // Hash files after they're linked instead of while linking them
+                let background = match parse_flag("background", params.get("background"), false) {
+                    Ok(background) => background,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::set_checksums(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    enabled,
+                    background,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn checksum_status(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "checksums" / "status")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::checksum_status(project_manager.clone(), collection, project_name)
+        })
+}
+
+#[instrument(skip(project_manager))]
+fn external_metadata(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "external-metadata")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let enabled = match require_flag("enabled", params.get("enabled")) {
+                    Ok(enabled) => enabled,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::set_external_metadata(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    enabled,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn duplicates(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "duplicates")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::find_duplicates(project_manager.clone(), collection, project_name)
+        })
+}
+
+#[instrument(skip(project_manager))]
+fn recently_accessed(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "accessed")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+                    Some(Ok(limit)) => limit,
+                    Some(Err(_)) => {
+                        tracing::error!("Request included invalid limit argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Invalid limit argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                    None => 20,
+                };
+                handlers::recently_accessed(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    limit,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn recently_modified(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "recent")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+                    Some(Ok(limit)) => limit,
+                    Some(Err(_)) => {
+                        tracing::error!("Request included invalid limit argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Invalid limit argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                    None => 20,
+                };
+                handlers::recently_modified(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    limit,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn file_endpoint(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "files" / "endpoint")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let project_path = match params.remove("project_path") {
+                    Some(project_path) => project_path,
+                    None => {
+                        tracing::error!("Query missing project_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::file_endpoint(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn modified_since(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // `since` is a unix timestamp, and only files changed after it come back
+    warp::path!("projects" / String / String / "changed")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let since = match params.get("since").map(|s| s.parse::<i64>()) {
+                    Some(Ok(since)) => since,
+                    Some(Err(_)) => {
+                        tracing::error!("Request included invalid since argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Invalid since argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                    None => {
+                        tracing::error!("Query missing since argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing since argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                };
+                handlers::modified_since(project_manager.clone(), collection, project_name, since)
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn history(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // The most recent changes made to the project, oldest first
+    warp::path!("projects" / String / String / "history")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+                    Some(Ok(limit)) => limit,
+                    Some(Err(_)) => {
+                        tracing::error!("Request included invalid limit argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Invalid limit argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                    None => 100,
+                };
+                handlers::history(project_manager.clone(), collection, project_name, limit)
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn undo(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Reverse the last `count` operations in the project's history
+    warp::path!("projects" / String / String / "undo")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let count = match params.get("count").map(|c| c.parse::<usize>()) {
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => {
+                        tracing::error!("Request included invalid count argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Invalid count argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                    None => 1,
+                };
+                handlers::undo(project_manager.clone(), collection, project_name, count)
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn largest_files(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "largest")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let limit = match params.get("limit").map(|l| l.parse::<usize>()) {
+                    Some(Ok(limit)) => limit,
+                    Some(Err(_)) => {
+                        tracing::error!("Request included invalid limit argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Invalid limit argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                    None => 10,
+                };
+                handlers::largest_files(project_manager.clone(), collection, project_name, limit)
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn watch(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "watch")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let enabled = match require_flag("enabled", params.get("enabled")) {
+                    Ok(enabled) => enabled,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::set_watch(project_manager.clone(), collection, project_name, enabled)
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn dangling(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "dangling")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::dangling(project_manager.clone(), collection, project_name)
+        })
+}
+
+#[instrument(skip(project_manager))]
+fn ancestors(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "ancestors")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let project_path = match params.get("project_path") {
+                    Some(project_path) => project_path.to_owned(),
+                    None => {
+                        tracing::error!("Query missing project_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::ancestors(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn file_context(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "files" / "context")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let project_path = match params.get("project_path") {
+                    Some(project_path) => project_path.to_owned(),
+                    None => {
+                        tracing::error!("Query missing project_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::file_context(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn get_by_uuid(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "by-uuid" / String)
+        .and(warp::get())
+        .map(move |collection, project_name, uuid| {
+            handlers::get_by_uuid(project_manager.clone(), collection, project_name, uuid)
+        })
+}
+
+#[instrument(skip(project_manager))]
+fn copy_metadata(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "files" / "copy-metadata")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let (source, dest) = match (params.remove("source"), params.remove("dest")) {
+                    (Some(source), Some(dest)) => (source, dest),
+                    _ => {
+                        tracing::error!("Query missing source or dest argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing source or dest argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let overwrite_existing = match parse_flag(
+                    "overwrite_existing",
+                    params.get("overwrite_existing"),
+                    false,
+                ) {
+                    Ok(overwrite_existing) => overwrite_existing,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::copy_metadata(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    source,
+                    dest,
+                    overwrite_existing,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn cas_metadata(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Leaving out `expected` means the key must not be set yet. Responds with whether
+    // the value was swapped.
+    warp::path!("projects" / String / String / "files" / "cas-metadata")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let args = (
+                    params.remove("project_path"),
+                    params.remove("key"),
+                    params.remove("value"),
+                );
+                let (project_path, key, value) = match args {
+                    (Some(project_path), Some(key), Some(value)) => (project_path, key, value),
+                    _ => {
+                        tracing::error!("Query missing project_path, key or value argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path, key or value argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::cas_metadata(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                    key,
+                    params.remove("expected"),
+                    value,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn rename_metadata_key(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Responds with the number of files whose key was renamed
+    warp::path!("projects" / String / String / "files" / "rename-metadata-key")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let overwrite = match parse_flag("overwrite", params.get("overwrite"), false) {
+                    Ok(overwrite) => overwrite,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                let (old_key, new_key) = match (params.remove("old_key"), params.remove("new_key"))
+                {
+                    (Some(old_key), Some(new_key)) => (old_key, new_key),
+                    _ => {
+                        tracing::error!("Query missing old_key or new_key argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing old_key or new_key argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::rename_metadata_key(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    old_key,
+                    new_key,
+                    overwrite,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn set_metadata_matching(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Anything other than the named arguments is metadata to set. Responds with the
+    // number of files changed.
+    warp::path!("projects" / String / String / "files" / "set-metadata-matching")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let pattern = match params.remove("pattern") {
+                    Some(pattern) => pattern,
+                    None => {
+                        tracing::error!("Query missing pattern argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing pattern argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let merge = match parse_flag("merge", params.remove("merge").as_ref(), true) {
+                    Ok(merge) => merge,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                let project_path = params.remove("project_path");
+                handlers::set_metadata_matching(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                    pattern,
+                    params,
+                    merge,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn delete_metadata_key(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Responds with the number of files the key was removed from
+    warp::path!("projects" / String / String / "files" / "delete-metadata-key")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let key = match params.remove("key") {
+                    Some(key) => key,
+                    None => {
+                        tracing::error!("Query missing key argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing key argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::delete_metadata_key(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    key,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn link_reference(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Link a file that points at a file in another project (or elsewhere in this one)
+    warp::path!("projects" / String / String / "files" / "reference")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let force = match parse_flag("force", params.get("force"), false) {
+                    Ok(force) => force,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                let args = (
+                    params.remove("project_path"),
+                    params.remove("target_collection"),
+                    params.remove("target_project"),
+                    params.remove("target_path"),
+                );
+                let (project_path, target) = match args {
+                    (Some(project_path), Some(collection), Some(project), Some(path)) => (
+                        project_path,
+                        FileRef {
+                            collection,
+                            project,
+                            path,
+                        },
+                    ),
+                    _ => {
+                        tracing::error!("Query missing project_path or target argument");
+                        return Ok(GodataError::new(GodataErrorType::InvalidPath, "Missing project_path, target_collection, target_project or target_path argument".to_string()).into_response());
+                    } // invalid request
+                };
+                handlers::link_reference(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                    target,
+                    force,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn get_preview(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "preview")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let project_path = match params.get("project_path") {
+                    Some(project_path) => project_path.to_owned(),
+                    None => {
+                        tracing::error!("Query missing project_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::read_preview(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn set_preview(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "preview")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                // Leaving out preview_path removes the preview
+                let project_path = match params.remove("project_path") {
+                    Some(project_path) => project_path,
+                    None => {
+                        tracing::error!("Query missing project_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::set_preview(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                    params.remove("preview_path"),
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn child_counts(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "child-counts")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                handlers::child_counts(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    params.remove("project_path"),
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn usage(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "usage")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                handlers::usage_breakdown(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    params.remove("project_path"),
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn content(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "content")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let project_path = match params.get("project_path") {
+                    Some(project_path) => project_path.to_owned(),
+                    None => {
+                        tracing::error!("Query missing project_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::read_content(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn metadata_keys(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "metadata-keys")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let counts = match parse_flag("counts", params.get("counts"), false) {
+                    Ok(counts) => counts,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::metadata_keys(project_manager.clone(), collection, project_name, counts)
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn metadata_values(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "metadata-values")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let key = match params.remove("key") {
+                    Some(key) => key,
+                    None => {
+                        tracing::error!("Query missing key argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing key argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::metadata_values(project_manager.clone(), collection, project_name, key)
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn metadata_histogram(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Number of files with each value of a key, files without it are counted under
+    // `__missing__`
+    warp::path!("projects" / String / String / "histogram")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let key = match params.remove("key") {
+                    Some(key) => key,
+                    None => {
+                        tracing::error!("Query missing key argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing key argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                };
+                handlers::metadata_histogram(project_manager.clone(), collection, project_name, key)
+            },
+        )
+}
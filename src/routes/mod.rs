@@ -1,14 +1,223 @@
 mod files;
 mod projects;
 
+use crate::errors::{GodataError, GodataErrorType, Result};
 use crate::project::ProjectManager;
 use std::sync::{Arc, Mutex};
 use warp::Filter;
 
+// Largest JSON body any route will accept
+const MAX_BODY_SIZE: u64 = 16 * 1024 * 1024;
+
+// warp already treats a single trailing slash as the end of the path, so `/x/` routes
+// the same as `/x` everywhere without any extra handling
 pub fn routes(
     project_manager: Arc<Mutex<ProjectManager>>,
+    address: String,
 ) -> impl warp::Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    projects::routes(project_manager.clone())
+    // `address` is the port or socket the server is listening on, for reporting back
+    projects::routes(project_manager.clone(), address)
         .or(files::routes(project_manager.clone()))
 }
 
+fn parse_flag<S: AsRef<str>>(name: &str, value: Option<S>, default: bool) -> Result<bool> {
+    // Boolean query flags must be `true` or `false`, anything else is a bad request
+    match value {
+        None => Ok(default),
+        Some(value) => value.as_ref().parse::<bool>().map_err(|_| {
+            tracing::error!("Request included invalid {} argument", name);
+            GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!(
+                    "Invalid {} argument `{}`, expected true or false",
+                    name,
+                    value.as_ref()
+                ),
+            )
+        }),
+    }
+}
+
+fn require_flag<S: AsRef<str>>(name: &str, value: Option<S>) -> Result<bool> {
+    // Like `parse_flag`, for flags the route can't do without
+    match value {
+        None => {
+            tracing::error!("Query missing {} argument", name);
+            Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!("Missing {} argument", name),
+            ))
+        }
+        Some(value) => parse_flag(name, Some(value), false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{manager, new_project, scratch_dir, unique, write_file};
+    use std::collections::HashMap;
+    use warp::http::StatusCode;
+
+    #[test]
+    fn flags_parse_true_false_missing_and_garbage() {
+        assert!(parse_flag("force", Some("true"), false).unwrap());
+        assert!(!parse_flag("force", Some("false"), true).unwrap());
+        assert!(parse_flag::<&str>("force", None, true).unwrap());
+        assert!(!parse_flag::<&str>("force", None, false).unwrap());
+        for garbage in ["yes", "1", "True", ""] {
+            let error = parse_flag("force", Some(garbage), false).unwrap_err();
+            assert_eq!(error.error_type, GodataErrorType::InvalidPath);
+            assert!(error.message.contains("force"));
+        }
+        assert!(require_flag("confirm", Some("true")).unwrap());
+        let error = require_flag::<&str>("confirm", None).unwrap_err();
+        assert_eq!(error.message, "Missing confirm argument");
+    }
+
+    #[tokio::test]
+    async fn malformed_flags_are_bad_requests() {
+        let routes = routes(manager(), "0".to_string());
+        let collection = unique("collection");
+        let requests = [
+            ("POST", format!("/create/{}/p?force=notabool", collection)),
+            ("GET", format!("/projects/{}?show_hidden=yes", collection)),
+            ("POST", format!("/projects/{}/p/unload?force=1", collection)),
+            (
+                "GET",
+                format!("/projects/{}/p/tree?with_metadata=maybe", collection),
+            ),
+        ];
+        for (method, path) in requests {
+            let response = warp::test::request()
+                .method(method)
+                .path(&path)
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", path);
+            let body = String::from_utf8_lossy(response.body()).to_string();
+            assert!(body.contains("expected true or false"), "{}", body);
+        }
+        // The bad create was refused before anything was made
+        assert!(crate::locations::load_project_dir("p", &collection).is_err());
+    }
+
+    #[tokio::test]
+    async fn trailing_slashes_route_the_same() {
+        let (collection, name, project) = new_project();
+        {
+            let mut project = project.lock().unwrap();
+            let path = write_file(&scratch_dir().join("a.txt"), "data");
+            project
+                .add_file("data/a.txt", path, HashMap::new(), false, false)
+                .unwrap();
+        }
+        let routes = routes(manager(), "0".to_string());
+        let base = format!("/projects/{}/{}", collection, name);
+        let paths = [
+            ("/version".to_string(), ""),
+            ("/collections".to_string(), ""),
+            (format!("/projects/{}", collection), ""),
+            (format!("{}/list", base), "?project_path=data"),
+            (format!("{}/files", base), "?project_path=data/a.txt"),
+            (format!("{}/exists", base), "?project_path=data/a.txt"),
+            (format!("{}/stats", base), ""),
+            (format!("{}/shape", base), ""),
+            (format!("{}/history", base), "?limit=5"),
+            (format!("{}/checksums/status", base), ""),
+            (format!("{}/quota", base), ""),
+        ];
+        for (path, query) in paths {
+            let mut responses = Vec::new();
+            for slash in ["", "/"] {
+                let response = warp::test::request()
+                    .method("GET")
+                    .path(&format!("{}{}{}", path, slash, query))
+                    .reply(&routes)
+                    .await;
+                assert_eq!(response.status(), StatusCode::OK, "{}{}", path, slash);
+                // Compared as JSON, since maps don't come out in a fixed order
+                let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+                responses.push(body);
+            }
+            assert_eq!(responses[0], responses[1], "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_path_arguments_are_typed_bad_requests() {
+        let routes = routes(manager(), "0".to_string());
+        let (collection, name, _project) = new_project();
+        let requests = [
+            (
+                "GET",
+                format!("/projects/{}/{}/exists", collection, name),
+                "project_path",
+            ),
+            (
+                "GET",
+                format!("/projects/{}/{}/generate", collection, name),
+                "project_path",
+            ),
+            (
+                "POST",
+                format!("/projects/{}/{}/files/move", collection, name),
+                "source_path",
+            ),
+            (
+                "POST",
+                format!("/projects/{}/{}/files/move?source_path=a", collection, name),
+                "destination_path",
+            ),
+            ("GET", "/projects-under".to_string(), "prefix"),
+            (
+                "POST",
+                format!("/projects/{}/{}/relocate", collection, name),
+                "storage_location",
+            ),
+            (
+                "GET",
+                format!("/export/{}/{}", collection, name),
+                "output_path",
+            ),
+        ];
+        for (method, path, argument) in requests {
+            let response = warp::test::request()
+                .method(method)
+                .path(&path)
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", path);
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["error_type"], "invalid_path");
+            assert_eq!(body["message"], format!("Missing {} argument", argument));
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_projects_are_typed_not_founds() {
+        let routes = routes(manager(), "0".to_string());
+        let (collection, _name, _project) = new_project();
+        let requests = [
+            ("POST", format!("/load/{}/no_such_project", collection)),
+            (
+                "DELETE",
+                format!(
+                    "/projects/{}/no_such_project/files?project_path=a",
+                    collection
+                ),
+            ),
+        ];
+        for (method, path) in requests {
+            let response = warp::test::request()
+                .method(method)
+                .path(&path)
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{}", path);
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["error_type"], "not_found", "{}", path);
+            assert!(body["message"].is_string());
+        }
+    }
+}
@@ -1,23 +1,58 @@
+use crate::archive::Compression;
+use crate::errors::{GodataError, GodataErrorType};
 use crate::handlers;
-use crate::project::ProjectManager;
+use crate::project::{ProjectManager, ProjectSort};
+use crate::routes::{parse_flag, MAX_BODY_SIZE};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use tracing::instrument;
-use warp::http::StatusCode;
 use warp::Filter;
+use warp::Reply;
 
 pub(super) fn routes(
     project_manager: Arc<Mutex<ProjectManager>>,
+    address: String,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     list_collections()
         .or(get_version())
+        .or(get_config(address))
         .or(list_projects(project_manager.clone()))
         .or(create_project(project_manager.clone()))
+        .or(ensure_project(project_manager.clone()))
         .or(delete_project(project_manager.clone()))
         .or(load_project(project_manager.clone()))
+        .or(load_status(project_manager.clone()))
         .or(drop_project(project_manager.clone()))
+        .or(unload_project(project_manager.clone()))
         .or(project_export_tree(project_manager.clone()))
         .or(import_project_tree(project_manager.clone()))
+        .or(export_bundle(project_manager.clone()))
+        .or(export_subtree(project_manager.clone()))
+        .or(import_bundle(project_manager.clone()))
+        .or(doctor(project_manager.clone()))
+        .or(projects_under(project_manager.clone()))
+        .or(validate_glob())
+        .or(validate_name())
+        .or(compact_project(project_manager.clone()))
+        .or(endpoint_info(project_manager.clone()))
+        .or(export_csv(project_manager.clone()))
+        .or(relocate_storage(project_manager.clone()))
+        .or(project_stats(project_manager.clone()))
+        .or(project_shape(project_manager.clone()))
+        .or(project_integrity(project_manager.clone()))
+        .or(reload_project(project_manager.clone()))
+        .or(gc_project(project_manager.clone()))
+        .or(manifest_hash(project_manager.clone()))
+        .or(disk_diff(project_manager.clone()))
+        .or(is_loaded(project_manager.clone()))
+        .or(export_json(project_manager.clone()))
+        .or(subtree(project_manager.clone()))
+        .or(get_project_metadata(project_manager.clone()))
+        .or(set_project_metadata(project_manager.clone()))
+        .or(trash_project(project_manager.clone()))
+        .or(list_trash(project_manager.clone()))
+        .or(restore_project(project_manager.clone()))
+        .or(purge_project(project_manager.clone()))
 }
 
 fn get_version() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -26,32 +61,149 @@ fn get_version() -> impl Filter<Extract = impl warp::Reply, Error = warp::Reject
         .map(handlers::get_version)
 }
 
+fn get_config(
+    address: String,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("config")
+        .and(warp::get())
+        .map(move || handlers::get_config(&address))
+}
+
 fn list_collections() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("collections")
         .and(warp::get())
-        .and(warp::query::<HashMap<String, bool>>())
-        .map(move |p: HashMap<String, bool>| match p.get("show_hidden") {
-            Some(show_hidden) => handlers::list_collections(*show_hidden),
-            None => handlers::list_collections(false),
+        .and(warp::query::<HashMap<String, String>>())
+        .map(move |p: HashMap<String, String>| {
+            match parse_flag("show_hidden", p.get("show_hidden"), false) {
+                Ok(show_hidden) => handlers::list_collections(show_hidden),
+                Err(e) => Ok(e.into_response()),
+            }
         })
 }
 
+fn validate_glob() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("glob" / "validate")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |mut p: HashMap<String, String>| match p.remove("pattern") {
+                Some(pattern) => handlers::validate_glob(pattern),
+                None => {
+                    tracing::error!("Query missing pattern argument");
+                    Ok(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        "Missing pattern argument".to_string(),
+                    )
+                    .into_response())
+                }
+            },
+        )
+}
+
+fn validate_name() -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Checks a project or collection name without creating anything
+    warp::path!("validate-name")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |mut p: HashMap<String, String>| match p.remove("name") {
+                Some(name) => handlers::validate_name(name),
+                None => {
+                    tracing::error!("Query missing name argument");
+                    Ok(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        "Missing name argument".to_string(),
+                    )
+                    .into_response())
+                }
+            },
+        )
+}
+
 fn list_projects(
     project_manager: Arc<Mutex<ProjectManager>>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("projects" / String)
         .and(warp::get())
-        .and(warp::query::<HashMap<String, bool>>())
-        .map(
-            move |collection, p: HashMap<String, bool>| match p.get("show_hidden") {
-                Some(show_hidden) => {
-                    handlers::list_projects(project_manager.clone(), collection, *show_hidden)
+        .and(warp::query::<HashMap<String, String>>())
+        .map(move |collection, params: HashMap<String, String>| {
+            let show_hidden = match parse_flag("show_hidden", params.get("show_hidden"), false) {
+                Ok(show_hidden) => show_hidden,
+                Err(e) => return Ok(e.into_response()),
+            };
+            let sort = match params.get("sort").map(|s| s.parse::<ProjectSort>()) {
+                Some(Ok(sort)) => sort,
+                Some(Err(e)) => {
+                    tracing::error!("Request included invalid sort argument");
+                    return Ok(e.into_response());
                 }
-                None => handlers::list_projects(project_manager.clone(), collection, false),
+                None => ProjectSort::Name,
+            };
+            // Sorting by size has to stat every file in every project in the
+            // collection, so it has to be asked for explicitly
+            let compute_size = match parse_flag("compute_size", params.get("compute_size"), false) {
+                Ok(compute_size) => compute_size,
+                Err(e) => return Ok(e.into_response()),
+            };
+            if sort == ProjectSort::Size && !compute_size {
+                tracing::error!("Request sorted by size without compute_size");
+                return Ok(GodataError::new(GodataErrorType::InvalidPath, "Sorting by size reads every file in the collection, pass compute_size=true to do it anyway".to_string()).into_response());
+            }
+            let descending = match params.get("order").map(|o| o.as_str()) {
+                Some("asc") | None => false,
+                Some("desc") => true,
+                Some(order) => {
+                    tracing::error!("Request included invalid order argument {}", order);
+                    return Ok(GodataError::new(GodataErrorType::InvalidPath, format!(
+                            "Invalid order argument {}, expected asc or desc",
+                            order
+                        )).into_response());
+                }
+            };
+            handlers::list_projects(
+                project_manager.clone(),
+                collection,
+                show_hidden,
+                sort,
+                descending,
+            )
+        })
+}
+
+fn doctor(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("doctor")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |p: HashMap<String, String>| match parse_flag("repair", p.get("repair"), false) {
+                Ok(repair) => handlers::doctor(project_manager.clone(), repair),
+                Err(e) => Ok(e.into_response()),
             },
         )
 }
 
+fn projects_under(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Projects whose storage lives under the prefix directory
+    warp::path!("projects-under")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(move |p: HashMap<String, String>| match p.get("prefix") {
+            Some(prefix) => handlers::projects_under(project_manager.clone(), prefix.to_owned()),
+            None => {
+                tracing::error!("Missing prefix argument");
+                Ok(GodataError::new(
+                    GodataErrorType::InvalidPath,
+                    "Missing prefix argument".to_string(),
+                )
+                .into_response())
+            }
+        })
+}
+
 #[instrument(skip(project_manager))]
 fn create_project(
     project_manager: Arc<Mutex<ProjectManager>>,
@@ -61,19 +213,61 @@ fn create_project(
         .and(warp::query::<HashMap<String, String>>())
         .map(
             move |collection, project_name, params: HashMap<String, String>| {
-                let force = match params.get("force") {
-                    Some(force) => force.parse::<bool>().unwrap(),
-                    None => false,
+                let force = match parse_flag("force", params.get("force"), false) {
+                    Ok(force) => force,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                // Temporary projects are never written to disk, and are gone once the
+                // last client drops them
+                let temporary = match parse_flag("temporary", params.get("temporary"), false) {
+                    Ok(temporary) => temporary,
+                    Err(e) => return Ok(e.into_response()),
                 };
                 let storage_location = params
                     .get("storage_location")
                     .map(|storage_location| storage_location.to_owned());
+                // A template lays out the new project's folders
+                let template = params.get("template").map(|template| template.to_owned());
+                if temporary && (storage_location.is_some() || template.is_some()) {
+                    tracing::error!(
+                        "Request gave a storage_location or template for a temporary project"
+                    );
+                    return Ok(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        "Temporary projects can't have a storage_location or template".to_string(),
+                    )
+                    .into_response());
+                }
                 handlers::create_project(
                     project_manager.clone(),
                     collection,
                     project_name,
                     force,
                     storage_location,
+                    temporary,
+                    template,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn ensure_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("ensure" / String / String)
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let storage_location = params
+                    .get("storage_location")
+                    .map(|storage_location| storage_location.to_owned());
+                handlers::ensure_project(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    storage_location,
                 )
             },
         )
@@ -88,11 +282,35 @@ fn delete_project(
         .and(warp::query::<HashMap<String, String>>())
         .map(
             move |collection, project_name, params: HashMap<String, String>| {
-                let force = match params.get("force") {
-                    Some(force) => force.parse::<bool>().unwrap(),
-                    None => false,
+                let force = match parse_flag("force", params.get("force"), false) {
+                    Ok(force) => force,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                let ignore_references =
+                    match parse_flag("ignore_references", params.get("ignore_references"), false) {
+                        Ok(ignore_references) => ignore_references,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                let confirm_count = match params.get("confirm_count").map(|c| c.parse::<usize>()) {
+                    Some(Ok(confirm_count)) => Some(confirm_count),
+                    Some(Err(_)) => {
+                        tracing::error!("Request included invalid confirm_count argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Invalid confirm_count argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                    None => None,
                 };
-                handlers::delete_project(project_manager.clone(), collection, project_name, force)
+                handlers::delete_project(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    force,
+                    ignore_references,
+                    confirm_count,
+                )
             },
         )
 }
@@ -107,6 +325,14 @@ fn load_project(
         })
 }
 
+fn load_status(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("load-status" / String)
+        .and(warp::get())
+        .map(move |load_id| handlers::load_status(project_manager.clone(), load_id))
+}
+
 fn drop_project(
     project_manager: Arc<Mutex<ProjectManager>>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -117,6 +343,322 @@ fn drop_project(
         })
 }
 
+/// Evicts a loaded project from the cache. This isn't unconditional: it's refused
+/// while another request holds the project, and `force=true` evicts it anyway,
+/// leaving that request with its copy. `flush` (default true) writes the database
+/// out first.
+fn unload_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "unload")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let flush = match parse_flag("flush", params.get("flush"), true) {
+                    Ok(flush) => flush,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                let force = match parse_flag("force", params.get("force"), false) {
+                    Ok(force) => force,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::unload_project(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    flush,
+                    force,
+                )
+            },
+        )
+}
+
+fn compact_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "compact")
+        .and(warp::post())
+        .map(move |collection, project_name| {
+            handlers::compact_project(project_manager.clone(), collection, project_name)
+        })
+}
+
+fn get_project_metadata(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "metadata")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::get_project_metadata(project_manager.clone(), collection, project_name)
+        })
+}
+
+fn set_project_metadata(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "metadata")
+        .and(warp::put())
+        .and(warp::body::content_length_limit(MAX_BODY_SIZE))
+        .and(warp::body::json::<HashMap<String, String>>())
+        .map(move |collection, project_name, metadata| {
+            handlers::set_project_metadata(
+                project_manager.clone(),
+                collection,
+                project_name,
+                metadata,
+            )
+        })
+}
+
+fn is_loaded(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "loaded")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::is_loaded(project_manager.clone(), collection, project_name)
+        })
+}
+
+fn project_stats(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "stats")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::project_stats(project_manager.clone(), collection, project_name)
+        })
+}
+
+fn project_shape(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "shape")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::project_shape(project_manager.clone(), collection, project_name)
+        })
+}
+
+fn project_integrity(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "integrity")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::project_integrity(project_manager.clone(), collection, project_name)
+        })
+}
+
+fn reload_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "reload")
+        .and(warp::post())
+        .map(move |collection, project_name| {
+            handlers::reload_project(project_manager.clone(), collection, project_name)
+        })
+}
+
+fn disk_diff(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "disk-diff")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::disk_diff(project_manager.clone(), collection, project_name)
+        })
+}
+
+fn manifest_hash(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "manifest-hash")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::manifest_hash(project_manager.clone(), collection, project_name)
+        })
+}
+
+fn gc_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "gc")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let dry_run = match parse_flag("dry_run", params.get("dry_run"), false) {
+                    Ok(dry_run) => dry_run,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::gc_project(project_manager.clone(), collection, project_name, dry_run)
+            },
+        )
+}
+
+fn export_json(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "export.json")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let anonymize = match parse_flag("anonymize", params.get("anonymize"), false) {
+                    Ok(anonymize) => anonymize,
+                    Err(e) => return Ok(e.into_response()),
+                };
+                handlers::export_json(project_manager.clone(), collection, project_name, anonymize)
+            },
+        )
+}
+
+fn subtree(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "tree")
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let max_depth = match params.get("max_depth").map(|d| d.parse::<usize>()) {
+                    Some(Ok(max_depth)) => Some(max_depth),
+                    Some(Err(_)) => {
+                        tracing::error!("Request included invalid max_depth argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Invalid max_depth argument".to_string(),
+                        )
+                        .into_response());
+                    }
+                    None => None,
+                };
+                let with_metadata =
+                    match parse_flag("with_metadata", params.get("with_metadata"), false) {
+                        Ok(with_metadata) => with_metadata,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                handlers::subtree(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    params.get("project_path").cloned(),
+                    max_depth,
+                    with_metadata,
+                )
+            },
+        )
+}
+
+fn export_csv(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "export.csv")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::export_csv(project_manager.clone(), collection, project_name)
+        })
+}
+
+fn endpoint_info(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "endpoint")
+        .and(warp::get())
+        .map(move |collection, project_name| {
+            handlers::endpoint_info(project_manager.clone(), collection, project_name)
+        })
+}
+
+#[instrument(skip(project_manager))]
+fn relocate_storage(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("projects" / String / String / "relocate")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let storage_location = match params.remove("storage_location") {
+                    Some(storage_location) => storage_location,
+                    None => {
+                        tracing::error!("Missing storage_location argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing storage_location argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                let ignore_references =
+                    match parse_flag("ignore_references", params.get("ignore_references"), false) {
+                        Ok(ignore_references) => ignore_references,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                handlers::relocate_storage(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    storage_location,
+                    ignore_references,
+                )
+            },
+        )
+}
+
+fn trash_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("trash" / String / String)
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, params: HashMap<String, String>| {
+                let ignore_references =
+                    match parse_flag("ignore_references", params.get("ignore_references"), false) {
+                        Ok(ignore_references) => ignore_references,
+                        Err(e) => return Ok(e.into_response()),
+                    };
+                handlers::trash_project(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    ignore_references,
+                )
+            },
+        )
+}
+
+fn list_trash(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("trash" / String)
+        .and(warp::get())
+        .map(move |collection| handlers::list_trash(project_manager.clone(), collection))
+}
+
+fn restore_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("restore" / String / String)
+        .and(warp::post())
+        .map(move |collection, project_name| {
+            handlers::restore_project(project_manager.clone(), collection, project_name)
+        })
+}
+
+fn purge_project(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("trash" / String / String)
+        .and(warp::delete())
+        .map(move |collection, project_name| {
+            handlers::purge_project(project_manager.clone(), collection, project_name)
+        })
+}
+
 #[instrument(skip(project_manager))]
 fn project_export_tree(
     project_manager: Arc<Mutex<ProjectManager>>,
@@ -130,17 +672,27 @@ fn project_export_tree(
                     Some(output_path) => output_path.to_owned(),
                     None => {
                         tracing::error!("Missing output_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing output_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
-                        ));
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing output_path argument".to_string(),
+                        )
+                        .into_response());
                     } // invalid request
                 };
+                let compression = match params.get("compress").map(|c| c.parse::<Compression>()) {
+                    Some(Ok(compression)) => compression,
+                    Some(Err(e)) => {
+                        tracing::error!("Request included invalid compress argument");
+                        return Ok(e.into_response());
+                    }
+                    None => Compression::None,
+                };
                 handlers::export_project_tree(
                     project_manager.clone(),
                     collection,
                     project_name,
                     output_path,
+                    compression,
                 )
             },
         )
@@ -159,10 +711,11 @@ fn import_project_tree(
                     Some(input_path) => input_path.to_owned(),
                     None => {
                         tracing::error!("Missing input_path argument");
-                        return Ok(warp::reply::with_status(
-                            warp::reply::json(&"Missing input_path argument".to_string()),
-                            StatusCode::BAD_REQUEST,
-                        ));
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing input_path argument".to_string(),
+                        )
+                        .into_response());
                     } // invalid request
                 };
                 handlers::import_project_tree(
@@ -174,3 +727,107 @@ fn import_project_tree(
             },
         )
 }
+
+#[instrument(skip(project_manager))]
+fn export_bundle(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("export-bundle" / String / String)
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let output_path = match params.remove("output_path") {
+                    Some(output_path) => output_path,
+                    None => {
+                        tracing::error!("Missing output_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing output_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::export_bundle(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    output_path,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn export_subtree(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    // Turns a folder into a new project, the source project is left alone
+    warp::path!("projects" / String / String / "export-subtree")
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let args = (
+                    params.remove("project_path"),
+                    params.remove("dest_collection"),
+                    params.remove("dest_name"),
+                );
+                let (project_path, dest_collection, dest_name) = match args {
+                    (Some(project_path), Some(dest_collection), Some(dest_name)) => {
+                        (project_path, dest_collection, dest_name)
+                    }
+                    _ => {
+                        tracing::error!(
+                            "Query missing project_path, dest_collection or dest_name argument"
+                        );
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing project_path, dest_collection or dest_name argument"
+                                .to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::export_subtree(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    project_path,
+                    dest_collection,
+                    dest_name,
+                )
+            },
+        )
+}
+
+#[instrument(skip(project_manager))]
+fn import_bundle(
+    project_manager: Arc<Mutex<ProjectManager>>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("import-bundle" / String / String)
+        .and(warp::get())
+        .and(warp::query::<HashMap<String, String>>())
+        .map(
+            move |collection, project_name, mut params: HashMap<String, String>| {
+                let input_path = match params.remove("input_path") {
+                    Some(input_path) => input_path,
+                    None => {
+                        tracing::error!("Missing input_path argument");
+                        return Ok(GodataError::new(
+                            GodataErrorType::InvalidPath,
+                            "Missing input_path argument".to_string(),
+                        )
+                        .into_response());
+                    } // invalid request
+                };
+                handlers::import_bundle(
+                    project_manager.clone(),
+                    collection,
+                    project_name,
+                    input_path,
+                    params.remove("storage_location"),
+                )
+            },
+        )
+}
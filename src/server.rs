@@ -1,28 +1,44 @@
+use crate::checksum::sha256_file;
+use crate::errors::{GodataError, GodataErrorType};
 use crate::project::{get_project_manager, ProjectManager};
 use crate::routes;
 
 use directories::UserDirs;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use sysinfo::System;
 use tokio::signal;
 use tokio_stream::wrappers::UnixListenerStream;
 use tracing::instrument;
 use warp::Filter;
 
+// How long the background hashing task waits before looking for more work once every
+// queue is empty
+const CHECKSUM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 pub struct Server {
     project_manager: Arc<Mutex<ProjectManager>>,
     url: (String, Option<u16>),
+    flush_interval: Option<u64>,
 }
 
 impl Server {
     pub async fn start(&self) {
+        if let Some(seconds) = self.flush_interval.filter(|seconds| *seconds > 0) {
+            flush_periodically(self.project_manager.clone(), Duration::from_secs(seconds));
+        }
+        tokio::spawn(hash_pending(self.project_manager.clone()));
         // If there's a port, start a TCP server
 
         if self.url.1.is_some() {
-            let (_, server) = warp::serve(routes::routes(self.project_manager.clone()))
-                .bind_with_graceful_shutdown(([127, 0, 0, 1], self.url.1.unwrap()), async {
-                    signal::ctrl_c().await.unwrap()
-                });
+            let (_, server) = warp::serve(routes::routes(
+                self.project_manager.clone(),
+                self.url.0.clone(),
+            ))
+            .bind_with_graceful_shutdown(([127, 0, 0, 1], self.url.1.unwrap()), async {
+                signal::ctrl_c().await.unwrap()
+            });
             server.await
         }
         // If there's no port, start a Unix socket server
@@ -40,17 +56,19 @@ impl Server {
             }
             let listener = tokio::net::UnixListener::bind(&self.url.0).unwrap();
             let incoming = UnixListenerStream::new(listener);
-            let server = warp::serve(routes::routes(self.project_manager.clone()).with(
-                warp::trace(|info| {
-                    let request_id = uuid::Uuid::new_v4();
-                    tracing::info_span!(
-                        "request",
-                        request_id = %request_id,
-                        method = %info.method(),
-                        path = %info.path(),
-                    )
-                }),
-            ))
+            let server = warp::serve(
+                routes::routes(self.project_manager.clone(), self.url.0.clone()).with(warp::trace(
+                    |info| {
+                        let request_id = uuid::Uuid::new_v4();
+                        tracing::info_span!(
+                            "request",
+                            request_id = %request_id,
+                            method = %info.method(),
+                            path = %info.path(),
+                        )
+                    },
+                )),
+            )
             .serve_incoming_with_graceful_shutdown(incoming, async {
                 signal::ctrl_c().await.unwrap()
             });
@@ -59,6 +77,89 @@ impl Server {
     }
 }
 
+fn flush_periodically(
+    project_manager: Arc<Mutex<ProjectManager>>,
+    period: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            flush_modified(&project_manager);
+        }
+    })
+}
+
+fn flush_modified(project_manager: &Arc<Mutex<ProjectManager>>) {
+    // Only hold the manager lock long enough to see what is loaded, so requests
+    // aren't held up while projects are written out
+    let projects = project_manager.lock().unwrap().loaded_projects();
+    for (key, project) in projects {
+        let mut project = project.lock().unwrap();
+        if !project.pending_changes() {
+            continue;
+        }
+        match project.tree.flush() {
+            Ok(_) => tracing::info!("Flushed unsaved changes for project `{}`", key),
+            Err(e) => tracing::error!("Failed to flush project `{}`: {}", key, e),
+        }
+    }
+}
+
+async fn hash_pending(project_manager: Arc<Mutex<ProjectManager>>) {
+    // Work through the checksum queues of loaded projects one file at a time, projects
+    // that aren't loaded are picked up once they are. Projects are only locked to take a
+    // file off the queue and to store its checksum, and only weakly held while hashing
+    // so unloading a project isn't held up. A project that goes away mid-hash just
+    // loses the result, and queues the file again when it is next loaded.
+    loop {
+        let projects: Vec<_> = project_manager
+            .lock()
+            .unwrap()
+            .loaded_projects()
+            .into_iter()
+            .map(|(key, project)| (key, Arc::downgrade(&project)))
+            .collect();
+        let mut hashed_any = false;
+        for (key, project) in projects {
+            let next = match project.upgrade() {
+                Some(project) => project.lock().unwrap().next_checksum(),
+                None => None,
+            };
+            let (project_path, real_path) = match next {
+                Some(next) => next,
+                None => continue,
+            };
+            hashed_any = true;
+            let path = real_path.clone();
+            let checksum = tokio::task::spawn_blocking(move || sha256_file(&path))
+                .await
+                .unwrap_or_else(|e| {
+                    Err(GodataError::new(
+                        GodataErrorType::InternalError,
+                        format!("Hashing task failed: {}", e),
+                    ))
+                });
+            match project.upgrade() {
+                Some(project) => {
+                    project
+                        .lock()
+                        .unwrap()
+                        .finish_checksum(&project_path, &real_path, checksum)
+                }
+                None => tracing::info!(
+                    "Project `{}` was unloaded before `{}` was hashed",
+                    key,
+                    project_path
+                ),
+            }
+        }
+        if !hashed_any {
+            tokio::time::sleep(CHECKSUM_POLL_INTERVAL).await;
+        }
+    }
+}
+
 impl Drop for Server {
     fn drop(&mut self) {
         println!("Shutting down server...");
@@ -70,11 +171,16 @@ impl Drop for Server {
 }
 
 #[instrument]
-pub fn get_server(port: Option<u16>) -> Server {
+pub fn get_server(
+    port: Option<u16>,
+    socket_path: Option<PathBuf>,
+    flush_interval: Option<u64>,
+) -> Server {
     tracing::info!("Getting server");
-    let url = match port {
-        Some(p) => format!("localhost:{}", p),
-        None => UserDirs::new()
+    let url = match (port, socket_path) {
+        (Some(p), _) => format!("localhost:{}", p),
+        (None, Some(path)) => path.to_str().unwrap().to_string(),
+        (None, None) => UserDirs::new()
             .unwrap()
             .home_dir()
             .join(".godata.sock")
@@ -94,5 +200,93 @@ pub fn get_server(port: Option<u16>) -> Server {
     Server {
         project_manager: Arc::new(Mutex::new(project_manager.unwrap())),
         url: (url, port),
+        flush_interval,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::retry_while_locked;
+    use crate::testing::{lock_manager, manager, new_project, scratch_dir, write_file};
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn periodic_flush_writes_out_pending_changes() {
+        let (collection, name, project) = new_project();
+        {
+            let mut project = project.lock().unwrap();
+            project.set_track_access(true).unwrap();
+            let path = write_file(&scratch_dir().join("a.txt"), "data");
+            project
+                .add_file("a.txt", path, HashMap::new(), false, false)
+                .unwrap();
+            // Reading the file records its access time without writing it out
+            project.get_file("a.txt").unwrap();
+            assert!(project.pending_changes());
+        }
+
+        let task = flush_periodically(manager(), Duration::from_millis(50));
+        let mut flushed = false;
+        for _ in 0..40 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            if !project.lock().unwrap().pending_changes() {
+                flushed = true;
+                break;
+            }
+        }
+        assert!(flushed);
+        task.abort();
+        let _ = task.await;
+        drop(project);
+
+        let mut manager = lock_manager();
+        manager
+            .unload_project(&name, &collection, false, false)
+            .unwrap();
+        let project = retry_while_locked(|| manager.load_project(&name, &collection)).unwrap();
+        let accessed = project.lock().unwrap().recently_accessed(10).unwrap();
+        assert_eq!(accessed.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn background_hashing_fills_in_checksums() {
+        let (_, _, project) = new_project();
+        let dir = scratch_dir();
+        let paths = ["a.txt", "sub/b.txt", "sub/c.txt"];
+        {
+            let mut project = project.lock().unwrap();
+            project.set_checksums(true, true).unwrap();
+            for name in paths {
+                write_file(&dir.join(name), name);
+            }
+            project
+                .add_folder("data", dir.clone(), true, None, false)
+                .unwrap();
+            // Linking doesn't wait for the files to be hashed
+            let status = serde_json::to_value(project.checksum_status()).unwrap();
+            assert_eq!(status["pending"], 3);
+            assert_eq!(status["hashed"], 0);
+        }
+
+        let task = tokio::spawn(hash_pending(manager()));
+        let mut drained = false;
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let status = serde_json::to_value(project.lock().unwrap().checksum_status()).unwrap();
+            if status["pending"] == 0 && status["hashed"] == 3 {
+                drained = true;
+                break;
+            }
+        }
+        task.abort();
+        let _ = task.await;
+        assert!(drained);
+        let project = project.lock().unwrap();
+        for name in paths {
+            let file = project.tree.get(&format!("data/{}", name)).unwrap();
+            let expected = sha256_file(&dir.join(name)).unwrap();
+            assert_eq!(file.checksum.as_deref(), Some(expected.as_str()));
+        }
     }
 }
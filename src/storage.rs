@@ -1,10 +1,14 @@
 use crate::errors::{GodataError, GodataErrorType, Result};
 use crate::locations::get_default_storage_dir;
+use chrono::Utc;
 use sled::Db;
 use std::fs;
+use std::io::Read;
+use std::path::Component;
 use std::path::Path;
 use std::path::PathBuf;
 use tracing::instrument;
+use uuid::Uuid;
 
 pub(crate) struct StorageManager {
     _root_path: PathBuf,
@@ -46,6 +50,22 @@ impl StorageManager {
         Ok(())
     }
 
+    #[instrument(skip(self))]
+    pub(crate) fn update(
+        &self,
+        name: &str,
+        collection: &str,
+        endpoint: &str,
+        path: PathBuf,
+    ) -> Result<()> {
+        // Replace the storage information of an existing project
+        self.get(name, collection)?;
+        let key = format!("{}/{}", name, collection);
+        let value = format!("{}:{}", endpoint, path.to_str().unwrap());
+        self.storage_db.insert(key, value.as_bytes())?;
+        Ok(())
+    }
+
     pub(crate) fn get(&self, name: &str, collection: &str) -> Result<(String, PathBuf)> {
         let key = format!("{}/{}", name, collection);
         let value = self.storage_db.get(key).unwrap();
@@ -77,6 +97,59 @@ impl StorageManager {
         Ok((endpoint.to_string(), path.to_path_buf()))
     }
 
+    pub(crate) fn entries(&self) -> Result<Vec<(String, String)>> {
+        // List the (name, collection) pairs of every project with storage information
+        let mut entries = Vec::new();
+        for item in self.storage_db.iter() {
+            let (key, _) = item?;
+            let key = String::from_utf8_lossy(&key).to_string();
+            match key.split_once('/') {
+                Some((name, collection)) => {
+                    entries.push((name.to_string(), collection.to_string()))
+                }
+                None => tracing::warn!("Skipping malformed storage key `{}`", key),
+            }
+        }
+        Ok(entries)
+    }
+
+    pub(crate) fn list_under(&self, prefix: PathBuf) -> Result<Vec<(String, String)>> {
+        // List the (name, collection) pairs of every project stored somewhere under the
+        // prefix. Paths are compared by component, so `/data/a` doesn't match `/data/ab`.
+        let mut matches = Vec::new();
+        for item in self.storage_db.iter() {
+            let (key, value) = item?;
+            let key = String::from_utf8_lossy(&key).to_string();
+            let value = String::from_utf8_lossy(&value).to_string();
+            let (name, collection) = match key.split_once('/') {
+                Some(entry) => entry,
+                None => {
+                    tracing::warn!("Skipping malformed storage key `{}`", key);
+                    continue;
+                }
+            };
+            let path = match value.split_once(':') {
+                Some((_, path)) => Path::new(path),
+                None => {
+                    tracing::warn!("Skipping corrupted storage information `{}`", value);
+                    continue;
+                }
+            };
+            if path.starts_with(&prefix) {
+                matches.push((name.to_string(), collection.to_string()));
+            }
+        }
+        Ok(matches)
+    }
+
+    pub(crate) fn remove(&self, name: &str, collection: &str) -> Result<(String, PathBuf)> {
+        // Drop the storage entry for a project without touching the data it points to
+        let key = format!("{}/{}", name, collection);
+        let entry = self.get(name, collection)?;
+        self.storage_db.remove(key)?;
+        Ok(entry)
+    }
+
     pub(crate) fn delete(&self, name: &str, collection: &str) -> Result<()> {
         let key = format!("{}/{}", name, collection);
         let path = self.get(name, collection)?;
@@ -100,39 +173,212 @@ pub(crate) trait StorageEndpoint {
     // reading and writing to python.
 
     fn generate_path(&self, project_path: &str) -> Result<PathBuf>;
+    fn generate_unique_path(&self, project_path: &str, suffix: Option<&str>) -> Result<PathBuf>;
     fn is_available(&self) -> Result<()>;
     fn discover_file(&self, project_path: &str, file_extension: String) -> Result<PathBuf>;
     fn move_file(&self, from: &str, to: &str) -> Result<()>;
-    fn copy_file(&self, from: &str, to: &str) -> Result<()>;
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<()>;
     fn delete_file(&self, path: &str) -> Result<()>;
     fn is_internal(&self, path: &Path) -> bool;
     fn get_relative_path(&self, path: &Path) -> PathBuf;
     fn resolve(&self, relpath: &Path) -> PathBuf;
+    fn open_read(&self, relpath: &Path) -> Result<Box<dyn Read>>;
+    fn kind(&self) -> &'static str;
+    fn root(&self) -> String;
+    fn set_path_template(&mut self, template: Option<PathTemplate>);
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct PathTemplate {
+    // A layout for generated paths, e.g. `{collection}/{name}/{yyyy}/{mm}/{leaf}`
+    template: String,
+    name: String,
+    collection: String,
+}
+
+impl PathTemplate {
+    pub(crate) fn new(template: &str, name: &str, collection: &str) -> Result<PathTemplate> {
+        let template = PathTemplate {
+            template: template.to_string(),
+            name: name.to_string(),
+            collection: collection.to_string(),
+        };
+        // Generated paths have to stay under the storage root, so the template can't be
+        // absolute or step out of it
+        if template.template.starts_with('/')
+            || template
+                .template
+                .split('/')
+                .any(|p| p.is_empty() || p == "." || p == "..")
+        {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!(
+                    "Path template `{}` must be a relative path without empty, `.` or `..` parts",
+                    template.template
+                ),
+            ));
+        }
+        // Expanding a throwaway path catches bad placeholders up front
+        template.expand("folder/file")?;
+        if !template.template.contains("{leaf}") && !template.template.contains("{path}") {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!(
+                    "Path template `{}` must include `{{leaf}}` or `{{path}}`",
+                    template.template
+                ),
+            ));
+        }
+        Ok(template)
+    }
+
+    fn expand(&self, project_path: &str) -> Result<String> {
+        let now = Utc::now();
+        let (parent, leaf) = match project_path.rsplit_once('/') {
+            Some((parent, leaf)) => (parent, leaf),
+            None => ("", project_path),
+        };
+        let mut output = String::new();
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            output.push_str(&rest[..start]);
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => {
+                    return Err(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        format!("Unclosed placeholder in path template `{}`", self.template),
+                    ))
+                }
+            };
+            match &rest[start + 1..end] {
+                "name" => output.push_str(&self.name),
+                "collection" => output.push_str(&self.collection),
+                "yyyy" => output.push_str(&now.format("%Y").to_string()),
+                "mm" => output.push_str(&now.format("%m").to_string()),
+                "dd" => output.push_str(&now.format("%d").to_string()),
+                "path" => output.push_str(project_path),
+                "parent" => output.push_str(parent),
+                "leaf" => output.push_str(leaf),
+                other => {
+                    return Err(GodataError::new(
+                        GodataErrorType::InvalidPath,
+                        format!("Unknown placeholder `{{{}}}` in path template", other),
+                    ))
+                }
+            }
+            rest = &rest[end + 1..];
+        }
+        output.push_str(rest);
+        // An empty {parent} would otherwise leave doubled or leading separators behind
+        let parts: Vec<&str> = output.split('/').filter(|p| !p.is_empty()).collect();
+        // The project path is filled in too, so check the result as well as the template
+        if parts.is_empty() || parts.iter().any(|p| *p == "." || *p == "..") {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                format!(
+                    "Path template `{}` expands `{}` to `{}`, which is outside the storage root",
+                    self.template, project_path, output
+                ),
+            ));
+        }
+        Ok(parts.join("/"))
+    }
 }
 
 pub(crate) struct LocalEndpoint {
     // Represents a local disk location.
     root_path: PathBuf,
+    template: Option<PathTemplate>,
 }
 
 impl LocalEndpoint {
     pub(crate) fn new(root_path: PathBuf) -> LocalEndpoint {
-        LocalEndpoint { root_path }
+        LocalEndpoint {
+            root_path,
+            template: None,
+        }
     }
 }
 
 impl StorageEndpoint for LocalEndpoint {
     fn generate_path(&self, project_path: &str) -> Result<PathBuf> {
         // Generate a path to a project. This is the path to the root of the project
-        // on the local disk.
-        let path = self.root_path.join(project_path);
+        // on the local disk, laid out by the project's path template if it has one.
+        let path = match &self.template {
+            Some(template) => self.root_path.join(template.expand(project_path)?),
+            None => self.root_path.join(project_path),
+        };
         Ok(path)
     }
 
+    fn generate_unique_path(&self, project_path: &str, suffix: Option<&str>) -> Result<PathBuf> {
+        // Same as generate_path, but the file is claimed by creating it empty, so two
+        // callers can never be handed the same path and nothing is ever overwritten. If
+        // something is already there, a random component is added to the file name.
+        let path = self.generate_path(project_path)?;
+        let leaf = match path.file_name().and_then(|n| n.to_str()) {
+            Some(leaf) => leaf.to_string(),
+            None => {
+                return Err(GodataError::new(
+                    GodataErrorType::InvalidPath,
+                    format!("Cannot store `{}`, it has no file name", project_path),
+                ))
+            }
+        };
+        // The suffix is added to the whole name, unless the name already ends with it
+        let suffix = suffix
+            .map(|s| s.trim_start_matches('.'))
+            .filter(|s| !s.is_empty());
+        let stem = match suffix {
+            Some(suffix) => leaf
+                .strip_suffix(&format!(".{}", suffix))
+                .unwrap_or(&leaf)
+                .to_string(),
+            None => leaf,
+        };
+        let file_name = |tag: Option<String>| {
+            let name = match tag {
+                Some(tag) => format!("{}-{}", stem, tag),
+                None => stem.clone(),
+            };
+            match suffix {
+                Some(suffix) => format!("{}.{}", name, suffix),
+                None => name,
+            }
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut candidate = path.with_file_name(file_name(None));
+        loop {
+            let claimed = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&candidate);
+            match claimed {
+                Ok(_) => return Ok(candidate),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let tag = Uuid::new_v4().simple().to_string();
+                    candidate = path.with_file_name(file_name(Some(tag)));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
     fn is_internal(&self, path: &Path) -> bool {
         // Check if a path is internal to the project. This means that it is a path
-        // that is not a symlink to a file outside the project.
-        path.starts_with(&self.root_path)
+        // that is not a symlink to a file outside the project. The root itself is
+        // never a file, and a path that steps back out with `..` is not inside it.
+        match path.strip_prefix(&self.root_path) {
+            Ok(rest) => {
+                rest.components().next().is_some()
+                    && rest.components().all(|c| matches!(c, Component::Normal(_)))
+            }
+            Err(_) => false,
+        }
     }
 
     fn is_available(&self) -> Result<()> {
@@ -162,10 +408,12 @@ impl StorageEndpoint for LocalEndpoint {
         fs::rename(from_path, to_path)?;
         Ok(())
     }
-    fn copy_file(&self, from: &str, to: &str) -> Result<()> {
-        let from_path = self.generate_path(from)?;
-        let to_path = self.generate_path(to)?;
-        fs::copy(from_path, to_path)?;
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<()> {
+        // Copy between real paths, which may be inside or outside of the storage root
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(from, to)?;
         Ok(())
     }
     fn delete_file(&self, path: &str) -> Result<()> {
@@ -192,4 +440,140 @@ impl StorageEndpoint for LocalEndpoint {
         }
         self.root_path.join(relpath)
     }
+
+    fn open_read(&self, relpath: &Path) -> Result<Box<dyn Read>> {
+        let file = fs::File::open(self.resolve(relpath))?;
+        Ok(Box::new(file))
+    }
+
+    fn kind(&self) -> &'static str {
+        "local"
+    }
+
+    fn root(&self) -> String {
+        self.root_path.to_str().unwrap().to_string()
+    }
+
+    fn set_path_template(&mut self, template: Option<PathTemplate>) {
+        self.template = template;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scratch_dir;
+
+    #[test]
+    fn unique_paths_are_claimed() {
+        let endpoint = LocalEndpoint::new(scratch_dir());
+        let first = endpoint.generate_unique_path("data/a", None).unwrap();
+        let second = endpoint.generate_unique_path("data/a", None).unwrap();
+        assert_ne!(first, second);
+        assert!(first.is_file() && second.is_file());
+        assert_eq!(first, endpoint.generate_path("data/a").unwrap());
+    }
+
+    #[test]
+    fn unique_paths_keep_dots_in_names() {
+        let endpoint = LocalEndpoint::new(scratch_dir());
+        let path = endpoint
+            .generate_unique_path("data/image.v1", Some(".fits"))
+            .unwrap();
+        assert_eq!(path.file_name().unwrap(), "image.v1.fits");
+        // The suffix isn't doubled, and a clash keeps it at the end of the name
+        let path = endpoint
+            .generate_unique_path("data/image.v1.fits", Some("fits"))
+            .unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("image.v1-") && name.ends_with(".fits"));
+    }
+
+    #[test]
+    fn path_templates_expand_placeholders() {
+        let template =
+            PathTemplate::new("{collection}/{name}/{yyyy}/{mm}/{leaf}", "m31", "survey").unwrap();
+        let date = Utc::now().format("%Y/%m").to_string();
+        assert_eq!(
+            template.expand("raw/image.fits").unwrap(),
+            format!("survey/m31/{}/image.fits", date)
+        );
+        let template = PathTemplate::new("{dd}/{parent}/{leaf}", "m31", "survey").unwrap();
+        let day = Utc::now().format("%d").to_string();
+        assert_eq!(
+            template.expand("raw/image.fits").unwrap(),
+            format!("{}/raw/image.fits", day)
+        );
+        // An empty parent doesn't leave a doubled separator behind
+        assert_eq!(
+            template.expand("image.fits").unwrap(),
+            format!("{}/image.fits", day)
+        );
+
+        let root = scratch_dir();
+        let mut endpoint = LocalEndpoint::new(root.clone());
+        let template = PathTemplate::new("by-name/{name}/{path}", "m31", "survey").unwrap();
+        endpoint.set_path_template(Some(template));
+        assert_eq!(
+            endpoint.generate_path("raw/image.fits").unwrap(),
+            root.join("by-name/m31/raw/image.fits")
+        );
+    }
+
+    #[test]
+    fn path_templates_stay_inside_storage() {
+        for bad in [
+            "../../{leaf}",
+            "/abs/{leaf}",
+            "a//{leaf}",
+            "./{leaf}",
+            "{leaf}/",
+            "{name}",
+            "{unknown}/{leaf}",
+            "{leaf",
+        ] {
+            let error = PathTemplate::new(bad, "m31", "survey").unwrap_err();
+            assert_eq!(error.error_type, GodataErrorType::InvalidPath, "{}", bad);
+        }
+        // The project path can't be used to step out either
+        let template = PathTemplate::new("{name}/{path}", "m31", "survey").unwrap();
+        assert!(template.expand("../../../etc/passwd").is_err());
+        assert!(template.expand("raw/../../..").is_err());
+
+        let root = scratch_dir();
+        let endpoint = LocalEndpoint::new(root.clone());
+        assert!(endpoint.is_internal(&root.join("data/file.txt")));
+        assert!(!endpoint.is_internal(&root));
+        assert!(!endpoint.is_internal(&root.join("../../file.txt")));
+        assert!(!endpoint.is_internal(&root.join("data/../../file.txt")));
+    }
+
+    #[test]
+    fn only_projects_under_the_prefix_are_listed() {
+        let root = scratch_dir();
+        let storage = StorageManager {
+            _root_path: root.clone(),
+            storage_db: sled::Config::new().temporary(true).open().unwrap(),
+        };
+        for (name, dir) in [
+            ("a", "data/a"),
+            ("b", "data/nested/b"),
+            ("c", "database/c"),
+            ("d", "other/d"),
+        ] {
+            storage
+                .add(name, "survey", "local", root.join(dir))
+                .unwrap();
+        }
+        let mut found = storage.list_under(root.join("data")).unwrap();
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                ("a".to_string(), "survey".to_string()),
+                ("b".to_string(), "survey".to_string())
+            ]
+        );
+        assert!(storage.list_under(root.join("missing")).unwrap().is_empty());
+    }
 }
@@ -0,0 +1,101 @@
+// Shared setup for the unit tests. godata keeps everything under the user's home and
+// data directories, so those are pointed at a scratch directory once per test run.
+// Tests share one project manager, like the server does, and stay out of each other's
+// way by working in collections of their own. A test that panics while holding the
+// manager doesn't take the others down with it, since the lock is recovered.
+
+use crate::project::{get_project_manager, Project, ProjectManager};
+use once_cell::sync::Lazy;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+static SCRATCH: Lazy<PathBuf> = Lazy::new(|| {
+    let dir = std::env::temp_dir().join(format!("godata-tests-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::env::set_var("HOME", &dir);
+    std::env::set_var("XDG_DATA_HOME", dir.join(".local/share"));
+    dir
+});
+
+static MANAGER: Lazy<Arc<Mutex<ProjectManager>>> = Lazy::new(|| {
+    Lazy::force(&SCRATCH);
+    Arc::new(Mutex::new(get_project_manager().unwrap()))
+});
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn manager() -> Arc<Mutex<ProjectManager>> {
+    // Handlers lock the manager themselves, so it's handed out unpoisoned
+    MANAGER.clear_poison();
+    MANAGER.clone()
+}
+
+pub(crate) fn lock_manager() -> MutexGuard<'static, ProjectManager> {
+    MANAGER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub(crate) fn unique(prefix: &str) -> String {
+    format!("{}{}", prefix, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+pub(crate) fn scratch_dir() -> PathBuf {
+    // An empty directory outside of godata's storage, for files to link from
+    let dir = SCRATCH.join("files").join(unique("dir"));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub(crate) fn write_file(path: &Path, contents: &str) -> PathBuf {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+    path.to_path_buf()
+}
+
+pub(crate) fn new_project() -> (String, String, Arc<Mutex<Project>>) {
+    // A fresh project in a collection of its own. Returns the collection and name.
+    let collection = unique("collection");
+    let name = unique("project");
+    let project = lock_manager()
+        .create_project(&name, &collection, true, None)
+        .unwrap();
+    (collection, name, project)
+}
+
+pub(crate) fn read_zip(bytes: &[u8]) -> Vec<(String, Vec<u8>)> {
+    // Unpack an archive without a comment through its central directory, checking each
+    // entry's size and crc along the way
+    use flate2::read::DeflateDecoder;
+    use std::io::Read;
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize;
+    let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    let end = bytes.len() - 22;
+    assert_eq!(u32_at(end), 0x06054b50);
+    let mut at = u32_at(end + 16) as usize;
+    let mut entries = Vec::new();
+    for _ in 0..u16_at(end + 10) {
+        assert_eq!(u32_at(at), 0x02014b50);
+        let (crc, compressed, size) = (u32_at(at + 16), u32_at(at + 20), u32_at(at + 24));
+        let name_len = u16_at(at + 28);
+        let name = String::from_utf8(bytes[at + 46..at + 46 + name_len].to_vec()).unwrap();
+        let local = u32_at(at + 42) as usize;
+        assert_eq!(u32_at(local), 0x04034b50);
+        let start = local + 30 + u16_at(local + 26) + u16_at(local + 28);
+        let mut content = Vec::new();
+        DeflateDecoder::new(&bytes[start..start + compressed as usize])
+            .read_to_end(&mut content)
+            .unwrap();
+        let mut check = flate2::Crc::new();
+        check.update(&content);
+        assert_eq!(
+            (check.sum(), content.len()),
+            (crc, size as usize),
+            "{}",
+            name
+        );
+        entries.push((name, content));
+        at += 46 + name_len + u16_at(at + 30) + u16_at(at + 32);
+    }
+    entries
+}
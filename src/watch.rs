@@ -0,0 +1,62 @@
+// Watches a project's storage directory for files that disappear out from under the
+// tree. The watcher lives on the project, so it stops when the project is dropped
+// from the cache.
+
+use crate::errors::Result;
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub(crate) struct StorageWatcher {
+    _watcher: RecommendedWatcher,
+    removed: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl StorageWatcher {
+    pub(crate) fn new(root: &Path) -> Result<StorageWatcher> {
+        let removed: Arc<Mutex<HashSet<PathBuf>>> = Arc::new(Mutex::new(HashSet::new()));
+        let events = removed.clone();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            let event = match res {
+                Ok(event) => event,
+                Err(e) => {
+                    tracing::error!("Storage watcher error: {}", e);
+                    return;
+                }
+            };
+            let mut removed = events.lock().unwrap();
+            match event.kind {
+                EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+                    removed.extend(event.paths)
+                }
+                EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+                    for path in event.paths {
+                        removed.remove(&path);
+                    }
+                }
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+                    // Paths are reported as [from, to]
+                    if let [from, to] = event.paths.as_slice() {
+                        removed.insert(from.clone());
+                        removed.remove(to);
+                    }
+                }
+                _ => (),
+            }
+        })?;
+        // A project that hasn't stored anything yet may not have its root on disk
+        std::fs::create_dir_all(root)?;
+        watcher.watch(root, RecursiveMode::Recursive)?;
+        tracing::info!("Watching storage directory `{}`", root.display());
+        Ok(StorageWatcher {
+            _watcher: watcher,
+            removed,
+        })
+    }
+
+    pub(crate) fn removed(&self) -> HashSet<PathBuf> {
+        self.removed.lock().unwrap().clone()
+    }
+}
@@ -0,0 +1,249 @@
+// A minimal zip writer for streaming files out of a project. Entries are deflated and
+// followed by a data descriptor, so nothing has to be seeked back over and the output
+// can go straight to a socket. Zip64 isn't supported, so archives are limited to 65535
+// entries and 4GB, and anything that might not fit is turned away before it's started.
+
+use crate::errors::{GodataError, GodataErrorType, Result};
+use chrono::{Datelike, Timelike, Utc};
+use flate2::write::DeflateEncoder;
+use flate2::Crc;
+use std::io::{Read, Write};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIR_SIGNATURE: u32 = 0x06054b50;
+// Version 2.0, the first with deflate. Central headers also mark the entries as
+// coming from unix so the permissions below are picked up.
+const VERSION: u16 = 20;
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION;
+// Sizes and crc come after the data, and names are utf-8
+const FLAGS: u16 = 0x0008 | 0x0800;
+const DEFLATE: u16 = 8;
+const FILE_MODE: u32 = 0o100644;
+
+pub(crate) const MAX_ENTRIES: usize = u16::MAX as usize;
+// Room kept for a manifest written at the end, whose size isn't known in advance
+const TRAILER_ALLOWANCE: u64 = 1 << 20;
+
+pub(crate) fn fits(entries: &[(&str, u64)]) -> bool {
+    // Whether files with these names and sizes are sure to fit in an archive, with one
+    // more entry to spare. Each takes a local header, its compressed data, a data
+    // descriptor and a central header, and deflate can make data that doesn't
+    // compress a little bigger (by at most zlib's compressBound).
+    let mut total = 22 + TRAILER_ALLOWANCE;
+    for (name, size) in entries {
+        let deflated = size + (size >> 12) + (size >> 14) + (size >> 25) + 13;
+        total += 30 + 16 + 46 + 2 * name.len() as u64 + deflated;
+    }
+    entries.len() < MAX_ENTRIES && total <= u64::from(u32::MAX)
+}
+
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct Entry {
+    name: String,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+pub(crate) struct ZipWriter<W: Write> {
+    out: CountingWriter<W>,
+    entries: Vec<Entry>,
+    // Every entry gets the time the archive was started, in DOS format
+    time: u16,
+    date: u16,
+}
+
+fn too_large() -> GodataError {
+    GodataError::new(
+        GodataErrorType::TooLarge,
+        "Archive is too large for a zip file without zip64".to_string(),
+    )
+}
+
+fn to_u32(value: u64) -> Result<u32> {
+    u32::try_from(value).map_err(|_| too_large())
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub(crate) fn new(out: W) -> ZipWriter<W> {
+        let now = Utc::now();
+        ZipWriter {
+            out: CountingWriter {
+                inner: out,
+                count: 0,
+            },
+            entries: Vec::new(),
+            time: ((now.hour() << 11) | (now.minute() << 5) | (now.second() / 2)) as u16,
+            date: (((now.year() - 1980).max(0) as u32) << 9 | now.month() << 5 | now.day()) as u16,
+        }
+    }
+
+    pub(crate) fn add<R: Read>(&mut self, name: &str, mut content: R) -> Result<()> {
+        if self.entries.len() >= MAX_ENTRIES {
+            return Err(too_large());
+        }
+        if name.len() > u16::MAX as usize {
+            return Err(GodataError::new(
+                GodataErrorType::InvalidPath,
+                "Name is too long for a zip entry".to_string(),
+            ));
+        }
+        let offset = to_u32(self.out.count)?;
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend(LOCAL_HEADER_SIGNATURE.to_le_bytes());
+        header.extend(VERSION.to_le_bytes());
+        header.extend(FLAGS.to_le_bytes());
+        header.extend(DEFLATE.to_le_bytes());
+        header.extend(self.time.to_le_bytes());
+        header.extend(self.date.to_le_bytes());
+        // crc and sizes are left empty here, they follow the data
+        header.extend([0u8; 12]);
+        header.extend((name.len() as u16).to_le_bytes());
+        header.extend(0u16.to_le_bytes());
+        header.extend(name.as_bytes());
+        self.out.write_all(&header)?;
+
+        let start = self.out.count;
+        let mut crc = Crc::new();
+        let mut size: u64 = 0;
+        let mut encoder = DeflateEncoder::new(&mut self.out, flate2::Compression::default());
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = content.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            crc.update(&buffer[..read]);
+            size += read as u64;
+            encoder.write_all(&buffer[..read])?;
+        }
+        encoder.finish()?;
+        let entry = Entry {
+            name: name.to_string(),
+            crc: crc.sum(),
+            compressed_size: to_u32(self.out.count - start)?,
+            size: to_u32(size)?,
+            offset,
+        };
+
+        let mut descriptor = Vec::with_capacity(16);
+        descriptor.extend(DATA_DESCRIPTOR_SIGNATURE.to_le_bytes());
+        descriptor.extend(entry.crc.to_le_bytes());
+        descriptor.extend(entry.compressed_size.to_le_bytes());
+        descriptor.extend(entry.size.to_le_bytes());
+        self.out.write_all(&descriptor)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<W> {
+        let start = to_u32(self.out.count)?;
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            directory.extend(CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+            directory.extend(VERSION_MADE_BY.to_le_bytes());
+            directory.extend(VERSION.to_le_bytes());
+            directory.extend(FLAGS.to_le_bytes());
+            directory.extend(DEFLATE.to_le_bytes());
+            directory.extend(self.time.to_le_bytes());
+            directory.extend(self.date.to_le_bytes());
+            directory.extend(entry.crc.to_le_bytes());
+            directory.extend(entry.compressed_size.to_le_bytes());
+            directory.extend(entry.size.to_le_bytes());
+            directory.extend((entry.name.len() as u16).to_le_bytes());
+            // Extra field, comment, disk number and internal attributes are all empty
+            directory.extend([0u8; 8]);
+            directory.extend((FILE_MODE << 16).to_le_bytes());
+            directory.extend(entry.offset.to_le_bytes());
+            directory.extend(entry.name.as_bytes());
+        }
+        let size = to_u32(directory.len() as u64)?;
+        let count = (self.entries.len() as u16).to_le_bytes();
+        directory.extend(END_OF_CENTRAL_DIR_SIGNATURE.to_le_bytes());
+        directory.extend([0u8; 4]);
+        directory.extend(count);
+        directory.extend(count);
+        directory.extend(size.to_le_bytes());
+        directory.extend(start.to_le_bytes());
+        directory.extend(0u16.to_le_bytes());
+        self.out.write_all(&directory)?;
+        self.out.flush()?;
+        Ok(self.out.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::read_zip;
+
+    struct FailingReader {
+        remaining: usize,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            // Hands out some data, then fails like a disk that went away
+            if self.remaining == 0 {
+                return Err(std::io::Error::other("device went away"));
+            }
+            let read = buf.len().min(self.remaining);
+            buf[..read].fill(b'x');
+            self.remaining -= read;
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn two_files_read_back() {
+        let mut archive = ZipWriter::new(Vec::new());
+        let big = "line of text\n".repeat(10000);
+        archive.add("a.txt", "first".as_bytes()).unwrap();
+        archive.add("dir/b.txt", big.as_bytes()).unwrap();
+        let bytes = archive.finish().unwrap();
+        // Repetitive text is actually compressed
+        assert!(bytes.len() < big.len() / 10);
+        let entries = read_zip(&bytes);
+        assert_eq!(
+            entries,
+            vec![
+                ("a.txt".to_string(), b"first".to_vec()),
+                ("dir/b.txt".to_string(), big.into_bytes()),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_read_is_an_error() {
+        let mut archive = ZipWriter::new(Vec::new());
+        let result = archive.add("a.txt", FailingReader { remaining: 100_000 });
+        assert_eq!(result.unwrap_err().error_type, GodataErrorType::IOError);
+    }
+
+    #[test]
+    fn archives_that_might_not_fit_are_refused() {
+        assert!(fits(&[("a.txt", 1 << 20), ("b.txt", 1 << 30)]));
+        assert!(!fits(&[("a.txt", u64::from(u32::MAX) - (1 << 10))]));
+        assert!(!fits(&vec![("a.txt", 0); MAX_ENTRIES]));
+        assert!(fits(&vec![("a.txt", 0); MAX_ENTRIES - 1]));
+    }
+}